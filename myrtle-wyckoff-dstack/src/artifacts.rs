@@ -11,11 +11,19 @@ sol! (
             uint256 nonce;
         }
 
+        event Deposit(
+            address indexed user,
+            uint256 indexed index,
+            uint256 eth_amount,
+            uint256 usdc_amount
+        );
+
         function GPv2Settlement() external view returns (address);
         function HookTrampoline() external view returns (address);
         function USDC() external view returns (address);
         function WETH() external view returns (address);
         function admin() external view returns (address);
+        function leader() external view returns (address);
 
         function deposit(
             address user,
@@ -41,11 +49,14 @@ sol! (
 
         function pull_settlement_funds(
             Order calldata order,
-            bytes calldata signature
+            bytes calldata signature,
+            bytes calldata leader_signature
         ) external;
 
         function set_admin(address newAdmin) external;
+        function set_leader(address new_leader) external;
         function settlement_nonce() external view returns (uint256);
+        function domain_separator() external view returns (bytes32);
     }
 );
 
@@ -54,6 +65,7 @@ sol! (
     interface ICheckpointer {
     struct Checkpoint {
         uint256 nonce;
+        bytes32 inventory_root;
         uint8[] inventory_state;
         string[] settlement_orders;
     }
@@ -61,9 +73,16 @@ sol! (
     event SettlementOrders(string[] settlement_orders);
 
     function admin() external view returns (address);
+    function leader() external view returns (address);
     function inventory_checkpoint_nonce() external view returns (uint256);
     function inventory_checkpoint(uint256) external view returns (uint8);
+    function inventory_root() external view returns (bytes32);
+    function get_inventory_checkpoint() external view returns (uint8[] memory);
+    function get_settlement_orders() external view returns (string[] memory);
+    function verify_inventory(bytes32[] calldata proof, bytes calldata inventory) external view returns (bool);
     function set_admin(address new_admin) external;
-    function checkpoint(bytes calldata signature, Checkpoint calldata _checkpoint) external;
+    function set_leader(address new_leader) external;
+    function domain_separator() external view returns (bytes32);
+    function checkpoint(bytes calldata signature, bytes calldata leader_signature, Checkpoint calldata _checkpoint) external;
 }
 );
@@ -0,0 +1,188 @@
+// Overview:
+// Remote attestation, so clients can check they're talking to this app's enclave before trusting
+// its signatures.
+// * /attestation asks the dstack guest agent for a TDX quote and returns it with the agent's event
+//   log, the quote's measurements say which image is running
+// * the quote's report data is abi.encode(signer, config hash), binding it to the address the app
+//   signs checkpoints and settlements with (Warehouse::signer) and to the config it runs with
+// * the config hash is keccak256 of config_json, which is served along with the quote so clients
+//   can recompute it. RPC URLs are left out since they tend to carry api keys and chain data is
+//   checked against quorums and proofs anyway (see verifier.rs)
+// * a quote whose report data isn't ours is refused instead of served
+//
+// TODO: verifying the quote itself (signature chain up to Intel, TCB status) is left to clients
+
+use alloy::{
+    hex,
+    primitives::{keccak256, Address, B256},
+    sol_types::SolValue,
+    transports::http::reqwest::Url,
+};
+
+use crate::{config::Config, errors::MwError, keys::KeyProviderConfig, sink::SinkConfig};
+
+pub const REPORT_DATA_BYTES: usize = 64;
+// a v4 TDX quote is a 48 byte header followed by the TD report body, whose last field is the
+// report data
+const QUOTE_REPORT_DATA_OFFSET: usize = 48 + 520;
+
+fn attestation_error(message: impl Into<String>) -> MwError {
+    MwError::AttestationError(message.into())
+}
+
+/// What the app is configured with, minus anything secret. Numbers are strings like everywhere
+/// else.
+pub fn config_json(config: &Config) -> serde_json::Value {
+    let chains: Vec<serde_json::Value> = config
+        .chains
+        .iter()
+        .map(|chain| {
+            serde_json::json!({
+                "chain_id": chain.spec.chain_id.to_string(),
+                "weth": chain.spec.weth.to_string(),
+                "usdc": chain.spec.usdc.to_string(),
+                "gpv2_settlement": chain.spec.gpv2_settlement.to_string(),
+                "rpc_endpoints": chain.rpc_urls.len().to_string(),
+            })
+        })
+        .collect();
+    let key_provider = match &config.key_provider {
+        KeyProviderConfig::Dstack => "dstack".to_string(),
+        KeyProviderConfig::File(path) => format!("file:{}", path.display()),
+        KeyProviderConfig::Env(var) => format!("env:{}", var),
+    };
+    let sink = match &config.sink {
+        SinkConfig::Checkpointer => "checkpointer".to_string(),
+        SinkConfig::Calldata { chain_id: None, to } => format!("calldata:{}", to),
+        SinkConfig::Calldata {
+            chain_id: Some(chain_id),
+            to,
+        } => format!("calldata:{}:{}", chain_id, to),
+        SinkConfig::File(directory) => format!("file:{}", directory.display()),
+    };
+    serde_json::json!({
+        "recover_from": config.recover_from.map(|address| address.to_string()),
+        "operator": config.operator.map(|address| address.to_string()),
+        "deposit_chains": chains,
+        "data_dir": config.storage.data_dir.display().to_string(),
+        "host_data_dir": config.storage.host_data_dir.display().to_string(),
+        "dstack_key_url": config.dstack_key_url.to_string(),
+        "dstack_quote_url": config.dstack_quote_url.to_string(),
+        "key_provider": key_provider,
+        "role": config.role.to_string(),
+        "leader_url": config.leader_url.as_ref().map(Url::to_string),
+        "cowswap_api_url": config.cowswap_api_url.to_string(),
+        "domains": {
+            "name": config.domains.name,
+            "version": config.domains.version,
+            "checkpoint_chain_id": config.domains.checkpoint_chain_id.to_string(),
+            "dstack_verifying_contract": config.domains.dstack_verifying_contract.to_string(),
+        },
+        "checkpoint_sink": sink,
+        "snapshot_interval_ms": config.scheduler.interval.as_millis().to_string(),
+        "deposit_confirmations": config.indexer.confirmations.to_string(),
+        "deposit_start_blocks": config
+            .indexer
+            .start_blocks
+            .iter()
+            .map(|(chain_id, block)| (chain_id.to_string(), block.to_string().into()))
+            .collect::<serde_json::Map<String, serde_json::Value>>(),
+    })
+}
+
+/// keccak256 of config_json as serialized, keys are sorted so it doesn't depend on field order.
+pub fn config_hash(config: &Config) -> B256 {
+    keccak256(serde_json::to_string(&config_json(config)).unwrap())
+}
+
+/// abi.encode(signer, config_hash), exactly the 64 bytes TDX has room for.
+pub fn report_data(signer: Address, config_hash: B256) -> [u8; REPORT_DATA_BYTES] {
+    let mut report_data = [0u8; REPORT_DATA_BYTES];
+    report_data.copy_from_slice(&(signer, config_hash).abi_encode());
+    report_data
+}
+
+/// The report data a quote was issued over, None if it's too short to be a TDX quote.
+pub fn quote_report_data(quote: &[u8]) -> Option<&[u8]> {
+    quote.get(QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + REPORT_DATA_BYTES)
+}
+
+/// The dstack guest agent's quote endpoint.
+pub struct GuestAgent {
+    pub quote_url: Url,
+}
+
+#[derive(serde::Deserialize)]
+struct QuoteResponse {
+    quote: String,
+    event_log: String,
+}
+
+impl GuestAgent {
+    /// A TDX quote over `report_data` and the event log the RTMRs in it were extended with.
+    pub async fn quote(
+        &self,
+        report_data: &[u8; REPORT_DATA_BYTES],
+    ) -> Result<(Vec<u8>, String), MwError> {
+        let response: QuoteResponse = reqwest::Client::new()
+            .post(self.quote_url.clone())
+            .json(&serde_json::json!({ "report_data": hex::encode(report_data) }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| attestation_error(format!("dstack guest agent: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| attestation_error(format!("dstack guest agent: {}", e)))?;
+        let quote =
+            hex::decode(response.quote.trim()).map_err(|_| attestation_error("quote isn't hex"))?;
+        Ok((quote, response.event_log))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub signer: Address,
+    pub config: serde_json::Value,
+    pub config_hash: B256,
+    pub report_data: [u8; REPORT_DATA_BYTES],
+    pub quote: Vec<u8>,
+    pub event_log: String,
+}
+impl Attestation {
+    pub fn to_json(&self) -> String {
+        let serializable_attestation = serde_json::json!({
+            "signer": self.signer.to_string(),
+            "config": self.config,
+            "config_hash": self.config_hash.to_string(),
+            "report_data": hex::encode_prefixed(self.report_data),
+            "quote": hex::encode_prefixed(&self.quote),
+            "event_log": self.event_log,
+        });
+        serde_json::to_string(&serializable_attestation).unwrap()
+    }
+}
+
+/// Gets a quote binding `signer` and `config`, checking the agent issued it over what we asked.
+pub async fn attest(
+    agent: &GuestAgent,
+    signer: Address,
+    config: &Config,
+) -> Result<Attestation, MwError> {
+    let config_hash = config_hash(config);
+    let report_data = report_data(signer, config_hash);
+    let (quote, event_log) = agent.quote(&report_data).await?;
+    if quote_report_data(&quote) != Some(&report_data[..]) {
+        return Err(attestation_error(
+            "quote isn't over the requested report data",
+        ));
+    }
+    Ok(Attestation {
+        signer,
+        config: config_json(config),
+        config_hash,
+        report_data,
+        quote,
+        event_log,
+    })
+}
@@ -0,0 +1,253 @@
+// Overview:
+// Batch order submission. Brokers re-quote every second across multiple pairs, so rather than
+// signing and sending every new/cancel/replace separately they can send one signed batch.
+// * operations are executed sequentially as a single sequenced command
+// * every operation gets its own result
+// * in atomic mode the batch runs against a copy of the warehouse and order book, which replaces
+//   the live state only if every operation succeeded, so the first failure rejects the batch with
+//   nothing changed
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, U256},
+    signers::Signature,
+    sol,
+    sol_types::SolStruct,
+};
+use optimized_lob::{order::OrderId, orderbook_manager::OrderBookManager};
+use tracing::info;
+
+use crate::{
+    errors::MwError,
+    orderhere::{self, Order},
+    structs,
+    warehouse::Warehouse,
+};
+
+pub const MAX_BATCH_SIZE: usize = 64;
+pub const BATCH_NEW: u8 = 0;
+pub const BATCH_CANCEL: u8 = 1;
+pub const BATCH_REPLACE: u8 = 2;
+
+sol! {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct BatchOperation {
+        uint8 kind; // 0 = new, 1 = cancel, 2 = replace
+        uint32 oid; // order being cancelled or replaced, ignored for new orders
+        uint256 price;
+        uint256 qty;
+        bool is_bid;
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct OrderBatch {
+        BatchOperation[] operations;
+        bool atomic;
+        uint64 timestamp; // unix timestamp in milliseconds
+    }
+}
+impl OrderBatch {
+    pub fn validate_timestamp(&self) -> Result<(), MwError> {
+        structs::validate_timestamp(self.timestamp)
+    }
+    pub fn validate_signature(
+        &self,
+        domain: &Eip712Domain,
+        signature: Signature,
+        user: Address,
+    ) -> Result<(), MwError> {
+        let batch_hash = self.eip712_signing_hash(domain);
+        let recovered_address = signature
+            .recover_address_from_prehash(&batch_hash)
+            .map_err(|_| MwError::SignatureRecoveryError)?;
+        if user != recovered_address {
+            return Err(MwError::InvalidSignature);
+        }
+        Ok(())
+    }
+    /// Whether the batch places any new liquidity, cancels only batches are allowed in more phases.
+    pub fn places_orders(&self) -> bool {
+        self.operations
+            .iter()
+            .any(|operation| operation.kind != BATCH_CANCEL)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BatchResult {
+    Placed {
+        qty_executed: U256,
+        volume_executed: U256,
+        order_id: Option<u32>,
+    },
+    Cancelled {
+        order_id: u32,
+    },
+    Replaced {
+        order_id: u32,
+        new_order_id: u32,
+    },
+    Failed {
+        error: MwError,
+    },
+}
+impl BatchResult {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Placed {
+                qty_executed,
+                volume_executed,
+                order_id,
+            } => serde_json::json!({
+                "status": "placed",
+                "qty_executed": qty_executed.to_string(),
+                "volume_executed": volume_executed.to_string(),
+                "order_id": order_id,
+            }),
+            Self::Cancelled { order_id } => serde_json::json!({
+                "status": "cancelled",
+                "order_id": order_id,
+            }),
+            Self::Replaced {
+                order_id,
+                new_order_id,
+            } => serde_json::json!({
+                "status": "replaced",
+                "order_id": order_id,
+                "new_order_id": new_order_id,
+            }),
+            Self::Failed { error } => serde_json::json!({
+                "status": "failed",
+                "error": error.to_string(),
+                "code": error.code(),
+            }),
+        }
+    }
+}
+
+pub fn results_to_json(results: &[BatchResult]) -> String {
+    serde_json::to_string(
+        &results
+            .iter()
+            .map(|result| result.to_json())
+            .collect::<Vec<_>>(),
+    )
+    .unwrap()
+}
+
+pub fn execute_batch(
+    user: Address,
+    batch: OrderBatch,
+    signature: Signature,
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+) -> Result<Vec<BatchResult>, MwError> {
+    batch.validate_signature(&warehouse.domains.dstack, signature, user)?;
+    batch.validate_timestamp()?;
+
+    apply_batch(warehouse, orderbook_manager, user, &batch)
+}
+
+//@Dev: this does not validate the user's signature, callers are responsible for authorizing the batch
+pub fn apply_batch(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    batch: &OrderBatch,
+) -> Result<Vec<BatchResult>, MwError> {
+    if batch.operations.is_empty() || batch.operations.len() > MAX_BATCH_SIZE {
+        return Err(MwError::InvalidOrderParams);
+    }
+
+    if !batch.atomic {
+        let results: Vec<BatchResult> = batch
+            .operations
+            .iter()
+            .map(|operation| {
+                execute_operation(
+                    warehouse,
+                    orderbook_manager,
+                    user,
+                    operation,
+                    batch.timestamp,
+                )
+                .unwrap_or_else(|error| BatchResult::Failed { error })
+            })
+            .collect();
+        info!("batch executed: {:?}", (user, results.len(), batch.atomic));
+        return Ok(results);
+    }
+
+    // atomic batches run against a copy, the live state is only replaced once all of it succeeded
+    let mut staged_warehouse = warehouse.clone();
+    let mut staged_orderbook_manager = orderbook_manager.clone();
+    let mut results = Vec::with_capacity(batch.operations.len());
+    for (index, operation) in batch.operations.iter().enumerate() {
+        match execute_operation(
+            &mut staged_warehouse,
+            &mut staged_orderbook_manager,
+            user,
+            operation,
+            batch.timestamp,
+        ) {
+            Ok(result) => results.push(result),
+            Err(error) => {
+                info!("atomic batch rejected: {:?}", (user, index, &error));
+                return Err(MwError::BatchFailed {
+                    index,
+                    reason: error.to_string(),
+                });
+            }
+        }
+    }
+    *warehouse = staged_warehouse;
+    *orderbook_manager = staged_orderbook_manager;
+    info!("batch executed: {:?}", (user, results.len(), batch.atomic));
+    Ok(results)
+}
+
+fn execute_operation(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    operation: &BatchOperation,
+    timestamp: u64,
+) -> Result<BatchResult, MwError> {
+    let order = Order {
+        price: operation.price,
+        qty: operation.qty,
+        is_bid: operation.is_bid,
+        timestamp,
+    };
+    match operation.kind {
+        BATCH_NEW => {
+            let (qty_executed, volume_executed, order_id) =
+                orderhere::submit_order(warehouse, orderbook_manager, user, order)?;
+            Ok(BatchResult::Placed {
+                qty_executed: qty_executed.0,
+                volume_executed: volume_executed.0,
+                order_id: order_id.map(|oid| oid.0),
+            })
+        }
+        BATCH_CANCEL => {
+            orderhere::submit_cancel(warehouse, orderbook_manager, user, OrderId(operation.oid))?;
+            Ok(BatchResult::Cancelled {
+                order_id: operation.oid,
+            })
+        }
+        BATCH_REPLACE => {
+            let new_oid = orderhere::submit_replace(
+                warehouse,
+                orderbook_manager,
+                user,
+                order,
+                OrderId(operation.oid),
+            )?;
+            Ok(BatchResult::Replaced {
+                order_id: operation.oid,
+                new_order_id: new_oid.0,
+            })
+        }
+        _ => Err(MwError::InvalidOrderParams),
+    }
+}
@@ -0,0 +1,62 @@
+// Overview:
+// Rebuilds engine state from an event log and checks it against the recorded state hashes.
+// Usage: replay <event-log> [expected-state-hash]
+// * every record is refolded in order, replay stops at the first record whose outcome or state
+//   hash doesn't match what was recorded
+// * if an expected hash is given (e.g. from a checkpoint) the final state must match it too
+
+use std::{env, process::exit, str::FromStr};
+
+use alloy::primitives::B256;
+use myrtle_wyckoff_dstack::{
+    events,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    session::{SessionController, SessionSchedule},
+    warehouse::Warehouse,
+};
+use optimized_lob::orderbook_manager::OrderBookManager;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: replay <event-log> [expected-state-hash]");
+        exit(2);
+    }
+    let records = events::read_records(&args[1]).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", args[1], e);
+        exit(2);
+    });
+
+    // keys aren't part of the folded state, settlement orders are already signed in the log
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[0u8; MIN_SECRET_BYTES]).unwrap());
+    let mut orderbook_manager = OrderBookManager::new();
+    let mut session = SessionController::new(SessionSchedule::default(), 0);
+
+    let state_hash = match events::replay(
+        &mut warehouse,
+        &mut orderbook_manager,
+        &mut session,
+        &records,
+    ) {
+        Ok(state_hash) => state_hash,
+        Err(seq) => {
+            eprintln!("replay diverged at seq {}", seq);
+            exit(1);
+        }
+    };
+    let last_seq = records.last().map_or(0, |record| record.seq);
+    println!("replayed {} events up to seq {}", records.len(), last_seq);
+    println!("state hash: {}", state_hash);
+
+    if let Some(expected) = args.get(2) {
+        let expected = B256::from_str(expected).unwrap_or_else(|_| {
+            eprintln!("invalid state hash {}", expected);
+            exit(2);
+        });
+        if expected != state_hash {
+            eprintln!("state hash mismatch, expected {}", expected);
+            exit(1);
+        }
+        println!("state hash matches");
+    }
+}
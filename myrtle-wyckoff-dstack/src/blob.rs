@@ -0,0 +1,150 @@
+// Overview:
+// Binary format of the encrypted inventory blob posted in each checkpoint.
+//
+// version 2, integers little endian unless noted:
+//   magic            4 bytes  "MWIB"
+//   version          1 byte
+//   checkpoint nonce 32 bytes big endian, the Checkpointer nonce the blob is posted with
+//   record count     4 bytes
+//   records          record count times:
+//     length         4 bytes, length of the rest of the record
+//     nonce          12 bytes, fresh per record
+//     ciphertext     Inventory::to_bytes encrypted with AES-256-GCM
+//     tag            16 bytes
+//
+// Every record is authenticated with the header and its own index as associated data, so records
+// can't be moved between checkpoints or reordered within one without failing to decrypt.
+//
+// version 1 is the same apart from the inventories, which were from before deposits on other chains
+// and only carry a mainnet deposit nonce (see Inventory::from_legacy_bytes). It's still decoded so
+// older checkpoints can be recovered from.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use alloy::primitives::U256;
+
+use crate::{
+    errors::MwError,
+    warehouse::{Inventory, INVENTORY_BYTES},
+};
+
+pub const BLOB_MAGIC: &[u8; 4] = b"MWIB";
+pub const BLOB_VERSION: u8 = 2;
+pub const LEGACY_BLOB_VERSION: u8 = 1;
+pub const HEADER_BYTES: usize = 4 + 1 + 32 + 4;
+pub const NONCE_BYTES: usize = 12;
+pub const TAG_BYTES: usize = 16;
+// length prefix + nonce + ciphertext + tag
+pub const RECORD_BYTES: usize = 4 + NONCE_BYTES + INVENTORY_BYTES + TAG_BYTES;
+
+pub struct InventoryBlob {
+    pub version: u8,
+    pub checkpoint_nonce: U256,
+    pub inventories: Vec<Inventory>,
+}
+
+fn header(version: u8, checkpoint_nonce: U256, record_count: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    header.extend_from_slice(BLOB_MAGIC);
+    header.push(version);
+    header.extend_from_slice(&checkpoint_nonce.to_be_bytes::<32>());
+    header.extend_from_slice(&record_count.to_le_bytes());
+    header
+}
+
+fn associated_data(header: &[u8], index: u32) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad
+}
+
+/// Encrypts `inventories` in the order given into a version 2 blob.
+pub fn encode(
+    key: &Key<Aes256Gcm>,
+    checkpoint_nonce: U256,
+    inventories: &[Inventory],
+) -> Result<Vec<u8>, MwError> {
+    let cipher = Aes256Gcm::new(key);
+    let record_count = u32::try_from(inventories.len()).map_err(|_| MwError::EncryptionError)?;
+    let header = header(BLOB_VERSION, checkpoint_nonce, record_count);
+
+    let mut blob = Vec::with_capacity(HEADER_BYTES + inventories.len() * RECORD_BYTES);
+    blob.extend_from_slice(&header);
+    for (index, inventory) in inventories.iter().enumerate() {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(&header, index as u32);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &inventory.to_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| MwError::EncryptionError)?;
+        let length = (NONCE_BYTES + ciphertext.len()) as u32;
+        blob.extend_from_slice(&length.to_le_bytes());
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+    }
+    Ok(blob)
+}
+
+pub fn decode(key: &Key<Aes256Gcm>, blob: &[u8]) -> Result<InventoryBlob, MwError> {
+    if blob.len() < HEADER_BYTES || &blob[0..4] != BLOB_MAGIC {
+        return Err(MwError::InvalidBlob("missing header".to_string()));
+    }
+    let version = blob[4];
+    if version != BLOB_VERSION && version != LEGACY_BLOB_VERSION {
+        return Err(MwError::InvalidBlob(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    let checkpoint_nonce = U256::from_be_slice(&blob[5..37]);
+    let record_count = u32::from_le_bytes(blob[37..41].try_into().unwrap());
+    let header = &blob[..HEADER_BYTES];
+    let cipher = Aes256Gcm::new(key);
+
+    let mut inventories = Vec::new();
+    let mut offset = HEADER_BYTES;
+    for index in 0..record_count {
+        let length = blob
+            .get(offset..offset + 4)
+            .map(|length| u32::from_le_bytes(length.try_into().unwrap()) as usize)
+            .ok_or(MwError::InvalidBlob("truncated record".to_string()))?;
+        offset += 4;
+        let record = blob
+            .get(offset..offset + length)
+            .filter(|record| record.len() >= NONCE_BYTES + TAG_BYTES)
+            .ok_or(MwError::InvalidBlob("truncated record".to_string()))?;
+        offset += length;
+
+        let (nonce, ciphertext) = record.split_at(NONCE_BYTES);
+        let aad = associated_data(header, index);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| MwError::DecryptionError)?;
+        inventories.push(match version {
+            LEGACY_BLOB_VERSION => Inventory::from_legacy_bytes(&plaintext)?,
+            _ => Inventory::from_bytes(&plaintext)?,
+        });
+    }
+    if offset != blob.len() {
+        return Err(MwError::InvalidBlob("trailing bytes".to_string()));
+    }
+
+    Ok(InventoryBlob {
+        version,
+        checkpoint_nonce,
+        inventories,
+    })
+}
@@ -0,0 +1,196 @@
+// Overview:
+// The chains users can deposit on. Each has its own RPC clients (see rpc.rs), DepositRegistry (set
+// through /contract-addresses), tokens and EIP-712 domains.
+// * deposits are credited per chain, each inventory tracks a deposit nonce per chain
+// * settlement orders name the chain they settle on, the taker's signature, the app's approval for
+//   pull_settlement_funds and the CoW order are all signed over that chain's domains, so an
+//   approval for one chain can't be replayed on another
+// * DEPOSIT_CHAINS lists them as <chain id>=<rpc url>|<rpc url>... separated by commas, the config
+//   file can also set them (see config.rs), the app won't start without either
+// * the first endpoint of a chain is used for logs and calls, a majority of all of them has to
+//   agree on a block before deposits are proven against it (see verifier.rs), so every chain needs
+//   at least MIN_RPC_ENDPOINTS of them, one alone would be trusted with every deposit
+// * Chains is generic over the client so tests can run every chain on a FakeChain (see fakechain.rs)
+// * balances aren't split by chain, WETH and USDC are treated as the same asset everywhere, but a
+//   registry can only pay out what was deposited into it. The warehouse tracks what each chain's
+//   registry holds and a settlement is only approved on a chain whose deposits cover what it pulls
+
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{address, Address},
+    signers::local::PrivateKeySigner,
+    sol_types::eip712_domain,
+    transports::http::reqwest::Url,
+};
+
+use crate::{
+    errors::MwError,
+    rpc::{AlloyClient, ChainClient},
+};
+
+pub const MAINNET_CHAIN_ID: u64 = 1;
+pub const BASE_CHAIN_ID: u64 = 8453;
+// every inventory has a deposit nonce slot per chain in this order (see Inventory::to_bytes), so
+// only ever append to it
+pub const SUPPORTED_CHAIN_IDS: [u64; 2] = [MAINNET_CHAIN_ID, BASE_CHAIN_ID];
+pub const MIN_RPC_ENDPOINTS: usize = 2;
+// GPv2Settlement is deployed at the same address on every chain CoW Protocol supports
+pub const GPV2_SETTLEMENT_ADDRESS: Address = address!("9008D19f58AAbD9eD0D60971565AA8510560ab41");
+
+// events from before deposits on other chains were all on mainnet
+pub fn mainnet_chain_id() -> u64 {
+    MAINNET_CHAIN_ID
+}
+
+/// Details of a chain we know how to take deposits on, addresses can be overridden in the config
+/// (see config.rs).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpec {
+    pub chain_id: u64,
+    pub name: &'static str,
+    pub weth: Address,
+    pub usdc: Address,
+    pub gpv2_settlement: Address,
+}
+
+pub const MAINNET: ChainSpec = ChainSpec {
+    chain_id: MAINNET_CHAIN_ID,
+    name: "mainnet",
+    weth: address!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+    usdc: address!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+    gpv2_settlement: GPV2_SETTLEMENT_ADDRESS,
+};
+
+pub const BASE: ChainSpec = ChainSpec {
+    chain_id: BASE_CHAIN_ID,
+    name: "base",
+    weth: address!("4200000000000000000000000000000000000006"),
+    usdc: address!("833589fcd6edb6e08f4c7c32d4f71b54bda02913"),
+    gpv2_settlement: GPV2_SETTLEMENT_ADDRESS,
+};
+
+impl ChainSpec {
+    pub fn from_chain_id(chain_id: u64) -> Result<Self, MwError> {
+        match chain_id {
+            MAINNET_CHAIN_ID => Ok(MAINNET),
+            BASE_CHAIN_ID => Ok(BASE),
+            _ => Err(MwError::UnknownChain { chain_id }),
+        }
+    }
+
+    /// Domain settlement orders and pull_settlement_funds approvals are signed over, matching the
+    /// DepositRegistry's domain separator on this chain.
+    pub fn deposit_domain(&self, deposit_contract: Address) -> Eip712Domain {
+        eip712_domain! {
+            name: "MyrtleWyckoff",
+            version: "1",
+            chain_id: self.chain_id,
+            verifying_contract: deposit_contract,
+        }
+    }
+
+    pub fn cowswap_domain(&self) -> Eip712Domain {
+        eip712_domain! {
+            name: "Gnosis Protocol",
+            version: "v2",
+            chain_id: self.chain_id,
+            verifying_contract: self.gpv2_settlement,
+        }
+    }
+}
+
+/// A deposit chain to connect to, parsed from one DEPOSIT_CHAINS entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainConfig {
+    pub spec: ChainSpec,
+    pub rpc_urls: Vec<Url>, // at least MIN_RPC_ENDPOINTS once validated (see config.rs)
+}
+
+/// Parses `<chain id>=<rpc url>|<rpc url>...,...`.
+pub fn parse_chain_configs(configs: &str) -> Result<Vec<ChainConfig>, MwError> {
+    let mut parsed: Vec<ChainConfig> = Vec::new();
+    for config in configs
+        .split(',')
+        .filter(|config| !config.trim().is_empty())
+    {
+        let invalid = || MwError::InvalidChainConfig(config.to_string());
+        let (chain_id, rpc_urls) = config.trim().split_once('=').ok_or_else(invalid)?;
+        let chain_id = u64::from_str(chain_id).map_err(|_| invalid())?;
+        if parsed.iter().any(|config| config.spec.chain_id == chain_id) {
+            return Err(invalid());
+        }
+        parsed.push(ChainConfig {
+            spec: ChainSpec::from_chain_id(chain_id)?,
+            rpc_urls: rpc_urls
+                .split('|')
+                .map(|rpc_url| Url::from_str(rpc_url.trim()).map_err(|_| invalid()))
+                .collect::<Result<Vec<Url>, MwError>>()?,
+        });
+    }
+    Ok(parsed)
+}
+
+pub struct Chain<C: ChainClient = AlloyClient> {
+    pub spec: ChainSpec,
+    pub client: Arc<C>,
+    pub clients: Vec<Arc<C>>, // every configured endpoint, including `client`
+    pub quorum: usize,        // how many of `clients` have to agree on a block
+}
+
+impl<C: ChainClient> Chain<C> {
+    /// `clients` can't be empty, the first one is used for logs and calls.
+    pub fn new(spec: ChainSpec, clients: Vec<Arc<C>>) -> Self {
+        Chain {
+            spec,
+            client: clients[0].clone(),
+            quorum: clients.len() / 2 + 1,
+            clients,
+        }
+    }
+}
+
+/// Connected deposit chains by chain id.
+pub struct Chains<C: ChainClient = AlloyClient> {
+    chains: BTreeMap<u64, Chain<C>>,
+}
+
+impl Chains<AlloyClient> {
+    pub fn connect(configs: Vec<ChainConfig>, signer: &PrivateKeySigner) -> Self {
+        Chains::new(
+            configs
+                .into_iter()
+                .map(|config| {
+                    let clients = config
+                        .rpc_urls
+                        .into_iter()
+                        .map(|rpc_url| Arc::new(AlloyClient::connect(rpc_url, signer)))
+                        .collect();
+                    Chain::new(config.spec, clients)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<C: ChainClient> Chains<C> {
+    pub fn new(chains: Vec<Chain<C>>) -> Self {
+        Chains {
+            chains: chains
+                .into_iter()
+                .map(|chain| (chain.spec.chain_id, chain))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, chain_id: u64) -> Result<&Chain<C>, MwError> {
+        self.chains
+            .get(&chain_id)
+            .ok_or(MwError::UnknownChain { chain_id })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Chain<C>> {
+        self.chains.values()
+    }
+}
@@ -0,0 +1,7 @@
+// Overview:
+// Wall clock time, in unix milliseconds, for everything that stamps or ages things.
+// * one helper so timestamps, session times and replication tokens all read the same clock
+
+pub fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
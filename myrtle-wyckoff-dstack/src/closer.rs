@@ -0,0 +1,181 @@
+// Overview:
+// Closing session procedure, run by the executor once the pit enters the closing phase.
+// 1. cancels every resting order, releasing the liabilities held against them
+// 2. offsets every account's session position (see pnl.rs) at the mark (mid) price by submitting
+//    orders on its behalf: longs sell their credit, shorts buy back their liability. The sells go
+//    on the book first so the buys fill against them, all at mid
+// 3. cancels whatever part of the offsets found no counterparty and reports the positions left
+//    over, these need to be covered by settlement orders before the session can be finalized
+// * takers are on margin and are offset like everyone else, other accounts only offset what their
+//   balances cover
+// * runs inside events::apply, so accounts go in address order and orders carry the event's
+//   timestamp, replaying the close gives the same state
+
+use alloy::primitives::{Address, I256, U256};
+use optimized_lob::{order::OrderId, orderbook_manager::OrderBookManager};
+use tracing::info;
+
+use crate::{
+    errors::MwError,
+    orderhere::{self, Order},
+    warehouse::Warehouse,
+};
+
+#[derive(Clone, Debug)]
+pub struct OffsetOrder {
+    pub address: Address,
+    pub qty: U256,
+    pub is_bid: bool,
+    pub qty_executed: U256,
+}
+
+/// Position an account still holds after the close, negative when it still owes eth.
+#[derive(Clone, Debug)]
+pub struct ResidualPosition {
+    pub address: Address,
+    pub position: I256,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CloseReport {
+    pub mid_price: U256,
+    pub cancelled_orders: Vec<u32>,
+    pub offset_orders: Vec<OffsetOrder>,
+    pub residual_positions: Vec<ResidualPosition>,
+}
+impl CloseReport {
+    pub fn to_json(&self) -> String {
+        let serializable_report = serde_json::json!({
+            "mid_price": self.mid_price.to_string(),
+            "cancelled_orders": self.cancelled_orders,
+            "offset_orders": self.offset_orders.iter().map(|offset| serde_json::json!({
+                "address": offset.address.to_string(),
+                "qty": offset.qty.to_string(),
+                "is_bid": offset.is_bid,
+                "qty_executed": offset.qty_executed.to_string(),
+            })).collect::<Vec<_>>(),
+            "residual_positions": self.residual_positions.iter().map(|residual| serde_json::json!({
+                "address": residual.address.to_string(),
+                "position": residual.position.to_string(),
+            })).collect::<Vec<_>>(),
+        });
+        serde_json::to_string(&serializable_report).unwrap()
+    }
+}
+
+pub fn close_session(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    mid_price: U256,
+    timestamp: u64,
+) -> Result<CloseReport, MwError> {
+    if mid_price.is_zero() {
+        return Err(MwError::PriceUnavailable {
+            asset: "ETH".to_string(),
+        });
+    }
+    let mut report = CloseReport {
+        mid_price,
+        ..Default::default()
+    };
+
+    // 1. cancel every resting order, sorted so the procedure is deterministic
+    for oid in resting_orders(warehouse) {
+        orderhere::remove_resting_order(warehouse, orderbook_manager, oid)?;
+        report.cancelled_orders.push(oid.0);
+    }
+
+    // 2. offset positions at mid, the book is empty so the sells rest and the buys take them
+    let mut positions: Vec<(Address, I256)> = warehouse
+        .pnl
+        .accounts
+        .iter()
+        .filter(|(_, account)| !account.position.is_zero())
+        .map(|(address, account)| (*address, account.position))
+        .collect();
+    positions.sort_by_key(|(address, _)| *address);
+    for is_bid in [false, true] {
+        for (address, position) in positions.iter() {
+            if position.is_negative() != is_bid {
+                continue;
+            }
+            let qty = offset_qty(
+                warehouse,
+                *address,
+                position.unsigned_abs(),
+                mid_price,
+                is_bid,
+            );
+            if qty.is_zero() {
+                continue;
+            }
+            let offset = Order {
+                price: mid_price,
+                qty,
+                is_bid,
+                timestamp,
+            };
+            let (qty_executed, _, _) =
+                orderhere::submit_order(warehouse, orderbook_manager, *address, offset)?;
+            report.offset_orders.push(OffsetOrder {
+                address: *address,
+                qty,
+                is_bid,
+                qty_executed: qty_executed.0,
+            });
+        }
+    }
+
+    // 3. sells nobody bought don't stay on the book holding liabilities, what they didn't offset
+    // goes to settlement
+    for oid in resting_orders(warehouse) {
+        orderhere::remove_resting_order(warehouse, orderbook_manager, oid)?;
+    }
+    report.residual_positions = warehouse
+        .pnl
+        .accounts
+        .iter()
+        .filter(|(_, account)| !account.position.is_zero())
+        .map(|(address, account)| ResidualPosition {
+            address: *address,
+            position: account.position,
+        })
+        .collect();
+    report
+        .residual_positions
+        .sort_by_key(|residual| residual.address);
+
+    info!(
+        "session closed: {} orders cancelled, {} positions offset, {} residual positions",
+        report.cancelled_orders.len(),
+        report.offset_orders.len(),
+        report.residual_positions.len()
+    );
+    Ok(report)
+}
+
+fn resting_orders(warehouse: &Warehouse) -> Vec<OrderId> {
+    let mut resting_orders: Vec<OrderId> = warehouse.address_by_oid.keys().cloned().collect();
+    resting_orders.sort_by_key(|oid| oid.0);
+    resting_orders
+}
+
+// how much of a position the account can offset, takers are on margin so theirs is all of it
+fn offset_qty(
+    warehouse: &Warehouse,
+    address: Address,
+    position: U256,
+    mid_price: U256,
+    is_bid: bool,
+) -> U256 {
+    let Some(inventory) = warehouse.inventories.get(&address) else {
+        return U256::ZERO;
+    };
+    if inventory.is_taker {
+        return position;
+    }
+    match is_bid {
+        true => position.min(inventory.net_usdc().0 / mid_price),
+        false => position.min(inventory.net_eth().0),
+    }
+}
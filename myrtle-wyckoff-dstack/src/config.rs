@@ -0,0 +1,424 @@
+// Overview:
+// Typed app configuration, read once at startup from an optional JSON file and the environment.
+// * MW_CONFIG points at the file, every field in it is optional and defaults to what the app used
+//   before any of this was configurable (mainnet tokens, /mnt/encrypted_data, the dstack guest
+//   key), except the RPCs: rpc_url and the deposit chains, with the block each one's indexer starts
+//   from, have to be set here or in the env
+// * the config the app runs with is attested to along with its signer (see attestation.rs), so
+//   anything secret in here has to be left out of attestation::config_json
+// * env vars override the file so existing deployments keep working, DEPOSIT_CHAINS replaces the
+//   file's chains as a whole
+// * everything is validated before anything else starts, a bad config fails boot naming the field
+//   instead of panicking somewhere deep in a module later
+// * the result drives the warehouse's storage, where the app's keys come from (see keys.rs), whether
+//   this container is the leader (see leader.rs) and which leader a replica follows (see
+//   replication.rs), the checkpoint chain's client, the deposit chains and cowswap, and every
+//   EIP-712 domain the app signs or verifies over (see domains.rs)
+// * the operator is the only one who can finalize a session or promote a replica, without one
+//   neither can happen
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use alloy::{primitives::Address, transports::http::reqwest::Url};
+
+use crate::{
+    chains::{self, ChainConfig, ChainSpec, MAINNET_CHAIN_ID, MIN_RPC_ENDPOINTS},
+    domains::DomainConfig,
+    errors::MwError,
+    gulper::IndexerConfig,
+    keys::KeyProviderConfig,
+    leader::InstanceRole,
+    scheduler::SchedulerConfig,
+    sink::SinkConfig,
+};
+
+pub const DEFAULT_DATA_DIR: &str = "/mnt/encrypted_data";
+pub const DEFAULT_HOST_DATA_DIR: &str = "/mnt/host_data";
+pub const DEFAULT_DSTACK_KEY_URL: &str = "http://dstack-guest/key/<tag>";
+pub const DEFAULT_DSTACK_QUOTE_URL: &str = "http://dstack-guest/prpc/Tappd.TdxQuote?json";
+pub const DEFAULT_COWSWAP_API_URL: &str = "https://api.cow.fi/mainnet";
+
+/// Where state lives. Everything but the RPC api key is on the encrypted volume.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageConfig {
+    pub data_dir: PathBuf,      // encrypted volume
+    pub host_data_dir: PathBuf, // provided by the host, not encrypted
+}
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            host_data_dir: PathBuf::from(DEFAULT_HOST_DATA_DIR),
+        }
+    }
+}
+impl StorageConfig {
+    pub fn data_path(&self, file: &str) -> PathBuf {
+        self.data_dir.join(file)
+    }
+    pub fn host_data_path(&self, file: &str) -> PathBuf {
+        self.host_data_dir.join(file)
+    }
+}
+
+/// A deposit chain as written in the config file. Token and settlement addresses default to the
+/// chain's known ones (see chains.rs).
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainFileConfig {
+    pub chain_id: u64,
+    pub rpc_urls: Vec<String>,
+    #[serde(default)]
+    pub weth: Option<Address>,
+    #[serde(default)]
+    pub usdc: Option<Address>,
+    #[serde(default)]
+    pub gpv2_settlement: Option<Address>,
+}
+
+/// The config file as written, nothing validated yet.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub rpc_url: Option<String>,
+    pub recover_from_checkpoint: Option<Address>,
+    pub deposit_chains: Vec<ChainFileConfig>,
+    pub data_dir: Option<PathBuf>,
+    pub host_data_dir: Option<PathBuf>,
+    pub dstack_key_url: Option<String>,
+    pub dstack_quote_url: Option<String>,
+    pub key_provider: Option<String>,
+    pub role: Option<String>,
+    pub leader_url: Option<String>,
+    pub cowswap_api_url: Option<String>,
+    pub domains: DomainConfig,
+    pub checkpoint_sink: Option<String>,
+    pub snapshot_interval_ms: Option<u64>,
+    pub deposit_confirmations: Option<u64>,
+    pub deposit_start_blocks: BTreeMap<u64, u64>, // chain id, block
+    pub operator: Option<Address>,
+}
+
+/// Validated config, built once at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub rpc_url: Url,                  // checkpoint chain
+    pub recover_from: Option<Address>, // Checkpointer to rebuild state from
+    pub chains: Vec<ChainConfig>,
+    pub storage: StorageConfig,
+    pub dstack_key_url: Url,
+    pub dstack_quote_url: Url, // see attestation.rs
+    pub key_provider: KeyProviderConfig,
+    pub role: InstanceRole,      // see leader.rs
+    pub leader_url: Option<Url>, // the leader a replica follows, see replication.rs
+    pub cowswap_api_url: Url,
+    pub domains: DomainConfig,
+    pub sink: SinkConfig,
+    pub scheduler: SchedulerConfig,
+    pub indexer: IndexerConfig,
+    pub operator: Option<Address>, // signs finalize-session and promote, see session.rs
+}
+
+fn invalid(field: &str, reason: impl std::fmt::Display) -> MwError {
+    MwError::InvalidConfig(format!("{}: {}", field, reason))
+}
+
+fn parse_url(field: &str, url: &str) -> Result<Url, MwError> {
+    Url::from_str(url.trim()).map_err(|e| invalid(field, format!("{} ({})", e, url)))
+}
+
+fn parse_number<T: FromStr>(field: &str, value: &str) -> Result<T, MwError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid(field, format!("{} isn't a number", value)))
+}
+
+fn absolute_dir(field: &str, dir: Option<PathBuf>, default: &str) -> Result<PathBuf, MwError> {
+    let dir = dir.unwrap_or_else(|| PathBuf::from(default));
+    if !dir.is_absolute() {
+        return Err(invalid(field, format!("{} isn't absolute", dir.display())));
+    }
+    Ok(dir)
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> Result<Self, MwError> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| invalid("MW_CONFIG", format!("{} ({})", e, path.display())))?;
+        serde_json::from_reader(file).map_err(|e| invalid("MW_CONFIG", e))
+    }
+
+    /// Overrides the file with whichever env vars `var` returns.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), MwError> {
+        if let Some(rpc_url) = var("RPC_URL") {
+            self.rpc_url = Some(rpc_url);
+        }
+        if let Some(address) = var("RECOVER_FROM_CHECKPOINT") {
+            self.recover_from_checkpoint = Some(
+                Address::from_str(address.trim())
+                    .map_err(|_| invalid("RECOVER_FROM_CHECKPOINT", "not an address"))?,
+            );
+        }
+        if let Some(address) = var("OPERATOR_ADDRESS") {
+            self.operator = Some(
+                Address::from_str(address.trim())
+                    .map_err(|_| invalid("OPERATOR_ADDRESS", "not an address"))?,
+            );
+        }
+        // see chains.rs for the format
+        if let Some(configs) = var("DEPOSIT_CHAINS") {
+            self.deposit_chains = chains::parse_chain_configs(&configs)?
+                .into_iter()
+                .map(|config| ChainFileConfig {
+                    chain_id: config.spec.chain_id,
+                    rpc_urls: config.rpc_urls.iter().map(Url::to_string).collect(),
+                    weth: None,
+                    usdc: None,
+                    gpv2_settlement: None,
+                })
+                .collect();
+        }
+        if let Some(data_dir) = var("DATA_DIR") {
+            self.data_dir = Some(PathBuf::from(data_dir));
+        }
+        if let Some(host_data_dir) = var("HOST_DATA_DIR") {
+            self.host_data_dir = Some(PathBuf::from(host_data_dir));
+        }
+        if let Some(key_url) = var("DSTACK_KEY_URL") {
+            self.dstack_key_url = Some(key_url);
+        }
+        if let Some(quote_url) = var("DSTACK_QUOTE_URL") {
+            self.dstack_quote_url = Some(quote_url);
+        }
+        if let Some(key_provider) = var("KEY_PROVIDER") {
+            self.key_provider = Some(key_provider);
+        }
+        if let Some(role) = var("INSTANCE_ROLE") {
+            self.role = Some(role);
+        }
+        if let Some(leader_url) = var("LEADER_URL") {
+            self.leader_url = Some(leader_url);
+        }
+        if let Some(api_url) = var("COWSWAP_API_URL") {
+            self.cowswap_api_url = Some(api_url);
+        }
+        if let Some(chain_id) = var("CHECKPOINT_CHAIN_ID") {
+            self.domains.checkpoint_chain_id = parse_number("CHECKPOINT_CHAIN_ID", &chain_id)?;
+        }
+        if let Some(sink) = var("CHECKPOINT_SINK") {
+            self.checkpoint_sink = Some(sink);
+        }
+        if let Some(interval_ms) = var("SNAPSHOT_INTERVAL_MS") {
+            self.snapshot_interval_ms = Some(parse_number("SNAPSHOT_INTERVAL_MS", &interval_ms)?);
+        }
+        if let Some(confirmations) = var("DEPOSIT_CONFIRMATIONS") {
+            self.deposit_confirmations =
+                Some(parse_number("DEPOSIT_CONFIRMATIONS", &confirmations)?);
+        }
+        // either a block for mainnet or <chain id>=<block> separated by commas
+        if let Some(start_blocks) = var("DEPOSIT_START_BLOCK") {
+            self.deposit_start_blocks.clear();
+            for start_block in start_blocks.split(',').filter(|s| !s.trim().is_empty()) {
+                let (chain_id, block) = match start_block.split_once('=') {
+                    Some((chain_id, block)) => {
+                        (parse_number("DEPOSIT_START_BLOCK", chain_id)?, block)
+                    }
+                    None => (MAINNET_CHAIN_ID, start_block),
+                };
+                self.deposit_start_blocks
+                    .insert(chain_id, parse_number("DEPOSIT_START_BLOCK", block)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// The chain's spec with the config's overrides, or the known defaults if it isn't a deposit
+    /// chain.
+    pub fn chain_spec(&self, chain_id: u64) -> Result<ChainSpec, MwError> {
+        match self
+            .chains
+            .iter()
+            .find(|config| config.spec.chain_id == chain_id)
+        {
+            Some(config) => Ok(config.spec.clone()),
+            None => ChainSpec::from_chain_id(chain_id),
+        }
+    }
+
+    /// Reads MW_CONFIG if set, applies the environment on top and validates the result.
+    pub fn load() -> Result<Self, MwError> {
+        let mut file = match std::env::var("MW_CONFIG") {
+            Ok(path) => ConfigFile::read(Path::new(&path))?,
+            Err(_) => ConfigFile::default(),
+        };
+        file.apply_env(|name| std::env::var(name).ok())?;
+        Self::from_file(file)
+    }
+
+    pub fn from_file(file: ConfigFile) -> Result<Self, MwError> {
+        let rpc_url = parse_url(
+            "rpc_url",
+            file.rpc_url
+                .as_deref()
+                .ok_or_else(|| invalid("rpc_url", "missing, set it or RPC_URL"))?,
+        )?;
+
+        let mut chains: Vec<ChainConfig> = Vec::with_capacity(file.deposit_chains.len());
+        for chain in file.deposit_chains {
+            let field = format!("deposit_chains.{}", chain.chain_id);
+            if chains
+                .iter()
+                .any(|config| config.spec.chain_id == chain.chain_id)
+            {
+                return Err(invalid(&field, "listed twice"));
+            }
+            if chain.rpc_urls.len() < MIN_RPC_ENDPOINTS {
+                return Err(invalid(
+                    &field,
+                    format!(
+                        "needs at least {} rpc_urls, deposits are only proven against a block \
+                         a majority of them agree on",
+                        MIN_RPC_ENDPOINTS
+                    ),
+                ));
+            }
+            let mut spec = ChainSpec::from_chain_id(chain.chain_id)
+                .map_err(|_| invalid(&field, "not a supported chain"))?;
+            spec.weth = chain.weth.unwrap_or(spec.weth);
+            spec.usdc = chain.usdc.unwrap_or(spec.usdc);
+            spec.gpv2_settlement = chain.gpv2_settlement.unwrap_or(spec.gpv2_settlement);
+            chains.push(ChainConfig {
+                spec,
+                rpc_urls: chain
+                    .rpc_urls
+                    .iter()
+                    .map(|rpc_url| parse_url(&field, rpc_url))
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        if chains.is_empty() {
+            return Err(invalid(
+                "deposit_chains",
+                "missing, set them or DEPOSIT_CHAINS",
+            ));
+        }
+
+        let storage = StorageConfig {
+            data_dir: absolute_dir("data_dir", file.data_dir, DEFAULT_DATA_DIR)?,
+            host_data_dir: absolute_dir(
+                "host_data_dir",
+                file.host_data_dir,
+                DEFAULT_HOST_DATA_DIR,
+            )?,
+        };
+        let dstack_key_url = parse_url(
+            "dstack_key_url",
+            file.dstack_key_url
+                .as_deref()
+                .unwrap_or(DEFAULT_DSTACK_KEY_URL),
+        )?;
+        let dstack_quote_url = parse_url(
+            "dstack_quote_url",
+            file.dstack_quote_url
+                .as_deref()
+                .unwrap_or(DEFAULT_DSTACK_QUOTE_URL),
+        )?;
+        let key_provider = match file.key_provider {
+            Some(key_provider) => KeyProviderConfig::from_str(&key_provider)
+                .map_err(|e| invalid("key_provider", e))?,
+            None => KeyProviderConfig::Dstack,
+        };
+        let role = match file.role {
+            Some(role) => InstanceRole::from_str(role.trim()).map_err(|e| invalid("role", e))?,
+            None => InstanceRole::Leader,
+        };
+        // a replica without one is a cold standby, it can still be promoted from its own log
+        let leader_url = match file.leader_url {
+            Some(_) if role == InstanceRole::Leader => {
+                return Err(invalid("leader_url", "only a replica follows a leader"))
+            }
+            Some(leader_url) => Some(parse_url("leader_url", &leader_url)?),
+            None => None,
+        };
+        let cowswap_api_url = parse_url(
+            "cowswap_api_url",
+            file.cowswap_api_url
+                .as_deref()
+                .unwrap_or(DEFAULT_COWSWAP_API_URL),
+        )?;
+        file.domains.validate()?;
+
+        let sink = match file.checkpoint_sink {
+            Some(sink) => SinkConfig::from_str(&sink).map_err(|e| invalid("checkpoint_sink", e))?,
+            None => SinkConfig::Checkpointer,
+        };
+        if let SinkConfig::Calldata {
+            chain_id: Some(chain_id),
+            ..
+        } = sink
+        {
+            if !chains.iter().any(|config| config.spec.chain_id == chain_id) {
+                return Err(invalid(
+                    "checkpoint_sink",
+                    format!("chain {} isn't a deposit chain", chain_id),
+                ));
+            }
+        }
+        let mut scheduler = SchedulerConfig::default();
+        if let Some(interval_ms) = file.snapshot_interval_ms {
+            if interval_ms == 0 {
+                return Err(invalid("snapshot_interval_ms", "must be above 0"));
+            }
+            scheduler.interval = Duration::from_millis(interval_ms);
+        }
+        let mut indexer = IndexerConfig::default();
+        if let Some(confirmations) = file.deposit_confirmations {
+            indexer.confirmations = confirmations;
+        }
+        for (chain_id, block) in file.deposit_start_blocks {
+            if !chains.iter().any(|config| config.spec.chain_id == chain_id) {
+                return Err(invalid(
+                    "deposit_start_blocks",
+                    format!("chain {} isn't a deposit chain", chain_id),
+                ));
+            }
+            indexer.start_blocks.insert(chain_id, block);
+        }
+        // indexing from genesis would crawl the whole chain, there's no default to fall back to
+        if let Some(config) = chains
+            .iter()
+            .find(|config| !indexer.start_blocks.contains_key(&config.spec.chain_id))
+        {
+            return Err(invalid(
+                "deposit_start_blocks",
+                format!("chain {} has no start block", config.spec.chain_id),
+            ));
+        }
+
+        Ok(Config {
+            rpc_url,
+            recover_from: file.recover_from_checkpoint,
+            chains,
+            storage,
+            dstack_key_url,
+            dstack_quote_url,
+            key_provider,
+            role,
+            leader_url,
+            cowswap_api_url,
+            domains: file.domains,
+            sink,
+            scheduler,
+            indexer,
+            operator: file.operator,
+        })
+    }
+}
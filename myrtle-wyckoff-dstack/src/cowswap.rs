@@ -1,17 +1,13 @@
 use alloy::{
-    hex::{self, FromHex, ToHexExt},
+    hex,
     primitives::{Address, Bytes},
     signers::{local::PrivateKeySigner, Signature, Signer},
     sol,
-    sol_types::{eip712_domain, SolStruct},
+    sol_types::SolStruct,
 };
 use chrono::Utc;
 
-use crate::{
-    artifacts::IDepositRegistry::Order,
-    constants::{USDC_ADDRESS, WETH_ADDRESS},
-    errors::MwError,
-};
+use crate::{artifacts::IDepositRegistry::Order, chains::ChainSpec, errors::MwError};
 
 #[derive(Debug)]
 pub struct CowSwapHook {
@@ -55,21 +51,22 @@ sol! {
 }
 impl CowSwapOrderDigest {
     pub fn from_settlement_order(
+        chain: &ChainSpec,
         deposit_registry_contract: &String,
         settlement_order: Order,
         app_data: String,
     ) -> CowSwapOrderDigest {
         let (sell_token, buy_token, sell_amount, buy_amount) = if settlement_order.isBid {
             (
-                WETH_ADDRESS,
-                USDC_ADDRESS,
+                chain.weth,
+                chain.usdc,
                 settlement_order.ethAmount,
                 settlement_order.usdcAmount,
             )
         } else {
             (
-                USDC_ADDRESS,
-                WETH_ADDRESS,
+                chain.usdc,
+                chain.weth,
                 settlement_order.usdcAmount,
                 settlement_order.ethAmount,
             )
@@ -93,8 +90,10 @@ impl CowSwapOrderDigest {
     }
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct CowSwapOrder {
+    #[serde(default = "crate::chains::mainnet_chain_id")]
+    pub chain_id: u64, // which chain's CoW Protocol API the order goes to
     sell_token: String,
     buy_token: String,
     receiver: String,
@@ -115,22 +114,13 @@ pub struct CowSwapOrder {
 impl CowSwapOrder {
     pub async fn from_cowswap_order_digest(
         signer: &PrivateKeySigner,
+        chain: &ChainSpec,
         cowswap_order_digest: CowSwapOrderDigest,
     ) -> Result<CowSwapOrder, MwError> {
-        // this should likely be declared in a constants file but I'll leave it here since it needs to be computed on-the-fly for a multi-chain setup
-        let cowswap_domain = eip712_domain! {
-            name: "Gnosis Protocol",
-            version: "v2",
-            chain_id: 1,
-            verifying_contract: Address::from_hex("0x9008D19f58AAbD9eD0D60971565AA8510560ab41".encode_hex()).unwrap(),
-        };
-
-        let hash = cowswap_order_digest.eip712_signing_hash(&cowswap_domain);
-        let signature: Signature = signer
-            .sign_hash(&hash)
-            .await
-            .map_err(|_| MwError::SigningError)?;
+        let hash = cowswap_order_digest.eip712_signing_hash(&chain.cowswap_domain());
+        let signature: Signature = signer.sign_hash(&hash).await?;
         Ok(CowSwapOrder {
+            chain_id: chain.chain_id,
             sell_token: cowswap_order_digest.sell_token,
             buy_token: cowswap_order_digest.buy_token,
             receiver: cowswap_order_digest.receiver,
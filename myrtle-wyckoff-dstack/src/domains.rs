@@ -1,24 +1,150 @@
 // EIP-712 implementation
 // might need a domain separator for different orderbooks but unsure
+// * name, version and the checkpoint chain come from the config (see config.rs)
+// * contract bound domains are built at runtime from the addresses set through /contract-addresses:
+//   checkpoints over the Checkpointer, settlement approvals over each chain's DepositRegistry (see
+//   chains.rs), so nothing is ever signed for the zero address
+// * on startup and whenever the addresses change the separators stored on chain are checked
+//   against the ones built here, a contract whose separator isn't set yet is skipped since the admin
+//   sets it after deployment
+// * /domain-separators serves everything built here so clients and operators can compare
 
-use alloy::{primitives::Address, sol_types::eip712_domain};
-
-pub const MAINNET_DOMAIN: alloy::dyn_abi::Eip712Domain = eip712_domain! {
-    name: "MyrtleWyckoff",
-    version: "1",
-    chain_id: 1,
-    verifying_contract: Address::ZERO, //TODO replace with deposit contract address
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, B256, U256},
 };
 
-pub const TOLIMAN_DOMAIN: alloy::dyn_abi::Eip712Domain = eip712_domain! {
-    name: "MyrtleWyckoff",
-    version: "1",
-    chain_id: 33626250,
-    verifying_contract: Address::ZERO, //TODO replace with checkpoint contract address
-};
+use crate::{chains::Chain, errors::MwError, rpc::ChainClient};
 
-pub const DSTACK_DOMAIN: alloy::dyn_abi::Eip712Domain = eip712_domain! {
-    name: "MyrtleWyckoff",
-    version: "1",
-    verifying_contract: Address::ZERO,
-};
+pub const DEFAULT_DOMAIN_NAME: &str = "MyrtleWyckoff";
+pub const DEFAULT_DOMAIN_VERSION: &str = "1";
+pub const DEFAULT_CHECKPOINT_CHAIN_ID: u64 = 33626250; // toliman
+
+/// What the dstack and checkpoint domains are built from.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DomainConfig {
+    pub name: String,
+    pub version: String,
+    pub checkpoint_chain_id: u64,
+    pub dstack_verifying_contract: Address, // user requests aren't bound to a chain or contract
+}
+impl Default for DomainConfig {
+    fn default() -> Self {
+        DomainConfig {
+            name: DEFAULT_DOMAIN_NAME.to_string(),
+            version: DEFAULT_DOMAIN_VERSION.to_string(),
+            checkpoint_chain_id: DEFAULT_CHECKPOINT_CHAIN_ID,
+            dstack_verifying_contract: Address::ZERO,
+        }
+    }
+}
+impl DomainConfig {
+    pub fn validate(&self) -> Result<(), MwError> {
+        let invalid = |reason: &str| MwError::InvalidConfig(format!("domains: {}", reason));
+        if self.name.is_empty() {
+            return Err(invalid("name is empty"));
+        }
+        if self.version.is_empty() {
+            return Err(invalid("version is empty"));
+        }
+        if self.checkpoint_chain_id == 0 {
+            return Err(invalid("checkpoint_chain_id is 0"));
+        }
+        Ok(())
+    }
+}
+
+/// The domains the app signs checkpoints and verifies user requests over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Domains {
+    pub dstack: Eip712Domain, // orders, cancels, batches and user requests
+    config: DomainConfig,
+}
+impl Domains {
+    pub fn new(config: &DomainConfig) -> Self {
+        Domains {
+            dstack: Eip712Domain::new(
+                Some(config.name.clone().into()),
+                Some(config.version.clone().into()),
+                None,
+                Some(config.dstack_verifying_contract),
+                None,
+            ),
+            config: config.clone(),
+        }
+    }
+
+    /// Domain checkpoints posted to `checkpoint_contract` are signed over.
+    pub fn toliman(&self, checkpoint_contract: Address) -> Eip712Domain {
+        Eip712Domain::new(
+            Some(self.config.name.clone().into()),
+            Some(self.config.version.clone().into()),
+            Some(U256::from(self.config.checkpoint_chain_id)),
+            Some(checkpoint_contract),
+            None,
+        )
+    }
+}
+impl Default for Domains {
+    fn default() -> Self {
+        Domains::new(&DomainConfig::default())
+    }
+}
+
+pub fn domain_json(domain: &Eip712Domain) -> serde_json::Value {
+    serde_json::json!({
+        "name": domain.name.as_deref(),
+        "version": domain.version.as_deref(),
+        "chain_id": domain.chain_id.map(|chain_id| chain_id.to_string()),
+        "verifying_contract": domain.verifying_contract.map(|contract| contract.to_string()),
+        "separator": domain.separator().to_string(),
+    })
+}
+
+/// Whether `onchain`, the separator `contract` verifies against, is the one the app signs with.
+/// Ok(false) if the contract doesn't have one yet.
+pub fn check_separator(
+    contract: Address,
+    expected: &Eip712Domain,
+    onchain: B256,
+) -> Result<bool, MwError> {
+    if onchain.is_zero() {
+        return Ok(false);
+    }
+    if onchain != expected.separator() {
+        return Err(MwError::DomainSeparatorMismatch {
+            contract: contract.to_string(),
+            expected: expected.separator().to_string(),
+            onchain: onchain.to_string(),
+        });
+    }
+    Ok(true)
+}
+
+/// Checks the Checkpointer verifies checkpoints over the domain they're signed with.
+pub async fn verify_checkpointer<C: ChainClient>(
+    client: &C,
+    domains: &Domains,
+    checkpoint_contract: Address,
+) -> Result<bool, MwError> {
+    let onchain = client.domain_separator(checkpoint_contract).await?;
+    check_separator(
+        checkpoint_contract,
+        &domains.toliman(checkpoint_contract),
+        onchain,
+    )
+}
+
+/// Checks the registry on `chain` verifies settlement approvals over the domain they're signed with.
+pub async fn verify_deposit_registry<C: ChainClient>(
+    chain: &Chain<C>,
+    deposit_contract: Address,
+) -> Result<bool, MwError> {
+    let onchain = chain.client.domain_separator(deposit_contract).await?;
+    check_separator(
+        deposit_contract,
+        &chain.spec.deposit_domain(deposit_contract),
+        onchain,
+    )
+}
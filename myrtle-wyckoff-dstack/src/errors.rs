@@ -1,18 +1,37 @@
+// Overview:
+// The app's error type and how it goes out over http.
+// * every error has a stable code clients can match on, codes never change once released even if
+//   a variant is renamed, new errors get new codes
+// * responses are {"code", "message", "details"}, details has the variant's fields with numbers as
+//   strings like everywhere else
+// * 4xx is for what the caller can fix (bad input, auth, state conflicts), 5xx is for the app,
+//   the enclave or a chain failing
+// * Display strings end up in the event log for rejected events (see events.rs) and replay compares
+//   them, so they can't change either
+// * route parameters are parsed with parse_param and parse_user, a malformed one is an
+//   invalid_parameter error naming it rather than a panic
+// * alloy, io and serde_json errors convert with ?
+
+use std::{fmt, str::FromStr};
+
+use alloy::primitives::Address;
 use rocket::{
     http::Status,
-    response::{status::Custom, Responder},
-    serde::json::Json,
+    response::{content::RawJson, status::Custom, Responder},
     Request,
 };
 use serde::Serialize;
-use std::fmt;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub enum MwError {
     InvalidSignature,
-    InsufficientBalance { token: String },
+    InsufficientBalance {
+        token: String,
+    },
     InvalidTimestamp,
-    OrderNotFound { order_id: u32 },
+    OrderNotFound {
+        order_id: u32,
+    },
     UnauthorizedAccess,
     InvalidOrderParams,
     NotTaker,
@@ -27,6 +46,57 @@ pub enum MwError {
     NoOrdersFound,
     SnapshotError(String),
     GulpError(String),
+    UnlistedAsset {
+        asset: String,
+    },
+    PriceUnavailable {
+        asset: String,
+    },
+    QuoteError(String),
+    ActionNotAllowed {
+        action: String,
+        phase: String,
+    },
+    SessionNotFound {
+        session_id: u64,
+    },
+    BatchFailed {
+        index: usize,
+        reason: String,
+    },
+    SequencerUnavailable,
+    ProofUnavailable,
+    DecryptionError,
+    InvalidBlob(String),
+    InvalidSinkConfig(String),
+    UnknownChain {
+        chain_id: u64,
+    },
+    InsufficientChainDeposits {
+        chain_id: u64,
+        token: String,
+    },
+    InvalidChainConfig(String),
+    DepositVerificationError(String),
+    InvalidConfig(String),
+    DomainSeparatorMismatch {
+        contract: String,
+        expected: String,
+        onchain: String,
+    },
+    RpcError(String),
+    KeyError(String),
+    AttestationError(String),
+    NotLeader {
+        action: String,
+    },
+    ReplicationError(String),
+    InvalidParameter {
+        field: String,
+        reason: String,
+    },
+    IoError(String),
+    SerializationError(String),
 }
 
 impl fmt::Display for MwError {
@@ -52,6 +122,57 @@ impl fmt::Display for MwError {
             Self::NoOrdersFound => write!(f, "No orders found"),
             Self::SnapshotError(message) => write!(f, "Snapshot error: {}", message),
             Self::GulpError(message) => write!(f, "Gulp error: {}", message),
+            Self::UnlistedAsset { asset } => write!(f, "Asset {} is not listed", asset),
+            Self::PriceUnavailable { asset } => write!(f, "No price available for {}", asset),
+            Self::QuoteError(message) => write!(f, "Quote error: {}", message),
+            Self::ActionNotAllowed { action, phase } => {
+                write!(f, "Action {} is not allowed during {}", action, phase)
+            }
+            Self::SessionNotFound { session_id } => {
+                write!(f, "Session {} not found", session_id)
+            }
+            Self::BatchFailed { index, reason } => {
+                write!(f, "Batch operation {} failed: {}", index, reason)
+            }
+            Self::SequencerUnavailable => write!(f, "Sequencer unavailable"),
+            Self::ProofUnavailable => write!(f, "No inventory proof in the latest checkpoint"),
+            Self::DecryptionError => write!(f, "Decryption error"),
+            Self::InvalidBlob(message) => write!(f, "Invalid inventory blob: {}", message),
+            Self::InvalidSinkConfig(config) => write!(f, "Invalid checkpoint sink {}", config),
+            Self::UnknownChain { chain_id } => write!(f, "Chain {} is not configured", chain_id),
+            Self::InsufficientChainDeposits { chain_id, token } => write!(
+                f,
+                "Not enough {} deposited on chain {} to settle there",
+                token, chain_id
+            ),
+            Self::InvalidChainConfig(config) => write!(f, "Invalid deposit chain {}", config),
+            Self::DepositVerificationError(message) => {
+                write!(f, "Deposit verification failed: {}", message)
+            }
+            Self::InvalidConfig(message) => write!(f, "Invalid config {}", message),
+            Self::DomainSeparatorMismatch {
+                contract,
+                expected,
+                onchain,
+            } => write!(
+                f,
+                "Domain separator of {} is {}, the app signs with {}",
+                contract, onchain, expected
+            ),
+            Self::RpcError(message) => write!(f, "RPC error: {}", message),
+            Self::KeyError(message) => write!(f, "Key error: {}", message),
+            Self::AttestationError(message) => write!(f, "Attestation error: {}", message),
+            Self::NotLeader { action } => {
+                write!(
+                    f,
+                    "This instance is a replica, {} has to go to the leader",
+                    action
+                )
+            }
+            Self::ReplicationError(message) => write!(f, "Replication error: {}", message),
+            Self::InvalidParameter { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+            Self::IoError(message) => write!(f, "IO error: {}", message),
+            Self::SerializationError(message) => write!(f, "Serialization error: {}", message),
         }
     }
 }
@@ -59,7 +180,122 @@ impl fmt::Display for MwError {
 impl std::error::Error for MwError {}
 
 impl MwError {
-    fn status_code(&self) -> Status {
+    /// Stable machine readable code, see the overview.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidSignature => "invalid_signature",
+            Self::InsufficientBalance { .. } => "insufficient_balance",
+            Self::InvalidTimestamp => "invalid_timestamp",
+            Self::OrderNotFound { .. } => "order_not_found",
+            Self::UnauthorizedAccess => "unauthorized_access",
+            Self::InvalidOrderParams => "invalid_order_params",
+            Self::NotTaker => "not_taker",
+            Self::InvalidRequestType => "invalid_request_type",
+            Self::SignatureRecoveryError => "signature_recovery_error",
+            Self::SignerCreationError => "signer_creation_error",
+            Self::SigningError => "signing_error",
+            Self::SignatureConversionError => "signature_conversion_error",
+            Self::TransactionError => "transaction_error",
+            Self::EncryptionError => "encryption_error",
+            Self::InvalidBook => "invalid_book",
+            Self::NoOrdersFound => "no_orders_found",
+            Self::SnapshotError(_) => "snapshot_error",
+            Self::GulpError(_) => "gulp_error",
+            Self::UnlistedAsset { .. } => "unlisted_asset",
+            Self::PriceUnavailable { .. } => "price_unavailable",
+            Self::QuoteError(_) => "quote_error",
+            Self::ActionNotAllowed { .. } => "action_not_allowed",
+            Self::SessionNotFound { .. } => "session_not_found",
+            Self::BatchFailed { .. } => "batch_failed",
+            Self::SequencerUnavailable => "sequencer_unavailable",
+            Self::ProofUnavailable => "proof_unavailable",
+            Self::DecryptionError => "decryption_error",
+            Self::InvalidBlob(_) => "invalid_blob",
+            Self::InvalidSinkConfig(_) => "invalid_sink_config",
+            Self::UnknownChain { .. } => "unknown_chain",
+            Self::InsufficientChainDeposits { .. } => "insufficient_chain_deposits",
+            Self::InvalidChainConfig(_) => "invalid_chain_config",
+            Self::DepositVerificationError(_) => "deposit_verification_error",
+            Self::InvalidConfig(_) => "invalid_config",
+            Self::DomainSeparatorMismatch { .. } => "domain_separator_mismatch",
+            Self::RpcError(_) => "rpc_error",
+            Self::KeyError(_) => "key_error",
+            Self::AttestationError(_) => "attestation_error",
+            Self::NotLeader { .. } => "not_leader",
+            Self::ReplicationError(_) => "replication_error",
+            Self::InvalidParameter { .. } => "invalid_parameter",
+            Self::IoError(_) => "io_error",
+            Self::SerializationError(_) => "serialization_error",
+        }
+    }
+
+    /// The variant's fields, numbers as strings. Errors that only carry a message have it as
+    /// `reason`.
+    pub fn details(&self) -> serde_json::Value {
+        match self {
+            Self::InsufficientBalance { token } => serde_json::json!({ "token": token }),
+            Self::OrderNotFound { order_id } => {
+                serde_json::json!({ "order_id": order_id.to_string() })
+            }
+            Self::UnlistedAsset { asset } | Self::PriceUnavailable { asset } => {
+                serde_json::json!({ "asset": asset })
+            }
+            Self::ActionNotAllowed { action, phase } => {
+                serde_json::json!({ "action": action, "phase": phase })
+            }
+            Self::SessionNotFound { session_id } => {
+                serde_json::json!({ "session_id": session_id.to_string() })
+            }
+            Self::BatchFailed { index, reason } => {
+                serde_json::json!({ "index": index.to_string(), "reason": reason })
+            }
+            Self::UnknownChain { chain_id } => {
+                serde_json::json!({ "chain_id": chain_id.to_string() })
+            }
+            Self::InsufficientChainDeposits { chain_id, token } => {
+                serde_json::json!({ "chain_id": chain_id.to_string(), "token": token })
+            }
+            Self::DomainSeparatorMismatch {
+                contract,
+                expected,
+                onchain,
+            } => serde_json::json!({
+                "contract": contract,
+                "expected": expected,
+                "onchain": onchain,
+            }),
+            Self::NotLeader { action } => serde_json::json!({ "action": action }),
+            Self::InvalidParameter { field, reason } => {
+                serde_json::json!({ "field": field, "reason": reason })
+            }
+            Self::SnapshotError(reason)
+            | Self::GulpError(reason)
+            | Self::QuoteError(reason)
+            | Self::InvalidBlob(reason)
+            | Self::InvalidSinkConfig(reason)
+            | Self::InvalidChainConfig(reason)
+            | Self::DepositVerificationError(reason)
+            | Self::InvalidConfig(reason)
+            | Self::RpcError(reason)
+            | Self::KeyError(reason)
+            | Self::AttestationError(reason)
+            | Self::ReplicationError(reason)
+            | Self::IoError(reason)
+            | Self::SerializationError(reason) => serde_json::json!({ "reason": reason }),
+            _ => serde_json::json!({}),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let serializable_error = serde_json::json!({
+            "code": self.code(),
+            "message": self.to_string(),
+            "details": self.details(),
+        });
+        serde_json::to_string(&serializable_error).unwrap()
+    }
+
+    pub fn status_code(&self) -> Status {
         match self {
             Self::InvalidSignature => Status::Unauthorized,
             Self::InsufficientBalance { .. } => Status::BadRequest,
@@ -67,24 +303,110 @@ impl MwError {
             Self::OrderNotFound { .. } => Status::NotFound,
             Self::UnauthorizedAccess => Status::Unauthorized,
             Self::InvalidOrderParams => Status::BadRequest,
-            Self::NotTaker => Status::Unauthorized,
+            Self::NotTaker => Status::Forbidden,
             Self::InvalidRequestType => Status::BadRequest,
             Self::SignatureRecoveryError => Status::BadRequest,
-            Self::SignerCreationError => Status::BadRequest,
-            Self::SigningError => Status::BadRequest,
-            Self::SignatureConversionError => Status::BadRequest,
-            Self::TransactionError => Status::BadRequest,
-            Self::EncryptionError => Status::BadRequest,
+            // the app's own keys failing, nothing the caller sent
+            Self::SignerCreationError => Status::InternalServerError,
+            Self::SigningError => Status::InternalServerError,
+            Self::SignatureConversionError => Status::InternalServerError,
+            Self::TransactionError => Status::BadGateway,
+            Self::EncryptionError => Status::InternalServerError,
             Self::InvalidBook => Status::BadRequest,
             Self::NoOrdersFound => Status::NotFound,
-            Self::SnapshotError(_) => Status::BadRequest,
-            Self::GulpError(_) => Status::BadRequest,
+            Self::SnapshotError(_) => Status::InternalServerError,
+            // the deposit cursor or a nonce moved under it, retrying is fine
+            Self::GulpError(_) => Status::Conflict,
+            Self::UnlistedAsset { .. } => Status::NotFound,
+            Self::PriceUnavailable { .. } => Status::ServiceUnavailable,
+            Self::QuoteError(_) => Status::BadGateway,
+            Self::ActionNotAllowed { .. } => Status::Conflict,
+            Self::SessionNotFound { .. } => Status::NotFound,
+            Self::BatchFailed { .. } => Status::BadRequest,
+            Self::SequencerUnavailable => Status::ServiceUnavailable,
+            Self::ProofUnavailable => Status::NotFound,
+            Self::DecryptionError => Status::InternalServerError,
+            Self::InvalidBlob(_) => Status::InternalServerError,
+            Self::InvalidSinkConfig(_) => Status::InternalServerError,
+            Self::UnknownChain { .. } => Status::NotFound,
+            Self::InsufficientChainDeposits { .. } => Status::BadRequest,
+            Self::InvalidChainConfig(_) => Status::InternalServerError,
+            Self::DepositVerificationError(_) => Status::BadGateway,
+            Self::InvalidConfig(_) => Status::InternalServerError,
+            Self::DomainSeparatorMismatch { .. } => Status::Conflict,
+            Self::RpcError(_) => Status::BadGateway,
+            Self::KeyError(_) => Status::InternalServerError,
+            Self::AttestationError(_) => Status::BadGateway,
+            Self::NotLeader { .. } => Status::Forbidden,
+            Self::ReplicationError(_) => Status::Conflict,
+            Self::InvalidParameter { .. } => Status::BadRequest,
+            Self::IoError(_) => Status::InternalServerError,
+            Self::SerializationError(_) => Status::InternalServerError,
         }
     }
 }
 
 impl<'r> Responder<'r, 'static> for MwError {
     fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
-        Custom(self.status_code(), Json(self)).respond_to(req)
+        Custom(self.status_code(), RawJson(self.to_json())).respond_to(req)
+    }
+}
+
+/// Body for errors rocket answers itself (unknown routes, bodies that don't parse), shaped like
+/// MwError's so clients only handle one kind. The code is the status reason in snake case.
+pub fn status_json(status: Status) -> String {
+    let reason = status.reason().unwrap_or("Unknown");
+    let serializable_error = serde_json::json!({
+        "code": reason.to_lowercase().replace([' ', '-'], "_"),
+        "message": reason,
+        "details": {},
+    });
+    serde_json::to_string(&serializable_error).unwrap()
+}
+
+/// Parses a route parameter, a bad one is an invalid_parameter error naming it.
+pub fn parse_param<T>(field: &str, value: &str) -> Result<T, MwError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    T::from_str(value).map_err(|e| MwError::InvalidParameter {
+        field: field.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// The user a route acts for, the 0x prefixed hex address their orders are signed with.
+pub fn parse_user(user: &str) -> Result<Address, MwError> {
+    parse_param::<Address>("user", user)
+}
+
+impl From<alloy::transports::TransportError> for MwError {
+    fn from(error: alloy::transports::TransportError) -> Self {
+        MwError::RpcError(error.to_string())
+    }
+}
+
+impl From<alloy::contract::Error> for MwError {
+    fn from(error: alloy::contract::Error) -> Self {
+        MwError::RpcError(error.to_string())
+    }
+}
+
+impl From<alloy::signers::Error> for MwError {
+    fn from(_: alloy::signers::Error) -> Self {
+        MwError::SigningError
+    }
+}
+
+impl From<std::io::Error> for MwError {
+    fn from(error: std::io::Error) -> Self {
+        MwError::IoError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for MwError {
+    fn from(error: serde_json::Error) -> Self {
+        MwError::SerializationError(error.to_string())
     }
 }
@@ -0,0 +1,500 @@
+// Overview:
+// Event sourcing for the engine. Every command the sequencer accepts becomes an Event, and the
+// Warehouse, OrderBookManager and session state is a pure fold over the event log, so anyone with
+// the log can prove what happened in a session.
+// * events carry everything needed to apply them, signatures, timestamps and session phases are
+//   checked by the sequencer before an event is created and never during apply
+// * settlement orders are the only way funds leave, so they double as withdrawals. They're signed
+//   before the event is created and the event carries the signed order
+// * apply never reads the clock or touches the network, time comes from the record's timestamp
+// * each record stores the state hash after it was applied, replay (src/bin/replay.rs) refolds the
+//   log and checks every hash. The hash covers everything Genesis carries, records from before it
+//   covered pnl, the session phase and per chain deposits keep the old hash (hash_version 0)
+// * an event apply rejects leaves the state as it was and isn't logged
+// * a restart replays the log, resting orders included (see sequencer.rs). A Genesis event is only
+//   written when a log starts, from the state loaded from the volume or recovered from a
+//   checkpoint, and replay resets to it
+// * the book starts empty at Genesis, so whatever the inventories had locked is released
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use alloy::primitives::{keccak256, Address, B256, U256};
+use optimized_lob::{order::OrderId, orderbook_manager::OrderBookManager, quantity::Qty};
+
+use crate::{
+    batch::{self, OrderBatch},
+    chains::{self, MAINNET_CHAIN_ID},
+    closer,
+    cowswap::CowSwapOrder,
+    errors::MwError,
+    gulper::{self, IndexedDeposit},
+    orderhere::{self, CancelAll, Order},
+    pnl::{AccountPnl, PnlBook},
+    sequencer::CommandOutput,
+    session::{SessionController, SessionSchedule, SessionState},
+    warehouse::{Inventory, Warehouse},
+};
+
+pub const EVENT_LOG_STORAGE_FILE: &str = "events.jsonl"; // under the configured data dir
+pub const STATE_HASH_VERSION: u8 = 1; // see state_hash_for
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Genesis {
+        inventories: Vec<String>, // Inventory::to_json, sorted by address
+        #[serde(default)]
+        deposit_contracts: BTreeMap<u64, Address>,
+        #[serde(default)]
+        deposit_cursors: BTreeMap<u64, u64>,
+        #[serde(default)]
+        chain_deposits: BTreeMap<u64, [U256; 2]>,
+        // logs from before deposits on other chains have the mainnet registry and cursor here
+        #[serde(default, skip_serializing)]
+        deposit_contract: Address,
+        #[serde(default, skip_serializing)] // and from before the deposit indexer not even that
+        deposit_cursor: u64,
+        checkpoint_contract: Address,
+        session_id: u64,
+        pnl_session_id: u64,
+        #[serde(default)] // the session's positions, logs from before they were stored lack them
+        pnl_accounts: BTreeMap<Address, AccountPnl>,
+        #[serde(default)] // logs from before the phase was stored start a new one at the record
+        session_state: Option<SessionState>,
+        #[serde(default)]
+        // queued and not posted yet, logs from before they were carried drop them
+        settlement_orders: Vec<CowSwapOrder>,
+    },
+    NewOrder {
+        user: Address,
+        order: Order,
+    },
+    CancelOrder {
+        user: Address,
+        oid: u32,
+    },
+    CancelAll {
+        user: Address,
+        cancel: CancelAll,
+    },
+    ReplaceOrder {
+        user: Address,
+        order: Order,
+        oid: u32,
+    },
+    Batch {
+        user: Address,
+        batch: OrderBatch,
+    },
+    // per-user gulp from before the deposit indexer, no longer created but still replayed
+    Deposit {
+        user: Address,
+        deposit_nonce: u32,
+        deposits: [U256; 2],
+    },
+    Gulp {
+        #[serde(default = "chains::mainnet_chain_id")]
+        chain_id: u64,
+        user: Address,
+        deposits: Vec<IndexedDeposit>,
+    },
+    DepositsIndexed {
+        #[serde(default = "chains::mainnet_chain_id")]
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+        deposits: Vec<IndexedDeposit>,
+    },
+    Settlement {
+        user: Address,
+        order: CowSwapOrder,
+        #[serde(default)] // eth and usdc it pulls from the order's chain, see settler.rs
+        pulled: [U256; 2],
+        #[serde(default)] // eth and usdc it costs the taker, logs from before it was taken lack it
+        debited: [U256; 2],
+    },
+    SetContractAddresses {
+        #[serde(default = "chains::mainnet_chain_id")]
+        chain_id: u64,
+        deposit_contract: Address,
+        checkpoint_contract: Address,
+    },
+    SetPnlVisibility {
+        user: Address,
+        is_public: bool,
+    },
+    CloseSession {
+        mark_price: U256,
+    },
+    FinalizeSession {
+        mark_price: U256,
+    },
+    ClearSettlementOrders {
+        count: usize,
+    },
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub timestamp: u64, // unix timestamp in milliseconds
+    pub event: Event,
+    // set when apply rejected the event after changing state, logs written before rejections
+    // were dropped also have ones that changed nothing
+    pub error: Option<String>,
+    pub state_hash: B256,
+    #[serde(default)] // what state_hash covers, see state_hash_for
+    pub hash_version: u8,
+}
+
+impl Event {
+    /// Genesis for the given state. The book starts empty, so the inventories go in with nothing
+    /// locked.
+    pub fn genesis(warehouse: &Warehouse, session: &SessionController) -> Self {
+        let mut inventories: Vec<Inventory> = warehouse.inventories.values().cloned().collect();
+        inventories.sort_by_key(|inventory| inventory.address);
+        Event::Genesis {
+            inventories: inventories
+                .into_iter()
+                .map(|mut inventory| {
+                    inventory.eth_liabilities = Qty(U256::ZERO);
+                    inventory.usdc_liabilities = Qty(U256::ZERO);
+                    inventory.to_json()
+                })
+                .collect(),
+            deposit_contracts: warehouse.deposit_contracts.clone(),
+            deposit_cursors: warehouse.deposit_cursors.clone(),
+            chain_deposits: warehouse.chain_deposits.clone(),
+            deposit_contract: Address::ZERO,
+            deposit_cursor: 0,
+            checkpoint_contract: warehouse.checkpoint_contract,
+            session_id: session.session_id,
+            pnl_session_id: warehouse.pnl.session_id,
+            pnl_accounts: warehouse
+                .pnl
+                .accounts
+                .iter()
+                .map(|(address, account)| (*address, account.clone()))
+                .collect(),
+            session_state: Some(session.state()),
+            settlement_orders: warehouse.settlement_orders.clone(),
+        }
+    }
+}
+
+/// Applies a single event. Deterministic given the same state, event and timestamp.
+pub fn apply(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    session: &mut SessionController,
+    timestamp: u64,
+    event: &Event,
+) -> Result<CommandOutput, MwError> {
+    match event {
+        Event::Genesis {
+            inventories,
+            deposit_contracts,
+            deposit_cursors,
+            chain_deposits,
+            deposit_contract,
+            deposit_cursor,
+            checkpoint_contract,
+            session_id,
+            pnl_session_id,
+            pnl_accounts,
+            session_state,
+            settlement_orders,
+        } => {
+            warehouse.inventories = inventories
+                .iter()
+                .map(|json| {
+                    let inventory = Inventory::from_json(json.clone());
+                    (inventory.address, inventory)
+                })
+                .collect();
+            warehouse.deposit_contracts = deposit_contracts.clone();
+            warehouse.deposit_cursors = deposit_cursors.clone();
+            warehouse.chain_deposits = chain_deposits.clone();
+            if !deposit_contract.is_zero() {
+                warehouse
+                    .deposit_contracts
+                    .insert(MAINNET_CHAIN_ID, *deposit_contract);
+            }
+            if *deposit_cursor != 0 {
+                warehouse
+                    .deposit_cursors
+                    .insert(MAINNET_CHAIN_ID, *deposit_cursor);
+            }
+            warehouse.checkpoint_contract = *checkpoint_contract;
+            warehouse.oid_qty_by_address.clear();
+            warehouse.address_by_oid.clear();
+            warehouse.settlement_orders = settlement_orders.clone();
+            warehouse.pnl = PnlBook {
+                session_id: *pnl_session_id,
+                accounts: pnl_accounts.clone().into_iter().collect(),
+            };
+            *orderbook_manager = OrderBookManager::new();
+            *session = SessionController::resume(
+                SessionSchedule::default(),
+                *session_id,
+                session_state.unwrap_or(SessionState::starting(timestamp)),
+            );
+            Ok(CommandOutput::Started)
+        }
+        Event::NewOrder { user, order } => Ok(CommandOutput::OrderPlaced(orderhere::submit_order(
+            warehouse,
+            orderbook_manager,
+            *user,
+            order.clone(),
+        )?)),
+        Event::CancelOrder { user, oid } => {
+            orderhere::submit_cancel(warehouse, orderbook_manager, *user, OrderId(*oid))?;
+            Ok(CommandOutput::OrderCancelled)
+        }
+        Event::CancelAll { user, cancel } => Ok(CommandOutput::OrdersCancelled(
+            orderhere::submit_cancel_all(warehouse, orderbook_manager, *user, cancel)?,
+        )),
+        Event::ReplaceOrder { user, order, oid } => {
+            Ok(CommandOutput::OrderReplaced(orderhere::submit_replace(
+                warehouse,
+                orderbook_manager,
+                *user,
+                order.clone(),
+                OrderId(*oid),
+            )?))
+        }
+        Event::Batch { user, batch } => Ok(CommandOutput::BatchExecuted(batch::apply_batch(
+            warehouse,
+            orderbook_manager,
+            *user,
+            batch,
+        )?)),
+        Event::Deposit {
+            user,
+            deposit_nonce,
+            deposits,
+        } => {
+            gulper::credit_deposits(warehouse, *user, *deposit_nonce, *deposits)?;
+            Ok(CommandOutput::DepositsCredited(*deposits))
+        }
+        Event::Gulp {
+            chain_id, deposits, ..
+        } => Ok(CommandOutput::DepositsIndexed(
+            gulper::credit_indexed_deposits(warehouse, *chain_id, deposits)?,
+        )),
+        Event::DepositsIndexed {
+            chain_id,
+            from_block,
+            to_block,
+            deposits,
+        } => Ok(CommandOutput::DepositsIndexed(gulper::index_deposits(
+            warehouse,
+            *chain_id,
+            *from_block,
+            *to_block,
+            deposits,
+        )?)),
+        Event::Settlement {
+            user,
+            order,
+            pulled,
+            debited,
+        } => {
+            warehouse.check_chain_deposits(order.chain_id, *pulled)?;
+            warehouse.debit_taker(*user, *debited)?;
+            warehouse.debit_chain_deposits(order.chain_id, *pulled)?;
+            warehouse.add_settlement_order(order.clone());
+            Ok(CommandOutput::SettlementOrderAdded)
+        }
+        Event::SetContractAddresses {
+            chain_id,
+            deposit_contract,
+            checkpoint_contract,
+        } => {
+            // a new registry has its own logs, index it from the configured start block
+            if warehouse.deposit_contract(*chain_id) != *deposit_contract {
+                warehouse.deposit_cursors.remove(chain_id);
+            }
+            warehouse
+                .deposit_contracts
+                .insert(*chain_id, *deposit_contract);
+            warehouse.checkpoint_contract = *checkpoint_contract;
+            Ok(CommandOutput::ContractAddressesSet)
+        }
+        Event::SetPnlVisibility { user, is_public } => {
+            warehouse.set_pnl_public(*user, *is_public);
+            Ok(CommandOutput::PnlVisibilitySet)
+        }
+        Event::CloseSession { mark_price } => Ok(CommandOutput::SessionClosed(
+            closer::close_session(warehouse, orderbook_manager, *mark_price, timestamp)?,
+        )),
+        Event::FinalizeSession { mark_price } => {
+            let session_id = session.finalize(timestamp)?;
+            warehouse.finalize_session_pnl(session_id, *mark_price);
+            Ok(CommandOutput::SessionFinalized(session_id))
+        }
+        Event::ClearSettlementOrders { count } => {
+            warehouse.remove_settlement_orders(*count);
+            Ok(CommandOutput::SettlementOrdersCleared)
+        }
+    }
+}
+
+/// State hash at the current STATE_HASH_VERSION.
+pub fn state_hash(
+    warehouse: &Warehouse,
+    orderbook_manager: &OrderBookManager,
+    session: &SessionController,
+) -> B256 {
+    state_hash_for(STATE_HASH_VERSION, warehouse, orderbook_manager, session)
+}
+
+/// Hash of the canonical encoding of the folded state: inventories sorted by address, resting
+/// orders sorted by order id, settlement orders in order, deposit contracts and cursors by chain
+/// id, the checkpoint contract and the session id. From version 1 also the deposits held per
+/// chain, the pnl book's session id and accounts sorted by address, and the session's phase.
+/// Leaderboard history isn't part of it.
+pub fn state_hash_for(
+    hash_version: u8,
+    warehouse: &Warehouse,
+    orderbook_manager: &OrderBookManager,
+    session: &SessionController,
+) -> B256 {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let mut inventories: Vec<&Inventory> = warehouse.inventories.values().collect();
+    inventories.sort_by_key(|inventory| inventory.address);
+    for inventory in inventories {
+        buffer.extend(inventory.to_bytes());
+    }
+
+    let mut orders: Vec<(&OrderId, &Address)> = warehouse.address_by_oid.iter().collect();
+    orders.sort_by_key(|(oid, _)| oid.0);
+    for (oid, owner) in orders {
+        buffer.extend(oid.0.to_le_bytes());
+        buffer.extend(&owner.0);
+        let qty = warehouse
+            .oid_qty_by_address
+            .get(owner)
+            .and_then(|orders| orders.get(oid))
+            .map_or(U256::ZERO, |qty| qty.0);
+        buffer.extend(qty.to_le_bytes::<32>());
+        if let Ok(price) = orderhere::resting_order_price(orderbook_manager, *oid) {
+            buffer.extend(price.absolute().to_le_bytes::<32>());
+            buffer.push(price.is_bid() as u8);
+        }
+    }
+
+    for order in warehouse.settlement_orders.iter() {
+        buffer.extend(serde_json::to_vec(order).unwrap());
+    }
+
+    for (chain_id, deposit_contract) in warehouse.deposit_contracts.iter() {
+        buffer.extend(chain_id.to_le_bytes());
+        buffer.extend(&deposit_contract.0);
+    }
+    for (chain_id, deposit_cursor) in warehouse.deposit_cursors.iter() {
+        buffer.extend(chain_id.to_le_bytes());
+        buffer.extend(deposit_cursor.to_le_bytes());
+    }
+    buffer.extend(&warehouse.checkpoint_contract.0);
+    buffer.extend(session.session_id.to_le_bytes());
+    if hash_version == 0 {
+        return keccak256(&buffer);
+    }
+
+    for (chain_id, [eth, usdc]) in warehouse.chain_deposits.iter() {
+        buffer.extend(chain_id.to_le_bytes());
+        buffer.extend(eth.to_le_bytes::<32>());
+        buffer.extend(usdc.to_le_bytes::<32>());
+    }
+    buffer.extend(warehouse.pnl.session_id.to_le_bytes());
+    let mut accounts: Vec<(&Address, &AccountPnl)> = warehouse.pnl.accounts.iter().collect();
+    accounts.sort_by_key(|(address, _)| **address);
+    for (address, account) in accounts {
+        buffer.extend(&address.0);
+        buffer.extend(serde_json::to_vec(account).unwrap());
+    }
+    buffer.extend(serde_json::to_vec(&session.state()).unwrap());
+    keccak256(&buffer)
+}
+
+/// Append only, newline delimited json log of event records.
+pub struct EventLog {
+    file: std::fs::File,
+    last_seq: u64,
+}
+
+impl EventLog {
+    /// Opens the log for appending, picking up the sequence number where the last run left off.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let last_seq = match std::fs::File::open(path) {
+            Ok(_) => read_records(path)?.last().map_or(0, |record| record.seq),
+            Err(_) => 0,
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(EventLog { file, last_seq })
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    pub fn append(&mut self, record: &EventRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.last_seq = record.seq;
+        Ok(())
+    }
+}
+
+pub fn read_records(
+    path: impl AsRef<Path>,
+) -> Result<Vec<EventRecord>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok(records)
+}
+
+/// Refolds `records` on top of the given state, checking each record's outcome and state hash.
+/// Returns the final state hash, or the seq of the first record that diverged.
+pub fn replay(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    session: &mut SessionController,
+    records: &[EventRecord],
+) -> Result<B256, u64> {
+    let mut hash = state_hash(warehouse, orderbook_manager, session);
+    for record in records {
+        let result = apply(
+            warehouse,
+            orderbook_manager,
+            session,
+            record.timestamp,
+            &record.event,
+        );
+        hash = state_hash_for(record.hash_version, warehouse, orderbook_manager, session);
+        let error = result.err().map(|error| error.to_string());
+        if error != record.error || hash != record.state_hash {
+            return Err(record.seq);
+        }
+    }
+    Ok(hash)
+}
@@ -0,0 +1,474 @@
+// Overview:
+// An in-memory chain behind ChainClient (see rpc.rs), for tests and local runs without an RPC.
+// * holds deposit registries, Checkpointers, a head block and the time, tests move them along with
+//   the setters and the app reads them like any other chain
+// * deposits are laid out the way DepositRegistry stores them and storage proofs come from a real
+//   trie, so verifier.rs checks them like proofs from a node
+// * block hashes are the block number, padded, state roots cover every registry as of that block
+// * transactions are mined as soon as they're sent, a checkpoint() call is applied to the
+//   checkpoint stored at its `to` and, like the Checkpointer, reverts on the wrong nonce or without
+//   a signature from the contract's leader, set_leader() elects one, anything else is just recorded
+// * like the Checkpointer, checkpoints renew the leader's lease and set_leader() reverts while it
+//   runs. Registries, the contracts deposits were made to, have no lease
+// * set_failing makes every call fail, to test what callers do when the RPC goes down
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use alloy::{
+    primitives::{keccak256, Address, Parity, Signature, TxHash, B256, U256},
+    rpc::types::{EIP1186AccountProofResponse, TransactionRequest},
+    sol_types::{SolCall, SolStruct},
+};
+use alloy_trie::{proof::ProofRetainer, HashBuilder, Nibbles, KECCAK_EMPTY};
+
+use crate::{
+    artifacts::ICheckpointer,
+    errors::MwError,
+    gulper::IndexedDeposit,
+    leader::LEADER_LEASE_SECS,
+    rpc::{ChainClient, StoredCheckpoint},
+    verifier::{self, TrieAccount},
+};
+
+pub const FAKE_GAS_PRICE: u128 = 1_000_000_000;
+// DepositRegistry.settlement_nonce, after admin
+const SETTLEMENT_NONCE_SLOT: u64 = 1;
+
+#[derive(Default)]
+struct FakeRegistry {
+    deposits: Vec<(u64, IndexedDeposit)>, // (block, deposit) in the order they were made
+    settlement_nonce: U256,
+}
+
+impl FakeRegistry {
+    fn deposits_at(&self, block: u64) -> impl Iterator<Item = &IndexedDeposit> {
+        self.deposits
+            .iter()
+            .filter(move |(deposited_at, _)| *deposited_at <= block)
+            .map(|(_, deposit)| deposit)
+    }
+
+    fn storage_at(&self, block: u64) -> HashMap<B256, U256> {
+        let mut storage = HashMap::new();
+        storage.insert(
+            B256::from(U256::from(SETTLEMENT_NONCE_SLOT)),
+            self.settlement_nonce,
+        );
+        for deposit in self.deposits_at(block) {
+            storage.insert(
+                verifier::deposit_count_slot(deposit.user),
+                U256::from(deposit.index + 1),
+            );
+            let [eth_slot, usdc_slot] = verifier::deposit_amount_slots(deposit.user, deposit.index);
+            storage.insert(eth_slot, deposit.amounts[0]);
+            storage.insert(usdc_slot, deposit.amounts[1]);
+        }
+        // empty slots aren't in the trie
+        storage.retain(|_, value| !value.is_zero());
+        storage
+    }
+}
+
+#[derive(Default)]
+struct FakeState {
+    head: u64,
+    now: u64, // block timestamp in seconds
+    failing: bool,
+    registries: BTreeMap<Address, FakeRegistry>,
+    checkpoints: HashMap<Address, StoredCheckpoint>,
+    separators: HashMap<Address, B256>,
+    leaders: HashMap<Address, Address>,
+    leases: HashMap<Address, u64>, // when the leader was elected or last checkpointed
+    sent: Vec<TransactionRequest>,
+    statuses: HashMap<TxHash, bool>,
+}
+
+/// See the overview, everything is behind one lock and nothing is held across an await.
+#[derive(Default)]
+pub struct FakeChain {
+    state: Mutex<FakeState>,
+}
+
+/// Hash the fake gives block `number`.
+pub fn fake_block_hash(number: u64) -> B256 {
+    B256::from(U256::from(number))
+}
+
+/// Builds a trie over `leaves` keyed by the hash of their key and returns its root and a proof for
+/// each of `targets`. Keys go in the way the state trie has them: accounts by their 20 byte
+/// address, storage slots by the 32 byte word.
+pub fn prove<K: AsRef<[u8]>>(leaves: Vec<(K, Vec<u8>)>, targets: &[K]) -> (B256, Vec<Vec<String>>) {
+    let mut leaves: Vec<(Nibbles, Vec<u8>)> = leaves
+        .into_iter()
+        .map(|(key, value)| (Nibbles::unpack(keccak256(key)), value))
+        .collect();
+    leaves.sort_by(|a, b| a.0.cmp(&b.0));
+    let targets: Vec<Nibbles> = targets
+        .iter()
+        .map(|key| Nibbles::unpack(keccak256(key)))
+        .collect();
+    let mut builder =
+        HashBuilder::default().with_proof_retainer(ProofRetainer::new(targets.clone()));
+    for (key, value) in leaves.iter() {
+        builder.add_leaf(key.clone(), value);
+    }
+    let root = builder.root();
+    let nodes = builder.take_proof_nodes();
+    let proofs = targets
+        .iter()
+        .map(|target| {
+            nodes
+                .matching_nodes_sorted(target)
+                .into_iter()
+                .map(|(_, node)| node.to_string())
+                .collect()
+        })
+        .collect();
+    (root, proofs)
+}
+
+// the app signs with 64 byte r || s signatures, so either parity can be the one
+fn signed_by(signature: &[u8], hash: B256, signer: Address) -> bool {
+    if signature.len() < 64 {
+        return false;
+    }
+    let r = U256::from_be_slice(&signature[..32]);
+    let s = U256::from_be_slice(&signature[32..64]);
+    [false, true].into_iter().any(|parity| {
+        Signature::new(r, s, Parity::Parity(parity))
+            .recover_address_from_prehash(&hash)
+            .is_ok_and(|recovered| recovered == signer)
+    })
+}
+
+fn storage_leaves(storage: &HashMap<B256, U256>) -> Vec<(B256, Vec<u8>)> {
+    storage
+        .iter()
+        .map(|(key, value)| (*key, alloy_rlp::encode(value)))
+        .collect()
+}
+
+fn registry_account(storage: &HashMap<B256, U256>) -> TrieAccount {
+    TrieAccount {
+        nonce: 1,
+        balance: U256::ZERO,
+        storage_root: prove(storage_leaves(storage), &[]).0,
+        code_hash: KECCAK_EMPTY,
+    }
+}
+
+impl FakeState {
+    fn check(&self) -> Result<(), MwError> {
+        match self.failing {
+            true => Err(MwError::RpcError("fake chain is failing".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    // state root over every registry as of `block`, with a proof for each of `targets`' accounts
+    fn state_at(&self, block: u64, targets: &[Address]) -> (B256, Vec<Vec<String>>) {
+        let accounts: Vec<(Address, Vec<u8>)> = self
+            .registries
+            .iter()
+            .map(|(address, registry)| {
+                let account = registry_account(&registry.storage_at(block));
+                (*address, alloy_rlp::encode(&account))
+            })
+            .collect();
+        prove(accounts, targets)
+    }
+}
+
+impl FakeChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn set_head(&self, head: u64) {
+        self.state().head = head;
+    }
+
+    pub fn set_time(&self, now: u64) {
+        self.state().now = now;
+    }
+
+    pub fn set_failing(&self, failing: bool) {
+        self.state().failing = failing;
+    }
+
+    /// Deposits into `registry` at the head block, returns the deposit's index.
+    pub fn deposit(&self, registry: Address, user: Address, amounts: [U256; 2]) -> u32 {
+        let mut state = self.state();
+        let head = state.head;
+        let registry = state.registries.entry(registry).or_default();
+        let index = registry
+            .deposits
+            .iter()
+            .filter(|(_, deposit)| deposit.user == user)
+            .count() as u32;
+        registry.deposits.push((
+            head,
+            IndexedDeposit {
+                user,
+                index,
+                amounts,
+            },
+        ));
+        index
+    }
+
+    pub fn set_settlement_nonce(&self, registry: Address, nonce: U256) {
+        self.state()
+            .registries
+            .entry(registry)
+            .or_default()
+            .settlement_nonce = nonce;
+    }
+
+    pub fn set_domain_separator(&self, contract: Address, separator: B256) {
+        self.state().separators.insert(contract, separator);
+    }
+
+    /// Elects `leader` on `contract` now, lease or not.
+    pub fn set_leader(&self, contract: Address, leader: Address) {
+        let mut state = self.state();
+        let now = state.now;
+        state.leaders.insert(contract, leader);
+        state.leases.insert(contract, now);
+    }
+
+    pub fn set_checkpoint(&self, checkpointer: Address, checkpoint: StoredCheckpoint) {
+        self.state().checkpoints.insert(checkpointer, checkpoint);
+    }
+
+    /// Every transaction sent so far, including reverted ones.
+    pub fn sent(&self) -> Vec<TransactionRequest> {
+        self.state().sent.clone()
+    }
+}
+
+impl ChainClient for FakeChain {
+    async fn block_number(&self) -> Result<u64, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state.head)
+    }
+
+    async fn block_roots(&self, number: u64) -> Result<Option<(B256, B256)>, MwError> {
+        let state = self.state();
+        state.check()?;
+        if number > state.head {
+            return Ok(None);
+        }
+        let (state_root, _) = state.state_at(number, &[]);
+        Ok(Some((fake_block_hash(number), state_root)))
+    }
+
+    async fn deposit_logs(
+        &self,
+        registry: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<IndexedDeposit>, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state
+            .registries
+            .get(&registry)
+            .map(|registry| {
+                registry
+                    .deposits
+                    .iter()
+                    .filter(|(block, _)| (from_block..=to_block).contains(block))
+                    .map(|(_, deposit)| deposit.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_deposits(
+        &self,
+        registry: Address,
+        user: Address,
+        next_index: u32,
+        block: u64,
+    ) -> Result<Vec<[U256; 2]>, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state
+            .registries
+            .get(&registry)
+            .map(|registry| {
+                registry
+                    .deposits_at(block)
+                    .filter(|deposit| deposit.user == user && deposit.index >= next_index)
+                    .map(|deposit| deposit.amounts)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn storage_proof(
+        &self,
+        contract: Address,
+        keys: Vec<B256>,
+        block_hash: B256,
+    ) -> Result<EIP1186AccountProofResponse, MwError> {
+        let state = self.state();
+        state.check()?;
+        let block = U256::from_be_bytes(block_hash.0);
+        if block > U256::from(state.head) {
+            return Err(MwError::RpcError(format!("unknown block {}", block_hash)));
+        }
+        let block = block.to::<u64>();
+        let storage = state
+            .registries
+            .get(&contract)
+            .map(|registry| registry.storage_at(block))
+            .ok_or_else(|| MwError::RpcError(format!("no registry at {}", contract)))?;
+        let account = registry_account(&storage);
+        let (_, storage_proofs) = prove(storage_leaves(&storage), &keys);
+        let (_, account_proofs) = state.state_at(block, &[contract]);
+        serde_json::from_value(serde_json::json!({
+            "address": contract,
+            "balance": account.balance,
+            "codeHash": account.code_hash,
+            "nonce": format!("{:#x}", account.nonce),
+            "storageHash": account.storage_root,
+            "accountProof": account_proofs[0],
+            "storageProof": keys
+                .iter()
+                .zip(storage_proofs)
+                .map(|(key, proof)| serde_json::json!({
+                    "key": key,
+                    "value": storage.get(key).copied().unwrap_or_default(),
+                    "proof": proof,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+        .map_err(|e| MwError::RpcError(e.to_string()))
+    }
+
+    async fn settlement_nonce(&self, registry: Address) -> Result<U256, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state
+            .registries
+            .get(&registry)
+            .map_or(U256::ZERO, |registry| registry.settlement_nonce))
+    }
+
+    async fn domain_separator(&self, contract: Address) -> Result<B256, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state.separators.get(&contract).copied().unwrap_or_default())
+    }
+
+    async fn leader(&self, contract: Address) -> Result<Address, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state.leaders.get(&contract).copied().unwrap_or_default())
+    }
+
+    async fn checkpoint_nonce(&self, checkpointer: Address) -> Result<U256, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state
+            .checkpoints
+            .get(&checkpointer)
+            .map_or(U256::ZERO, |checkpoint| checkpoint.next_nonce))
+    }
+
+    async fn latest_checkpoint(&self, checkpointer: Address) -> Result<StoredCheckpoint, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state
+            .checkpoints
+            .get(&checkpointer)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn transaction_count(&self, _sender: Address) -> Result<u64, MwError> {
+        // there's only ever one sender
+        let state = self.state();
+        state.check()?;
+        Ok(state.sent.len() as u64)
+    }
+
+    async fn gas_price(&self) -> Result<u128, MwError> {
+        self.state().check()?;
+        Ok(FAKE_GAS_PRICE)
+    }
+
+    async fn send_transaction(&self, tx: TransactionRequest) -> Result<TxHash, MwError> {
+        let mut state = self.state();
+        state.check()?;
+        let hash = keccak256((state.sent.len() as u64).to_be_bytes());
+        let to = tx.to.and_then(|to| to.to().copied()).unwrap_or_default();
+        let input = tx.input.input().cloned().unwrap_or_default();
+        let status = if let Ok(call) = ICheckpointer::checkpointCall::abi_decode(&input, true) {
+            let checkpoint = call._checkpoint;
+            let mut message = vec![0x19, 0x01];
+            message.extend_from_slice(
+                state
+                    .separators
+                    .get(&to)
+                    .copied()
+                    .unwrap_or_default()
+                    .as_slice(),
+            );
+            message.extend_from_slice(checkpoint.eip712_hash_struct().as_slice());
+            let leader_signed = state.leaders.get(&to).is_some_and(|leader| {
+                signed_by(&call.leader_signature, keccak256(&message), *leader)
+            });
+            let stored = state.checkpoints.entry(to).or_default();
+            let applies = leader_signed && checkpoint.nonce == stored.next_nonce;
+            if applies {
+                *stored = StoredCheckpoint {
+                    next_nonce: checkpoint.nonce + U256::from(1),
+                    inventory_root: checkpoint.inventory_root,
+                    inventory_state: checkpoint.inventory_state,
+                    settlement_orders: checkpoint.settlement_orders,
+                };
+                let now = state.now;
+                state.leases.insert(to, now);
+            }
+            applies
+        } else if let Ok(call) = ICheckpointer::set_leaderCall::abi_decode(&input, true) {
+            // the registry's set_leader has the same selector
+            let leader = state.leaders.get(&to).copied().unwrap_or_default();
+            let lease_runs = !state.registries.contains_key(&to)
+                && !leader.is_zero()
+                && call.new_leader != leader
+                && state
+                    .leases
+                    .get(&to)
+                    .is_some_and(|renewed_at| state.now <= renewed_at + LEADER_LEASE_SECS);
+            if !lease_runs {
+                let now = state.now;
+                state.leaders.insert(to, call.new_leader);
+                state.leases.insert(to, now);
+            }
+            !lease_runs
+        } else {
+            true
+        };
+        state.sent.push(tx);
+        state.statuses.insert(hash, status);
+        Ok(hash)
+    }
+
+    async fn transaction_status(&self, hash: TxHash) -> Result<Option<bool>, MwError> {
+        let state = self.state();
+        state.check()?;
+        Ok(state.statuses.get(&hash).copied())
+    }
+}
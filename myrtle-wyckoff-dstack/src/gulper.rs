@@ -1,73 +1,426 @@
 // Overview:
-// Responsible for gulping new deposits from the mainnet deposit registry contract
+// Responsible for gulping new deposits from the deposit registry contracts.
+// A background indexer follows each chain's registry Deposit logs from a block cursor kept in the
+// warehouse and credits every depositor, so nobody has to poll per user.
+// * every chain (see chains.rs) has its own registry, cursor and per-user deposit nonce, deposits on
+//   one chain never move another chain's nonce
+// * only blocks at least `confirmations` deep are read, so a reorg shallower than that can't undo a
+//   credit
+// * logs are fetched off the sequencer since it's an RPC call, the sequencer gets the decoded
+//   deposits along with the block range and rejects them if the cursor moved in the meantime
+// * nothing read from the RPC is credited until it's proven against the registry's storage at a
+//   block a quorum of the chain's endpoints agree on (see verifier.rs)
+// * deposits are credited by their index in the registry, an index the user was already credited
+//   for is skipped so indexing the same range twice is harmless
+// * a user whose first logged deposit is ahead of their deposit nonce (they deposited before the
+//   cursor started) has the missing ones read with get_deposits first
+// * /gulp-deposits/<user> credits one user's confirmed deposits on a chain without waiting for the
+//   indexer, from their deposit nonce to the end of their registry entries, and is a no-op if there
+//   are none
+// * a chain starts from its start block (deposit_start_blocks), which every deposit chain must
+//   have, and restarts from it when its deposit contract changes
+// * only the leader indexes, a replica gets the same deposits through the leader's events
+// * deposits are only credited while the session allows them (see session.rs), outside that window
+//   a poll is skipped without touching the cursor and the range is picked up once it opens
+// * chains are read through their ChainClient (see rpc.rs), so the indexer runs the same against a
+//   FakeChain
+// * credit_deposits is the old per-user gulp, kept so older event logs still replay the way they
+//   were recorded, it only ever credited mainnet
 
-use std::sync::Arc;
-
-use alloy::{
-    network::{Ethereum, EthereumWallet},
-    primitives::{Address, U256},
-    providers::RootProvider,
-    transports::http::{Client, Http},
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Duration,
 };
+
+use alloy::primitives::{Address, U256};
 use core::ops::AddAssign as AddAssignTrait;
 use optimized_lob::quantity::Qty;
+use tracing::{info, warn};
 
 use crate::{
-    artifacts::IDepositRegistry,
+    chains::{Chain, Chains, MAINNET_CHAIN_ID},
+    clock::now_ms,
+    errors::MwError,
+    leader::InstanceRole,
+    rpc::ChainClient,
+    sequencer::{Command, CommandOutput, SequencerHandle},
+    session::SessionAction,
+    verifier,
     warehouse::{Inventory, Warehouse},
 };
 
-#[tokio::main]
-pub async fn gulp_deposits(
-    warehouse: &mut Warehouse,
-    provider: &Arc<
-        alloy::providers::fillers::FillProvider<
-            alloy::providers::fillers::JoinFill<
-                alloy::providers::fillers::JoinFill<
-                    alloy::providers::Identity,
-                    alloy::providers::fillers::JoinFill<
-                        alloy::providers::fillers::GasFiller,
-                        alloy::providers::fillers::JoinFill<
-                            alloy::providers::fillers::BlobGasFiller,
-                            alloy::providers::fillers::JoinFill<
-                                alloy::providers::fillers::NonceFiller,
-                                alloy::providers::fillers::ChainIdFiller,
-                            >,
-                        >,
-                    >,
-                >,
-                alloy::providers::fillers::WalletFiller<EthereumWallet>,
-            >,
-            RootProvider<Http<Client>>,
-            Http<Client>,
-            Ethereum,
-        >,
-    >,
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 12_000;
+pub const DEFAULT_CONFIRMATIONS: u64 = 12;
+pub const DEFAULT_MAX_BLOCK_RANGE: u64 = 1_000;
+
+/// A single DepositRegistry deposit, `index` is its position in deposit_registry[user].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IndexedDeposit {
+    pub user: Address,
+    pub index: u32,
+    pub amounts: [U256; 2], // [eth_amount, usdc_amount]
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexerConfig {
+    pub poll_interval: Duration,
+    pub confirmations: u64,
+    pub max_block_range: u64, // keeps a cold start from asking the RPC for the whole chain at once
+    // chain id, where to start when the cursor hasn't, e.g. the registry deployment
+    pub start_blocks: BTreeMap<u64, u64>,
+}
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            poll_interval: Duration::from_millis(DEFAULT_POLL_INTERVAL_MS),
+            confirmations: DEFAULT_CONFIRMATIONS,
+            max_block_range: DEFAULT_MAX_BLOCK_RANGE,
+            start_blocks: BTreeMap::new(),
+        }
+    }
+}
+impl IndexerConfig {
+    /// Where the indexer starts on `chain_id`. There's no default, starting from genesis would
+    /// crawl the whole chain a block range per poll.
+    pub fn start_block(&self, chain_id: u64) -> Result<u64, MwError> {
+        self.start_blocks.get(&chain_id).copied().ok_or_else(|| {
+            MwError::GulpError(format!("no deposit start block for chain {}", chain_id))
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IndexerStatus {
+    pub last_indexed_blocks: BTreeMap<u64, u64>, // chain id, last block indexed there
+    pub last_run_at: Option<u64>,                // unix timestamp in milliseconds
+    pub deposits_credited: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+}
+impl IndexerStatus {
+    pub fn to_json(&self) -> String {
+        let serializable_status = serde_json::json!({
+            "last_indexed_blocks": self
+                .last_indexed_blocks
+                .iter()
+                .map(|(chain_id, block)| (chain_id.to_string(), block.to_string().into()))
+                .collect::<serde_json::Map<String, serde_json::Value>>(),
+            "last_run_at": self.last_run_at.map(|at| at.to_string()),
+            "deposits_credited": self.deposits_credited.to_string(),
+            "last_error": self.last_error,
+            "last_error_at": self.last_error_at.map(|at| at.to_string()),
+        });
+        serde_json::to_string(&serializable_status).unwrap()
+    }
+}
+
+/// Shared between the indexer task and the http handlers.
+#[derive(Default)]
+pub struct DepositIndexer {
+    status: RwLock<IndexerStatus>,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl DepositIndexer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> IndexerStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Indexes the next confirmed block range on every chain, returning how many deposits were
+    /// credited. A chain that fails doesn't hold up the others, the last error is returned.
+    pub async fn run_once<C: ChainClient>(
+        &self,
+        sequencer: &SequencerHandle,
+        chains: &Chains<C>,
+        config: &IndexerConfig,
+    ) -> Result<usize, MwError> {
+        let _running = self.running.lock().await;
+        if sequencer.snapshot().role != InstanceRole::Leader {
+            return Ok(0);
+        }
+        let mut credited_total = 0;
+        let mut failure = None;
+        for chain in chains.iter() {
+            let chain_id = chain.spec.chain_id;
+            let result = index_next_range(sequencer, chain, config).await;
+            let mut status = self.status.write().unwrap();
+            status.last_run_at = Some(now_ms());
+            match result {
+                Ok(Some((to_block, credited))) => {
+                    status.last_indexed_blocks.insert(chain_id, to_block);
+                    status.deposits_credited += credited as u64;
+                    credited_total += credited;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    warn!("deposit indexing on chain {} failed: {}", chain_id, error);
+                    status.last_error = Some(format!("chain {}: {}", chain_id, error));
+                    status.last_error_at = Some(now_ms());
+                    failure = Some(error);
+                }
+            }
+        }
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(credited_total),
+        }
+    }
+}
+
+/// Returns the last block indexed on `chain` and the number of deposits credited, or None if
+/// there was nothing confirmed to index.
+async fn index_next_range<C: ChainClient>(
+    sequencer: &SequencerHandle,
+    chain: &Chain<C>,
+    config: &IndexerConfig,
+) -> Result<Option<(u64, usize)>, MwError> {
+    let chain_id = chain.spec.chain_id;
+    let client = chain.client.as_ref();
+    let snapshot = sequencer.snapshot();
+    let deposit_contract = snapshot.deposit_contract(chain_id);
+    if deposit_contract.is_zero() {
+        return Ok(None);
+    }
+    // the sequencer would reject the range anyway, no point reading it
+    if snapshot
+        .session
+        .ensure_allowed(SessionAction::Deposit, now_ms())
+        .is_err()
+    {
+        return Ok(None);
+    }
+    let head = client.block_number().await?;
+    let Some(confirmed) = head.checked_sub(config.confirmations) else {
+        return Ok(None);
+    };
+    let from_block = match snapshot.deposit_cursor(chain_id) {
+        Some(cursor) => cursor,
+        None => config.start_block(chain_id)?,
+    };
+    if from_block > confirmed {
+        return Ok(None);
+    }
+    let to_block = confirmed.min(from_block + config.max_block_range.max(1) - 1);
+
+    // the RPC can leave logs out, but anything it returns is proven before it's credited
+    let logged = client
+        .deposit_logs(deposit_contract, from_block, to_block)
+        .await?;
+    let nonces: HashMap<Address, u32> = snapshot
+        .inventories
+        .iter()
+        .map(|(user, inventory)| (*user, inventory.deposit_nonce(chain_id)))
+        .collect();
+    let deposits = backfill(client, deposit_contract, &nonces, logged, to_block).await?;
+    verifier::verify_deposits(chain, deposit_contract, to_block, &deposits).await?;
+
+    let applied = sequencer
+        .submit(Command::IndexDeposits {
+            chain_id,
+            from_block,
+            to_block,
+            deposits,
+        })
+        .await?;
+    let CommandOutput::DepositsIndexed(credited) = applied.output else {
+        unreachable!()
+    };
+    if credited > 0 {
+        info!(
+            "credited {} deposits from blocks {}..={} on chain {}",
+            credited, from_block, to_block, chain_id
+        );
+    }
+    Ok(Some((to_block, credited)))
+}
+
+/// Fills in any deposits between a user's nonce and their first logged deposit from the registry,
+/// read at `block` so nothing past the confirmed range is picked up.
+async fn backfill<C: ChainClient>(
+    client: &C,
+    deposit_contract: Address,
+    nonces: &HashMap<Address, u32>,
+    logged: Vec<IndexedDeposit>,
+    block: u64,
+) -> Result<Vec<IndexedDeposit>, MwError> {
+    let mut seen: HashSet<Address> = HashSet::new();
+    let mut deposits = Vec::with_capacity(logged.len());
+    for deposit in logged {
+        let nonce = nonces.get(&deposit.user).copied().unwrap_or_default();
+        if seen.insert(deposit.user) && deposit.index > nonce {
+            let missing =
+                fetch_user_deposits(client, deposit_contract, deposit.user, nonce, block).await?;
+            deposits.extend(
+                missing
+                    .into_iter()
+                    .filter(|missing| missing.index < deposit.index),
+            );
+        }
+        deposits.push(deposit);
+    }
+    Ok(deposits)
+}
+
+/// Every deposit `user` made from `next_index` on, as of `block`. Empty if there are none.
+pub async fn fetch_user_deposits<C: ChainClient>(
+    client: &C,
+    deposit_contract: Address,
     user: Address,
-) -> Result<[U256; 2], Box<dyn std::error::Error>> {
-    // this should use the dstack in-TDX light client rather than the provider so we don't need to trust the RPC
-    // but this is a demo/poc so RPC for now
-    let deposit_registry_contract = IDepositRegistry::new(warehouse.deposit_contract, provider);
+    next_index: u32,
+    block: u64,
+) -> Result<Vec<IndexedDeposit>, MwError> {
+    let amounts = client
+        .get_deposits(deposit_contract, user, next_index, block)
+        .await?;
+    Ok(amounts
+        .into_iter()
+        .zip(next_index..)
+        .map(|(amounts, index)| IndexedDeposit {
+            user,
+            index,
+            amounts,
+        })
+        .collect())
+}
+
+/// Credits `user`'s confirmed deposits on `chain` right away instead of waiting for the indexer
+/// to reach them. Returns how many were credited, nothing is submitted if there are no new deposits.
+pub async fn gulp_user<C: ChainClient>(
+    sequencer: &SequencerHandle,
+    chain: &Chain<C>,
+    config: &IndexerConfig,
+    user: Address,
+) -> Result<usize, MwError> {
+    let chain_id = chain.spec.chain_id;
+    let client = chain.client.as_ref();
+    let snapshot = sequencer.snapshot();
+    let deposit_contract = snapshot.deposit_contract(chain_id);
+    if deposit_contract.is_zero() {
+        return Ok(0);
+    }
+    let head = client.block_number().await?;
+    let Some(confirmed) = head.checked_sub(config.confirmations) else {
+        return Ok(0);
+    };
+    let next_index = snapshot
+        .inventories
+        .get(&user)
+        .map_or(0, |inventory| inventory.deposit_nonce(chain_id));
+    let deposits =
+        fetch_user_deposits(client, deposit_contract, user, next_index, confirmed).await?;
+    verifier::verify_deposits(chain, deposit_contract, confirmed, &deposits).await?;
+    if deposits.is_empty() {
+        return Ok(0);
+    }
+    let applied = sequencer
+        .submit(Command::CreditDeposits {
+            chain_id,
+            user,
+            deposits,
+        })
+        .await?;
+    let CommandOutput::DepositsIndexed(credited) = applied.output else {
+        unreachable!()
+    };
+    Ok(credited)
+}
+
+/// Credits each deposit on `chain_id` whose index is the user's next one there, skipping any
+/// already credited. Nothing is credited if a user's deposits skip an index.
+pub fn credit_indexed_deposits(
+    warehouse: &mut Warehouse,
+    chain_id: u64,
+    deposits: &[IndexedDeposit],
+) -> Result<usize, MwError> {
+    // check before crediting anything so a gap leaves the warehouse untouched
+    let mut next_index: HashMap<Address, u32> = HashMap::new();
+    for deposit in deposits {
+        let expected = next_index.entry(deposit.user).or_insert_with(|| {
+            warehouse
+                .inventories
+                .get(&deposit.user)
+                .map_or(0, |inventory| inventory.deposit_nonce(chain_id))
+        });
+        if deposit.index > *expected {
+            return Err(MwError::GulpError(format!(
+                "missing deposit {} for {}",
+                expected, deposit.user
+            )));
+        }
+        if deposit.index == *expected {
+            *expected += 1;
+        }
+    }
+
+    let mut credited = 0;
+    for deposit in deposits {
+        let inventory = warehouse
+            .inventories
+            .entry(deposit.user)
+            .or_insert(Inventory::default());
+        inventory.address = deposit.user;
+        let deposit_nonce = inventory.deposit_nonces.entry(chain_id).or_default();
+        if deposit.index != *deposit_nonce {
+            continue; // already credited
+        }
+        *deposit_nonce += 1;
+        inventory.eth_balance.add_assign(Qty(deposit.amounts[0]));
+        inventory.usdc_balance.add_assign(Qty(deposit.amounts[1]));
+        warehouse.credit_chain_deposits(chain_id, deposit.amounts);
+        credited += 1;
+    }
+    Ok(credited)
+}
+
+/// Credits deposits indexed from `from_block..=to_block` on `chain_id` and moves that chain's
+/// cursor past them. Nothing is credited if the range doesn't start at the cursor or a user's
+/// deposits skip an index.
+pub fn index_deposits(
+    warehouse: &mut Warehouse,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    deposits: &[IndexedDeposit],
+) -> Result<usize, MwError> {
+    if warehouse
+        .deposit_cursor(chain_id)
+        .is_some_and(|cursor| cursor != from_block)
+    {
+        return Err(MwError::GulpError("deposit cursor moved".to_string()));
+    }
+    let credited = credit_indexed_deposits(warehouse, chain_id, deposits)?;
+    warehouse.deposit_cursors.insert(chain_id, to_block + 1);
+    Ok(credited)
+}
 
+/// Credits deposits fetched at `deposit_nonce`. Rejects the credit if the user's nonce moved
+/// since the fetch so the same deposits can't be credited twice.
+/// Only for replaying Event::Deposit, it bumps the nonce by one however many deposits were summed
+/// into `new_deposits`, which is why it was replaced by credit_indexed_deposits.
+pub fn credit_deposits(
+    warehouse: &mut Warehouse,
+    user: Address,
+    deposit_nonce: u32,
+    new_deposits: [U256; 2],
+) -> Result<(), MwError> {
     // Get user inventory
     let inventory = warehouse
         .inventories
         .entry(user)
         .or_insert(Inventory::default());
-
-    // Call the get_deposits function
-    let deposits: Vec<[U256; 2]> = deposit_registry_contract
-        .get_deposits(inventory.deposit_nonce.clone(), user.clone())
-        .call()
-        .await?
-        ._0;
-
-    let mut new_deposits: [U256; 2] = [U256::ZERO, U256::ZERO];
-    for deposit in deposits {
-        new_deposits[0] += deposit[0];
-        new_deposits[1] += deposit[1];
+    inventory.address = user;
+    if inventory.deposit_nonce(MAINNET_CHAIN_ID) != deposit_nonce {
+        return Err(MwError::GulpError("deposit nonce changed".to_string()));
     }
-    inventory.deposit_nonce += 1;
+
+    inventory
+        .deposit_nonces
+        .insert(MAINNET_CHAIN_ID, deposit_nonce + 1);
 
     inventory
         .eth_balance
@@ -75,7 +428,22 @@ pub async fn gulp_deposits(
     inventory
         .usdc_balance
         .add_assign(Qty(new_deposits[1].clone()));
+    warehouse.credit_chain_deposits(MAINNET_CHAIN_ID, new_deposits);
+
+    Ok(())
+}
 
-    warehouse.store(); // maybe don't store here?
-    Ok(new_deposits)
+/// Runs the indexer until the process exits.
+pub fn spawn<C: ChainClient + 'static>(
+    indexer: Arc<DepositIndexer>,
+    sequencer: SequencerHandle,
+    chains: Arc<Chains<C>>,
+    config: IndexerConfig,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(config.poll_interval).await;
+            let _ = indexer.run_once(&sequencer, &chains, &config).await;
+        }
+    });
 }
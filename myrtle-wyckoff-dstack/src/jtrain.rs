@@ -4,84 +4,74 @@
 // remain running until the next checkpoint is ready, at which point it should save the new state and exit.
 // not entirely sure how to implement.
 
-use alloy::{
-    network::{Ethereum, EthereumWallet},
-    providers::{ProviderBuilder, RootProvider},
-    rpc::client::ClientBuilder,
-    transports::http::{reqwest::Url, Client, Http},
-};
+use alloy::primitives::Address;
 use optimized_lob::orderbook_manager::OrderBookManager;
-use std::sync::Arc;
+use tracing::info;
 
-use crate::warehouse::Warehouse;
+use crate::{
+    clock::now_ms,
+    config::Config,
+    errors::MwError,
+    keys::{self, KeySource},
+    recovery,
+    rpc::ChainClient,
+    session::{SessionController, SessionSchedule, SessionState},
+    warehouse::Warehouse,
+};
 
 pub struct Jtrain {
-    pub warehouse: Warehouse,                //TODO: make this thread safe
-    pub orderbook_manager: OrderBookManager, //TODO: make this thread safe
-    pub provider: Arc<
-        alloy::providers::fillers::FillProvider<
-            alloy::providers::fillers::JoinFill<
-                alloy::providers::fillers::JoinFill<
-                    alloy::providers::Identity,
-                    alloy::providers::fillers::JoinFill<
-                        alloy::providers::fillers::GasFiller,
-                        alloy::providers::fillers::JoinFill<
-                            alloy::providers::fillers::BlobGasFiller,
-                            alloy::providers::fillers::JoinFill<
-                                alloy::providers::fillers::NonceFiller,
-                                alloy::providers::fillers::ChainIdFiller,
-                            >,
-                        >,
-                    >,
-                >,
-                alloy::providers::fillers::WalletFiller<EthereumWallet>,
-            >,
-            RootProvider<Http<Client>>,
-            Http<Client>,
-            Ethereum,
-        >,
-    >,
+    pub warehouse: Warehouse, // only ever touched by the sequencer
+    pub orderbook_manager: OrderBookManager, // only ever touched by the sequencer
+    pub session: SessionController,
 }
 
 impl Jtrain {
-    pub async fn new(http: Url) -> Self {
-        let warehouse = Warehouse::load().await;
+    /// Loads state from the configured volume with keys from the configured provider. The config's
+    /// `recover_from` is left to the caller since recovering needs a client for the checkpoint
+    /// chain, see `recover`.
+    pub async fn new(config: &Config) -> Self {
+        let key_source = KeySource::new(&config.key_provider, &config.dstack_key_url);
+        let keys = keys::load_keys(&key_source)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        let warehouse = Warehouse::load(config, &keys).unwrap_or_else(|e| panic!("{}", e));
         let orderbook_manager = OrderBookManager::new();
-        let client = ClientBuilder::default().http(http); //TODO: revisit this when testing
-
-        let wallet = EthereumWallet::from(warehouse.signer.clone());
-        let provider: Arc<
-            alloy::providers::fillers::FillProvider<
-                alloy::providers::fillers::JoinFill<
-                    alloy::providers::fillers::JoinFill<
-                        alloy::providers::Identity,
-                        alloy::providers::fillers::JoinFill<
-                            alloy::providers::fillers::GasFiller,
-                            alloy::providers::fillers::JoinFill<
-                                alloy::providers::fillers::BlobGasFiller,
-                                alloy::providers::fillers::JoinFill<
-                                    alloy::providers::fillers::NonceFiller,
-                                    alloy::providers::fillers::ChainIdFiller,
-                                >,
-                            >,
-                        >,
-                    >,
-                    alloy::providers::fillers::WalletFiller<EthereumWallet>,
-                >,
-                RootProvider<Http<Client>>,
-                Http<Client>,
-                Ethereum,
-            >,
-        > = Arc::new(
-            ProviderBuilder::new()
-                .with_recommended_fillers()
-                .wallet(wallet)
-                .on_client(client),
+        let session_state = SessionState::load(&config.storage)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .unwrap_or(SessionState::starting(now_ms()));
+        // the session id is stored once, with the session's pnl
+        let session = SessionController::resume(
+            SessionSchedule::default(),
+            warehouse.pnl.session_id,
+            session_state,
         );
         Self {
             warehouse,
             orderbook_manager,
-            provider,
+            session,
         }
     }
+
+    /// Replaces the loaded state with the latest checkpoint on `checkpoint_contract` and stores it.
+    pub async fn recover<C: ChainClient>(
+        &mut self,
+        client: &C,
+        checkpoint_contract: Address,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let checkpoint_nonce =
+            recovery::recover(&mut self.warehouse, client, checkpoint_contract).await?;
+        info!(
+            "recovered {} inventories, next checkpoint nonce {}",
+            self.warehouse.inventories.len(),
+            checkpoint_nonce
+        );
+        self.store()?;
+        Ok(())
+    }
+
+    /// Writes the warehouse and the session to the data dir.
+    pub fn store(&self) -> Result<(), MwError> {
+        self.warehouse.store()?;
+        self.session.state().store(&self.warehouse.storage)
+    }
 }
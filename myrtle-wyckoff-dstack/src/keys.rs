@@ -0,0 +1,249 @@
+// Overview:
+// Where the dstack shared secret comes from and the keys the app derives from it.
+// * a KeyProvider hands over the raw secret: the dstack guest agent (the default), a dev key from a
+//   file or an env var for local runs, or a fixed test key
+// * the provider is picked with KEY_PROVIDER or the config's key_provider, "dstack" (default),
+//   "file:<path>" or "env:<var>" (see config.rs)
+// * the secret is hex, 0x optional, and has to be at least 32 bytes, anything else is refused
+//   instead of being used as is
+// * the secret itself is never used as a key, HKDF-SHA256 derives separate ones for signing
+//   (the app's address, the contracts' admin), checkpoint encryption (see blob.rs), volume
+//   encryption (see warehouse.rs) and the replication channel (see replication.rs), so one leaking
+//   doesn't give away the others
+// * versions before the split signed with the secret itself and keyed checkpoints off it with an
+//   unsalted HKDF, so the app's address changed with it: the admin on deployed contracts has to be
+//   handed over with set_admin (see README) and blobs under the old checkpoint key stay readable
+//   through legacy_checkpoint, only for recovering from a checkpoint posted before the upgrade
+// * every container of the app gets the same secret so they all derive the same keys, which is
+//   what lets a new container recover from a checkpoint (see recovery.rs)
+
+use std::{future::Future, path::PathBuf, str::FromStr};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use alloy::{primitives::B256, signers::local::PrivateKeySigner, transports::http::reqwest::Url};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::errors::MwError;
+
+pub const MIN_SECRET_BYTES: usize = 32;
+const HKDF_SALT: &[u8] = b"myrtle-wyckoff";
+const SIGNING_KEY_INFO: &[u8] = b"signing";
+const CHECKPOINT_KEY_INFO: &[u8] = b"checkpoint-encryption";
+const VOLUME_KEY_INFO: &[u8] = b"volume-encryption";
+const REPLICATION_KEY_INFO: &[u8] = b"replication";
+const LEGACY_CHECKPOINT_KEY_INFO: &[u8] = b"aes-key";
+const SEAL_NONCE_BYTES: usize = 12;
+
+fn key_error(message: impl Into<String>) -> MwError {
+    MwError::KeyError(message.into())
+}
+
+/// Somewhere the shared secret can be read from.
+pub trait KeyProvider {
+    fn shared_secret(&self) -> impl Future<Output = Result<Vec<u8>, MwError>> + Send;
+}
+
+/// Parses a hex secret, with or without 0x, and checks it's long enough to derive keys from.
+pub fn parse_secret(secret: &str) -> Result<Vec<u8>, MwError> {
+    let secret = secret.trim();
+    let secret = alloy::hex::decode(secret).map_err(|_| key_error("secret isn't hex"))?;
+    if secret.len() < MIN_SECRET_BYTES {
+        return Err(key_error(format!(
+            "secret is {} bytes, at least {} are needed",
+            secret.len(),
+            MIN_SECRET_BYTES
+        )));
+    }
+    Ok(secret)
+}
+
+/// The dstack guest agent, which answers with this app's secret as a JSON string.
+pub struct DstackKeyProvider {
+    pub key_url: Url,
+}
+impl KeyProvider for DstackKeyProvider {
+    async fn shared_secret(&self) -> Result<Vec<u8>, MwError> {
+        let response = reqwest::get(self.key_url.clone())
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| key_error(format!("dstack guest agent: {}", e)))?;
+        let secret: String = response
+            .json()
+            .await
+            .map_err(|e| key_error(format!("dstack guest agent: {}", e)))?;
+        parse_secret(&secret)
+    }
+}
+
+/// A key kept outside the enclave, only for running the app locally.
+pub enum DevKeyProvider {
+    File(PathBuf),
+    Env(String), // name of the variable
+}
+impl KeyProvider for DevKeyProvider {
+    async fn shared_secret(&self) -> Result<Vec<u8>, MwError> {
+        match self {
+            DevKeyProvider::File(path) => {
+                let secret = std::fs::read_to_string(path)
+                    .map_err(|e| key_error(format!("{}: {}", path.display(), e)))?;
+                parse_secret(&secret)
+            }
+            DevKeyProvider::Env(var) => {
+                let secret =
+                    std::env::var(var).map_err(|e| key_error(format!("{}: {}", var, e)))?;
+                parse_secret(&secret)
+            }
+        }
+    }
+}
+
+/// A fixed secret, for tests.
+pub struct TestKeyProvider {
+    pub secret: Vec<u8>,
+}
+impl TestKeyProvider {
+    /// A different secret for every `seed`.
+    pub fn new(seed: u8) -> Self {
+        TestKeyProvider {
+            secret: vec![seed; MIN_SECRET_BYTES],
+        }
+    }
+}
+impl KeyProvider for TestKeyProvider {
+    async fn shared_secret(&self) -> Result<Vec<u8>, MwError> {
+        Ok(self.secret.clone())
+    }
+}
+
+/// Which provider to read the secret from, parsed from KEY_PROVIDER.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyProviderConfig {
+    Dstack,
+    File(PathBuf),
+    Env(String),
+}
+impl FromStr for KeyProviderConfig {
+    type Err = MwError;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        match config.split_once(':') {
+            None if config == "dstack" => Ok(KeyProviderConfig::Dstack),
+            Some(("file", path)) if !path.is_empty() => {
+                Ok(KeyProviderConfig::File(PathBuf::from(path)))
+            }
+            Some(("env", var)) if !var.is_empty() => Ok(KeyProviderConfig::Env(var.to_string())),
+            _ => Err(key_error(format!("unknown key provider {}", config))),
+        }
+    }
+}
+
+/// The configured provider, dispatches to the one picked at startup.
+pub enum KeySource {
+    Dstack(DstackKeyProvider),
+    Dev(DevKeyProvider),
+}
+impl KeySource {
+    pub fn new(config: &KeyProviderConfig, dstack_key_url: &Url) -> Self {
+        match config {
+            KeyProviderConfig::Dstack => KeySource::Dstack(DstackKeyProvider {
+                key_url: dstack_key_url.clone(),
+            }),
+            KeyProviderConfig::File(path) => KeySource::Dev(DevKeyProvider::File(path.clone())),
+            KeyProviderConfig::Env(var) => KeySource::Dev(DevKeyProvider::Env(var.clone())),
+        }
+    }
+}
+impl KeyProvider for KeySource {
+    async fn shared_secret(&self) -> Result<Vec<u8>, MwError> {
+        match self {
+            KeySource::Dstack(provider) => provider.shared_secret().await,
+            KeySource::Dev(provider) => provider.shared_secret().await,
+        }
+    }
+}
+
+/// Everything derived from the shared secret.
+#[derive(Clone)]
+pub struct AppKeys {
+    pub signer: PrivateKeySigner,
+    pub checkpoint: Key<Aes256Gcm>, // inventory blobs posted in checkpoints
+    pub volume: Key<Aes256Gcm>,     // state written to the data dir
+    pub replication: Key<Aes256Gcm>, // the leader's event stream to replicas
+    pub legacy_checkpoint: Key<Aes256Gcm>, // only decrypts blobs from before the HKDF split
+}
+impl AppKeys {
+    pub fn derive(secret: &[u8]) -> Result<Self, MwError> {
+        if secret.len() < MIN_SECRET_BYTES {
+            return Err(key_error("secret is too short"));
+        }
+        let hkdf = Hkdf::<Sha256>::new(Some(HKDF_SALT), secret);
+        let expand = |info: &[u8]| {
+            let mut key = [0u8; 32];
+            hkdf.expand(info, &mut key)
+                .map_err(|e| key_error(e.to_string()))?;
+            Ok::<[u8; 32], MwError>(key)
+        };
+        let signer = PrivateKeySigner::from_bytes(&B256::from(expand(SIGNING_KEY_INFO)?))
+            .map_err(|e| key_error(format!("signing key: {}", e)))?;
+        Ok(AppKeys {
+            signer,
+            checkpoint: *Key::<Aes256Gcm>::from_slice(&expand(CHECKPOINT_KEY_INFO)?),
+            volume: *Key::<Aes256Gcm>::from_slice(&expand(VOLUME_KEY_INFO)?),
+            replication: *Key::<Aes256Gcm>::from_slice(&expand(REPLICATION_KEY_INFO)?),
+            legacy_checkpoint: legacy_checkpoint_key(secret)?,
+        })
+    }
+}
+
+// older versions ran HKDF over the secret as the guest agent serves it, lowercase hex without 0x
+fn legacy_checkpoint_key(secret: &[u8]) -> Result<Key<Aes256Gcm>, MwError> {
+    let served = alloy::hex::encode(secret);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, served.as_bytes())
+        .expand(LEGACY_CHECKPOINT_KEY_INFO, &mut key)
+        .map_err(|e| key_error(e.to_string()))?;
+    Ok(*Key::<Aes256Gcm>::from_slice(&key))
+}
+
+/// Reads the secret from `provider` and derives the app's keys from it.
+pub async fn load_keys<P: KeyProvider>(provider: &P) -> Result<AppKeys, MwError> {
+    AppKeys::derive(&provider.shared_secret().await?)
+}
+
+/// Encrypts `plaintext` with AES-256-GCM under a fresh nonce, `label` is authenticated along with
+/// it so a sealed file can't be passed off as another.
+pub fn seal(key: &Key<Aes256Gcm>, label: &str, plaintext: &[u8]) -> Result<Vec<u8>, MwError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: label.as_bytes(),
+            },
+        )
+        .map_err(|_| MwError::EncryptionError)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn unseal(key: &Key<Aes256Gcm>, label: &str, sealed: &[u8]) -> Result<Vec<u8>, MwError> {
+    if sealed.len() < SEAL_NONCE_BYTES {
+        return Err(MwError::DecryptionError);
+    }
+    let (nonce, ciphertext) = sealed.split_at(SEAL_NONCE_BYTES);
+    Aes256Gcm::new(key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: label.as_bytes(),
+            },
+        )
+        .map_err(|_| MwError::DecryptionError)
+}
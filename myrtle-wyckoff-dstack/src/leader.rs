@@ -0,0 +1,265 @@
+// Overview:
+// Leader election among the app's dstack containers. They all derive the same signer from the
+// shared secret (see keys.rs), so that signer alone can't tell which of them is posting.
+// * every container generates its own leader key on startup, kept in memory only
+//   (Warehouse::leader) and served at /leader
+// * the Checkpointer and each deposit registry store an elected leader, which only the admin (the
+//   shared signer) can set, and want checkpoints and settlement approvals signed by both
+// * a container runs as the leader or a replica, picked with INSTANCE_ROLE or the config's role,
+//   "leader" by default so a single container works as it always did
+// * the Checkpointer's leader holds a lease its checkpoints renew (LEADER_LEASE_SECS), another key
+//   can only be elected once it runs out, so two live containers can't take turns claiming it
+// * a campaign keeps each container's role in line with the Checkpointer. A leader that hasn't won
+//   the lease yet, on startup or after a promotion, steps down and claims until it does, then
+//   serves again. A leader that finds another key elected steps down for good, its state has
+//   moved on without it
+// * deposit registries are only claimed once the Checkpointer is held. They keep accepting the
+//   previous leader's approvals for a while after a new one is elected, so settlement orders it
+//   posted before a failover can still be pulled. Orders it hadn't posted yet are in the log the
+//   replicas follow, so the new leader posts them
+// * replicas don't sequence anything themselves, they follow the leader's event stream and post no
+//   checkpoints until promoted, at which point they claim the contracts (see replication.rs)
+// * the role lives with the sequencer so it changes in the same step as promotion or stepping down
+// * a restarted leader has a new key and waits out its old key's lease before serving again
+
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, TxHash},
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+};
+use tracing::{info, warn};
+
+use crate::{
+    artifacts::ICheckpointer,
+    chains::Chains,
+    errors::MwError,
+    rpc::ChainClient,
+    sequencer::{Command, SequencerHandle, StateSnapshot},
+    sink::{self, TxPolicy},
+};
+
+/// LEADER_LEASE in Checkpointer.sol.
+pub const LEADER_LEASE_SECS: u64 = 600;
+pub const DEFAULT_CAMPAIGN_INTERVAL_MS: u64 = 30_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceRole {
+    Leader,
+    Replica,
+}
+impl FromStr for InstanceRole {
+    type Err = MwError;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "leader" => Ok(InstanceRole::Leader),
+            "replica" => Ok(InstanceRole::Replica),
+            _ => Err(MwError::InvalidConfig(format!("unknown role {}", role))),
+        }
+    }
+}
+impl fmt::Display for InstanceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceRole::Leader => write!(f, "leader"),
+            InstanceRole::Replica => write!(f, "replica"),
+        }
+    }
+}
+
+/// This container's role and leader key.
+#[derive(Clone, Debug)]
+pub struct Leadership {
+    pub role: InstanceRole,
+    pub address: Address, // of the leader key
+}
+impl Leadership {
+    /// This container's role and leader key as of `snapshot`.
+    pub fn from_snapshot(snapshot: &StateSnapshot) -> Self {
+        Leadership {
+            role: snapshot.role,
+            address: snapshot.leader_address,
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == InstanceRole::Leader
+    }
+
+    /// Refuses `action` on a replica.
+    pub fn require_leader(&self, action: &str) -> Result<(), MwError> {
+        match self.role {
+            InstanceRole::Leader => Ok(()),
+            InstanceRole::Replica => Err(MwError::NotLeader {
+                action: action.to_string(),
+            }),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let serializable_leadership = serde_json::json!({
+            "address": self.address.to_string(),
+            "role": self.role.to_string(),
+        });
+        serde_json::to_string(&serializable_leadership).unwrap()
+    }
+}
+
+/// Elects `leader` on `contract`, sent from the admin. Nothing is sent if it's already the leader
+/// or the contract isn't set. On the Checkpointer it reverts while another leader's lease runs.
+pub async fn claim<C: ChainClient>(
+    client: &C,
+    contract: Address,
+    admin: Address,
+    leader: Address,
+    policy: &TxPolicy,
+) -> Result<Option<TxHash>, MwError> {
+    if contract.is_zero() || client.leader(contract).await? == leader {
+        return Ok(None);
+    }
+    // the registry's set_leader has the same signature
+    let tx = TransactionRequest::default()
+        .with_to(contract)
+        .with_input(ICheckpointer::set_leaderCall { new_leader: leader }.abi_encode());
+    sink::send_with_retries(client, admin, tx, policy)
+        .await
+        .map(Some)
+}
+
+/// Claims the Checkpointer and every deposit registry set in `snapshot` for this container.
+pub async fn claim_contracts<C: ChainClient>(
+    snapshot: &StateSnapshot,
+    chains: &Chains<C>,
+    client: &C,
+    policy: &TxPolicy,
+) -> Result<(), MwError> {
+    claim(
+        client,
+        snapshot.checkpoint_contract,
+        snapshot.signer_address,
+        snapshot.leader_address,
+        policy,
+    )
+    .await?;
+    for chain in chains.iter() {
+        claim(
+            chain.client.as_ref(),
+            snapshot.deposit_contract(chain.spec.chain_id),
+            snapshot.signer_address,
+            snapshot.leader_address,
+            policy,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Whether this container's key is the one elected on the Checkpointer, always true until one is
+/// set.
+pub async fn holds_lease<C: ChainClient>(
+    client: &C,
+    snapshot: &StateSnapshot,
+) -> Result<bool, MwError> {
+    if snapshot.checkpoint_contract.is_zero() {
+        return Ok(true);
+    }
+    Ok(client.leader(snapshot.checkpoint_contract).await? == snapshot.leader_address)
+}
+
+/// Keeps this container's role in line with who's elected on the Checkpointer, see the overview.
+#[derive(Clone, Debug, Default)]
+pub struct Campaign {
+    candidate: bool, // a replica that claims the contracts and takes over once it wins
+    elected: bool,   // held the lease since it last became the leader
+}
+impl Campaign {
+    /// A campaign for a container started as `role`, only a leader stands.
+    pub fn new(role: InstanceRole) -> Self {
+        Campaign {
+            candidate: role == InstanceRole::Leader,
+            elected: false,
+        }
+    }
+
+    pub fn is_candidate(&self) -> bool {
+        self.candidate
+    }
+
+    /// One round of the campaign, returns the container's role after it.
+    pub async fn run_once<C: ChainClient>(
+        &mut self,
+        sequencer: &SequencerHandle,
+        chains: &Chains<C>,
+        client: &C,
+        policy: &TxPolicy,
+    ) -> Result<InstanceRole, MwError> {
+        let snapshot = sequencer.snapshot();
+        let holds = holds_lease(client, &snapshot).await?;
+        match snapshot.role {
+            InstanceRole::Leader if holds => {
+                self.elected = true;
+                self.candidate = false;
+                // registries set since, or elected on before the Checkpointer was won
+                claim_contracts(&snapshot, chains, client, policy).await?;
+            }
+            InstanceRole::Leader if self.elected => {
+                warn!("another key was elected on the Checkpointer, stepping down");
+                self.elected = false;
+                sequencer.submit(Command::StepDown).await?;
+            }
+            InstanceRole::Leader => {
+                match claim_contracts(&snapshot, chains, client, policy).await {
+                    Ok(()) => {
+                        self.elected = true;
+                        self.candidate = false;
+                    }
+                    Err(e) => {
+                        warn!("not elected, stepping down until the lease is won: {}", e);
+                        self.candidate = true;
+                        sequencer.submit(Command::StepDown).await?;
+                    }
+                }
+            }
+            InstanceRole::Replica if self.candidate => {
+                if claim_contracts(&snapshot, chains, client, policy)
+                    .await
+                    .is_ok()
+                {
+                    info!("won the Checkpointer's lease, taking over");
+                    sequencer
+                        .submit(Command::Promote { expected: None })
+                        .await?;
+                    self.elected = true;
+                    self.candidate = false;
+                }
+            }
+            InstanceRole::Replica => {}
+        }
+        Ok(sequencer.snapshot().role)
+    }
+}
+
+/// Runs the campaign every `interval` until the process exits.
+pub fn spawn<C: ChainClient + 'static>(
+    mut campaign: Campaign,
+    sequencer: SequencerHandle,
+    chains: Arc<Chains<C>>,
+    client: Arc<C>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let policy = TxPolicy::default();
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = campaign
+                .run_once(&sequencer, &chains, client.as_ref(), &policy)
+                .await
+            {
+                warn!("campaign round failed: {}", e);
+            }
+        }
+    });
+}
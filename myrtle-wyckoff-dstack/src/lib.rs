@@ -1,13 +1,35 @@
 pub mod artifacts;
+pub mod attestation;
+pub mod batch;
+pub mod blob;
+pub mod chains;
+pub mod clock;
+pub mod closer;
+pub mod config;
 pub mod constants;
 pub mod cowswap;
 pub mod domains;
 pub mod errors;
+pub mod events;
+pub mod fakechain;
 pub mod gulper;
 pub mod jtrain;
+pub mod keys;
+pub mod leader;
 pub mod matchmaker;
+pub mod merkle;
+pub mod oracle;
 pub mod orderhere;
+pub mod pnl;
+pub mod recovery;
+pub mod replication;
+pub mod rpc;
+pub mod scheduler;
+pub mod sequencer;
+pub mod session;
 pub mod settler;
+pub mod sink;
 pub mod snapshotter;
 pub mod structs;
+pub mod verifier;
 pub mod warehouse;
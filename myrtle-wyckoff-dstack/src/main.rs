@@ -1,31 +1,84 @@
-use std::{env, str::FromStr, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
-use alloy::{primitives::Address, signers::Signature, transports::http::reqwest::Url};
+use aes_gcm::{Aes256Gcm, Key};
+use alloy::{
+    primitives::{Address, B256, U256},
+    signers::Signature,
+};
 use myrtle_wyckoff_dstack::{
     artifacts::IDepositRegistry,
-    errors::MwError,
-    gulper,
+    attestation::{self, GuestAgent},
+    batch::{self, OrderBatch},
+    chains::{Chains, MAINNET_CHAIN_ID},
+    clock::now_ms,
+    config::Config,
+    domains::{self, Domains},
+    errors::{self, MwError},
+    events::EVENT_LOG_STORAGE_FILE,
+    gulper::{self, DepositIndexer, IndexerConfig},
     jtrain::Jtrain,
-    orderhere::{self, CancelOrder, Order},
-    settler::create_settlement_order,
-    snapshotter,
+    leader::{self, Campaign, InstanceRole, Leadership},
+    oracle::{
+        CowSwapQuoter, ListedAsset, PriceOracle, DEFAULT_MAX_AGE_MS, DEFAULT_MAX_STALENESS_MS,
+    },
+    orderhere::{CancelAll, CancelOrder, Order},
+    pnl,
+    replication::{self, Follower, RecordFeed},
+    rpc::{AlloyClient, ChainClient},
+    scheduler::{self, SnapshotTracker},
+    sequencer::{self, Command, CommandOutput, SequencerHandle, StateSnapshot},
+    session::{self, SessionAction},
+    settler,
+    sink::{Sink, TxPolicy},
     structs::UserRequest,
 };
 use optimized_lob::order::OrderId;
 use rocket::{
-    catch, catchers, delete, get, http::Status, launch, post, put, response::Redirect, routes,
-    serde::json::Json, tokio::sync::RwLock, Request, State,
+    catch, catchers, delete, get,
+    http::Status,
+    launch, post, put,
+    response::{
+        content::RawJson,
+        stream::{Event, EventStream},
+        Redirect,
+    },
+    routes,
+    serde::json::Json,
+    State,
 };
+use tracing::warn;
 
+// no lock here, writes go through the sequencer and reads come from its published snapshots
 struct AppState {
-    jtrain: Jtrain,
+    sequencer: SequencerHandle,
+    chains: Arc<Chains>,
+    snapshots: Arc<SnapshotTracker>,
+    sink: Arc<Sink>,
+    deposit_indexer: Arc<DepositIndexer>,
+    indexer_config: IndexerConfig,
+    client: Arc<AlloyClient>, // checkpoint chain
+    domains: Domains,
+    weth: ListedAsset, // as configured on the chain marks are quoted on
+    guest_agent: GuestAgent,
+    config: Config, // what /attestation commits to
+    replication_key: Key<Aes256Gcm>,
+    follower: Option<Follower>, // the leader this replica follows, if any
+}
+impl AppState {
+    fn leadership(&self) -> Leadership {
+        Leadership::from_snapshot(&self.sequencer.snapshot())
+    }
 }
 
-type SharedState = Arc<RwLock<AppState>>;
+type SharedState = Arc<AppState>;
+type SharedOracle = Arc<PriceOracle<CowSwapQuoter>>;
 
+const LEADERBOARD_SIZE: usize = 10;
+
+// rocket's own errors, shaped like MwError's, see errors.rs
 #[catch(default)]
-fn default_catcher(status: Status, request: &Request) -> String {
-    format!("ERROR: {} - {:?}", status.code, status.reason())
+fn default_catcher(status: Status) -> RawJson<String> {
+    RawJson(errors::status_json(status))
 }
 
 #[get("/")]
@@ -45,51 +98,256 @@ fn health() -> &'static str {
 
 #[get("/public-key")]
 async fn get_public_key(state: &State<SharedState>) -> String {
-    let jtrain = &state.read().await.jtrain;
-    jtrain.warehouse.signer.address().to_string()
+    state.sequencer.snapshot().signer_address.to_string()
+}
+
+/// A TDX quote binding the enclave to the app's signer and config, see attestation.rs.
+#[get("/attestation")]
+async fn get_attestation(state: &State<SharedState>) -> Result<String, MwError> {
+    let signer = state.sequencer.snapshot().signer_address;
+    Ok(
+        attestation::attest(&state.guest_agent, signer, &state.config)
+            .await?
+            .to_json(),
+    )
+}
+
+/// This container's leader key and whether it runs as the leader.
+#[get("/leader")]
+fn get_leader(state: &State<SharedState>) -> String {
+    state.leadership().to_json()
+}
+
+/// Elects this container's leader key on every contract set, only on the leader.
+#[post("/claim-leadership")]
+async fn claim_leadership(state: &State<SharedState>) -> Result<String, MwError> {
+    state.leadership().require_leader("claim_leadership")?;
+    leader::claim_contracts(
+        &state.sequencer.snapshot(),
+        &state.chains,
+        state.client.as_ref(),
+        &TxPolicy::default(),
+    )
+    .await?;
+    Ok(state.leadership().to_json())
+}
+
+/// The event log after `after`, then every record as it's sequenced, for replicas. See
+/// replication.rs.
+#[get("/replication/<after>?<token>")]
+fn replication_stream(
+    state: &State<SharedState>,
+    after: u64,
+    token: String,
+) -> Result<EventStream![], MwError> {
+    replication::check_request_token(&state.replication_key, &token, after, now_ms())?;
+    state.leadership().require_leader("replication")?;
+    let mut feed = RecordFeed::open(
+        &state.sequencer,
+        state.config.storage.data_path(EVENT_LOG_STORAGE_FILE),
+        state.replication_key,
+        after,
+    )?;
+    Ok(EventStream! {
+        while let Some(frame) = feed.next().await {
+            yield Event::data(frame);
+        }
+    })
+}
+
+/// Makes this replica the leader and claims the contracts for it, on a request signed by the
+/// operator. Its state has to match `seq` and `state_hash` if given, otherwise the old leader's
+/// /sequence if it still answers.
+#[post("/promote/<signature>?<seq>&<state_hash>", data = "<request>")]
+async fn promote(
+    state: &State<SharedState>,
+    signature: String,
+    seq: Option<u64>,
+    state_hash: Option<String>,
+    request: Json<UserRequest>,
+) -> Result<String, MwError> {
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    request.validate_signature(&state.domains.dstack, signature, request.user)?;
+    request.validate_timestamp()?;
+    request.validate_request_type("promote")?;
+    session::authorize_operator(state.config.operator, request.user)?;
+    let expected = match (seq, state_hash) {
+        (Some(seq), Some(state_hash)) => {
+            Some((seq, errors::parse_param::<B256>("state_hash", &state_hash)?))
+        }
+        (None, None) => match &state.follower {
+            Some(follower) => match follower.leader_sequence().await {
+                Ok(sequence) => Some(sequence),
+                Err(e) => {
+                    warn!("promoting on the replica's own state hash: {}", e);
+                    None
+                }
+            },
+            None => None,
+        },
+        _ => {
+            return Err(MwError::ReplicationError(
+                "seq and state_hash go together".to_string(),
+            ))
+        }
+    };
+    state
+        .sequencer
+        .submit(Command::Promote { expected })
+        .await?;
+    if let Err(e) = leader::claim_contracts(
+        &state.sequencer.snapshot(),
+        &state.chains,
+        state.client.as_ref(),
+        &TxPolicy::default(),
+    )
+    .await
+    {
+        // the campaign steps it down until the old leader's lease runs out, then claims again
+        warn!("failed to claim leadership: {}", e);
+    }
+    Ok(state.leadership().to_json())
 }
 
-#[put("/contract-addresses/<deposit_registry_address>/<checkpointer_address>")]
+#[get("/sequence")]
+async fn get_sequence(state: &State<SharedState>) -> String {
+    let snapshot = state.sequencer.snapshot();
+    serde_json::to_string(&serde_json::json!({
+        "seq": snapshot.seq.to_string(),
+        "state_hash": snapshot.state_hash.to_string(),
+    }))
+    .unwrap()
+}
+
+/// Sets the deposit registry on `chain_id`, mainnet if not given, and the checkpointer.
+/// Contracts that already have a domain separator must match the domains the app signs with.
+#[put("/contract-addresses/<deposit_registry_address>/<checkpointer_address>?<chain_id>")]
 async fn set_contract_addresses(
     state: &State<SharedState>,
     deposit_registry_address: String,
     checkpointer_address: String,
-) -> String {
-    let mut guard = state.write().await;
+    chain_id: Option<u64>,
+) -> Result<String, MwError> {
+    let chain = state.chains.get(chain_id.unwrap_or(MAINNET_CHAIN_ID))?;
+    let deposit_contract =
+        errors::parse_param("deposit_registry_address", &deposit_registry_address)?;
+    let checkpoint_contract = errors::parse_param("checkpointer_address", &checkpointer_address)?;
+    domains::verify_deposit_registry(chain, deposit_contract).await?;
+    domains::verify_checkpointer(state.client.as_ref(), &state.domains, checkpoint_contract)
+        .await?;
+    state
+        .sequencer
+        .submit(Command::SetContractAddresses {
+            chain_id: chain.spec.chain_id,
+            deposit_contract,
+            checkpoint_contract,
+        })
+        .await?;
+    if state.leadership().is_leader() {
+        // the addresses are set either way, /claim-leadership can retry
+        if let Err(e) = leader::claim_contracts(
+            &state.sequencer.snapshot(),
+            &state.chains,
+            state.client.as_ref(),
+            &TxPolicy::default(),
+        )
+        .await
+        {
+            warn!("failed to claim leadership: {}", e);
+        }
+    }
+    Ok("Thanks!".to_string())
+}
 
-    guard.jtrain.warehouse.deposit_contract = Address::from_str(&deposit_registry_address).unwrap();
-    guard.jtrain.warehouse.checkpoint_contract = Address::from_str(&checkpointer_address).unwrap();
-    guard.jtrain.warehouse.store();
-    "Thanks!".to_string()
+/// The EIP-712 domains the app signs and verifies with, contract bound ones once their addresses
+/// are set.
+#[get("/domain-separators")]
+fn get_domain_separators(state: &State<SharedState>) -> String {
+    let snapshot = state.sequencer.snapshot();
+    let deposit: Vec<serde_json::Value> = state
+        .chains
+        .iter()
+        .filter_map(|chain| {
+            let deposit_contract = snapshot.deposit_contract(chain.spec.chain_id);
+            (!deposit_contract.is_zero())
+                .then(|| domains::domain_json(&chain.spec.deposit_domain(deposit_contract)))
+        })
+        .collect();
+    let checkpoint = (!snapshot.checkpoint_contract.is_zero())
+        .then(|| domains::domain_json(&state.domains.toliman(snapshot.checkpoint_contract)));
+    serde_json::to_string(&serde_json::json!({
+        "dstack": domains::domain_json(&state.domains.dstack),
+        "checkpoint": checkpoint,
+        "deposit": deposit,
+    }))
+    .unwrap()
 }
 
-#[post("/new-settlement-order/<user>/<taker_signature>", data = "<order>")]
+/// Checks every contract address the app starts with against the domains it signs with.
+async fn verify_domain_separators<C: ChainClient>(
+    snapshot: &StateSnapshot,
+    chains: &Chains<C>,
+    client: &C,
+    domains: &Domains,
+) -> Result<(), MwError> {
+    for chain in chains.iter() {
+        let deposit_contract = snapshot.deposit_contract(chain.spec.chain_id);
+        if !deposit_contract.is_zero()
+            && !domains::verify_deposit_registry(chain, deposit_contract).await?
+        {
+            warn!(
+                "deposit registry {} on chain {} has no domain separator yet",
+                deposit_contract, chain.spec.chain_id
+            );
+        }
+    }
+    if !snapshot.checkpoint_contract.is_zero()
+        && !domains::verify_checkpointer(client, domains, snapshot.checkpoint_contract).await?
+    {
+        warn!(
+            "checkpointer {} has no domain separator yet",
+            snapshot.checkpoint_contract
+        );
+    }
+    Ok(())
+}
+
+/// The order settles on `chain_id`, mainnet if not given, and is signed over that chain's domain.
+#[post(
+    "/new-settlement-order/<user>/<taker_signature>?<chain_id>",
+    data = "<order>"
+)]
 async fn new_settlement_order(
     state: &State<SharedState>,
     user: String,
     taker_signature: String,
+    chain_id: Option<u64>,
     order: Json<IDepositRegistry::Order>,
 ) -> Result<String, MwError> {
-    let jtrain = &state.read().await.jtrain;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let taker_signature = Signature::from_str(&taker_signature).unwrap();
-    let new_order = create_settlement_order(
-        &jtrain.warehouse,
-        &jtrain.provider,
-        user,
-        order.0,
-        taker_signature,
-    )
-    .await?;
-    let mut guard = state.write().await;
-    guard.jtrain.warehouse.add_settlement_order(new_order);
+    state.leadership().require_leader("new_settlement_order")?;
+    let user = errors::parse_user(&user)?;
+    let taker_signature = errors::parse_param::<Signature>("taker_signature", &taker_signature)?;
+    let chain = state.chains.get(chain_id.unwrap_or(MAINNET_CHAIN_ID))?;
+    let deposit_contract = state
+        .sequencer
+        .snapshot()
+        .deposit_contract(chain.spec.chain_id);
+    settler::check_settlement_nonce(chain, deposit_contract, &order).await?;
+    state
+        .sequencer
+        .submit(Command::NewSettlementOrder {
+            chain: chain.spec.clone(),
+            user,
+            order: order.0,
+            signature: taker_signature,
+        })
+        .await?;
     Ok("Added settlement order. Thanks!".to_string())
 }
 
 #[get("/get-settlement-order-length")]
 async fn get_settlement_order_length(state: &State<SharedState>) -> String {
-    let jtrain = &state.read().await.jtrain;
-    format!("{:?}", jtrain.warehouse.settlement_orders.len())
+    format!("{:?}", state.sequencer.snapshot().settlement_orders.len())
 }
 
 #[get("/get-orders/<user>/<signature>", data = "<request>")]
@@ -99,16 +357,14 @@ async fn get_orders(
     signature: String,
     request: Json<UserRequest>,
 ) -> Result<String, MwError> {
-    let jtrain = &state.read().await.jtrain;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let signature = Signature::from_str(&signature).unwrap();
-    request.validate_signature(signature, user)?;
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    request.validate_signature(&state.domains.dstack, signature, user)?;
     request.validate_timestamp()?;
     request.validate_request_type("orders")?;
-    let orders = jtrain
-        .warehouse
-        .get_orders(&jtrain.orderbook_manager, user)?;
-    Ok(serde_json::to_string(&orders).unwrap())
+    let snapshot = state.sequencer.snapshot();
+    let orders = snapshot.orders.get(&user).ok_or(MwError::NoOrdersFound)?;
+    Ok(serde_json::to_string(orders)?)
 }
 
 #[post("/send-order/<user>/<signature>", data = "<order>")]
@@ -118,21 +374,45 @@ async fn send_order(
     signature: String,
     order: Json<Order>,
 ) -> Result<String, MwError> {
-    let mut guard = state.write().await;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let signature = Signature::from_str(&signature).unwrap();
-    let jtrain = &mut guard.jtrain;
-    let result = orderhere::new_order(
-        &mut jtrain.warehouse,
-        &mut jtrain.orderbook_manager,
-        user,
-        order.0,
-        signature,
-    )?;
-
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    let applied = state
+        .sequencer
+        .submit(Command::NewOrder {
+            user,
+            order: order.0,
+            signature,
+        })
+        .await?;
+    let CommandOutput::OrderPlaced(result) = applied.output else {
+        unreachable!()
+    };
     Ok(format!("{:?}", result))
 }
 
+#[post("/send-batch/<user>/<signature>", data = "<batch>")]
+async fn send_batch(
+    state: &State<SharedState>,
+    user: String,
+    signature: String,
+    batch: Json<OrderBatch>,
+) -> Result<String, MwError> {
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    let applied = state
+        .sequencer
+        .submit(Command::Batch {
+            user,
+            batch: batch.0,
+            signature,
+        })
+        .await?;
+    let CommandOutput::BatchExecuted(results) = applied.output else {
+        unreachable!()
+    };
+    Ok(batch::results_to_json(&results))
+}
+
 #[delete("/cancel-order/<user>/<signature>", data = "<cancel>")]
 async fn cancel_order(
     state: &State<SharedState>,
@@ -140,20 +420,42 @@ async fn cancel_order(
     signature: String,
     cancel: Json<CancelOrder>,
 ) -> Result<String, MwError> {
-    let mut guard = state.write().await;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let signature = Signature::from_str(&signature).unwrap();
-    let jtrain = &mut guard.jtrain;
-    orderhere::cancel_order(
-        user,
-        cancel.0,
-        signature,
-        &mut jtrain.warehouse,
-        &mut jtrain.orderbook_manager,
-    )?;
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    state
+        .sequencer
+        .submit(Command::CancelOrder {
+            user,
+            cancel: cancel.0,
+            signature,
+        })
+        .await?;
     Ok("Thanks!".to_string())
 }
 
+#[delete("/cancel-all/<user>/<signature>", data = "<cancel>")]
+async fn cancel_all(
+    state: &State<SharedState>,
+    user: String,
+    signature: String,
+    cancel: Json<CancelAll>,
+) -> Result<String, MwError> {
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    let applied = state
+        .sequencer
+        .submit(Command::CancelAll {
+            user,
+            cancel: cancel.0,
+            signature,
+        })
+        .await?;
+    let CommandOutput::OrdersCancelled(cancelled) = applied.output else {
+        unreachable!()
+    };
+    Ok(serde_json::to_string(&cancelled)?)
+}
+
 #[put("/modify-order/<user>/<signature>/<order_id>", data = "<order>")]
 async fn modify_order(
     state: &State<SharedState>,
@@ -162,19 +464,21 @@ async fn modify_order(
     order_id: String,
     order: Json<Order>,
 ) -> Result<String, MwError> {
-    let mut guard = state.write().await;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let signature = Signature::from_str(&signature).unwrap();
-    let jtrain = &mut guard.jtrain;
-    let order_id = OrderId(u32::from_str(&order_id).unwrap());
-    let new_oid = orderhere::replace_order(
-        user,
-        order.0,
-        order_id,
-        signature,
-        &mut jtrain.warehouse,
-        &mut jtrain.orderbook_manager,
-    )?;
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    let order_id = OrderId(errors::parse_param("order_id", &order_id)?);
+    let applied = state
+        .sequencer
+        .submit(Command::ReplaceOrder {
+            user,
+            order: order.0,
+            oid: order_id,
+            signature,
+        })
+        .await?;
+    let CommandOutput::OrderReplaced(new_oid) = applied.output else {
+        unreachable!()
+    };
     Ok(format!("{:?}", new_oid))
 }
 
@@ -185,14 +489,14 @@ async fn get_inventory(
     signature: String,
     request: Json<UserRequest>,
 ) -> Result<String, MwError> {
-    let jtrain = &state.read().await.jtrain;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let signature = Signature::from_str(&signature).unwrap();
-    request.validate_signature(signature, user)?;
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    request.validate_signature(&state.domains.dstack, signature, user)?;
     request.validate_timestamp()?;
     request.validate_request_type("inventory")?;
-    let inventory = jtrain
-        .warehouse
+    let inventory = state
+        .sequencer
+        .snapshot()
         .inventories
         .get(&user)
         .cloned()
@@ -200,55 +504,348 @@ async fn get_inventory(
     Ok(inventory.to_json())
 }
 
-#[put("/gulp-deposits/<user>")]
-async fn gulp_deposits(state: &State<SharedState>, user: String) -> Result<String, MwError> {
-    let mut guard = state.write().await;
-    let jtrain = &mut guard.jtrain;
-    let user = Address::from_raw_public_key(user.as_bytes());
-    let new_deposits = gulper::gulp_deposits(&mut jtrain.warehouse, &jtrain.provider, user)
-        .map_err(|e| MwError::GulpError(e.to_string()))?;
-    Ok(format!("{:?}", new_deposits))
+#[get("/inventory-proof/<user>/<signature>", data = "<request>")]
+async fn get_inventory_proof(
+    state: &State<SharedState>,
+    user: String,
+    signature: String,
+    request: Json<UserRequest>,
+) -> Result<String, MwError> {
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    request.validate_signature(&state.domains.dstack, signature, user)?;
+    request.validate_timestamp()?;
+    request.validate_request_type("inventory-proof")?;
+    state
+        .snapshots
+        .latest_commitment()
+        .and_then(|commitment| commitment.proof_json(user))
+        .ok_or(MwError::ProofUnavailable)
+}
+
+/// Credits the user's confirmed deposits on `chain_id`, mainnet if not given, now instead of
+/// waiting for the indexer to reach them.
+#[put("/gulp-deposits/<user>?<chain_id>")]
+async fn gulp_deposits(
+    state: &State<SharedState>,
+    user: String,
+    chain_id: Option<u64>,
+) -> Result<String, MwError> {
+    let user = errors::parse_user(&user)?;
+    let chain = state.chains.get(chain_id.unwrap_or(MAINNET_CHAIN_ID))?;
+    let credited = gulper::gulp_user(&state.sequencer, chain, &state.indexer_config, user).await?;
+    Ok(credited.to_string())
+}
+
+#[get("/deposit-status")]
+fn get_deposit_status(state: &State<SharedState>) -> String {
+    state.deposit_indexer.status().to_json()
 }
 
-///@dev: run ever 5 seconds
+#[get("/session")]
+async fn get_session(state: &State<SharedState>) -> String {
+    state.sequencer.snapshot().session.to_json(now_ms())
+}
+
+/// Starts the next session, signed by the Checkpointer's admin or leader.
+#[post("/finalize-session/<signature>", data = "<request>")]
+async fn finalize_session(
+    state: &State<SharedState>,
+    price_oracle: &State<SharedOracle>,
+    signature: String,
+    request: Json<UserRequest>,
+) -> Result<String, MwError> {
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    request.validate_signature(&state.domains.dstack, signature, request.user)?;
+    request.validate_timestamp()?;
+    request.validate_request_type("finalize-session")?;
+    session::authorize_operator(state.config.operator, request.user)?;
+    let mark_price = weth_mark_price(state, price_oracle).await?;
+    let applied = state
+        .sequencer
+        .submit(Command::FinalizeSession { mark_price })
+        .await?;
+    let CommandOutput::SessionFinalized(session_id) = applied.output else {
+        unreachable!()
+    };
+    Ok(format!("Session {} started", session_id))
+}
+
+async fn weth_mark_price(
+    state: &State<SharedState>,
+    price_oracle: &State<SharedOracle>,
+) -> Result<U256, MwError> {
+    let weth = &state.weth;
+    let book_mid = state.sequencer.snapshot().book_mid(weth.book_id);
+    // the session's pnl is in the books' units
+    Ok(price_oracle
+        .get_price(weth.token, book_mid)
+        .await?
+        .book_price())
+}
+
+#[get("/leaderboard")]
+async fn get_live_leaderboard(
+    state: &State<SharedState>,
+    price_oracle: &State<SharedOracle>,
+) -> Result<String, MwError> {
+    let mark_price = weth_mark_price(state, price_oracle).await?;
+    let snapshot = state.sequencer.snapshot();
+    let results = snapshot.pnl.results(mark_price);
+    Ok(pnl::leaderboard_json(
+        snapshot.pnl.session_id,
+        &results,
+        |address| snapshot.public_pnl.contains(address),
+        LEADERBOARD_SIZE,
+    ))
+}
+
+#[get("/leaderboard/<session_id>")]
+async fn get_leaderboard(state: &State<SharedState>, session_id: u64) -> Result<String, MwError> {
+    let snapshot = state.sequencer.snapshot();
+    let results = snapshot
+        .session_results
+        .get(&session_id)
+        .ok_or(MwError::SessionNotFound { session_id })?;
+    Ok(pnl::leaderboard_json(
+        session_id,
+        results,
+        |address| snapshot.public_pnl.contains(address),
+        LEADERBOARD_SIZE,
+    ))
+}
+
+#[put("/leaderboard-visibility/<user>/<signature>", data = "<request>")]
+async fn set_leaderboard_visibility(
+    state: &State<SharedState>,
+    user: String,
+    signature: String,
+    request: Json<UserRequest>,
+) -> Result<String, MwError> {
+    let user = errors::parse_user(&user)?;
+    let signature = errors::parse_param::<Signature>("signature", &signature)?;
+    state
+        .sequencer
+        .submit(Command::SetPnlVisibility {
+            user,
+            request: request.0,
+            signature,
+        })
+        .await?;
+    Ok("Thanks!".to_string())
+}
+
+#[post("/close-session")]
+async fn close_session(
+    state: &State<SharedState>,
+    price_oracle: &State<SharedOracle>,
+) -> Result<String, MwError> {
+    // take the mark before orders are cleared since the book mid goes with them
+    state
+        .sequencer
+        .snapshot()
+        .session
+        .ensure_allowed(SessionAction::CloseSession, now_ms())?;
+    let mark_price = weth_mark_price(state, price_oracle).await?;
+    let applied = state
+        .sequencer
+        .submit(Command::CloseSession { mark_price })
+        .await?;
+    let CommandOutput::SessionClosed(report) = applied.output else {
+        unreachable!()
+    };
+    Ok(report.to_json())
+}
+
+#[get("/get-price/<asset>")]
+async fn get_price(
+    state: &State<SharedState>,
+    price_oracle: &State<SharedOracle>,
+    asset: String,
+) -> Result<String, MwError> {
+    let asset = errors::parse_param::<Address>("asset", &asset)?;
+    let book_id = price_oracle
+        .listed_asset(asset)
+        .ok_or(MwError::UnlistedAsset {
+            asset: asset.to_string(),
+        })?
+        .book_id;
+    let book_mid = state.sequencer.snapshot().book_mid(book_id);
+    let mark_price = price_oracle.get_price(asset, book_mid).await?;
+    Ok(mark_price.to_json())
+}
+
+/// Posts a checkpoint now instead of waiting for the scheduler.
 #[post("/take_snapshot")]
 async fn take_snapshot(state: &State<SharedState>) -> Result<String, MwError> {
-    let jtrain = &state.read().await.jtrain;
-    let tx_receipt = snapshotter::snapshot(&jtrain.warehouse, &jtrain.provider)
-        .await
-        .map_err(|e| MwError::SnapshotError(e.to_string()))?;
+    state.leadership().require_leader("take_snapshot")?;
+    state
+        .snapshots
+        .run_once(&state.sequencer, state.sink.as_ref())
+        .await?;
+    Ok(state.snapshots.status().to_json())
+}
 
-    Ok(serde_json::to_string(&tx_receipt)
-        .unwrap_or_else(|_| "Failed to serialize transaction receipt".to_string()))
+#[get("/snapshot-status")]
+fn get_snapshot_status(state: &State<SharedState>) -> String {
+    state.snapshots.status().to_json()
 }
 
 #[launch]
 async fn rocket() -> _ {
-    let initial_state = AppState {
-        jtrain: Jtrain::new(Url::from_str(&env::var("RPC_URL").unwrap().to_string()).unwrap())
-            .await,
+    // MW_CONFIG and the environment, see config.rs
+    let config = Config::load().unwrap_or_else(|e| panic!("{}", e));
+    let mut jtrain = Jtrain::new(&config).await;
+    let client = Arc::new(AlloyClient::connect(
+        config.rpc_url.clone(),
+        &jtrain.warehouse.signer,
+    ));
+    if let Some(checkpoint_contract) = config.recover_from {
+        jtrain
+            .recover(client.as_ref(), checkpoint_contract)
+            .await
+            .expect("failed to recover from checkpoint");
+    }
+    let chains = Arc::new(Chains::connect(
+        config.chains.clone(),
+        &jtrain.warehouse.signer,
+    ));
+    let domains = jtrain.warehouse.domains.clone();
+    let replication_key = jtrain.warehouse.replication_key;
+    let volume_key = jtrain.warehouse.volume_key;
+    let sequencer = match config.role {
+        // recovered state doesn't come from the log, it starts the log over
+        InstanceRole::Leader if config.recover_from.is_some() => sequencer::spawn_genesis(jtrain),
+        InstanceRole::Leader => sequencer::spawn(jtrain),
+        InstanceRole::Replica => sequencer::spawn_replica(jtrain),
     };
-    let shared_state = Arc::new(RwLock::new(initial_state));
+    // refuse to run against contracts that would reject what the app signs
+    verify_domain_separators(&sequencer.snapshot(), &chains, client.as_ref(), &domains)
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
+    // checkpoints and settlement approvals need this container's key elected on the contracts, a
+    // leader that isn't yet waits as a replica until the old key's lease runs out
+    let mut campaign = Campaign::new(config.role);
+    if let Err(e) = campaign
+        .run_once(&sequencer, &chains, client.as_ref(), &TxPolicy::default())
+        .await
+    {
+        warn!("failed to claim leadership: {}", e);
+    }
+    leader::spawn(
+        campaign,
+        sequencer.clone(),
+        chains.clone(),
+        client.clone(),
+        Duration::from_millis(leader::DEFAULT_CAMPAIGN_INTERVAL_MS),
+    );
+    let snapshots = Arc::new(SnapshotTracker::load(&config.storage, volume_key));
+    let sink = Arc::new(
+        Sink::new(
+            config.sink.clone(),
+            client.clone(),
+            &chains,
+            TxPolicy::default(),
+            &config.storage,
+        )
+        .unwrap_or_else(|e| panic!("{}", e)),
+    );
+    // both only do anything on the leader, so a promoted replica picks up where it left off
+    scheduler::spawn(
+        snapshots.clone(),
+        sequencer.clone(),
+        sink.clone(),
+        config.scheduler.clone(),
+    );
+    let deposit_indexer = Arc::new(DepositIndexer::new());
+    gulper::spawn(
+        deposit_indexer.clone(),
+        sequencer.clone(),
+        chains.clone(),
+        config.indexer.clone(),
+    );
+    let follower = config.leader_url.clone().map(|leader_url| Follower {
+        leader_url,
+        key: replication_key,
+    });
+    if let Some(follower) = &follower {
+        replication::spawn(
+            follower.clone(),
+            sequencer.clone(),
+            Duration::from_millis(replication::DEFAULT_RETRY_MS),
+        );
+    }
+    // marks come from CoW on mainnet, priced in mainnet USDC
+    let quote_chain = config.chain_spec(MAINNET_CHAIN_ID).unwrap();
+    let weth = ListedAsset::weth_on(&quote_chain);
+    let shared_state: SharedState = Arc::new(AppState {
+        sequencer,
+        chains,
+        snapshots,
+        sink,
+        deposit_indexer,
+        indexer_config: config.indexer.clone(),
+        client,
+        domains,
+        weth: weth.clone(),
+        guest_agent: GuestAgent {
+            quote_url: config.dstack_quote_url.clone(),
+        },
+        config: config.clone(),
+        replication_key,
+        follower,
+    });
+    let listed_assets = vec![weth];
+    let price_oracle: SharedOracle = Arc::new(PriceOracle::new(
+        CowSwapQuoter::new(
+            config.cowswap_api_url.as_str(),
+            quote_chain.usdc,
+            &listed_assets,
+        ),
+        listed_assets,
+        DEFAULT_MAX_AGE_MS,
+        DEFAULT_MAX_STALENESS_MS,
+    ));
 
     rocket::build()
         .manage(shared_state)
+        .manage(price_oracle)
         .mount(
             "/",
             routes![
                 index,
                 health,
                 set_contract_addresses,
+                get_domain_separators,
                 get_public_key,
+                get_attestation,
+                get_leader,
+                claim_leadership,
+                replication_stream,
+                promote,
+                get_sequence,
                 hello,
                 new_settlement_order,
                 get_settlement_order_length,
                 get_orders,
                 send_order,
+                send_batch,
                 cancel_order,
+                cancel_all,
                 modify_order,
                 get_inventory,
+                get_inventory_proof,
                 gulp_deposits,
+                get_deposit_status,
                 take_snapshot,
+                get_snapshot_status,
+                get_price,
+                get_session,
+                finalize_session,
+                close_session,
+                get_live_leaderboard,
+                get_leaderboard,
+                set_leaderboard_visibility,
             ],
         )
         .register("/", catchers![default_catcher])
@@ -6,7 +6,7 @@ use optimized_lob::{
 /// Overview: matching engine
 /// Matches an order against the current orderbook state, performing executions where necessary.
 ///
-/// Returns a tuple of 4 values:
+/// Returns a tuple of 5 values:
 /// - total qty executed
 /// - total volume of matches (this is the revenue for an ask and the cost for a bid)
 /// - new order id if one was created
@@ -128,8 +128,8 @@ pub fn match_order(
         new_order_id = Some(order_id);
     }
     (
-        volume,
         Qty(qty.value() - remaining_qty.value()),
+        volume,
         new_order_id,
         filled_orders,
         partially_filled_order_id,
@@ -0,0 +1,160 @@
+// Overview:
+// Merkle commitment of inventory state, posted alongside the encrypted blob in each checkpoint so
+// users can verify their own balance against it without seeing anyone else's.
+// * leaves are keccak256 of Inventory::to_bytes, ordered by address
+// * pairs are hashed sorted (smaller hash first), so proofs don't carry positions and can be
+//   checked on chain with solady's MerkleProofLib
+// * an unpaired node is carried up to the next layer as is
+// * the root of an empty tree is zero
+// * a commitment serializes to its nonce and inventories, so the last posted one can be stored and
+//   its tree rebuilt after a restart (see scheduler.rs)
+
+use std::collections::HashMap;
+
+use alloy::primitives::{keccak256, Address, B256, U256};
+
+use crate::{
+    errors::MwError,
+    warehouse::{Inventory, INVENTORY_BYTES},
+};
+
+pub struct MerkleTree {
+    layers: Vec<Vec<B256>>, // leaves first, root last
+}
+
+impl MerkleTree {
+    pub fn from_leaves(leaves: Vec<B256>) -> Self {
+        let mut layers = vec![leaves];
+        while layers.last().unwrap().len() > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_pair(*left, *right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(next);
+        }
+        MerkleTree { layers }
+    }
+
+    pub fn root(&self) -> B256 {
+        self.layers
+            .last()
+            .and_then(|layer| layer.first())
+            .copied()
+            .unwrap_or(B256::ZERO)
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    /// Sibling hashes from the leaf up to the root, skipping layers where the node is unpaired.
+    pub fn proof(&self, index: usize) -> Option<Vec<B256>> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+        let mut proof = Vec::new();
+        let mut index = index;
+        for layer in &self.layers[..self.layers.len() - 1] {
+            if let Some(sibling) = layer.get(index ^ 1) {
+                proof.push(*sibling);
+            }
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+pub fn hash_pair(a: B256, b: B256) -> B256 {
+    let mut buffer = [0u8; 64];
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    buffer[..32].copy_from_slice(first.as_slice());
+    buffer[32..].copy_from_slice(second.as_slice());
+    keccak256(buffer)
+}
+
+pub fn verify(root: B256, leaf: B256, proof: &[B256]) -> bool {
+    proof
+        .iter()
+        .fold(leaf, |node, sibling| hash_pair(node, *sibling))
+        == root
+}
+
+pub fn inventory_leaf(inventory: &Inventory) -> B256 {
+    keccak256(inventory.to_bytes())
+}
+
+/// Inventory tree for a checkpoint along with what's needed to hand out proofs for it later.
+pub struct InventoryCommitment {
+    pub checkpoint_nonce: U256, // set once the checkpoint is posted
+    pub inventories: Vec<Inventory>,
+    pub tree: MerkleTree,
+}
+
+impl InventoryCommitment {
+    pub fn new(inventories: &HashMap<Address, Inventory>) -> Self {
+        let mut inventories: Vec<Inventory> = inventories.values().cloned().collect();
+        inventories.sort_by_key(|inventory| inventory.address);
+        let tree = MerkleTree::from_leaves(inventories.iter().map(inventory_leaf).collect());
+        InventoryCommitment {
+            checkpoint_nonce: U256::ZERO,
+            inventories,
+            tree,
+        }
+    }
+
+    pub fn root(&self) -> B256 {
+        self.tree.root()
+    }
+
+    /// The checkpoint nonce followed by every inventory in tree order, the tree is rebuilt from
+    /// them by from_bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(32 + self.inventories.len() * INVENTORY_BYTES);
+        buffer.extend(&self.checkpoint_nonce.to_le_bytes::<32>());
+        for inventory in &self.inventories {
+            buffer.extend(inventory.to_bytes());
+        }
+        buffer
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MwError> {
+        if bytes.len() < 32 || (bytes.len() - 32) % INVENTORY_BYTES != 0 {
+            return Err(MwError::DecryptionError);
+        }
+        let checkpoint_nonce = U256::from_le_slice(&bytes[..32]);
+        let inventories = bytes[32..]
+            .chunks(INVENTORY_BYTES)
+            .map(Inventory::from_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+        let tree = MerkleTree::from_leaves(inventories.iter().map(inventory_leaf).collect());
+        Ok(InventoryCommitment {
+            checkpoint_nonce,
+            inventories,
+            tree,
+        })
+    }
+
+    pub fn proof_json(&self, user: Address) -> Option<String> {
+        let index = self
+            .inventories
+            .binary_search_by_key(&user, |inventory| inventory.address)
+            .ok()?;
+        let inventory = &self.inventories[index];
+        let proof = self.tree.proof(index)?;
+        let serializable_proof = serde_json::json!({
+            "checkpoint_nonce": self.checkpoint_nonce.to_string(),
+            "root": self.root().to_string(),
+            "index": index.to_string(),
+            "inventory": alloy::hex::encode_prefixed(inventory.to_bytes()),
+            "leaf": inventory_leaf(inventory).to_string(),
+            "proof": proof.iter().map(|node| node.to_string()).collect::<Vec<_>>(),
+        });
+        Some(serde_json::to_string(&serializable_proof).unwrap())
+    }
+}
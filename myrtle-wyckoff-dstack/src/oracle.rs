@@ -0,0 +1,297 @@
+// Overview:
+// Mark price oracle for listed assets, priced against USDC.
+// * EVM assets are quoted through the CoW Protocol quoter (/api/v1/quote)
+// * quotes are cached and refreshed once they're older than max_age_ms
+// * if the quoter is unavailable we fall back to the orderbook mid, and failing that to the
+//   cached quote as long as it's younger than max_staleness_ms
+// * the price source is a trait so tests can use a fixed price stand-in
+// * mark prices are fixed-point with PRICE_DECIMALS, the books quote whole USDC so a book mid is
+//   scaled up to match and a mark is rounded back to the book's units for pnl (see book_price)
+// TODO: solana assets should use the jupiter quoter
+
+use std::{collections::HashMap, future::Future, sync::RwLock};
+
+use alloy::primitives::{Address, U256};
+use optimized_lob::{orderbook_manager::OrderBookManager, utils::BookId};
+use tracing::warn;
+
+use crate::{
+    chains::{ChainSpec, MAINNET},
+    clock::now_ms,
+    constants::USDC_DECIMALS,
+    errors::MwError,
+};
+
+pub const DEFAULT_MAX_AGE_MS: u64 = 5_000;
+pub const DEFAULT_MAX_STALENESS_MS: u64 = 60_000;
+pub const PRICE_DECIMALS: u8 = USDC_DECIMALS; // a price is USDC base units per whole unit
+
+fn price_scale() -> U256 {
+    U256::from(10).pow(U256::from(PRICE_DECIMALS))
+}
+
+/// Something that can price an asset in USDC.
+/// Prices are USDC per whole unit of the asset, in fixed-point with PRICE_DECIMALS.
+pub trait PriceSource {
+    fn fetch_price(&self, asset: Address) -> impl Future<Output = Result<U256, MwError>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceOrigin {
+    Quoter,
+    BookMid,
+    StaleQuote,
+}
+impl PriceOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Quoter => "quoter",
+            Self::BookMid => "book_mid",
+            Self::StaleQuote => "stale_quote",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MarkPrice {
+    pub asset: Address,
+    pub price: U256,
+    pub origin: PriceOrigin,
+    pub timestamp: u64, // unix timestamp in milliseconds
+}
+impl MarkPrice {
+    /// The price in whole USDC the way the books quote it, rounded to the nearest.
+    pub fn book_price(&self) -> U256 {
+        (self.price + price_scale() / U256::from(2)) / price_scale()
+    }
+
+    pub fn to_json(&self) -> String {
+        let serializable_price = serde_json::json!({
+            "asset": self.asset.to_string(),
+            "price": self.price.to_string(),
+            "decimals": PRICE_DECIMALS.to_string(),
+            "origin": self.origin.as_str(),
+            "timestamp": self.timestamp.to_string(),
+        });
+        serde_json::to_string(&serializable_price).unwrap()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ListedAsset {
+    pub token: Address,
+    pub decimals: u8,
+    pub book_id: BookId,
+}
+impl ListedAsset {
+    pub fn weth() -> Self {
+        Self::weth_on(&MAINNET)
+    }
+    /// WETH as configured on `chain` (see config.rs).
+    pub fn weth_on(chain: &ChainSpec) -> Self {
+        ListedAsset {
+            token: chain.weth,
+            decimals: 18,
+            book_id: BookId(0),
+        }
+    }
+}
+
+pub struct PriceOracle<S: PriceSource> {
+    source: S,
+    listed_assets: Vec<ListedAsset>,
+    max_age_ms: u64,
+    max_staleness_ms: u64,
+    cache: RwLock<HashMap<Address, MarkPrice>>,
+}
+
+impl<S: PriceSource> PriceOracle<S> {
+    pub fn new(
+        source: S,
+        listed_assets: Vec<ListedAsset>,
+        max_age_ms: u64,
+        max_staleness_ms: u64,
+    ) -> Self {
+        PriceOracle {
+            source,
+            listed_assets,
+            max_age_ms,
+            max_staleness_ms,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn listed_asset(&self, asset: Address) -> Option<&ListedAsset> {
+        self.listed_assets
+            .iter()
+            .find(|listed| listed.token == asset)
+    }
+
+    pub fn listed_assets(&self) -> &[ListedAsset] {
+        &self.listed_assets
+    }
+
+    pub async fn get_price(
+        &self,
+        asset: Address,
+        book_mid: Option<U256>,
+    ) -> Result<MarkPrice, MwError> {
+        self.get_price_at(asset, book_mid, now_ms()).await
+    }
+
+    /// `book_mid` is in whole USDC, as book_mid returns it.
+    pub async fn get_price_at(
+        &self,
+        asset: Address,
+        book_mid: Option<U256>,
+        now: u64,
+    ) -> Result<MarkPrice, MwError> {
+        if self.listed_asset(asset).is_none() {
+            return Err(MwError::UnlistedAsset {
+                asset: asset.to_string(),
+            });
+        }
+
+        // the lock is never held across the quote request
+        let cached = self.cache.read().unwrap().get(&asset).cloned();
+        if let Some(cached) = &cached {
+            if now.saturating_sub(cached.timestamp) <= self.max_age_ms {
+                return Ok(cached.clone());
+            }
+        }
+
+        match self.source.fetch_price(asset).await {
+            Ok(price) => {
+                let mark_price = MarkPrice {
+                    asset,
+                    price,
+                    origin: PriceOrigin::Quoter,
+                    timestamp: now,
+                };
+                self.cache
+                    .write()
+                    .unwrap()
+                    .insert(asset, mark_price.clone());
+                Ok(mark_price)
+            }
+            Err(e) => {
+                warn!("price source failed for {}: {}", asset, e);
+                if let Some(mid) = book_mid {
+                    return Ok(MarkPrice {
+                        asset,
+                        price: mid * price_scale(),
+                        origin: PriceOrigin::BookMid,
+                        timestamp: now,
+                    });
+                }
+                match cached {
+                    Some(cached)
+                        if now.saturating_sub(cached.timestamp) <= self.max_staleness_ms =>
+                    {
+                        Ok(MarkPrice {
+                            origin: PriceOrigin::StaleQuote,
+                            ..cached
+                        })
+                    }
+                    _ => Err(MwError::PriceUnavailable {
+                        asset: asset.to_string(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Midpoint of the best bid and best ask on a book in whole USDC, if both sides have liquidity.
+pub fn book_mid(orderbook_manager: &OrderBookManager, book_id: BookId) -> Option<U256> {
+    let book = orderbook_manager
+        .books
+        .get(book_id.value() as usize)?
+        .as_ref()?;
+    if book.bids.len() == 0 || book.asks.len() == 0 {
+        return None;
+    }
+    // best levels sit at the end of each side, see matchmaker::match_order
+    let best_bid = book.bids.get(book.bids.len() - 1).price().absolute();
+    let best_ask = book.asks.get(book.asks.len() - 1).price().absolute();
+    Some((best_bid + best_ask) / U256::from(2))
+}
+
+/// Prices assets by asking the CoW Protocol quoter what one whole unit sells for in USDC.
+pub struct CowSwapQuoter {
+    api_url: String,
+    usdc: Address, // on the chain api_url quotes on
+    client: reqwest::Client,
+    decimals: HashMap<Address, u8>,
+}
+impl CowSwapQuoter {
+    pub fn new(api_url: &str, usdc: Address, listed_assets: &[ListedAsset]) -> Self {
+        CowSwapQuoter {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            usdc,
+            client: reqwest::Client::new(),
+            decimals: listed_assets
+                .iter()
+                .map(|listed| (listed.token, listed.decimals))
+                .collect(),
+        }
+    }
+}
+impl PriceSource for CowSwapQuoter {
+    async fn fetch_price(&self, asset: Address) -> Result<U256, MwError> {
+        let decimals = *self.decimals.get(&asset).ok_or(MwError::UnlistedAsset {
+            asset: asset.to_string(),
+        })?;
+        let sell_amount = U256::from(10).pow(U256::from(decimals));
+        let request = serde_json::json!({
+            "sellToken": asset.to_string(),
+            "buyToken": self.usdc.to_string(),
+            "from": Address::ZERO.to_string(),
+            "kind": "sell",
+            "sellAmountBeforeFee": sell_amount.to_string(),
+            "priceQuality": "fast",
+            "signingScheme": "eip712",
+        });
+        let response: serde_json::Value = self
+            .client
+            .post(format!("{}/api/v1/quote", self.api_url))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| MwError::QuoteError(e.to_string()))?
+            .error_for_status()
+            .map_err(|e| MwError::QuoteError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| MwError::QuoteError(e.to_string()))?;
+
+        let buy_amount = response["quote"]["buyAmount"]
+            .as_str()
+            .ok_or(MwError::QuoteError("missing buyAmount".to_string()))?;
+        let buy_amount =
+            U256::from_str_radix(buy_amount, 10).map_err(|e| MwError::QuoteError(e.to_string()))?;
+        // already fixed-point, buyAmount is in USDC base units
+        Ok(buy_amount)
+    }
+}
+
+/// Fixed price stand-in for tests and local runs.
+#[derive(Default)]
+pub struct FixedPriceSource {
+    prices: HashMap<Address, U256>,
+}
+impl FixedPriceSource {
+    pub fn new(prices: HashMap<Address, U256>) -> Self {
+        FixedPriceSource { prices }
+    }
+}
+impl PriceSource for FixedPriceSource {
+    async fn fetch_price(&self, asset: Address) -> Result<U256, MwError> {
+        self.prices
+            .get(&asset)
+            .cloned()
+            .ok_or(MwError::PriceUnavailable {
+                asset: asset.to_string(),
+            })
+    }
+}
@@ -1,7 +1,6 @@
-use crate::{
-    domains::DSTACK_DOMAIN, errors::MwError, matchmaker::match_order, warehouse::Warehouse,
-};
+use crate::{errors::MwError, matchmaker::match_order, structs, warehouse::Warehouse};
 use alloy::{
+    dyn_abi::Eip712Domain,
     primitives::{Address, U256},
     signers::Signature,
     sol,
@@ -24,14 +23,15 @@ sol! {
 }
 impl Order {
     pub fn validate_timestamp(&self) -> Result<(), MwError> {
-        let min_timestamp = chrono::Utc::now().timestamp_millis() - 60000; // 1 minute buffer
-        if self.timestamp < min_timestamp.unsigned_abs() {
-            return Err(MwError::InvalidTimestamp);
-        }
-        Ok(())
+        structs::validate_timestamp(self.timestamp)
     }
-    pub fn validate_signature(&self, signature: Signature, user: Address) -> Result<(), MwError> {
-        let order_hash = self.eip712_signing_hash(&DSTACK_DOMAIN);
+    pub fn validate_signature(
+        &self,
+        domain: &Eip712Domain,
+        signature: Signature,
+        user: Address,
+    ) -> Result<(), MwError> {
+        let order_hash = self.eip712_signing_hash(domain);
         let recovered_address = signature
             .recover_address_from_prehash(&order_hash)
             .map_err(|_| MwError::SignatureRecoveryError)?;
@@ -50,14 +50,44 @@ sol! {
 }
 impl CancelOrder {
     pub fn validate_timestamp(&self) -> Result<(), MwError> {
-        let min_timestamp = chrono::Utc::now().timestamp_millis() - 60000; // 1 minute buffer
-        if self.timestamp < min_timestamp.unsigned_abs() {
-            return Err(MwError::InvalidTimestamp);
+        structs::validate_timestamp(self.timestamp)
+    }
+    pub fn validate_signature(
+        &self,
+        domain: &Eip712Domain,
+        signature: Signature,
+        user: Address,
+    ) -> Result<(), MwError> {
+        let order_hash = self.eip712_signing_hash(domain);
+        let recovered_address = signature
+            .recover_address_from_prehash(&order_hash)
+            .map_err(|_| MwError::SignatureRecoveryError)?;
+        if user != recovered_address {
+            return Err(MwError::InvalidSignature);
         }
         Ok(())
     }
-    pub fn validate_signature(&self, signature: Signature, user: Address) -> Result<(), MwError> {
-        let order_hash = self.eip712_signing_hash(&DSTACK_DOMAIN);
+}
+sol! {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct CancelAll {
+        bool scope_book; // only cancel orders on book_id
+        uint32 book_id;
+        uint8 side; // 0 = both sides, 1 = bids only, 2 = asks only
+        uint64 timestamp; // unix timestamp in milliseconds
+    }
+}
+impl CancelAll {
+    pub fn validate_timestamp(&self) -> Result<(), MwError> {
+        structs::validate_timestamp(self.timestamp)
+    }
+    pub fn validate_signature(
+        &self,
+        domain: &Eip712Domain,
+        signature: Signature,
+        user: Address,
+    ) -> Result<(), MwError> {
+        let order_hash = self.eip712_signing_hash(domain);
         let recovered_address = signature
             .recover_address_from_prehash(&order_hash)
             .map_err(|_| MwError::SignatureRecoveryError)?;
@@ -66,6 +96,16 @@ impl CancelOrder {
         }
         Ok(())
     }
+    pub fn matches(&self, book_id: BookId, price: Price) -> bool {
+        if self.scope_book && BookId(self.book_id) != book_id {
+            return false;
+        }
+        match self.side {
+            1 => price.is_bid(),
+            2 => !price.is_bid(),
+            _ => true,
+        }
+    }
 }
 
 pub fn new_order(
@@ -76,23 +116,41 @@ pub fn new_order(
     signature: Signature,
 ) -> Result<(Qty, Qty, Option<OrderId>), MwError> {
     // validate signature and timestamp
-    order.validate_signature(signature, user)?;
+    order.validate_signature(&warehouse.domains.dstack, signature, user)?;
     order.validate_timestamp()?;
 
-    let user_inventory = warehouse.inventories.entry(user).or_default();
+    let result = submit_order(warehouse, orderbook_manager, user, order)?;
+    warehouse.store()?; //TODO: do we store here?
+    Ok(result)
+}
 
+//@Dev: this does not validate the user's signature, callers are responsible for authorizing the order
+pub fn submit_order(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    order: Order,
+) -> Result<(Qty, Qty, Option<OrderId>), MwError> {
+    // checked before the inventory is created, a rejected order leaves no trace
+    let user_inventory = warehouse
+        .inventories
+        .get(&user)
+        .cloned()
+        .unwrap_or_default();
     // we don't need to validate taker inventory state since they're on margin
     if !user_inventory.is_taker {
         if order.is_bid && Qty(order.qty * order.price).gt(&user_inventory.net_usdc()) {
             return Err(MwError::InsufficientBalance {
                 token: "USDC".to_string(),
             });
-        } else if Qty(order.qty).gt(&user_inventory.net_eth()) {
+        } else if !order.is_bid && Qty(order.qty).gt(&user_inventory.net_eth()) {
             return Err(MwError::InsufficientBalance {
                 token: "ETH".to_string(),
             });
         }
     }
+    let user_inventory = warehouse.inventories.entry(user).or_default();
+    user_inventory.address = user;
 
     // execute order
     let (qty_executed, volume_executed, new_order_id, filled_orders, partially_filled_order) =
@@ -116,6 +174,9 @@ pub fn new_order(
         user_inventory.eth_balance.sub_assign(qty_executed);
         user_inventory.usdc_balance.add_assign(volume_executed);
     }
+    warehouse
+        .pnl
+        .record_fill(user, order.is_bid, qty_executed.0, volume_executed.0);
 
     // update orders
     if let Some(new_order_id) = new_order_id {
@@ -156,7 +217,6 @@ pub fn new_order(
     info!("filled_orders: {:?}", filled_orders);
     info!("partially_filled_order: {:?}", partially_filled_order);
     info!("new_order_id: {:?}", new_order_id);
-    warehouse.store(); //TODO: do we store here?
     Ok((qty_executed, volume_executed, new_order_id))
 }
 
@@ -167,16 +227,93 @@ pub fn cancel_order(
     warehouse: &mut Warehouse,
     orderbook_manager: &mut OrderBookManager,
 ) -> Result<(), MwError> {
-    cancel.validate_signature(signature, user)?;
+    cancel.validate_signature(&warehouse.domains.dstack, signature, user)?;
+    cancel.validate_timestamp()?;
+
+    submit_cancel(warehouse, orderbook_manager, user, OrderId(cancel.oid))
+}
+
+//@Dev: this does not validate the user's signature, callers are responsible for authorizing the cancel
+pub fn submit_cancel(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    oid: OrderId,
+) -> Result<(), MwError> {
+    let owner = warehouse
+        .address_by_oid
+        .get(&oid)
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?;
+    if *owner != user {
+        return Err(MwError::UnauthorizedAccess);
+    }
+
+    remove_resting_order(warehouse, orderbook_manager, oid)?;
+    info!("order cancelled: {:?}", (user, oid));
+    Ok(())
+}
+
+pub fn cancel_all(
+    user: Address,
+    cancel: CancelAll,
+    signature: Signature,
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+) -> Result<Vec<u32>, MwError> {
+    cancel.validate_signature(&warehouse.domains.dstack, signature, user)?;
     cancel.validate_timestamp()?;
 
-    let oid = OrderId(cancel.oid);
+    submit_cancel_all(warehouse, orderbook_manager, user, &cancel)
+}
+
+//@Dev: this does not validate the user's signature, callers are responsible for authorizing the cancel
+pub fn submit_cancel_all(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    cancel: &CancelAll,
+) -> Result<Vec<u32>, MwError> {
+    if cancel.side > 2 {
+        return Err(MwError::InvalidOrderParams);
+    }
+
+    let mut user_orders: Vec<OrderId> = warehouse
+        .oid_qty_by_address
+        .get(&user)
+        .map(|orders| orders.keys().cloned().collect())
+        .unwrap_or_default();
+    user_orders.sort_by_key(|oid| oid.0);
+
+    // find every order to cancel first, so an order that can't be cancelled fails the whole request
+    let mut matching = Vec::new();
+    for oid in user_orders {
+        if warehouse.address_by_oid.get(&oid) != Some(&user) {
+            return Err(MwError::OrderNotFound { order_id: oid.0 });
+        }
+        // every order currently sits on BookId(0)
+        let price = resting_order_price(orderbook_manager, oid)?;
+        if cancel.matches(BookId(0), price) {
+            matching.push(oid);
+        }
+    }
+
+    let mut cancelled = Vec::new();
+    for oid in matching {
+        remove_resting_order(warehouse, orderbook_manager, oid)?;
+        cancelled.push(oid.0);
+    }
+    info!("orders cancelled: {:?}", (user, &cancelled));
+    Ok(cancelled)
+}
+
+pub fn resting_order_price(
+    orderbook_manager: &OrderBookManager,
+    oid: OrderId,
+) -> Result<Price, MwError> {
     let level_id = orderbook_manager
         .oid_map
         .get(oid)
-        .ok_or(MwError::OrderNotFound {
-            order_id: cancel.oid,
-        })?
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?
         .level_id();
 
     let book = orderbook_manager
@@ -186,27 +323,41 @@ pub fn cancel_order(
         .as_ref()
         .ok_or(MwError::InvalidBook)?;
 
-    let price = book
+    Ok(book
         .level_pool
         .get(level_id)
-        .ok_or(MwError::OrderNotFound {
-            order_id: cancel.oid,
-        })?
-        .price();
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?
+        .price())
+}
+
+/// The eth and usdc an order locks while it rests on the book.
+pub fn order_liabilities(qty: Qty, price: Price) -> (Qty, Qty) {
+    if price.is_bid() {
+        (Qty(U256::ZERO), Qty(qty.0 * price.absolute()))
+    } else {
+        (qty, Qty(U256::ZERO))
+    }
+}
 
-    let (_, inventory) = if price.is_bid() {
+/// Removes a resting order from the book and releases its liabilities.
+/// Returns the owner of the order along with its remaining qty and price.
+//@Dev: this will not validate that the order is owned by a specific user
+pub fn remove_resting_order(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    oid: OrderId,
+) -> Result<(Address, Qty, Price), MwError> {
+    let price = resting_order_price(orderbook_manager, oid)?;
+
+    let (qty, inventory) = if price.is_bid() {
         warehouse.remove_bid(oid, price)?
     } else {
         warehouse.remove_ask(oid)?
     };
-
-    if inventory.address != user {
-        return Err(MwError::UnauthorizedAccess);
-    }
+    let owner = inventory.address;
 
     orderbook_manager.remove_order(oid);
-    info!("order cancelled: {:?}", (user, oid));
-    Ok(())
+    Ok((owner, qty, price))
 }
 
 pub fn replace_order(
@@ -217,38 +368,60 @@ pub fn replace_order(
     warehouse: &mut Warehouse,
     orderbook_manager: &mut OrderBookManager,
 ) -> Result<OrderId, MwError> {
-    order.validate_signature(signature, user)?;
+    order.validate_signature(&warehouse.domains.dstack, signature, user)?;
     order.validate_timestamp()?;
 
-    let new_oid = orderbook_manager.oid_map.next_id();
-
-    let (_, new_inventory) = warehouse.replace_order(
-        oid,
-        new_oid,
-        Qty(order.qty),
-        Price::from_u256(order.price, order.is_bid),
-    )?;
+    submit_replace(warehouse, orderbook_manager, user, order, oid)
+}
 
-    if new_inventory.address != user {
+//@Dev: this does not validate the user's signature, callers are responsible for authorizing the replace
+pub fn submit_replace(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    user: Address,
+    order: Order,
+    oid: OrderId,
+) -> Result<OrderId, MwError> {
+    let owner = warehouse
+        .address_by_oid
+        .get(&oid)
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?;
+    if *owner != user {
         return Err(MwError::UnauthorizedAccess);
     }
+
+    let old_price = resting_order_price(orderbook_manager, oid)?;
+    let old_qty = warehouse
+        .oid_qty_by_address
+        .get(&user)
+        .and_then(|orders| orders.get(&oid))
+        .map(|qty| Qty(qty.0))
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?;
+    let price = Price::from_u256(order.price, order.is_bid);
+
+    // check the replacement fits before anything changes, a failed replace leaves the order resting
+    let inventory = warehouse
+        .inventories
+        .get(&user)
+        .ok_or(MwError::OrderNotFound { order_id: oid.0 })?;
     // we don't need to validate taker inventory state since they're on margin
-    if !new_inventory.is_taker {
-        if new_inventory.eth_liabilities.gt(&new_inventory.eth_balance)
-            || new_inventory
-                .usdc_liabilities
-                .gt(&new_inventory.usdc_balance)
-        {
+    if !inventory.is_taker {
+        let (old_eth, old_usdc) = order_liabilities(old_qty, old_price);
+        let (new_eth, new_usdc) = order_liabilities(Qty(order.qty), price);
+        if inventory.eth_liabilities.0 - old_eth.0 + new_eth.0 > inventory.eth_balance.0 {
             return Err(MwError::InsufficientBalance {
-                token: if new_inventory.eth_liabilities.gt(&new_inventory.eth_balance) {
-                    "ETH".to_string()
-                } else {
-                    "USDC".to_string()
-                },
+                token: "ETH".to_string(),
+            });
+        }
+        if inventory.usdc_liabilities.0 - old_usdc.0 + new_usdc.0 > inventory.usdc_balance.0 {
+            return Err(MwError::InsufficientBalance {
+                token: "USDC".to_string(),
             });
         }
     }
 
+    let new_oid = orderbook_manager.oid_map.next_id();
+    warehouse.replace_order(oid, old_price, new_oid, Qty(order.qty), price)?;
     orderbook_manager.replace_order(oid, new_oid, Qty(order.qty), order.price);
     Ok(new_oid)
 }
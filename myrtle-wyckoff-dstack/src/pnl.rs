@@ -0,0 +1,175 @@
+// Overview:
+// Per session PnL tracking for the pit leaderboard ("publish top ten prompts by pnl").
+// * every fill on the ETH/USDC book is recorded against both the taker and the maker
+// * positions use average cost accounting, realized pnl is booked when a position is reduced
+// * unrealized pnl is marked against the oracle price
+// * at finalize the session's results are frozen and open positions roll into the next session at the mark
+// * the current session's book is stored on the volume (see warehouse.rs) and carried by Genesis
+//   events, its session id is the one the session controller runs with after a restart
+
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, I256, U256};
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AccountPnl {
+    pub position: I256,   // signed eth position, negative when short
+    pub cost_basis: I256, // signed usdc cost of the open position
+    pub realized: I256,   // usdc
+    pub volume: U256,     // usdc traded this session
+}
+impl AccountPnl {
+    /// Records a fill of `qty` eth for `volume` usdc.
+    pub fn record_fill(&mut self, is_buy: bool, qty: U256, volume: U256) {
+        if qty.is_zero() {
+            return;
+        }
+        self.volume += volume;
+        let direction = if is_buy { I256::ONE } else { I256::MINUS_ONE };
+        let qty_signed = I256::from_raw(qty);
+        let volume_signed = I256::from_raw(volume);
+
+        // opening or adding to a position
+        if self.position.is_zero() || self.position.is_negative() == !is_buy {
+            self.position += direction * qty_signed;
+            self.cost_basis += direction * volume_signed;
+            return;
+        }
+
+        // reducing a position, and possibly flipping it
+        let open_qty = self.position.abs();
+        let closing_qty = open_qty.min(qty_signed);
+        let closing_volume = volume_signed * closing_qty / qty_signed;
+        let cost_removed = self.cost_basis * closing_qty / open_qty;
+        let position_sign = if self.position.is_negative() {
+            I256::MINUS_ONE
+        } else {
+            I256::ONE
+        };
+        self.realized += position_sign * closing_volume - cost_removed;
+        self.cost_basis -= cost_removed;
+        self.position += direction * closing_qty;
+
+        let remaining_qty = qty_signed - closing_qty;
+        if !remaining_qty.is_zero() {
+            self.position = direction * remaining_qty;
+            self.cost_basis = direction * (volume_signed - closing_volume);
+        }
+    }
+
+    pub fn unrealized(&self, mark_price: U256) -> I256 {
+        self.position * I256::from_raw(mark_price) - self.cost_basis
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SessionResult {
+    pub address: Address,
+    pub realized: I256,
+    pub unrealized: I256,
+    pub total: I256,
+    pub volume: U256,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct PnlBook {
+    pub session_id: u64,
+    pub accounts: HashMap<Address, AccountPnl>,
+}
+
+impl PnlBook {
+    pub fn new(session_id: u64) -> Self {
+        PnlBook {
+            session_id,
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn record_fill(&mut self, address: Address, is_buy: bool, qty: U256, volume: U256) {
+        self.accounts
+            .entry(address)
+            .or_default()
+            .record_fill(is_buy, qty, volume);
+    }
+
+    /// Results for every account that traded this session, ranked by total pnl.
+    pub fn results(&self, mark_price: U256) -> Vec<SessionResult> {
+        let mut results: Vec<SessionResult> = self
+            .accounts
+            .iter()
+            .map(|(address, account)| {
+                let unrealized = account.unrealized(mark_price);
+                SessionResult {
+                    address: *address,
+                    realized: account.realized,
+                    unrealized,
+                    total: account.realized + unrealized,
+                    volume: account.volume,
+                }
+            })
+            .collect();
+        rank(&mut results);
+        results
+    }
+
+    /// Starts the next session. Open positions carry over with their cost reset to the mark
+    /// so the next session starts every account at zero pnl.
+    pub fn roll(&mut self, session_id: u64, mark_price: U256) {
+        self.session_id = session_id;
+        self.accounts = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| !account.position.is_zero())
+            .map(|(address, account)| {
+                (
+                    *address,
+                    AccountPnl {
+                        position: account.position,
+                        cost_basis: account.position * I256::from_raw(mark_price),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+    }
+}
+
+/// Sorts by total pnl, highest first. Ties are broken by address so rankings are stable.
+pub fn rank(results: &mut [SessionResult]) {
+    results.sort_by(|a, b| b.total.cmp(&a.total).then(a.address.cmp(&b.address)));
+}
+
+/// Leaderboard view of ranked results, only accounts that opted in expose their address and pnl.
+pub fn leaderboard_json(
+    session_id: u64,
+    results: &[SessionResult],
+    is_public: impl Fn(&Address) -> bool,
+    limit: usize,
+) -> String {
+    let entries: Vec<serde_json::Value> = results
+        .iter()
+        .take(limit)
+        .enumerate()
+        .map(|(index, result)| {
+            if is_public(&result.address) {
+                serde_json::json!({
+                    "rank": index + 1,
+                    "address": result.address.to_string(),
+                    "realized": result.realized.to_string(),
+                    "unrealized": result.unrealized.to_string(),
+                    "total": result.total.to_string(),
+                })
+            } else {
+                serde_json::json!({
+                    "rank": index + 1,
+                    "private": true,
+                })
+            }
+        })
+        .collect();
+    serde_json::to_string(&serde_json::json!({
+        "session_id": session_id.to_string(),
+        "entries": entries,
+    }))
+    .unwrap()
+}
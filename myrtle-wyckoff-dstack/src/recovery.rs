@@ -0,0 +1,78 @@
+// Overview:
+// Recovery mode, rebuilds the Warehouse from the latest checkpoint on the Checkpointer contract so
+// the app can be brought back up in any dstack container running this application.
+// * the inventory blob is decrypted with the HKDF derived key, which any container for this app gets
+// * the decrypted inventories are checked against the checkpoint's inventory root
+// * pending settlement orders are restored from the checkpoint
+// * resting orders aren't checkpointed so the book starts empty, and what they locked is released,
+//   the deposit registry address still has to be set through /contract-addresses
+// * checkpointing resumes from the checkpoint's next nonce, whichever sink it's posted to
+
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, U256};
+
+use optimized_lob::quantity::Qty;
+
+use crate::{
+    cowswap::CowSwapOrder,
+    errors::MwError,
+    merkle::InventoryCommitment,
+    rpc::ChainClient,
+    warehouse::{mainnet_deposits, Inventory, Warehouse},
+};
+
+/// Replaces the warehouse's inventories and settlement orders with the latest checkpoint.
+/// Returns the nonce the next checkpoint will be posted with.
+pub async fn recover<C: ChainClient>(
+    warehouse: &mut Warehouse,
+    client: &C,
+    checkpoint_contract: Address,
+) -> Result<U256, Box<dyn std::error::Error>> {
+    let checkpoint = client.latest_checkpoint(checkpoint_contract).await?;
+    let checkpoint_nonce = checkpoint.next_nonce;
+    if checkpoint_nonce.is_zero() {
+        return Err(Box::new(MwError::SnapshotError(
+            "no checkpoint to recover from".to_string(),
+        )));
+    }
+
+    let blob = warehouse.decrypt_inventory(&checkpoint.inventory_state)?;
+    if blob.checkpoint_nonce + U256::from(1) != checkpoint_nonce {
+        return Err(Box::new(MwError::SnapshotError(
+            "inventory blob is from a different checkpoint".to_string(),
+        )));
+    }
+    let mut inventories: HashMap<Address, Inventory> = blob
+        .inventories
+        .into_iter()
+        .map(|inventory| (inventory.address, inventory))
+        .collect();
+    if InventoryCommitment::new(&inventories).root() != checkpoint.inventory_root {
+        return Err(Box::new(MwError::SnapshotError(
+            "inventory root mismatch".to_string(),
+        )));
+    }
+    // the orders these were locked for didn't make it into the checkpoint
+    for inventory in inventories.values_mut() {
+        inventory.eth_liabilities = Qty(U256::ZERO);
+        inventory.usdc_liabilities = Qty(U256::ZERO);
+    }
+    let settlement_orders: Vec<CowSwapOrder> = checkpoint
+        .settlement_orders
+        .iter()
+        .map(|order| serde_json::from_str(order))
+        .collect::<Result<_, _>>()?;
+
+    // checkpoints don't carry deposits per chain, a volume that has none takes them as mainnet's
+    if warehouse.chain_deposits.is_empty() {
+        warehouse.chain_deposits = mainnet_deposits(&inventories);
+    }
+    warehouse.inventories = inventories;
+    warehouse.settlement_orders = settlement_orders;
+    warehouse.oid_qty_by_address.clear();
+    warehouse.address_by_oid.clear();
+    warehouse.checkpoint_contract = checkpoint_contract;
+    warehouse.resumed_checkpoint_nonce = checkpoint_nonce;
+    Ok(checkpoint_nonce)
+}
@@ -0,0 +1,294 @@
+// Overview:
+// Hot standby. A replica follows the leader's event log as it's written and applies every record to
+// its own Warehouse and OrderBookManager, so if the leader dies a replica can take over without
+// losing a trade.
+// * the leader streams its records at /replication/<after> as server-sent events, first whatever is
+//   already in its log after `after`, then every record as it's appended
+// * the channel is authenticated with a replication key HKDF derived from the shared secret (see
+//   keys.rs), which only containers of this app get: requests carry a short lived token sealed
+//   with it and every record is sealed with it, so nobody else can follow the leader, read its
+//   orders or feed a replica records
+// * a replica applies records through its sequencer in seq order and stops at the first one whose
+//   outcome or state hash it can't reproduce (see sequencer.rs)
+// * a dropped connection, or a replica that fell too far behind the live stream, reconnects from
+//   its last seq
+// * /promote, signed by the operator (see session.rs), makes a replica the leader once its state
+//   hash matches the old leader's last one, asked from the old leader if it's still up or given
+//   by whoever promotes, then it claims the contracts for its own leader key so the old leader
+//   can't post anymore (see leader.rs)
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use aes_gcm::{Aes256Gcm, Key};
+use alloy::{hex, primitives::B256, transports::http::reqwest::Url};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{
+    clock::now_ms,
+    errors::MwError,
+    events::EventRecord,
+    keys,
+    leader::InstanceRole,
+    sequencer::{Command, SequencerHandle},
+};
+
+pub const REQUEST_TOKEN_TTL_MS: u64 = 30_000;
+pub const DEFAULT_RETRY_MS: u64 = 1_000;
+const REQUEST_LABEL: &str = "replication-request";
+const RECORD_LABEL: &str = "replication-record";
+
+fn replication_error(message: impl Into<String>) -> MwError {
+    MwError::ReplicationError(message.into())
+}
+
+/// Token a replica asks for the records after `after` with, issued at `now`.
+pub fn request_token(key: &Key<Aes256Gcm>, after: u64, now: u64) -> Result<String, MwError> {
+    let mut request = after.to_be_bytes().to_vec();
+    request.extend_from_slice(&now.to_be_bytes());
+    Ok(hex::encode(keys::seal(key, REQUEST_LABEL, &request)?))
+}
+
+/// Checks the token was sealed with the replication key for `after` and isn't stale.
+pub fn check_request_token(
+    key: &Key<Aes256Gcm>,
+    token: &str,
+    after: u64,
+    now: u64,
+) -> Result<(), MwError> {
+    let sealed = hex::decode(token).map_err(|_| MwError::UnauthorizedAccess)?;
+    let request =
+        keys::unseal(key, REQUEST_LABEL, &sealed).map_err(|_| MwError::UnauthorizedAccess)?;
+    if request.len() != 16 {
+        return Err(MwError::UnauthorizedAccess);
+    }
+    let token_after = u64::from_be_bytes(request[..8].try_into().unwrap());
+    let issued_at = u64::from_be_bytes(request[8..].try_into().unwrap());
+    if token_after != after || now.abs_diff(issued_at) > REQUEST_TOKEN_TTL_MS {
+        return Err(MwError::UnauthorizedAccess);
+    }
+    Ok(())
+}
+
+/// A record as sent over the stream, hex of the sealed json.
+pub fn seal_record(key: &Key<Aes256Gcm>, record: &EventRecord) -> Result<String, MwError> {
+    let json = serde_json::to_vec(record).map_err(|e| replication_error(e.to_string()))?;
+    Ok(hex::encode(keys::seal(key, RECORD_LABEL, &json)?))
+}
+
+pub fn open_record(key: &Key<Aes256Gcm>, frame: &str) -> Result<EventRecord, MwError> {
+    let sealed = hex::decode(frame.trim()).map_err(|_| replication_error("frame isn't hex"))?;
+    let json = keys::unseal(key, RECORD_LABEL, &sealed)?;
+    serde_json::from_slice(&json).map_err(|e| replication_error(e.to_string()))
+}
+
+/// Records after `after` already in the log at `path`. A last line the sequencer is still writing
+/// is left for the live stream.
+pub fn backlog(path: impl AsRef<Path>, after: u64) -> Result<Vec<EventRecord>, MwError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| replication_error(e.to_string()))?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: EventRecord =
+            serde_json::from_str(&line).map_err(|e| replication_error(e.to_string()))?;
+        if record.seq > after {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// The leader's side of a replica's stream: sealed records after `after`, from the log and then
+/// live off the sequencer.
+pub struct RecordFeed {
+    key: Key<Aes256Gcm>,
+    backlog: VecDeque<EventRecord>,
+    live: broadcast::Receiver<Arc<EventRecord>>,
+    last_seq: u64,
+}
+impl RecordFeed {
+    pub fn open(
+        sequencer: &SequencerHandle,
+        log_path: impl AsRef<Path>,
+        key: Key<Aes256Gcm>,
+        after: u64,
+    ) -> Result<Self, MwError> {
+        // subscribe before reading the log so nothing appended in between is missed, whatever
+        // shows up in both is skipped by seq
+        let live = sequencer.subscribe();
+        let seq = sequencer.snapshot().seq;
+        if after > seq {
+            return Err(replication_error(format!(
+                "the replica is at seq {}, ahead of the leader at {}",
+                after, seq
+            )));
+        }
+        Ok(RecordFeed {
+            key,
+            backlog: backlog(log_path, after)?.into(),
+            live,
+            last_seq: after,
+        })
+    }
+
+    /// The next sealed record, None once the replica has to reconnect: it fell too far behind
+    /// the live stream or the sequencer is gone.
+    pub async fn next(&mut self) -> Option<String> {
+        loop {
+            let record = match self.backlog.pop_front() {
+                Some(record) => Arc::new(record),
+                None => self.live.recv().await.ok()?,
+            };
+            if record.seq <= self.last_seq {
+                continue;
+            }
+            if record.seq != self.last_seq + 1 {
+                return None;
+            }
+            self.last_seq = record.seq;
+            return match seal_record(&self.key, &record) {
+                Ok(frame) => Some(frame),
+                Err(e) => {
+                    warn!("failed to seal record {}: {}", record.seq, e);
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// Splits a server-sent event stream into the data of each event, comments (keep-alives) and
+/// other fields are dropped.
+#[derive(Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>,
+    data: String,
+}
+impl EventStreamDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(std::mem::take(&mut self.data));
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                if !self.data.is_empty() {
+                    self.data.push('\n');
+                }
+                self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+            }
+        }
+        events
+    }
+}
+
+/// Opens a frame from the leader and applies it through the replica's sequencer, returning the
+/// record's seq.
+pub async fn apply_frame(
+    sequencer: &SequencerHandle,
+    key: &Key<Aes256Gcm>,
+    frame: &str,
+) -> Result<u64, MwError> {
+    let record = open_record(key, frame)?;
+    Ok(sequencer.submit(Command::Replicate { record }).await?.seq)
+}
+
+/// Where a replica follows the leader from.
+#[derive(Clone)]
+pub struct Follower {
+    pub leader_url: Url, // the leader's app root
+    pub key: Key<Aes256Gcm>,
+}
+impl Follower {
+    fn leader_endpoint(&self, path: &str) -> Result<Url, MwError> {
+        self.leader_url
+            .join(path)
+            .map_err(|e| replication_error(format!("leader url: {}", e)))
+    }
+
+    /// The leader's last seq and state hash, from its /sequence.
+    pub async fn leader_sequence(&self) -> Result<(u64, B256), MwError> {
+        let sequence: serde_json::Value = reqwest::get(self.leader_endpoint("sequence")?)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| replication_error(format!("leader: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| replication_error(format!("leader: {}", e)))?;
+        let seq = sequence["seq"].as_str().and_then(|seq| seq.parse().ok());
+        let state_hash = sequence["state_hash"]
+            .as_str()
+            .and_then(|hash| B256::from_str(hash).ok());
+        match (seq, state_hash) {
+            (Some(seq), Some(state_hash)) => Ok((seq, state_hash)),
+            _ => Err(replication_error("leader sent an invalid sequence")),
+        }
+    }
+
+    /// Streams from the leader starting after the replica's seq and applies every record until
+    /// the stream ends. Returns how many were applied.
+    pub async fn follow_once(&self, sequencer: &SequencerHandle) -> Result<u64, MwError> {
+        let after = sequencer.snapshot().seq;
+        let mut url = self.leader_endpoint(&format!("replication/{}", after))?;
+        url.query_pairs_mut()
+            .append_pair("token", &request_token(&self.key, after, now_ms())?);
+        let mut response = reqwest::get(url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| replication_error(format!("leader: {}", e)))?;
+        let mut decoder = EventStreamDecoder::default();
+        let mut applied = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| replication_error(format!("leader: {}", e)))?
+        {
+            for frame in decoder.push(&chunk) {
+                apply_frame(sequencer, &self.key, &frame).await?;
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+/// Follows the leader until this replica is promoted, reconnecting after `retry` whenever the
+/// stream ends or fails.
+pub fn spawn(follower: Follower, sequencer: SequencerHandle, retry: Duration) {
+    tokio::spawn(async move {
+        while sequencer.snapshot().role == InstanceRole::Replica {
+            match follower.follow_once(&sequencer).await {
+                Ok(applied) => info!("leader stream ended after {} records", applied),
+                Err(e) => warn!("following the leader failed: {}", e),
+            }
+            tokio::time::sleep(retry).await;
+        }
+        info!("promoted, stopped following the leader");
+    });
+}
@@ -0,0 +1,288 @@
+// Overview:
+// Everything the app reads from or sends to a chain goes through ChainClient, so nothing else names
+// alloy's provider type and tests can run against an in-memory chain (see fakechain.rs).
+// * AlloyClient is the real one, an HTTP provider with the app's signer as its wallet
+// * deposit chains get a client per configured endpoint (see chains.rs), the checkpoint chain one
+//   for RPC_URL
+// * only the calls the app makes are here: deposit logs, deposits and storage proofs on the
+//   registries, the settlement nonce, domain separators, the elected leader, the Checkpointer's
+//   nonce and latest checkpoint, and sending the transactions checkpoint sinks and leader claims
+//   post
+// * every RPC failure comes back as MwError::RpcError, callers decide whether it's fatal
+
+use std::future::Future;
+
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    network::{Ethereum, EthereumWallet},
+    primitives::{Address, TxHash, B256, U256},
+    providers::{Provider as _, ProviderBuilder, RootProvider},
+    rpc::{
+        client::ClientBuilder,
+        types::{EIP1186AccountProofResponse, Filter, TransactionRequest},
+    },
+    signers::local::PrivateKeySigner,
+    sol_types::SolEvent,
+    transports::http::{reqwest::Url, Client, Http},
+};
+
+use crate::{
+    artifacts::{ICheckpointer, IDepositRegistry},
+    errors::MwError,
+    gulper::IndexedDeposit,
+};
+
+/// What the Checkpointer holds after its latest checkpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredCheckpoint {
+    pub next_nonce: U256, // zero if nothing was ever checkpointed
+    pub inventory_root: B256,
+    pub inventory_state: Vec<u8>,
+    pub settlement_orders: Vec<String>,
+}
+
+/// The calls the app makes to a chain.
+pub trait ChainClient: Send + Sync {
+    fn block_number(&self) -> impl Future<Output = Result<u64, MwError>> + Send;
+    /// Hash and state root of block `number`, None if the endpoint doesn't have it.
+    fn block_roots(
+        &self,
+        number: u64,
+    ) -> impl Future<Output = Result<Option<(B256, B256)>, MwError>> + Send;
+    /// Deposits `registry` logged in `from_block..=to_block`, in the order they were made.
+    fn deposit_logs(
+        &self,
+        registry: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> impl Future<Output = Result<Vec<IndexedDeposit>, MwError>> + Send;
+    /// Amounts of deposit_registry[user][next_index..] as of `block`.
+    fn get_deposits(
+        &self,
+        registry: Address,
+        user: Address,
+        next_index: u32,
+        block: u64,
+    ) -> impl Future<Output = Result<Vec<[U256; 2]>, MwError>> + Send;
+    /// eth_getProof for `keys` in `contract`'s storage at the block with `block_hash`.
+    fn storage_proof(
+        &self,
+        contract: Address,
+        keys: Vec<B256>,
+        block_hash: B256,
+    ) -> impl Future<Output = Result<EIP1186AccountProofResponse, MwError>> + Send;
+    /// Nonce the registry expects the next pull_settlement_funds order to have.
+    fn settlement_nonce(
+        &self,
+        registry: Address,
+    ) -> impl Future<Output = Result<U256, MwError>> + Send;
+    /// Zero if the contract's separator hasn't been set.
+    fn domain_separator(
+        &self,
+        contract: Address,
+    ) -> impl Future<Output = Result<B256, MwError>> + Send;
+    /// The leader a Checkpointer or registry takes leader signatures from, zero if none was set.
+    fn leader(&self, contract: Address) -> impl Future<Output = Result<Address, MwError>> + Send;
+    fn checkpoint_nonce(
+        &self,
+        checkpointer: Address,
+    ) -> impl Future<Output = Result<U256, MwError>> + Send;
+    fn latest_checkpoint(
+        &self,
+        checkpointer: Address,
+    ) -> impl Future<Output = Result<StoredCheckpoint, MwError>> + Send;
+    fn transaction_count(
+        &self,
+        sender: Address,
+    ) -> impl Future<Output = Result<u64, MwError>> + Send;
+    fn gas_price(&self) -> impl Future<Output = Result<u128, MwError>> + Send;
+    /// Signs and broadcasts `tx`, returns as soon as it's accepted.
+    fn send_transaction(
+        &self,
+        tx: TransactionRequest,
+    ) -> impl Future<Output = Result<TxHash, MwError>> + Send;
+    /// Whether the transaction succeeded, None until it's mined.
+    fn transaction_status(
+        &self,
+        hash: TxHash,
+    ) -> impl Future<Output = Result<Option<bool>, MwError>> + Send;
+}
+
+// for errors without a From, transport and contract errors convert with ? (see errors.rs)
+fn rpc_error(error: impl ToString) -> MwError {
+    MwError::RpcError(error.to_string())
+}
+
+type AlloyProvider = alloy::providers::fillers::FillProvider<
+    alloy::providers::fillers::JoinFill<
+        alloy::providers::fillers::JoinFill<
+            alloy::providers::Identity,
+            alloy::providers::fillers::JoinFill<
+                alloy::providers::fillers::GasFiller,
+                alloy::providers::fillers::JoinFill<
+                    alloy::providers::fillers::BlobGasFiller,
+                    alloy::providers::fillers::JoinFill<
+                        alloy::providers::fillers::NonceFiller,
+                        alloy::providers::fillers::ChainIdFiller,
+                    >,
+                >,
+            >,
+        >,
+        alloy::providers::fillers::WalletFiller<EthereumWallet>,
+    >,
+    RootProvider<Http<Client>>,
+    Http<Client>,
+    Ethereum,
+>;
+
+/// A chain reached over HTTP, transactions are signed with `signer`.
+pub struct AlloyClient {
+    provider: AlloyProvider,
+}
+
+impl AlloyClient {
+    pub fn connect(rpc_url: Url, signer: &PrivateKeySigner) -> Self {
+        AlloyClient {
+            provider: ProviderBuilder::new()
+                .with_recommended_fillers()
+                .wallet(EthereumWallet::from(signer.clone()))
+                .on_client(ClientBuilder::default().http(rpc_url)),
+        }
+    }
+}
+
+impl ChainClient for AlloyClient {
+    async fn block_number(&self) -> Result<u64, MwError> {
+        Ok(self.provider.get_block_number().await?)
+    }
+
+    async fn block_roots(&self, number: u64) -> Result<Option<(B256, B256)>, MwError> {
+        Ok(self
+            .provider
+            .get_block_by_number(BlockNumberOrTag::Number(number), false)
+            .await?
+            .map(|block| (block.header.hash, block.header.state_root)))
+    }
+
+    async fn deposit_logs(
+        &self,
+        registry: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<IndexedDeposit>, MwError> {
+        let filter = Filter::new()
+            .address(registry)
+            .event_signature(IDepositRegistry::Deposit::SIGNATURE_HASH)
+            .from_block(from_block)
+            .to_block(to_block);
+        let logs = self.provider.get_logs(&filter).await?;
+        logs.iter()
+            .map(|log| {
+                let deposit = log
+                    .log_decode::<IDepositRegistry::Deposit>()
+                    .map_err(rpc_error)?
+                    .inner
+                    .data;
+                Ok(IndexedDeposit {
+                    user: deposit.user,
+                    index: u32::try_from(deposit.index)
+                        .map_err(|_| rpc_error("deposit index out of range"))?,
+                    amounts: [deposit.eth_amount, deposit.usdc_amount],
+                })
+            })
+            .collect()
+    }
+
+    async fn get_deposits(
+        &self,
+        registry: Address,
+        user: Address,
+        next_index: u32,
+        block: u64,
+    ) -> Result<Vec<[U256; 2]>, MwError> {
+        Ok(IDepositRegistry::new(registry, &self.provider)
+            .get_deposits(next_index, user)
+            .block(BlockId::number(block))
+            .call()
+            .await?
+            ._0)
+    }
+
+    async fn storage_proof(
+        &self,
+        contract: Address,
+        keys: Vec<B256>,
+        block_hash: B256,
+    ) -> Result<EIP1186AccountProofResponse, MwError> {
+        Ok(self
+            .provider
+            .get_proof(contract, keys)
+            .block_id(BlockId::from(block_hash))
+            .await?)
+    }
+
+    async fn settlement_nonce(&self, registry: Address) -> Result<U256, MwError> {
+        Ok(IDepositRegistry::new(registry, &self.provider)
+            .settlement_nonce()
+            .call()
+            .await?
+            ._0)
+    }
+
+    async fn domain_separator(&self, contract: Address) -> Result<B256, MwError> {
+        // both contracts expose the same getter
+        Ok(ICheckpointer::new(contract, &self.provider)
+            .domain_separator()
+            .call()
+            .await?
+            ._0)
+    }
+
+    async fn leader(&self, contract: Address) -> Result<Address, MwError> {
+        // both contracts expose the same getter
+        Ok(ICheckpointer::new(contract, &self.provider)
+            .leader()
+            .call()
+            .await?
+            ._0)
+    }
+
+    async fn checkpoint_nonce(&self, checkpointer: Address) -> Result<U256, MwError> {
+        Ok(ICheckpointer::new(checkpointer, &self.provider)
+            .inventory_checkpoint_nonce()
+            .call()
+            .await?
+            ._0)
+    }
+
+    async fn latest_checkpoint(&self, checkpointer: Address) -> Result<StoredCheckpoint, MwError> {
+        let checkpointer = ICheckpointer::new(checkpointer, &self.provider);
+        Ok(StoredCheckpoint {
+            next_nonce: checkpointer.inventory_checkpoint_nonce().call().await?._0,
+            inventory_root: checkpointer.inventory_root().call().await?._0,
+            inventory_state: checkpointer.get_inventory_checkpoint().call().await?._0,
+            settlement_orders: checkpointer.get_settlement_orders().call().await?._0,
+        })
+    }
+
+    async fn transaction_count(&self, sender: Address) -> Result<u64, MwError> {
+        Ok(self.provider.get_transaction_count(sender).await?)
+    }
+
+    async fn gas_price(&self) -> Result<u128, MwError> {
+        Ok(self.provider.get_gas_price().await?)
+    }
+
+    async fn send_transaction(&self, tx: TransactionRequest) -> Result<TxHash, MwError> {
+        let pending = self.provider.send_transaction(tx).await?;
+        Ok(*pending.tx_hash())
+    }
+
+    async fn transaction_status(&self, hash: TxHash) -> Result<Option<bool>, MwError> {
+        Ok(self
+            .provider
+            .get_transaction_receipt(hash)
+            .await?
+            .map(|receipt| receipt.status()))
+    }
+}
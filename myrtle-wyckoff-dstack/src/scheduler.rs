@@ -0,0 +1,278 @@
+// Overview:
+// Background task that posts checkpoints to suave on an interval, replacing the external cron
+// hitting /take_snapshot.
+// * a tick is skipped if nothing was sequenced since the last posted checkpoint, unless the
+//   leader's lease on the Checkpointer is due for renewal (see leader.rs)
+// * checkpoints go to whichever CheckpointSink is configured (see sink.rs), which handles its own
+//   retries
+// * settlement orders are only cleared from the warehouse once the receipt confirms, and only the
+//   ones that were in the posted checkpoint. Until then they're in the event log, stored with the
+//   warehouse and carried by Genesis, so a restart between queueing and posting still posts them
+// * failed ticks back off exponentially, and the last success and last error are kept for
+//   /snapshot-status
+// * /take_snapshot still works as a manual trigger, posting is serialized so it can't race a tick
+// * replicas skip every tick until they're promoted, the leader's checkpoints cover their state
+// * the last posted inventory commitment is sealed to the data dir, so /inventory-proof keeps
+//   answering for it after a restart instead of waiting for the next checkpoint
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use aes_gcm::{Aes256Gcm, Key};
+use alloy::primitives::{TxHash, U256};
+use tracing::{info, warn};
+
+use crate::{
+    clock::now_ms,
+    config::StorageConfig,
+    errors::MwError,
+    keys,
+    leader::{InstanceRole, LEADER_LEASE_SECS},
+    merkle::InventoryCommitment,
+    sequencer::{Command, CommandOutput, SequencerHandle},
+    sink::CheckpointSink,
+    snapshotter::{self, SignedCheckpoint},
+};
+
+pub const DEFAULT_SNAPSHOT_INTERVAL_MS: u64 = 5_000;
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 300_000;
+// an idle leader posts the same state again this long after its last checkpoint, well inside the
+// lease
+pub const LEASE_RENEWAL_MS: u64 = LEADER_LEASE_SECS * 1000 / 2;
+// in the data dir, sealed with the volume key
+pub const COMMITMENT_STORAGE_FILE: &str = "inventory_commitment.sealed";
+
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    pub interval: Duration,
+    pub max_backoff: Duration,
+}
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            interval: Duration::from_millis(DEFAULT_SNAPSHOT_INTERVAL_MS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MS),
+        }
+    }
+}
+impl SchedulerConfig {
+    /// Delay before the next tick after `consecutive_failures` failed ticks in a row.
+    pub fn backoff(&self, consecutive_failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(consecutive_failures.min(16));
+        self.interval.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotStatus {
+    pub last_success_at: Option<u64>, // unix timestamp in milliseconds
+    pub last_tx_hash: Option<TxHash>,
+    pub last_checkpoint_nonce: Option<U256>,
+    pub last_posted_seq: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+    pub consecutive_failures: u32,
+}
+impl SnapshotStatus {
+    pub fn to_json(&self) -> String {
+        let serializable_status = serde_json::json!({
+            "last_success_at": self.last_success_at.map(|at| at.to_string()),
+            "last_tx_hash": self.last_tx_hash.map(|hash| hash.to_string()),
+            "last_checkpoint_nonce": self.last_checkpoint_nonce.map(|nonce| nonce.to_string()),
+            "last_posted_seq": self.last_posted_seq.map(|seq| seq.to_string()),
+            "last_error": self.last_error,
+            "last_error_at": self.last_error_at.map(|at| at.to_string()),
+            "consecutive_failures": self.consecutive_failures.to_string(),
+        });
+        serde_json::to_string(&serializable_status).unwrap()
+    }
+}
+
+/// Shared between the scheduler task and the http handlers.
+pub struct SnapshotTracker {
+    status: RwLock<SnapshotStatus>,
+    latest_commitment: RwLock<Option<Arc<InventoryCommitment>>>, // last confirmed inventory commitment
+    posting: tokio::sync::Mutex<()>,
+    store: Option<CommitmentStore>, // None keeps the commitment in memory only
+}
+
+struct CommitmentStore {
+    path: PathBuf,
+    volume_key: Key<Aes256Gcm>,
+}
+impl CommitmentStore {
+    fn load(&self) -> Result<Option<InventoryCommitment>, MwError> {
+        let sealed = match std::fs::read(&self.path) {
+            Ok(sealed) => sealed,
+            Err(_) => return Ok(None),
+        };
+        let bytes = keys::unseal(&self.volume_key, COMMITMENT_STORAGE_FILE, &sealed)?;
+        InventoryCommitment::from_bytes(&bytes).map(Some)
+    }
+
+    fn store(&self, commitment: &InventoryCommitment) -> Result<(), MwError> {
+        let sealed = keys::seal(
+            &self.volume_key,
+            COMMITMENT_STORAGE_FILE,
+            &commitment.to_bytes(),
+        )?;
+        let staged = self.path.with_extension("sealed.tmp");
+        std::fs::write(&staged, sealed).map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        std::fs::rename(&staged, &self.path).map_err(|e| MwError::SnapshotError(e.to_string()))
+    }
+}
+
+impl Default for SnapshotTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl SnapshotTracker {
+    pub fn new() -> Self {
+        SnapshotTracker {
+            status: RwLock::new(SnapshotStatus::default()),
+            latest_commitment: RwLock::new(None),
+            posting: tokio::sync::Mutex::new(()),
+            store: None,
+        }
+    }
+
+    /// A tracker that keeps the latest commitment in the data dir, starting from the one stored
+    /// there. One that can't be read is dropped, proofs resume with the next checkpoint.
+    pub fn load(storage: &StorageConfig, volume_key: Key<Aes256Gcm>) -> Self {
+        let store = CommitmentStore {
+            path: storage.data_path(COMMITMENT_STORAGE_FILE),
+            volume_key,
+        };
+        let latest_commitment = store.load().unwrap_or_else(|e| {
+            warn!("failed to load the last inventory commitment: {}", e);
+            None
+        });
+        SnapshotTracker {
+            latest_commitment: RwLock::new(latest_commitment.map(Arc::new)),
+            store: Some(store),
+            ..Self::new()
+        }
+    }
+
+    pub fn status(&self) -> SnapshotStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn latest_commitment(&self) -> Option<Arc<InventoryCommitment>> {
+        self.latest_commitment.read().unwrap().clone()
+    }
+
+    /// Posts a checkpoint if anything changed since the last one or the lease is due for renewal.
+    /// Returns whether one was posted.
+    pub async fn run_once<S: CheckpointSink>(
+        &self,
+        sequencer: &SequencerHandle,
+        sink: &S,
+    ) -> Result<bool, MwError> {
+        let _posting = self.posting.lock().await;
+        let snapshot = sequencer.snapshot();
+        let status = self.status();
+        let renewal_due = status
+            .last_success_at
+            .is_some_and(|at| now_ms() >= at + LEASE_RENEWAL_MS);
+        if snapshot.role != InstanceRole::Leader
+            || !sink.is_ready(snapshot.checkpoint_contract)
+            || (status.last_posted_seq == Some(snapshot.seq) && !renewal_due)
+        {
+            return Ok(false);
+        }
+
+        let result = self.post_checkpoint(sequencer, sink).await;
+        let mut status = self.status.write().unwrap();
+        match &result {
+            Ok(()) => status.consecutive_failures = 0,
+            Err(error) => {
+                warn!("checkpoint failed: {}", error);
+                status.last_error = Some(error.to_string());
+                status.last_error_at = Some(now_ms());
+                status.consecutive_failures += 1;
+            }
+        }
+        result.map(|_| true)
+    }
+
+    async fn post_checkpoint<S: CheckpointSink>(
+        &self,
+        sequencer: &SequencerHandle,
+        sink: &S,
+    ) -> Result<(), MwError> {
+        let applied = sequencer.submit(Command::PrepareCheckpoint).await?;
+        let CommandOutput::Checkpoint(draft) = applied.output else {
+            unreachable!()
+        };
+        // a sink that lost count, like a calldata sink on a new volume, resumes after recovery
+        let checkpoint_nonce = sink
+            .next_nonce(draft.checkpoint_contract)
+            .await?
+            .max(draft.min_nonce);
+        let signed = snapshotter::sign(draft, checkpoint_nonce)
+            .await
+            .map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        let tx_hash = sink.publish(&signed).await?;
+
+        // the checkpoint is durable, so the orders it carried have been published and can be dropped
+        let mut posted_seq = applied.seq;
+        if signed.settlement_order_count > 0 {
+            posted_seq = sequencer
+                .submit(Command::ClearSettlementOrders {
+                    count: signed.settlement_order_count,
+                })
+                .await?
+                .seq;
+        }
+        info!(
+            "posted checkpoint {} at seq {}",
+            signed.checkpoint.nonce, applied.seq
+        );
+
+        let SignedCheckpoint {
+            inventory_commitment,
+            checkpoint,
+            ..
+        } = signed;
+        // the checkpoint is already posted, failing to store its commitment only costs proofs
+        // for it after a restart
+        if let Some(Err(e)) = self
+            .store
+            .as_ref()
+            .map(|store| store.store(&inventory_commitment))
+        {
+            warn!("failed to store the inventory commitment: {}", e);
+        }
+        *self.latest_commitment.write().unwrap() = Some(Arc::new(inventory_commitment));
+        let mut status = self.status.write().unwrap();
+        status.last_success_at = Some(now_ms());
+        status.last_tx_hash = tx_hash.or(status.last_tx_hash);
+        status.last_checkpoint_nonce = Some(checkpoint.nonce);
+        status.last_posted_seq = Some(posted_seq);
+        Ok(())
+    }
+}
+
+/// Runs the scheduler until the process exits.
+pub fn spawn<S: CheckpointSink + Send + Sync + 'static>(
+    tracker: Arc<SnapshotTracker>,
+    sequencer: SequencerHandle,
+    sink: Arc<S>,
+    config: SchedulerConfig,
+) {
+    tokio::spawn(async move {
+        let mut delay = config.interval;
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = match tracker.run_once(&sequencer, sink.as_ref()).await {
+                Ok(_) => config.interval,
+                Err(_) => config.backoff(tracker.status().consecutive_failures),
+            };
+        }
+    });
+}
@@ -0,0 +1,918 @@
+// Overview:
+// Single writer sequencer. A dedicated task owns the Jtrain (Warehouse, OrderBookManager and
+// session) and applies commands one at a time off a channel, so there is no lock around the
+// matching engine and a slow reader can never block a fill.
+// * every accepted state change is assigned the next global sequence number
+// * after each state change a read-only StateSnapshot is published, queries are served from the
+//   latest snapshot rather than the live state
+// * a snapshot only copies the parts of the state the event could have changed, the rest is
+//   shared with the previous snapshot
+// * RPC calls (quotes, deposit logs, checkpoint posting) happen outside the sequencer, the
+//   sequencer only ever receives their results
+// * commands are authorized here and turned into events, state only changes by applying events
+//   (see events.rs), and every applied event is appended to the event log with its seq
+// * the log is what makes state durable, the warehouse and session are only written to the volume
+//   when a checkpoint is prepared, and a restart replays the log on top of them
+// * every appended record is also broadcast, which is what the leader streams to replicas
+// * a replica's sequencer (spawn_replica) refuses commands and only applies the leader's records,
+//   checking each one's state hash, until it's promoted (see replication.rs)
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use alloy::{
+    primitives::{Address, B256, U256},
+    signers::{Signature, Signer},
+};
+use optimized_lob::{order::OrderId, quantity::Qty, utils::BookId};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tracing::{info, warn};
+
+use crate::{
+    artifacts::IDepositRegistry,
+    batch::{BatchResult, OrderBatch},
+    chains::ChainSpec,
+    clock::now_ms,
+    closer::CloseReport,
+    cowswap::CowSwapOrder,
+    errors::MwError,
+    events::{self, Event, EventLog, EventRecord, EVENT_LOG_STORAGE_FILE, STATE_HASH_VERSION},
+    gulper::IndexedDeposit,
+    jtrain::Jtrain,
+    leader::InstanceRole,
+    oracle,
+    orderhere::{CancelAll, CancelOrder, Order},
+    pnl::{PnlBook, SessionResult},
+    session::{SessionAction, SessionController},
+    settler::{self, create_settlement_order},
+    snapshotter::CheckpointDraft,
+    structs::UserRequest,
+    warehouse::Inventory,
+};
+
+const COMMAND_QUEUE_SIZE: usize = 1024;
+const RECORD_BROADCAST_SIZE: usize = 4096; // a replica further behind than this reconnects
+
+pub enum Command {
+    NewOrder {
+        user: Address,
+        order: Order,
+        signature: Signature,
+    },
+    CancelOrder {
+        user: Address,
+        cancel: CancelOrder,
+        signature: Signature,
+    },
+    CancelAll {
+        user: Address,
+        cancel: CancelAll,
+        signature: Signature,
+    },
+    ReplaceOrder {
+        user: Address,
+        order: Order,
+        oid: OrderId,
+        signature: Signature,
+    },
+    Batch {
+        user: Address,
+        batch: OrderBatch,
+        signature: Signature,
+    },
+    NewSettlementOrder {
+        chain: ChainSpec, // as configured, the tokens and settlement contract can be overridden
+        user: Address,
+        order: IDepositRegistry::Order,
+        signature: Signature,
+    },
+    CreditDeposits {
+        chain_id: u64,
+        user: Address,
+        deposits: Vec<IndexedDeposit>,
+    },
+    IndexDeposits {
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+        deposits: Vec<IndexedDeposit>,
+    },
+    SetContractAddresses {
+        chain_id: u64,
+        deposit_contract: Address,
+        checkpoint_contract: Address,
+    },
+    SetPnlVisibility {
+        user: Address,
+        request: UserRequest,
+        signature: Signature,
+    },
+    CloseSession {
+        mark_price: U256,
+    },
+    FinalizeSession {
+        mark_price: U256,
+    },
+    // sent by the snapshot scheduler once a checkpoint with the first `count` orders confirms
+    ClearSettlementOrders {
+        count: usize,
+    },
+    // a record streamed from the leader, only accepted by a replica
+    Replicate {
+        record: EventRecord,
+    },
+    // makes a replica the leader if its state is at the expected seq and state hash, when given
+    Promote {
+        expected: Option<(u64, B256)>,
+    },
+    // makes the leader a replica once another container holds the Checkpointer's lease, see
+    // leader.rs
+    StepDown,
+    // read only, doesn't get a sequence number
+    PrepareCheckpoint,
+}
+pub enum CommandOutput {
+    Started,
+    OrderPlaced((Qty, Qty, Option<OrderId>)),
+    OrderCancelled,
+    OrdersCancelled(Vec<u32>),
+    OrderReplaced(OrderId),
+    BatchExecuted(Vec<BatchResult>),
+    SettlementOrderAdded,
+    DepositsCredited([U256; 2]),
+    DepositsIndexed(usize),
+    ContractAddressesSet,
+    PnlVisibilitySet,
+    SessionClosed(CloseReport),
+    SessionFinalized(u64),
+    SettlementOrdersCleared,
+    Replicated,
+    Promoted(B256), // state hash the new leader starts from
+    SteppedDown,
+    Checkpoint(CheckpointDraft),
+}
+
+pub struct Applied {
+    pub seq: u64,
+    pub output: CommandOutput,
+}
+
+struct Envelope {
+    command: Command,
+    reply: oneshot::Sender<Result<Applied, MwError>>,
+}
+
+/// Read only view of the state as of sequence number `seq`. The larger parts are shared between
+/// snapshots until an event changes them.
+pub struct StateSnapshot {
+    pub seq: u64,
+    pub state_hash: B256,
+    pub role: InstanceRole,
+    pub signer_address: Address,
+    pub leader_address: Address, // this container's, see leader.rs
+    pub deposit_contracts: BTreeMap<u64, Address>,
+    pub deposit_cursors: BTreeMap<u64, u64>,
+    pub checkpoint_contract: Address,
+    pub inventories: Arc<HashMap<Address, Inventory>>,
+    pub orders: Arc<HashMap<Address, Vec<Order>>>,
+    pub settlement_orders: Arc<Vec<CowSwapOrder>>,
+    pub book_mids: Vec<Option<U256>>, // indexed by book id
+    pub session: SessionController,
+    pub pnl: Arc<PnlBook>,
+    pub session_results: Arc<HashMap<u64, Vec<SessionResult>>>,
+    pub public_pnl: Arc<HashSet<Address>>,
+}
+
+/// The shared parts of a snapshot an event can change.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SnapshotChanges {
+    pub inventories: bool,
+    pub orders: bool,
+    pub settlement_orders: bool,
+    pub pnl: bool,
+    pub session_results: bool,
+    pub public_pnl: bool,
+}
+impl SnapshotChanges {
+    pub const ALL: Self = SnapshotChanges {
+        inventories: true,
+        orders: true,
+        settlement_orders: true,
+        pnl: true,
+        session_results: true,
+        public_pnl: true,
+    };
+    const TRADING: Self = SnapshotChanges {
+        inventories: true,
+        orders: true,
+        settlement_orders: false,
+        pnl: true,
+        session_results: false,
+        public_pnl: false,
+    };
+    const DEPOSITS: Self = SnapshotChanges {
+        inventories: true,
+        orders: false,
+        settlement_orders: false,
+        pnl: false,
+        session_results: false,
+        public_pnl: false,
+    };
+
+    /// What applying `event` can change, whether or not it's rejected part way.
+    pub fn of(event: &Event) -> Self {
+        match event {
+            Event::Genesis { .. } => Self::ALL,
+            Event::NewOrder { .. }
+            | Event::CancelOrder { .. }
+            | Event::CancelAll { .. }
+            | Event::ReplaceOrder { .. }
+            | Event::Batch { .. }
+            | Event::CloseSession { .. } => Self::TRADING,
+            Event::Deposit { .. } | Event::Gulp { .. } | Event::DepositsIndexed { .. } => {
+                Self::DEPOSITS
+            }
+            Event::Settlement { .. } => SnapshotChanges {
+                inventories: true,
+                settlement_orders: true,
+                ..Self::default()
+            },
+            Event::ClearSettlementOrders { .. } => SnapshotChanges {
+                settlement_orders: true,
+                ..Self::default()
+            },
+            Event::SetContractAddresses { .. } => Self::default(),
+            Event::SetPnlVisibility { .. } => SnapshotChanges {
+                public_pnl: true,
+                ..Self::default()
+            },
+            Event::FinalizeSession { .. } => SnapshotChanges {
+                pnl: true,
+                session_results: true,
+                public_pnl: true,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+// the previous snapshot's part if it didn't change, a fresh copy of the live state if it did
+fn share<T>(previous: &Arc<T>, changed: bool, live: impl FnOnce() -> T) -> Arc<T> {
+    match changed {
+        true => Arc::new(live()),
+        false => previous.clone(),
+    }
+}
+
+impl StateSnapshot {
+    fn capture(seq: u64, state_hash: B256, role: InstanceRole, jtrain: &Jtrain) -> Self {
+        let warehouse = &jtrain.warehouse;
+        StateSnapshot {
+            seq,
+            state_hash,
+            role,
+            signer_address: warehouse.signer.address(),
+            leader_address: warehouse.leader.address(),
+            deposit_contracts: warehouse.deposit_contracts.clone(),
+            deposit_cursors: warehouse.deposit_cursors.clone(),
+            checkpoint_contract: warehouse.checkpoint_contract,
+            inventories: Arc::new(warehouse.inventories.clone()),
+            orders: Arc::new(resting_orders(jtrain)),
+            settlement_orders: Arc::new(warehouse.settlement_orders.clone()),
+            book_mids: book_mids(jtrain),
+            session: jtrain.session.clone(),
+            pnl: Arc::new(warehouse.pnl.clone()),
+            session_results: Arc::new(warehouse.session_results.clone()),
+            public_pnl: Arc::new(warehouse.public_pnl.clone()),
+        }
+    }
+
+    /// The next snapshot, copying only what `changes` says moved since this one.
+    fn update(
+        &self,
+        seq: u64,
+        state_hash: B256,
+        role: InstanceRole,
+        jtrain: &Jtrain,
+        changes: SnapshotChanges,
+    ) -> Self {
+        let warehouse = &jtrain.warehouse;
+        StateSnapshot {
+            seq,
+            state_hash,
+            role,
+            signer_address: warehouse.signer.address(),
+            leader_address: warehouse.leader.address(),
+            deposit_contracts: warehouse.deposit_contracts.clone(),
+            deposit_cursors: warehouse.deposit_cursors.clone(),
+            checkpoint_contract: warehouse.checkpoint_contract,
+            inventories: share(&self.inventories, changes.inventories, || {
+                warehouse.inventories.clone()
+            }),
+            orders: share(&self.orders, changes.orders, || resting_orders(jtrain)),
+            settlement_orders: share(&self.settlement_orders, changes.settlement_orders, || {
+                warehouse.settlement_orders.clone()
+            }),
+            book_mids: book_mids(jtrain),
+            session: jtrain.session.clone(),
+            pnl: share(&self.pnl, changes.pnl, || warehouse.pnl.clone()),
+            session_results: share(&self.session_results, changes.session_results, || {
+                warehouse.session_results.clone()
+            }),
+            public_pnl: share(&self.public_pnl, changes.public_pnl, || {
+                warehouse.public_pnl.clone()
+            }),
+        }
+    }
+
+    pub fn deposit_contract(&self, chain_id: u64) -> Address {
+        self.deposit_contracts
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn deposit_cursor(&self, chain_id: u64) -> Option<u64> {
+        self.deposit_cursors.get(&chain_id).copied()
+    }
+
+    pub fn book_mid(&self, book_id: BookId) -> Option<U256> {
+        self.book_mids
+            .get(book_id.value() as usize)
+            .copied()
+            .flatten()
+    }
+}
+
+fn resting_orders(jtrain: &Jtrain) -> HashMap<Address, Vec<Order>> {
+    let warehouse = &jtrain.warehouse;
+    warehouse
+        .oid_qty_by_address
+        .keys()
+        .filter_map(|user| {
+            warehouse
+                .get_orders(&jtrain.orderbook_manager, *user)
+                .ok()
+                .map(|orders| (*user, orders))
+        })
+        .collect()
+}
+
+fn book_mids(jtrain: &Jtrain) -> Vec<Option<U256>> {
+    (0..jtrain.orderbook_manager.books.len())
+        .map(|index| oracle::book_mid(&jtrain.orderbook_manager, BookId(index as u32)))
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct SequencerHandle {
+    commands: mpsc::Sender<Envelope>,
+    snapshots: watch::Receiver<Arc<StateSnapshot>>,
+    records: broadcast::Sender<Arc<EventRecord>>,
+}
+
+impl SequencerHandle {
+    pub async fn submit(&self, command: Command) -> Result<Applied, MwError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(Envelope { command, reply })
+            .await
+            .map_err(|_| MwError::SequencerUnavailable)?;
+        response.await.map_err(|_| MwError::SequencerUnavailable)?
+    }
+
+    /// Latest published snapshot, never waits on the sequencer.
+    pub fn snapshot(&self) -> Arc<StateSnapshot> {
+        self.snapshots.borrow().clone()
+    }
+
+    /// Every record appended from now on, in order. A receiver that lags too far behind gets an
+    /// error and has to catch up from the event log.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EventRecord>> {
+        self.records.subscribe()
+    }
+}
+
+struct Sequencer {
+    jtrain: Jtrain,
+    seq: u64,
+    state_hash: B256, // after the last appended record
+    hash_version: u8, // what state_hash covers, a replica keeps the version of the leader's record
+    role: InstanceRole,
+    diverged_at: Option<u64>, // seq of the first leader record a replica couldn't reproduce
+    unlogged: Option<u64>,    // seq of an applied record the log couldn't take
+    log: EventLog,
+    snapshots: watch::Sender<Arc<StateSnapshot>>,
+    records: broadcast::Sender<Arc<EventRecord>>,
+}
+
+impl Sequencer {
+    fn new(
+        jtrain: Jtrain,
+        role: InstanceRole,
+    ) -> (Self, mpsc::Receiver<Envelope>, SequencerHandle) {
+        let log = EventLog::open(jtrain.warehouse.storage.data_path(EVENT_LOG_STORAGE_FILE))
+            .expect("failed to open the event log");
+        let seq = log.last_seq();
+        let state_hash = events::state_hash(
+            &jtrain.warehouse,
+            &jtrain.orderbook_manager,
+            &jtrain.session,
+        );
+        let (commands, receiver) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        let (snapshots, snapshot_receiver) = watch::channel(Arc::new(StateSnapshot::capture(
+            seq, state_hash, role, &jtrain,
+        )));
+        let (records, _) = broadcast::channel(RECORD_BROADCAST_SIZE);
+        let handle = SequencerHandle {
+            commands,
+            snapshots: snapshot_receiver,
+            records: records.clone(),
+        };
+        let sequencer = Sequencer {
+            jtrain,
+            seq,
+            state_hash,
+            hash_version: STATE_HASH_VERSION,
+            role,
+            diverged_at: None,
+            unlogged: None,
+            log,
+            snapshots,
+            records,
+        };
+        (sequencer, receiver, handle)
+    }
+}
+
+/// Moves the Jtrain onto its own task and returns a handle for submitting commands.
+/// The state is rebuilt by replaying the event log, resting orders included, and sequencing picks
+/// up after its last record. A log that hasn't started yet starts with a Genesis event for the
+/// state loaded from the volume.
+pub fn spawn(mut jtrain: Jtrain) -> SequencerHandle {
+    let resumed = replay_log(&mut jtrain);
+    let (mut sequencer, receiver, handle) = Sequencer::new(jtrain, InstanceRole::Leader);
+    if !resumed {
+        sequencer.commit_genesis();
+    }
+    tokio::spawn(sequencer.run(receiver));
+    handle
+}
+
+/// Like spawn, but the log restarts from a Genesis event for the given state instead of being
+/// replayed, for state that doesn't come from the log, like a recovered checkpoint.
+pub fn spawn_genesis(jtrain: Jtrain) -> SequencerHandle {
+    let (mut sequencer, receiver, handle) = Sequencer::new(jtrain, InstanceRole::Leader);
+    sequencer.commit_genesis();
+    tokio::spawn(sequencer.run(receiver));
+    handle
+}
+
+/// Like spawn, but for a replica. Its event log is a copy of the leader's, so it's replayed and
+/// replication carries on from its last seq, it never writes a Genesis event of its own.
+pub fn spawn_replica(mut jtrain: Jtrain) -> SequencerHandle {
+    replay_log(&mut jtrain);
+    let (sequencer, receiver, handle) = Sequencer::new(jtrain, InstanceRole::Replica);
+    tokio::spawn(sequencer.run(receiver));
+    handle
+}
+
+/// Refolds the event log onto `jtrain` and stores the result, returns whether there was a log.
+fn replay_log(jtrain: &mut Jtrain) -> bool {
+    let path = jtrain.warehouse.storage.data_path(EVENT_LOG_STORAGE_FILE);
+    let records = match path.exists() {
+        true => events::read_records(&path).expect("failed to read the event log"),
+        false => Vec::new(),
+    };
+    if records.is_empty() {
+        return false;
+    }
+    events::replay(
+        &mut jtrain.warehouse,
+        &mut jtrain.orderbook_manager,
+        &mut jtrain.session,
+        &records,
+    )
+    .unwrap_or_else(|seq| {
+        panic!(
+            "the event log diverges at seq {}, clear the data dir to resync from the leader",
+            seq
+        )
+    });
+    jtrain
+        .store()
+        .unwrap_or_else(|e| panic!("failed to store the replayed state: {}", e));
+    true
+}
+
+fn replication_error(message: impl Into<String>) -> MwError {
+    MwError::ReplicationError(message.into())
+}
+
+impl Sequencer {
+    async fn run(mut self, mut commands: mpsc::Receiver<Envelope>) {
+        while let Some(Envelope { command, reply }) = commands.recv().await {
+            let result = self.handle(command).await;
+            let _ = reply.send(result);
+        }
+    }
+
+    async fn handle(&mut self, command: Command) -> Result<Applied, MwError> {
+        // the live state is ahead of the log, anything on top of it couldn't be replayed
+        if let Some(seq) = self.unlogged {
+            return Err(MwError::IoError(format!(
+                "event {} isn't in the event log, restart to resume from the log",
+                seq
+            )));
+        }
+        let now = now_ms();
+        match command {
+            Command::PrepareCheckpoint => {
+                // the volume keeps what the checkpoint covers, for recovery without the log
+                self.jtrain.store()?;
+                return Ok(Applied {
+                    seq: self.seq,
+                    output: CommandOutput::Checkpoint(CheckpointDraft::from_warehouse(
+                        &self.jtrain.warehouse,
+                    )),
+                });
+            }
+            Command::Replicate { record } => return self.replicate(record),
+            Command::Promote { expected } => return self.promote(expected),
+            Command::StepDown => return self.step_down(),
+            // anything applied here that the leader didn't sequence would fork the replica
+            _ if self.role == InstanceRole::Replica => {
+                return Err(MwError::NotLeader {
+                    action: "sequencing commands".to_string(),
+                })
+            }
+            _ => {}
+        }
+        let event = self.authorize(command, now).await?;
+        self.commit(event, now)
+    }
+
+    /// Checks signatures, timestamps and the session phase, and turns the command into an event.
+    /// Nothing is mutated here.
+    async fn authorize(&self, command: Command, now: u64) -> Result<Event, MwError> {
+        let jtrain = &self.jtrain;
+        match command {
+            Command::NewOrder {
+                user,
+                order,
+                signature,
+            } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::PlaceOrder, now)?;
+                order.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                order.validate_timestamp()?;
+                Ok(Event::NewOrder { user, order })
+            }
+            Command::CancelOrder {
+                user,
+                cancel,
+                signature,
+            } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::CancelOrder, now)?;
+                cancel.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                cancel.validate_timestamp()?;
+                Ok(Event::CancelOrder {
+                    user,
+                    oid: cancel.oid,
+                })
+            }
+            Command::CancelAll {
+                user,
+                cancel,
+                signature,
+            } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::CancelOrder, now)?;
+                cancel.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                cancel.validate_timestamp()?;
+                Ok(Event::CancelAll { user, cancel })
+            }
+            Command::ReplaceOrder {
+                user,
+                order,
+                oid,
+                signature,
+            } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::PlaceOrder, now)?;
+                order.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                order.validate_timestamp()?;
+                Ok(Event::ReplaceOrder {
+                    user,
+                    order,
+                    oid: oid.0,
+                })
+            }
+            Command::Batch {
+                user,
+                batch,
+                signature,
+            } => {
+                let action = if batch.places_orders() {
+                    SessionAction::PlaceOrder
+                } else {
+                    SessionAction::CancelOrder
+                };
+                jtrain.session.ensure_allowed(action, now)?;
+                batch.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                batch.validate_timestamp()?;
+                Ok(Event::Batch { user, batch })
+            }
+            Command::NewSettlementOrder {
+                chain,
+                user,
+                order,
+                signature,
+            } => {
+                jtrain.session.ensure_allowed(SessionAction::Settle, now)?;
+                let pulled = settler::pulled_amounts(&order);
+                let debited = settler::taker_amounts(&order);
+                let order =
+                    create_settlement_order(&jtrain.warehouse, &chain, user, order, signature)
+                        .await?;
+                Ok(Event::Settlement {
+                    user,
+                    order,
+                    pulled,
+                    debited,
+                })
+            }
+            Command::CreditDeposits {
+                chain_id,
+                user,
+                deposits,
+            } => {
+                jtrain.session.ensure_allowed(SessionAction::Deposit, now)?;
+                Ok(Event::Gulp {
+                    chain_id,
+                    user,
+                    deposits,
+                })
+            }
+            Command::IndexDeposits {
+                chain_id,
+                from_block,
+                to_block,
+                deposits,
+            } => {
+                jtrain.session.ensure_allowed(SessionAction::Deposit, now)?;
+                Ok(Event::DepositsIndexed {
+                    chain_id,
+                    from_block,
+                    to_block,
+                    deposits,
+                })
+            }
+            Command::SetContractAddresses {
+                chain_id,
+                deposit_contract,
+                checkpoint_contract,
+            } => {
+                ChainSpec::from_chain_id(chain_id)?;
+                Ok(Event::SetContractAddresses {
+                    chain_id,
+                    deposit_contract,
+                    checkpoint_contract,
+                })
+            }
+            Command::SetPnlVisibility {
+                user,
+                request,
+                signature,
+            } => {
+                request.validate_signature(&jtrain.warehouse.domains.dstack, signature, user)?;
+                request.validate_timestamp()?;
+                let is_public = match request.request_type.as_str() {
+                    "publish-pnl" => true,
+                    "hide-pnl" => false,
+                    _ => return Err(MwError::InvalidRequestType),
+                };
+                Ok(Event::SetPnlVisibility { user, is_public })
+            }
+            Command::CloseSession { mark_price } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::CloseSession, now)?;
+                Ok(Event::CloseSession { mark_price })
+            }
+            Command::FinalizeSession { mark_price } => {
+                jtrain
+                    .session
+                    .ensure_allowed(SessionAction::FinalizeSession, now)?;
+                Ok(Event::FinalizeSession { mark_price })
+            }
+            Command::ClearSettlementOrders { count } => Ok(Event::ClearSettlementOrders { count }),
+            Command::Replicate { .. } | Command::Promote { .. } | Command::StepDown => {
+                unreachable!("replication is handled before authorizing")
+            }
+            Command::PrepareCheckpoint => unreachable!("queries are never turned into events"),
+        }
+    }
+
+    fn commit_genesis(&mut self) {
+        let genesis = Event::genesis(&self.jtrain.warehouse, &self.jtrain.session);
+        self.commit(genesis, now_ms())
+            .expect("failed to apply the genesis event");
+    }
+
+    /// Applies an event, assigns it the next seq and appends it to the log. apply checks an event
+    /// before changing anything, so a rejected event leaves nothing to replay and isn't logged.
+    fn commit(&mut self, event: Event, timestamp: u64) -> Result<Applied, MwError> {
+        let jtrain = &mut self.jtrain;
+        let result = events::apply(
+            &mut jtrain.warehouse,
+            &mut jtrain.orderbook_manager,
+            &mut jtrain.session,
+            timestamp,
+            &event,
+        );
+        let state_hash = events::state_hash(
+            &jtrain.warehouse,
+            &jtrain.orderbook_manager,
+            &jtrain.session,
+        );
+        if let Err(error) = &result {
+            if state_hash == self.state_hash {
+                return Err(error.clone());
+            }
+            // a check apply missed, the record keeps the log replaying to the same state
+            warn!(
+                "event {} was rejected after changing state: {}",
+                self.seq + 1,
+                error
+            );
+        }
+        let record = EventRecord {
+            seq: self.seq + 1,
+            timestamp,
+            event,
+            error: result.as_ref().err().map(|error| error.to_string()),
+            state_hash,
+            hash_version: STATE_HASH_VERSION,
+        };
+        self.append(record)?;
+        info!("sequenced event {}: {:?}", self.seq, self.state_hash);
+        result.map(|output| Applied {
+            seq: self.seq,
+            output,
+        })
+    }
+
+    /// Applies a record from the leader's log. The replica has to come out of it with the same
+    /// outcome and state hash the leader recorded, otherwise it stops replicating for good since
+    /// its state is no longer the leader's.
+    fn replicate(&mut self, record: EventRecord) -> Result<Applied, MwError> {
+        if self.role != InstanceRole::Replica {
+            return Err(replication_error("the leader doesn't replicate"));
+        }
+        if let Some(seq) = self.diverged_at {
+            return Err(replication_error(format!(
+                "diverged from the leader at seq {}, clear the data dir to resync",
+                seq
+            )));
+        }
+        if record.seq != self.seq + 1 {
+            return Err(replication_error(format!(
+                "expected seq {}, the leader sent {}",
+                self.seq + 1,
+                record.seq
+            )));
+        }
+        let jtrain = &mut self.jtrain;
+        let result = events::apply(
+            &mut jtrain.warehouse,
+            &mut jtrain.orderbook_manager,
+            &mut jtrain.session,
+            record.timestamp,
+            &record.event,
+        );
+        let state_hash = events::state_hash_for(
+            record.hash_version,
+            &jtrain.warehouse,
+            &jtrain.orderbook_manager,
+            &jtrain.session,
+        );
+        if result.err().map(|error| error.to_string()) != record.error
+            || state_hash != record.state_hash
+        {
+            self.diverged_at = Some(record.seq);
+            return Err(replication_error(format!(
+                "diverged from the leader at seq {}, got state hash {} instead of {}",
+                record.seq, state_hash, record.state_hash
+            )));
+        }
+        self.append(record)?;
+        Ok(Applied {
+            seq: self.seq,
+            output: CommandOutput::Replicated,
+        })
+    }
+
+    /// Makes this replica the leader. Its live state has to hash to what the last record it
+    /// applied says, and to `expected` if given (usually the old leader's last seq and state
+    /// hash), so a replica that's behind or diverged can't take over.
+    fn promote(&mut self, expected: Option<(u64, B256)>) -> Result<Applied, MwError> {
+        if self.role != InstanceRole::Replica {
+            return Err(replication_error("already the leader"));
+        }
+        if let Some(seq) = self.diverged_at {
+            return Err(replication_error(format!(
+                "diverged from the leader at seq {}, can't be promoted",
+                seq
+            )));
+        }
+        let jtrain = &self.jtrain;
+        let state_hash = events::state_hash_for(
+            self.hash_version,
+            &jtrain.warehouse,
+            &jtrain.orderbook_manager,
+            &jtrain.session,
+        );
+        if state_hash != self.state_hash {
+            return Err(replication_error(format!(
+                "state hash {} doesn't match {} recorded at seq {}",
+                state_hash, self.state_hash, self.seq
+            )));
+        }
+        if let Some((seq, expected_hash)) = expected {
+            if seq != self.seq || expected_hash != state_hash {
+                return Err(replication_error(format!(
+                    "expected seq {} with state hash {}, the replica is at seq {} with {}",
+                    seq, expected_hash, self.seq, state_hash
+                )));
+            }
+        }
+        self.role = InstanceRole::Leader;
+        // the leader's own records hash at the current version
+        self.state_hash = events::state_hash(
+            &jtrain.warehouse,
+            &jtrain.orderbook_manager,
+            &jtrain.session,
+        );
+        self.hash_version = STATE_HASH_VERSION;
+        self.publish(SnapshotChanges::default());
+        info!("promoted to leader at seq {}: {:?}", self.seq, state_hash);
+        Ok(Applied {
+            seq: self.seq,
+            output: CommandOutput::Promoted(state_hash),
+        })
+    }
+
+    /// Makes the leader a replica, it stops sequencing and posting and keeps the state it has. See
+    /// leader.rs for when it's promoted again.
+    fn step_down(&mut self) -> Result<Applied, MwError> {
+        if self.role != InstanceRole::Leader {
+            return Err(replication_error("not the leader"));
+        }
+        self.role = InstanceRole::Replica;
+        self.publish(SnapshotChanges::default());
+        warn!("stepped down at seq {}: {:?}", self.seq, self.state_hash);
+        Ok(Applied {
+            seq: self.seq,
+            output: CommandOutput::SteppedDown,
+        })
+    }
+
+    /// Appends an applied record to the log and publishes it. If the log can't take the record
+    /// nothing is published and the sequencer stops taking commands, a restart replays the log up
+    /// to the last logged record.
+    fn append(&mut self, record: EventRecord) -> Result<(), MwError> {
+        if let Err(e) = self.log.append(&record) {
+            self.unlogged = Some(record.seq);
+            return Err(MwError::IoError(format!(
+                "failed to append event {} to the event log: {}",
+                record.seq, e
+            )));
+        }
+        self.seq = record.seq;
+        self.state_hash = record.state_hash;
+        self.hash_version = record.hash_version;
+        self.publish(SnapshotChanges::of(&record.event));
+        // nobody listening is fine, replicas catch up from the log when they connect
+        let _ = self.records.send(Arc::new(record));
+        Ok(())
+    }
+
+    fn publish(&self, changes: SnapshotChanges) {
+        let snapshot = self.snapshots.borrow().update(
+            self.seq,
+            self.state_hash,
+            self.role,
+            &self.jtrain,
+            changes,
+        );
+        self.snapshots.send_replace(Arc::new(snapshot));
+    }
+}
@@ -0,0 +1,233 @@
+// Overview:
+// Trading session state machine for CME: The Pit (see CME-ThePit.md)
+// * PreSession - deposits and withdrawals, users pick brokers and pairs
+// * Trading - orders only
+// * Closing - orders are cleared and positions offset, settlement orders can be posted
+// * Finalize - settler keeps posting settlement orders until liabilities are covered, then calls finalize
+// PreSession, Trading and Closing run on a fixed schedule. Finalize lasts until the settler
+// finalizes the session, at which point the next session's PreSession starts.
+// Finalize doubles as the post-session, so deposits and withdrawals are open again there.
+// The phase and when it started are stored on the volume and carried by Genesis events, so a
+// restart resumes the session instead of starting a new one. The session id is stored once, with
+// the session's pnl (see pnl.rs).
+// Finalizing takes a request signed by the operator set in the config, someone outside the
+// enclave, the app's own keys can't finalize a session.
+
+use alloy::primitives::Address;
+
+use crate::{config::StorageConfig, errors::MwError};
+
+const SESSION_STORAGE_FILE: &str = "session.json"; // under the configured data dir
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPhase {
+    PreSession,
+    Trading,
+    Closing,
+    Finalize,
+}
+impl SessionPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PreSession => "pre_session",
+            Self::Trading => "trading",
+            Self::Closing => "closing",
+            Self::Finalize => "finalize",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionAction {
+    Deposit,
+    Withdraw,
+    PlaceOrder,
+    CancelOrder,
+    Settle,
+    CloseSession,
+    FinalizeSession,
+}
+impl SessionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdraw => "withdraw",
+            Self::PlaceOrder => "place_order",
+            Self::CancelOrder => "cancel_order",
+            Self::Settle => "settle",
+            Self::CloseSession => "close_session",
+            Self::FinalizeSession => "finalize_session",
+        }
+    }
+    pub fn is_allowed_in(&self, phase: SessionPhase) -> bool {
+        match self {
+            Self::Deposit | Self::Withdraw => {
+                matches!(phase, SessionPhase::PreSession | SessionPhase::Finalize)
+            }
+            Self::PlaceOrder => phase == SessionPhase::Trading,
+            Self::CancelOrder => matches!(phase, SessionPhase::Trading | SessionPhase::Closing),
+            Self::Settle => matches!(phase, SessionPhase::Closing | SessionPhase::Finalize),
+            Self::CloseSession => phase == SessionPhase::Closing,
+            Self::FinalizeSession => phase == SessionPhase::Finalize,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SessionSchedule {
+    pub pre_session_ms: u64,
+    pub trading_ms: u64,
+    pub closing_ms: u64,
+}
+impl Default for SessionSchedule {
+    fn default() -> Self {
+        SessionSchedule {
+            pre_session_ms: 30 * 60 * 1000,
+            trading_ms: 50 * 60 * 1000,
+            closing_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+/// The phase a session was last moved to and when, scheduled transitions since are applied on top.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SessionState {
+    pub phase: SessionPhase,
+    pub phase_started_at: u64, // unix timestamp in milliseconds
+}
+impl SessionState {
+    /// A session that starts at `now`.
+    pub fn starting(now: u64) -> Self {
+        SessionState {
+            phase: SessionPhase::PreSession,
+            phase_started_at: now,
+        }
+    }
+
+    /// The state stored in the data dir, None on a volume that has none yet.
+    pub fn load(storage: &StorageConfig) -> Result<Option<Self>, MwError> {
+        match std::fs::File::open(storage.data_path(SESSION_STORAGE_FILE)) {
+            Ok(file) => Ok(Some(serde_json::from_reader(file)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn store(&self, storage: &StorageConfig) -> Result<(), MwError> {
+        let file = std::fs::File::create(storage.data_path(SESSION_STORAGE_FILE))?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionController {
+    pub session_id: u64,
+    pub schedule: SessionSchedule,
+    phase: SessionPhase,
+    phase_started_at: u64, // unix timestamp in milliseconds
+}
+
+impl SessionController {
+    pub fn new(schedule: SessionSchedule, now: u64) -> Self {
+        Self::resume(schedule, 1, SessionState::starting(now))
+    }
+
+    /// Picks a stored session back up.
+    pub fn resume(schedule: SessionSchedule, session_id: u64, state: SessionState) -> Self {
+        SessionController {
+            session_id,
+            schedule,
+            phase: state.phase,
+            phase_started_at: state.phase_started_at,
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        SessionState {
+            phase: self.phase,
+            phase_started_at: self.phase_started_at,
+        }
+    }
+
+    /// Phase and start time at `now`, applying any scheduled transitions that have elapsed.
+    /// Finalize never ends on a timer.
+    pub fn phase_at(&self, now: u64) -> (SessionPhase, u64) {
+        let mut phase = self.phase;
+        let mut started_at = self.phase_started_at;
+        while let Some((next_phase, transition_at)) = self.scheduled_transition(phase, started_at) {
+            if now < transition_at {
+                break;
+            }
+            phase = next_phase;
+            started_at = transition_at;
+        }
+        (phase, started_at)
+    }
+
+    pub fn next_transition_at(&self, now: u64) -> Option<u64> {
+        let (phase, started_at) = self.phase_at(now);
+        self.scheduled_transition(phase, started_at)
+            .map(|(_, transition_at)| transition_at)
+    }
+
+    fn scheduled_transition(
+        &self,
+        phase: SessionPhase,
+        started_at: u64,
+    ) -> Option<(SessionPhase, u64)> {
+        match phase {
+            SessionPhase::PreSession => Some((
+                SessionPhase::Trading,
+                started_at + self.schedule.pre_session_ms,
+            )),
+            SessionPhase::Trading => {
+                Some((SessionPhase::Closing, started_at + self.schedule.trading_ms))
+            }
+            SessionPhase::Closing => Some((
+                SessionPhase::Finalize,
+                started_at + self.schedule.closing_ms,
+            )),
+            SessionPhase::Finalize => None,
+        }
+    }
+
+    pub fn ensure_allowed(&self, action: SessionAction, now: u64) -> Result<(), MwError> {
+        let (phase, _) = self.phase_at(now);
+        if !action.is_allowed_in(phase) {
+            return Err(MwError::ActionNotAllowed {
+                action: action.as_str().to_string(),
+                phase: phase.as_str().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Called by the settler once all liabilities are covered, starts the next session.
+    pub fn finalize(&mut self, now: u64) -> Result<u64, MwError> {
+        self.ensure_allowed(SessionAction::FinalizeSession, now)?;
+        self.session_id += 1;
+        self.phase = SessionPhase::PreSession;
+        self.phase_started_at = now;
+        Ok(self.session_id)
+    }
+
+    pub fn to_json(&self, now: u64) -> String {
+        let (phase, started_at) = self.phase_at(now);
+        let serializable_session = serde_json::json!({
+            "session_id": self.session_id.to_string(),
+            "phase": phase.as_str(),
+            "phase_started_at": started_at.to_string(),
+            "next_transition_at": self.next_transition_at(now).map(|t| t.to_string()),
+        });
+        serde_json::to_string(&serializable_session).unwrap()
+    }
+}
+
+/// Checks `signer` is the configured operator, the only one allowed to finalize a session or
+/// promote a replica. Nobody is if there's no operator.
+pub fn authorize_operator(operator: Option<Address>, signer: Address) -> Result<(), MwError> {
+    match operator {
+        Some(operator) if !operator.is_zero() && operator == signer => Ok(()),
+        _ => Err(MwError::UnauthorizedAccess),
+    }
+}
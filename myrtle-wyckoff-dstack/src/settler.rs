@@ -3,56 +3,70 @@
 
 use crate::{
     artifacts::IDepositRegistry,
+    chains::{Chain, ChainSpec},
     cowswap::{CowSwapHook, CowSwapOrder, CowSwapOrderDigest},
-    domains::MAINNET_DOMAIN,
     errors::MwError,
+    rpc::ChainClient,
     warehouse::Warehouse,
 };
 use alloy::{
-    network::{Ethereum, EthereumWallet},
-    primitives::Address,
-    providers::RootProvider,
+    primitives::{Address, U256},
     signers::{Signature, Signer},
-    sol_types::SolStruct,
-    transports::http::{Client, Http},
+    sol_types::{SolCall, SolStruct},
 };
 
-use std::sync::Arc;
+/// Rejects an order the registry on `chain` would revert on because its settlement nonce has
+/// already been used. Checked before the order reaches the sequencer since it's an RPC call.
+pub async fn check_settlement_nonce<C: ChainClient>(
+    chain: &Chain<C>,
+    deposit_contract: Address,
+    order: &IDepositRegistry::Order,
+) -> Result<(), MwError> {
+    if deposit_contract.is_zero() {
+        return Ok(()); // create_settlement_order rejects it
+    }
+    if order.nonce < chain.client.settlement_nonce(deposit_contract).await? {
+        return Err(MwError::InvalidOrderParams);
+    }
+    Ok(())
+}
+
+/// Eth and usdc pull_settlement_funds lets GPv2Settlement take from the registry for `order`.
+pub fn pulled_amounts(order: &IDepositRegistry::Order) -> [U256; 2] {
+    match order.isBid {
+        true => [order.ethAmount, U256::ZERO],
+        false => [U256::ZERO, order.usdcAmount],
+    }
+}
+
+/// Eth and usdc the taker pays for `order`, what create_settlement_order checks they have free.
+pub fn taker_amounts(order: &IDepositRegistry::Order) -> [U256; 2] {
+    match order.isBid {
+        true => [U256::ZERO, order.usdcAmount],
+        false => [order.ethAmount, U256::ZERO],
+    }
+}
 
 // Create settlement orders to be posted as part of the state snapshot
 // TODO: this should probably use cowshed https://github.com/cowdao-grants/cow-shed/tree/main to properly distribute surplus to the taker
 // Note: A malicious taker could submit settlement orders that will never fill but will cause state updates. This is solved with a state lock system.
+// The taker signs over `chain`'s deposit domain and everything the app signs is bound to that chain,
+// so a settlement on one chain can't be replayed against the registry on another.
 pub async fn create_settlement_order(
     warehouse: &Warehouse,
-    provider: &Arc<
-        alloy::providers::fillers::FillProvider<
-            alloy::providers::fillers::JoinFill<
-                alloy::providers::fillers::JoinFill<
-                    alloy::providers::Identity,
-                    alloy::providers::fillers::JoinFill<
-                        alloy::providers::fillers::GasFiller,
-                        alloy::providers::fillers::JoinFill<
-                            alloy::providers::fillers::BlobGasFiller,
-                            alloy::providers::fillers::JoinFill<
-                                alloy::providers::fillers::NonceFiller,
-                                alloy::providers::fillers::ChainIdFiller,
-                            >,
-                        >,
-                    >,
-                >,
-                alloy::providers::fillers::WalletFiller<EthereumWallet>,
-            >,
-            RootProvider<Http<Client>>,
-            Http<Client>,
-            Ethereum,
-        >,
-    >,
+    chain: &ChainSpec,
     user: Address,
     order: IDepositRegistry::Order,
     taker_signature: Signature,
 ) -> Result<CowSwapOrder, MwError> {
+    let deposit_contract = warehouse.deposit_contract(chain.chain_id);
+    if deposit_contract.is_zero() {
+        return Err(MwError::UnknownChain {
+            chain_id: chain.chain_id,
+        });
+    }
     // Validate order
-    let order_hash = order.eip712_signing_hash(&MAINNET_DOMAIN);
+    let order_hash = order.eip712_signing_hash(&chain.deposit_domain(deposit_contract));
     let recovered_address = taker_signature
         .recover_address_from_prehash(&order_hash)
         .map_err(|_| MwError::SignatureRecoveryError)?;
@@ -88,34 +102,44 @@ pub async fn create_settlement_order(
         });
     }
 
-    let deposit_registry_contract = IDepositRegistry::new(warehouse.deposit_contract, provider);
+    // the registry on this chain pays it, whichever chain the taker deposited on
+    warehouse.check_chain_deposits(chain.chain_id, pulled_amounts(&order))?;
 
-    let hook_signature = warehouse
-        .signer
-        .sign_hash(&order_hash)
-        .await
-        .map_err(|_| MwError::SigningError)?;
+    let hook_signature = warehouse.signer.sign_hash(&order_hash).await?;
 
     let k256_sig = hook_signature
         .to_k256()
         .map_err(|_| MwError::SignatureConversionError)?;
 
     let signature_bytes = k256_sig.to_bytes().to_vec();
-    let pre_hook_calldata = deposit_registry_contract
-        .pull_settlement_funds(order.clone(), signature_bytes.into())
-        .calldata()
-        .clone();
+    // the registry also wants the leader's approval, see leader.rs
+    let leader_signature = warehouse
+        .leader
+        .sign_hash(&order_hash)
+        .await?
+        .to_k256()
+        .map_err(|_| MwError::SignatureConversionError)?
+        .to_bytes()
+        .to_vec();
+    let pre_hook_calldata = IDepositRegistry::pull_settlement_fundsCall {
+        order: order.clone(),
+        signature: signature_bytes.into(),
+        leader_signature: leader_signature.into(),
+    }
+    .abi_encode();
 
     let pre_hook = CowSwapHook::new(
-        deposit_registry_contract.address().clone(),
-        pre_hook_calldata,
+        deposit_contract,
+        pre_hook_calldata.into(),
         "100".to_string(), // TODO: figure out what gas cost is
     );
     let app_data = pre_hook.to_app_data();
     Ok(CowSwapOrder::from_cowswap_order_digest(
         &warehouse.signer,
+        chain,
         CowSwapOrderDigest::from_settlement_order(
-            &warehouse.deposit_contract.to_string(),
+            chain,
+            &deposit_contract.to_string(),
             order,
             app_data,
         ),
@@ -0,0 +1,411 @@
+// Overview:
+// Data availability backends for checkpoints. The snapshotter signs a checkpoint and a sink makes
+// it available, so moving off suave only means adding a sink.
+// * checkpointer: the Checkpointer contract on suave, the default and the only one recovery reads
+// * calldata: sends the abi encoded checkpoint() call as calldata to any address, on suave or any
+//   configured deposit chain. Nothing on chain counts them, so the checkpoint nonce is tracked in
+//   the volume and claimed before sending: a post that fails leaves a gap, a nonce is never reused
+// * EIP-4844 blobs are out of scope, they'd need KZG commitments and a blob-carrying RPC on every
+//   chain, and are pruned after a few weeks so recovery couldn't rely on them. Calldata is the way
+//   off suave
+// * file: writes each checkpoint as json to a local directory, for tests and local runs
+// * the sink is picked with CHECKPOINT_SINK, "checkpointer" (default), "calldata:<address>",
+//   "calldata:<chain id>:<address>" or "file:<directory>"
+// * transactions that aren't confirmed in time are resent with the same nonce and a bumped gas
+//   price, and every hash sent is checked so an earlier attempt landing counts as success
+// * transactions go out through a ChainClient (see rpc.rs), so tests can post to a FakeChain
+
+use std::{future::Future, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, TxHash, U256},
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+};
+use tracing::warn;
+
+use crate::{
+    artifacts::ICheckpointer,
+    chains::Chains,
+    config::StorageConfig,
+    errors::MwError,
+    rpc::{AlloyClient, ChainClient},
+    snapshotter::SignedCheckpoint,
+};
+
+pub const CALLDATA_SINK_NONCE_STORAGE_FILE: &str = "calldata_sink_nonce.json"; // in the data dir
+pub const DEFAULT_CONFIRMATION_TIMEOUT_MS: u64 = 30_000;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_GAS_BUMP_PERCENT: u128 = 15;
+pub const RECEIPT_POLL_INTERVAL_MS: u64 = 1_000;
+
+/// Somewhere checkpoints can be made available.
+pub trait CheckpointSink {
+    /// Whether a checkpoint can be posted yet, e.g. the Checkpointer address has been set.
+    fn is_ready(&self, checkpoint_contract: Address) -> bool;
+    /// Nonce the next checkpoint has to be signed with.
+    fn next_nonce(
+        &self,
+        checkpoint_contract: Address,
+    ) -> impl Future<Output = Result<U256, MwError>> + Send;
+    /// Returns once the checkpoint is durable, with the hash of the transaction that carried it if
+    /// there was one and it's known.
+    fn publish(
+        &self,
+        signed: &SignedCheckpoint,
+    ) -> impl Future<Output = Result<Option<TxHash>, MwError>> + Send;
+}
+
+#[derive(Clone, Debug)]
+pub struct TxPolicy {
+    pub confirmation_timeout: Duration, // per attempt
+    pub max_attempts: u32,
+    pub gas_bump_percent: u128, // nodes usually need at least 10% to accept a replacement
+}
+impl Default for TxPolicy {
+    fn default() -> Self {
+        TxPolicy {
+            confirmation_timeout: Duration::from_millis(DEFAULT_CONFIRMATION_TIMEOUT_MS),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            gas_bump_percent: DEFAULT_GAS_BUMP_PERCENT,
+        }
+    }
+}
+
+pub struct GasSettings {
+    pub tx_nonce: u64,
+    pub gas_price: u128,
+}
+impl GasSettings {
+    pub async fn current<C: ChainClient>(client: &C, sender: Address) -> Result<Self, MwError> {
+        Ok(GasSettings {
+            tx_nonce: client.transaction_count(sender).await?,
+            gas_price: client.gas_price().await?,
+        })
+    }
+    /// Same nonce with the gas price bumped, replaces a stuck transaction.
+    pub fn bumped(&self, bump_percent: u128) -> Self {
+        GasSettings {
+            tx_nonce: self.tx_nonce,
+            gas_price: self.gas_price + self.gas_price * bump_percent / 100 + 1,
+        }
+    }
+}
+
+/// Waits up to `timeout` for `hash` to be mined, returns whether it succeeded or None if it wasn't
+/// mined in time.
+async fn wait_for_status<C: ChainClient>(
+    client: &C,
+    hash: TxHash,
+    timeout: Duration,
+) -> Result<Option<bool>, MwError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = client.transaction_status(hash).await? {
+            return Ok(Some(status));
+        }
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_millis(RECEIPT_POLL_INTERVAL_MS)))
+            .await;
+    }
+}
+
+/// Sends `tx`, resending it with the same transaction nonce and a bumped gas price each time it
+/// isn't confirmed in time. Returns the hash of whichever attempt landed.
+pub async fn send_with_retries<C: ChainClient>(
+    client: &C,
+    sender: Address,
+    tx: TransactionRequest,
+    policy: &TxPolicy,
+) -> Result<TxHash, MwError> {
+    let mut gas = GasSettings::current(client, sender).await?;
+    let mut sent: Vec<TxHash> = Vec::new();
+    let mut last_error = String::new();
+    for attempt in 1..=policy.max_attempts {
+        let error = match client
+            .send_transaction(
+                tx.clone()
+                    .with_nonce(gas.tx_nonce)
+                    .with_gas_price(gas.gas_price),
+            )
+            .await
+        {
+            Ok(hash) => {
+                sent.push(hash);
+                match wait_for_status(client, hash, policy.confirmation_timeout).await {
+                    Ok(Some(true)) => return Ok(hash),
+                    Ok(Some(false)) => {
+                        return Err(MwError::SnapshotError(format!(
+                            "transaction {} reverted",
+                            hash
+                        )))
+                    }
+                    Ok(None) => format!("transaction {} wasn't confirmed in time", hash),
+                    Err(error) => error.to_string(),
+                }
+            }
+            Err(error) => error.to_string(),
+        };
+        // an earlier attempt can land while we wait on a later one
+        for hash in sent.iter() {
+            if let Ok(Some(true)) = client.transaction_status(*hash).await {
+                return Ok(*hash);
+            }
+        }
+        warn!("checkpoint attempt {} failed: {}", attempt, error);
+        last_error = error;
+        gas = gas.bumped(policy.gas_bump_percent);
+    }
+    Err(MwError::SnapshotError(format!(
+        "gave up after {} attempts: {}",
+        policy.max_attempts, last_error
+    )))
+}
+
+fn checkpoint_calldata(signed: &SignedCheckpoint) -> Vec<u8> {
+    ICheckpointer::checkpointCall {
+        signature: signed.signature.clone().into(),
+        leader_signature: signed.leader_signature.clone().into(),
+        _checkpoint: signed.checkpoint.clone(),
+    }
+    .abi_encode()
+}
+
+/// The Checkpointer contract on suave, at the address set through /contract-addresses.
+pub struct CheckpointerSink<C: ChainClient = AlloyClient> {
+    pub client: Arc<C>,
+    pub policy: TxPolicy,
+}
+impl<C: ChainClient> CheckpointSink for CheckpointerSink<C> {
+    fn is_ready(&self, checkpoint_contract: Address) -> bool {
+        !checkpoint_contract.is_zero()
+    }
+
+    async fn next_nonce(&self, checkpoint_contract: Address) -> Result<U256, MwError> {
+        self.client.checkpoint_nonce(checkpoint_contract).await
+    }
+
+    async fn publish(&self, signed: &SignedCheckpoint) -> Result<Option<TxHash>, MwError> {
+        let tx = TransactionRequest::default()
+            .with_to(signed.checkpoint_contract)
+            .with_input(checkpoint_calldata(signed));
+        send_with_retries(
+            self.client.as_ref(),
+            signed.signer_address,
+            tx,
+            &self.policy,
+        )
+        .await
+        .map(Some)
+    }
+}
+
+/// Posts checkpoints as calldata to `to` through `client`'s chain, `to` doesn't need to be a
+/// contract.
+pub struct CalldataSink<C: ChainClient = AlloyClient> {
+    pub client: Arc<C>,
+    pub to: Address,
+    pub policy: TxPolicy,
+    pub nonce_path: PathBuf,
+}
+impl<C: ChainClient> CalldataSink<C> {
+    fn stored_nonce(&self) -> Result<U256, MwError> {
+        match std::fs::File::open(&self.nonce_path) {
+            Ok(file) => {
+                serde_json::from_reader(file).map_err(|e| MwError::SnapshotError(e.to_string()))
+            }
+            Err(_) => Ok(U256::ZERO),
+        }
+    }
+
+    // written next to the nonce file and renamed over it, so a crash can't leave half a nonce
+    fn store_nonce(&self, nonce: U256) -> Result<(), MwError> {
+        let staged = self.nonce_path.with_extension("json.tmp");
+        let file =
+            std::fs::File::create(&staged).map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        serde_json::to_writer(file, &nonce).map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        std::fs::rename(&staged, &self.nonce_path)
+            .map_err(|e| MwError::SnapshotError(e.to_string()))
+    }
+}
+impl<C: ChainClient> CheckpointSink for CalldataSink<C> {
+    fn is_ready(&self, _checkpoint_contract: Address) -> bool {
+        true
+    }
+
+    async fn next_nonce(&self, _checkpoint_contract: Address) -> Result<U256, MwError> {
+        self.stored_nonce()
+    }
+
+    async fn publish(&self, signed: &SignedCheckpoint) -> Result<Option<TxHash>, MwError> {
+        // claimed before anything is sent, if it can't be stored the nonce may be signed again
+        self.store_nonce(signed.checkpoint.nonce + U256::from(1))?;
+        let tx = TransactionRequest::default()
+            .with_to(self.to)
+            .with_input(checkpoint_calldata(signed));
+        send_with_retries(
+            self.client.as_ref(),
+            signed.signer_address,
+            tx,
+            &self.policy,
+        )
+        .await
+        .map(Some)
+    }
+}
+
+/// Writes checkpoints to `directory` as checkpoint-<nonce>.json.
+pub struct FileSink {
+    pub directory: PathBuf,
+}
+impl FileSink {
+    pub fn path(&self, nonce: U256) -> PathBuf {
+        self.directory.join(format!("checkpoint-{}.json", nonce))
+    }
+}
+impl CheckpointSink for FileSink {
+    fn is_ready(&self, _checkpoint_contract: Address) -> bool {
+        true
+    }
+
+    // one past the latest written, a directory first used after a recovery doesn't start at 0
+    async fn next_nonce(&self, _checkpoint_contract: Address) -> Result<U256, MwError> {
+        let Ok(entries) = std::fs::read_dir(&self.directory) else {
+            return Ok(U256::ZERO);
+        };
+        Ok(entries
+            .flatten()
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix("checkpoint-")?
+                    .strip_suffix(".json")?
+                    .parse::<U256>()
+                    .ok()
+            })
+            .map(|nonce| nonce + U256::from(1))
+            .max()
+            .unwrap_or_default())
+    }
+
+    async fn publish(&self, signed: &SignedCheckpoint) -> Result<Option<TxHash>, MwError> {
+        let checkpoint = &signed.checkpoint;
+        let serializable_checkpoint = serde_json::json!({
+            "nonce": checkpoint.nonce.to_string(),
+            "inventory_root": checkpoint.inventory_root.to_string(),
+            "inventory_state": alloy::hex::encode_prefixed(&checkpoint.inventory_state),
+            "settlement_orders": checkpoint.settlement_orders,
+            "signature": alloy::hex::encode_prefixed(&signed.signature),
+            "leader_signature": alloy::hex::encode_prefixed(&signed.leader_signature),
+        });
+        std::fs::create_dir_all(&self.directory)
+            .map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        let file = std::fs::File::create(self.path(checkpoint.nonce))
+            .map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        serde_json::to_writer(file, &serializable_checkpoint)
+            .map_err(|e| MwError::SnapshotError(e.to_string()))?;
+        Ok(None)
+    }
+}
+
+/// Which sink to post to, parsed from CHECKPOINT_SINK.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkConfig {
+    Checkpointer,
+    Calldata {
+        chain_id: Option<u64>, // a deposit chain, suave if not given
+        to: Address,
+    },
+    File(PathBuf),
+}
+impl FromStr for SinkConfig {
+    type Err = MwError;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let invalid = || MwError::InvalidSinkConfig(config.to_string());
+        match config.split_once(':') {
+            None if config == "checkpointer" => Ok(SinkConfig::Checkpointer),
+            Some(("calldata", target)) => {
+                let (chain_id, to) = match target.split_once(':') {
+                    Some((chain_id, to)) => {
+                        (Some(u64::from_str(chain_id).map_err(|_| invalid())?), to)
+                    }
+                    None => (None, target),
+                };
+                Ok(SinkConfig::Calldata {
+                    chain_id,
+                    to: Address::from_str(to).map_err(|_| invalid())?,
+                })
+            }
+            Some(("blob", _)) => Err(MwError::InvalidSinkConfig(format!(
+                "{}, blobs aren't supported, post calldata instead",
+                config
+            ))),
+            Some(("file", directory)) if !directory.is_empty() => {
+                Ok(SinkConfig::File(PathBuf::from(directory)))
+            }
+            _ => Err(MwError::InvalidSinkConfig(config.to_string())),
+        }
+    }
+}
+
+/// The configured sink, dispatches to the backend picked at startup.
+pub enum Sink<C: ChainClient = AlloyClient> {
+    Checkpointer(CheckpointerSink<C>),
+    Calldata(CalldataSink<C>),
+    File(FileSink),
+}
+impl<C: ChainClient> Sink<C> {
+    /// `client` is suave's, calldata sinks on a deposit chain post through its client in `chains`.
+    pub fn new(
+        config: SinkConfig,
+        client: Arc<C>,
+        chains: &Chains<C>,
+        policy: TxPolicy,
+        storage: &StorageConfig,
+    ) -> Result<Self, MwError> {
+        Ok(match config {
+            SinkConfig::Checkpointer => Sink::Checkpointer(CheckpointerSink { client, policy }),
+            SinkConfig::Calldata { chain_id, to } => Sink::Calldata(CalldataSink {
+                client: match chain_id {
+                    Some(chain_id) => chains.get(chain_id)?.client.clone(),
+                    None => client,
+                },
+                to,
+                policy,
+                nonce_path: storage.data_path(CALLDATA_SINK_NONCE_STORAGE_FILE),
+            }),
+            SinkConfig::File(directory) => Sink::File(FileSink { directory }),
+        })
+    }
+}
+impl<C: ChainClient> CheckpointSink for Sink<C> {
+    fn is_ready(&self, checkpoint_contract: Address) -> bool {
+        match self {
+            Sink::Checkpointer(sink) => sink.is_ready(checkpoint_contract),
+            Sink::Calldata(sink) => sink.is_ready(checkpoint_contract),
+            Sink::File(sink) => sink.is_ready(checkpoint_contract),
+        }
+    }
+
+    async fn next_nonce(&self, checkpoint_contract: Address) -> Result<U256, MwError> {
+        match self {
+            Sink::Checkpointer(sink) => sink.next_nonce(checkpoint_contract).await,
+            Sink::Calldata(sink) => sink.next_nonce(checkpoint_contract).await,
+            Sink::File(sink) => sink.next_nonce(checkpoint_contract).await,
+        }
+    }
+
+    async fn publish(&self, signed: &SignedCheckpoint) -> Result<Option<TxHash>, MwError> {
+        match self {
+            Sink::Checkpointer(sink) => sink.publish(signed).await,
+            Sink::Calldata(sink) => sink.publish(signed).await,
+            Sink::File(sink) => sink.publish(signed).await,
+        }
+    }
+}
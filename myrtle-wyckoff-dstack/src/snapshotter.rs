@@ -1,59 +1,72 @@
 // Overview:
-// Responsible for creating a state checkpoint to be posted to suave.
-// This should be run every 5 seconds with the timer reset every new suave block.
-// * encrypts inventory state with dstack shared secret app key
+// Responsible for creating a state checkpoint to be posted to the DA layer.
+// Run on an interval by the scheduler (see scheduler.rs).
+// * encrypts inventory state with dstack shared secret app key, bound to the checkpoint nonce (see blob.rs)
 // * grabs settlement orders to be posted
-// * grabs current settlement nonce
-// * creates a signature of the above data
-// * posts the encrypted inventory state, and settlement orders to suave via the Checkpointer contracts checkpoint() function
+// * binds the checkpoint to the sink's next checkpoint nonce
+// * creates a signature of the above data with the shared secret signer and another with this
+//   container's leader key, the Checkpointer wants both (see leader.rs)
+// * commits to the inventory state with a merkle root (see merkle.rs) so users can verify their balance
+// * hands the signed checkpoint to the configured sink (see sink.rs), by default suave's Checkpointer contract
 
+use aes_gcm::{Aes256Gcm, Key};
 use alloy::{
-    network::{Ethereum, EthereumWallet},
-    providers::RootProvider,
-    rpc::types::TransactionReceipt,
-    signers::Signer,
+    dyn_abi::Eip712Domain,
+    primitives::{Address, U256},
+    signers::{local::PrivateKeySigner, Signer},
     sol_types::SolStruct,
-    transports::http::{Client, Http},
 };
 
-use std::sync::Arc;
+use crate::{
+    artifacts::ICheckpointer, blob, cowswap::CowSwapOrder, merkle::InventoryCommitment,
+    warehouse::Warehouse,
+};
 
-use crate::{artifacts::ICheckpointer, domains::TOLIMAN_DOMAIN, warehouse::Warehouse};
+/// Everything needed to post a checkpoint, captured from the warehouse by the sequencer so the
+/// RPC calls can happen without holding up matching.
+/// Encryption happens at posting time since the blob is bound to the checkpoint nonce.
+pub struct CheckpointDraft {
+    pub checkpoint_contract: Address,
+    pub min_nonce: U256, // see Warehouse::resumed_checkpoint_nonce
+    pub inventory_commitment: InventoryCommitment,
+    pub settlement_orders: Vec<CowSwapOrder>,
+    pub signer: PrivateKeySigner,
+    pub leader: PrivateKeySigner,
+    pub encryption_key: Key<Aes256Gcm>,
+    pub domain: Eip712Domain, // bound to checkpoint_contract
+}
+impl CheckpointDraft {
+    pub fn from_warehouse(warehouse: &Warehouse) -> Self {
+        CheckpointDraft {
+            checkpoint_contract: warehouse.checkpoint_contract,
+            min_nonce: warehouse.resumed_checkpoint_nonce,
+            inventory_commitment: InventoryCommitment::new(&warehouse.inventories),
+            settlement_orders: warehouse.settlement_orders.clone(),
+            signer: warehouse.signer.clone(),
+            leader: warehouse.leader.clone(),
+            encryption_key: warehouse.encryption_key,
+            domain: warehouse.domains.toliman(warehouse.checkpoint_contract),
+        }
+    }
+}
 
-pub async fn snapshot(
-    warehouse: &Warehouse,
-    provider: &Arc<
-        alloy::providers::fillers::FillProvider<
-            alloy::providers::fillers::JoinFill<
-                alloy::providers::fillers::JoinFill<
-                    alloy::providers::Identity,
-                    alloy::providers::fillers::JoinFill<
-                        alloy::providers::fillers::GasFiller,
-                        alloy::providers::fillers::JoinFill<
-                            alloy::providers::fillers::BlobGasFiller,
-                            alloy::providers::fillers::JoinFill<
-                                alloy::providers::fillers::NonceFiller,
-                                alloy::providers::fillers::ChainIdFiller,
-                            >,
-                        >,
-                    >,
-                >,
-                alloy::providers::fillers::WalletFiller<EthereumWallet>,
-            >,
-            RootProvider<Http<Client>>,
-            Http<Client>,
-            Ethereum,
-        >,
-    >,
-) -> Result<TransactionReceipt, Box<dyn std::error::Error>> {
-    let checkpointer_contract = ICheckpointer::new(warehouse.checkpoint_contract, provider);
-    let checkpoint_nonce = checkpointer_contract
-        .inventory_checkpoint_nonce()
-        .call()
-        .await?
-        ._0;
+/// A signed checkpoint ready to hand to a CheckpointSink (see sink.rs).
+pub struct SignedCheckpoint {
+    pub checkpoint_contract: Address,
+    pub signer_address: Address,
+    pub checkpoint: ICheckpointer::Checkpoint,
+    pub signature: Vec<u8>,
+    pub leader_signature: Vec<u8>,
+    pub inventory_commitment: InventoryCommitment,
+    pub settlement_order_count: usize,
+}
 
-    let settlement_orders_json: Vec<String> = warehouse
+/// Builds and signs the checkpoint, `checkpoint_nonce` comes from the sink it'll be posted to.
+pub async fn sign(
+    draft: CheckpointDraft,
+    checkpoint_nonce: U256,
+) -> Result<SignedCheckpoint, Box<dyn std::error::Error>> {
+    let settlement_orders_json: Vec<String> = draft
         .settlement_orders
         .iter()
         .map(|order| serde_json::to_string(order))
@@ -62,18 +75,29 @@ pub async fn snapshot(
     // Create and sign checkpoint
     let checkpoint = ICheckpointer::Checkpoint {
         nonce: checkpoint_nonce,
-        inventory_state: warehouse.get_encrypted_inventory()?,
+        inventory_root: draft.inventory_commitment.root(),
+        inventory_state: blob::encode(
+            &draft.encryption_key,
+            checkpoint_nonce,
+            &draft.inventory_commitment.inventories,
+        )?,
         settlement_orders: settlement_orders_json,
     };
-    let hash = checkpoint.eip712_signing_hash(&TOLIMAN_DOMAIN);
-    let signature = warehouse.signer.sign_hash(&hash).await?;
+    let hash = checkpoint.eip712_signing_hash(&draft.domain);
+    let signature = draft.signer.sign_hash(&hash).await?;
     let k256_sig = signature.to_k256()?.to_bytes().to_vec();
+    let leader_signature = draft.leader.sign_hash(&hash).await?;
+    let leader_k256_sig = leader_signature.to_k256()?.to_bytes().to_vec();
 
-    // Execute transaction and return receipt directly
-    Ok(checkpointer_contract
-        .checkpoint(k256_sig.into(), checkpoint)
-        .send()
-        .await?
-        .get_receipt()
-        .await?)
+    let mut inventory_commitment = draft.inventory_commitment;
+    inventory_commitment.checkpoint_nonce = checkpoint_nonce;
+    Ok(SignedCheckpoint {
+        checkpoint_contract: draft.checkpoint_contract,
+        signer_address: draft.signer.address(),
+        checkpoint,
+        signature: k256_sig,
+        leader_signature: leader_k256_sig,
+        inventory_commitment,
+        settlement_order_count: draft.settlement_orders.len(),
+    })
 }
@@ -1,7 +1,21 @@
-use alloy::{primitives::Address, signers::Signature, sol, sol_types::SolStruct};
+use alloy::{
+    dyn_abi::Eip712Domain, primitives::Address, signers::Signature, sol, sol_types::SolStruct,
+};
 
-use crate::domains::DSTACK_DOMAIN;
-use crate::errors::MwError;
+use crate::{clock::now_ms, errors::MwError};
+
+pub const MAX_REQUEST_AGE_MS: u64 = 60_000;
+pub const MAX_CLOCK_SKEW_MS: u64 = 30_000; // how far ahead of ours a signer's clock can be
+
+/// Checks a signed request's timestamp is recent, and not so far ahead that the signature could be
+/// replayed once it's current.
+pub fn validate_timestamp(timestamp: u64) -> Result<(), MwError> {
+    let now = now_ms();
+    if timestamp < now.saturating_sub(MAX_REQUEST_AGE_MS) || timestamp > now + MAX_CLOCK_SKEW_MS {
+        return Err(MwError::InvalidTimestamp);
+    }
+    Ok(())
+}
 
 sol! {
     struct Checkpoint {
@@ -23,19 +37,22 @@ sol! {
     struct UserRequest {
         address user;
         uint64 timestamp;
-        string request_type; // "inventory" or "orders"
+        // "inventory", "inventory-proof", "orders", "publish-pnl", "hide-pnl", "finalize-session"
+        // or "promote"
+        string request_type;
     }
 }
 impl UserRequest {
     pub fn validate_timestamp(&self) -> Result<(), MwError> {
-        let min_timestamp = chrono::Utc::now().timestamp_millis() - 60000; // 1 minute buffer
-        if self.timestamp < min_timestamp.unsigned_abs() {
-            return Err(MwError::InvalidTimestamp);
-        }
-        Ok(())
+        validate_timestamp(self.timestamp)
     }
-    pub fn validate_signature(&self, signature: Signature, user: Address) -> Result<(), MwError> {
-        let order_hash = self.eip712_signing_hash(&DSTACK_DOMAIN);
+    pub fn validate_signature(
+        &self,
+        domain: &Eip712Domain,
+        signature: Signature,
+        user: Address,
+    ) -> Result<(), MwError> {
+        let order_hash = self.eip712_signing_hash(domain);
         let recovered_address = signature
             .recover_address_from_prehash(&order_hash)
             .map_err(|_| MwError::SignatureRecoveryError)?;
@@ -0,0 +1,202 @@
+// Overview:
+// Checks deposits against the chain's state instead of trusting whatever the RPC says.
+// Until the dstack in-TDX light client is available this is the next best thing.
+// * a block's hash and state root are only trusted once a quorum of the chain's configured RPC
+//   endpoints report the same ones (see chains.rs)
+// * the registry's storage is read with eth_getProof at that block, the account proof is checked
+//   against the trusted state root and every storage proof against the proven storage root
+// * a deposit verifies if its index is below the user's proven deposit count and its amounts match
+//   the proven slots, otherwise nothing in the batch is credited
+// * the indexer and /gulp-deposits verify everything before it reaches the sequencer
+// * this relies on DepositRegistry's storage layout, deposit_registry has to stay in slot 2
+
+use std::collections::HashMap;
+
+use alloy::{
+    primitives::{keccak256, Address, B256, U256},
+    rpc::types::EIP1186AccountProofResponse,
+};
+use alloy_rlp::RlpEncodable;
+use alloy_trie::{proof::verify_proof, Nibbles};
+
+use crate::{chains::Chain, errors::MwError, gulper::IndexedDeposit, rpc::ChainClient};
+
+// storage slot of DepositRegistry.deposit_registry, after admin and settlement_nonce
+pub const DEPOSIT_REGISTRY_SLOT: u64 = 2;
+
+/// A block a quorum of endpoints agreed on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustedBlock {
+    pub number: u64,
+    pub hash: B256,
+    pub state_root: B256,
+}
+
+// the account as it's stored in the state trie
+#[derive(RlpEncodable)]
+pub(crate) struct TrieAccount {
+    pub(crate) nonce: u64,
+    pub(crate) balance: U256,
+    pub(crate) storage_root: B256,
+    pub(crate) code_hash: B256,
+}
+
+fn invalid(message: impl Into<String>) -> MwError {
+    MwError::DepositVerificationError(message.into())
+}
+
+/// Slot holding the length of deposit_registry[user].
+pub fn deposit_count_slot(user: Address) -> B256 {
+    let mut key = [0u8; 64];
+    key[12..32].copy_from_slice(user.as_slice());
+    key[32..64].copy_from_slice(&U256::from(DEPOSIT_REGISTRY_SLOT).to_be_bytes::<32>());
+    keccak256(key)
+}
+
+/// Slots holding the eth and usdc amount of deposit_registry[user][index]. Each entry is a
+/// uint256[2], so two slots, stored from keccak256 of the length slot.
+pub fn deposit_amount_slots(user: Address, index: u32) -> [B256; 2] {
+    let data = U256::from_be_bytes(keccak256(deposit_count_slot(user)).0);
+    let eth = data + U256::from(index) * U256::from(2);
+    [B256::from(eth), B256::from(eth + U256::from(1))]
+}
+
+/// Every slot needed to verify `deposits`, without duplicates.
+pub fn storage_keys(deposits: &[IndexedDeposit]) -> Vec<B256> {
+    let mut keys: Vec<B256> = Vec::with_capacity(deposits.len() * 3);
+    for deposit in deposits {
+        let [eth_slot, usdc_slot] = deposit_amount_slots(deposit.user, deposit.index);
+        for key in [deposit_count_slot(deposit.user), eth_slot, usdc_slot] {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+/// Picks the block reported by the most endpoints, as long as at least `quorum` of them agree.
+/// Endpoints that didn't answer are passed as None.
+pub fn agree_on_block(
+    number: u64,
+    reported: &[Option<(B256, B256)>], // (block hash, state root) per endpoint
+    quorum: usize,
+) -> Result<TrustedBlock, MwError> {
+    let mut votes: HashMap<(B256, B256), usize> = HashMap::new();
+    for block in reported.iter().flatten() {
+        *votes.entry(*block).or_default() += 1;
+    }
+    match votes.into_iter().max_by_key(|(_, count)| *count) {
+        Some(((hash, state_root), count)) if count >= quorum => Ok(TrustedBlock {
+            number,
+            hash,
+            state_root,
+        }),
+        _ => Err(invalid(format!(
+            "fewer than {} endpoints agree on block {}",
+            quorum, number
+        ))),
+    }
+}
+
+/// Checks the account and storage proofs against `block` and returns the proven slots.
+pub fn verify_proof_response(
+    block: &TrustedBlock,
+    registry: Address,
+    proof: &EIP1186AccountProofResponse,
+) -> Result<HashMap<B256, U256>, MwError> {
+    if proof.address != registry {
+        return Err(invalid("proof is for another account"));
+    }
+    let account = TrieAccount {
+        nonce: proof.nonce,
+        balance: proof.balance,
+        storage_root: proof.storage_hash,
+        code_hash: proof.code_hash,
+    };
+    verify_proof(
+        block.state_root,
+        Nibbles::unpack(keccak256(registry)),
+        Some(alloy_rlp::encode(&account)),
+        &proof.account_proof,
+    )
+    .map_err(|e| invalid(format!("account proof: {}", e)))?;
+
+    let mut proven = HashMap::with_capacity(proof.storage_proof.len());
+    for storage_proof in proof.storage_proof.iter() {
+        let key = storage_proof.key.as_b256();
+        // empty slots aren't in the trie, the proof shows their absence
+        let expected =
+            (!storage_proof.value.is_zero()).then(|| alloy_rlp::encode(storage_proof.value));
+        verify_proof(
+            proof.storage_hash,
+            Nibbles::unpack(keccak256(key)),
+            expected,
+            &storage_proof.proof,
+        )
+        .map_err(|e| invalid(format!("storage proof for {}: {}", key, e)))?;
+        proven.insert(key, storage_proof.value);
+    }
+    Ok(proven)
+}
+
+/// Checks every deposit against the proven registry slots.
+pub fn check_deposits(
+    proven: &HashMap<B256, U256>,
+    deposits: &[IndexedDeposit],
+) -> Result<(), MwError> {
+    let slot = |key: B256| {
+        proven
+            .get(&key)
+            .copied()
+            .ok_or_else(|| invalid(format!("no proof for slot {}", key)))
+    };
+    for deposit in deposits {
+        let count = slot(deposit_count_slot(deposit.user))?;
+        if U256::from(deposit.index) >= count {
+            return Err(invalid(format!(
+                "deposit {} for {} doesn't exist",
+                deposit.index, deposit.user
+            )));
+        }
+        let [eth_slot, usdc_slot] = deposit_amount_slots(deposit.user, deposit.index);
+        if [slot(eth_slot)?, slot(usdc_slot)?] != deposit.amounts {
+            return Err(invalid(format!(
+                "deposit {} for {} doesn't match the registry",
+                deposit.index, deposit.user
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Asks every endpoint of `chain` for block `number` and returns the one a quorum agrees on.
+pub async fn trusted_block<C: ChainClient>(
+    chain: &Chain<C>,
+    number: u64,
+) -> Result<TrustedBlock, MwError> {
+    let mut reported = Vec::with_capacity(chain.clients.len());
+    for client in chain.clients.iter() {
+        reported.push(client.block_roots(number).await.ok().flatten());
+    }
+    agree_on_block(number, &reported, chain.quorum)
+}
+
+/// Proves `deposits` against `registry`'s storage at block `number` on `chain`.
+pub async fn verify_deposits<C: ChainClient>(
+    chain: &Chain<C>,
+    registry: Address,
+    number: u64,
+    deposits: &[IndexedDeposit],
+) -> Result<(), MwError> {
+    if deposits.is_empty() {
+        return Ok(());
+    }
+    let block = trusted_block(chain, number).await?;
+    let proof = chain
+        .client
+        .storage_proof(registry, storage_keys(deposits), block.hash)
+        .await?;
+    let proven = verify_proof_response(&block, registry, &proof)?;
+    check_deposits(&proven, deposits)
+}
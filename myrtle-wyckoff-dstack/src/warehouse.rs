@@ -16,30 +16,55 @@
 // Both of these probably need some caching mechanism since we don't
 // want to constantly encrypt and decrypt, but again unsure on the volume side
 
-use aes_gcm::{
-    aead::{heapless::Vec as HeaplessVec, AeadCore, AeadInPlace, KeyInit, OsRng},
-    Aes256Gcm, Key,
-};
+use aes_gcm::{Aes256Gcm, Key};
 use alloy::{
-    hex::{FromHex, ToHexExt},
     primitives::{Address, Uint, U256},
     signers::local::PrivateKeySigner,
 };
 use core::ops::{AddAssign as AddAssignTrait, SubAssign as SubAssignTrait};
-use hkdf::Hkdf;
 use optimized_lob::{
     order::OrderId, orderbook_manager::OrderBookManager, price::Price, quantity::Qty,
 };
-use sha2::Sha256;
 
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+};
 
-use crate::{cowswap::CowSwapOrder, errors::MwError, orderhere::Order};
+use crate::{
+    blob::{self, InventoryBlob},
+    chains::{MAINNET_CHAIN_ID, SUPPORTED_CHAIN_IDS},
+    clock::now_ms,
+    config::{Config, StorageConfig},
+    cowswap::CowSwapOrder,
+    domains::Domains,
+    errors::MwError,
+    keys::{self, AppKeys},
+    orderhere::Order,
+    pnl::{PnlBook, SessionResult},
+};
 
-const INVENTORY_STORAGE_PATH: &str = "/mnt/encrypted_data/inventories.json";
-const DEPOSIT_CONTRACT_STORAGE_PATH: &str = "/mnt/encrypted_data/deposit_contract.json";
-const CHECKPOINT_CONTRACT_STORAGE_PATH: &str = "/mnt/encrypted_data/checkpoint_contract.json";
-const RPC_API_KEY_STORAGE_PATH: &str = "/mnt/host_data/rpc_api_key.json";
+// under the configured data dir (see config.rs), except the RPC api key which the host provides
+const INVENTORY_STORAGE_FILE: &str = "inventories.json"; // plaintext, only read from older volumes
+const SEALED_INVENTORY_STORAGE_FILE: &str = "inventories.sealed"; // sealed with the volume key
+const DEPOSIT_CONTRACT_STORAGE_FILE: &str = "deposit_contract.json";
+const DEPOSIT_CONTRACTS_STORAGE_FILE: &str = "deposit_contracts.json";
+const CHECKPOINT_CONTRACT_STORAGE_FILE: &str = "checkpoint_contract.json";
+const RPC_API_KEY_STORAGE_FILE: &str = "rpc_api_key.json";
+const SESSION_RESULTS_STORAGE_FILE: &str = "session_results.json";
+const PUBLIC_PNL_STORAGE_FILE: &str = "public_pnl.json";
+const SESSION_PNL_STORAGE_FILE: &str = "session_pnl.json";
+const DEPOSIT_CURSOR_STORAGE_FILE: &str = "deposit_cursor.json";
+const DEPOSIT_CURSORS_STORAGE_FILE: &str = "deposit_cursors.json";
+const CHAIN_DEPOSITS_STORAGE_FILE: &str = "chain_deposits.json";
+const RESUMED_CHECKPOINT_NONCE_STORAGE_FILE: &str = "resumed_checkpoint_nonce.json";
+const SETTLEMENT_ORDERS_STORAGE_FILE: &str = "settlement_orders.json"; // queued, not yet posted
+
+// address (20 bytes) + eth_balance, eth_liabilities, usdc_balance, usdc_liabilities (32 bytes each) +
+// is_taker (1 byte) + a deposit nonce (4 bytes) for each of SUPPORTED_CHAIN_IDS in order
+pub const INVENTORY_BYTES: usize = 149 + 4 * SUPPORTED_CHAIN_IDS.len();
+// before deposits on other chains: the address and quantities, the mainnet deposit nonce, is_taker
+pub const LEGACY_INVENTORY_BYTES: usize = 153;
 
 #[derive(Clone, Debug)]
 pub struct Inventory {
@@ -48,7 +73,7 @@ pub struct Inventory {
     pub eth_liabilities: Qty,
     pub usdc_balance: Qty,
     pub usdc_liabilities: Qty,
-    pub deposit_nonce: u32,
+    pub deposit_nonces: BTreeMap<u64, u32>, // chain id, next deposit index in that chain's registry
     pub is_taker: bool,
 }
 impl Inventory {
@@ -58,7 +83,7 @@ impl Inventory {
         eth_liabilities: Qty,
         usdc_balance: Qty,
         usdc_liabilities: Qty,
-        deposit_nonce: u32,
+        deposit_nonces: BTreeMap<u64, u32>,
         is_taker: bool,
     ) -> Self {
         Inventory {
@@ -67,23 +92,72 @@ impl Inventory {
             eth_liabilities,
             usdc_balance,
             usdc_liabilities,
-            deposit_nonce,
+            deposit_nonces,
             is_taker,
         }
     }
+    pub fn deposit_nonce(&self, chain_id: u64) -> u32 {
+        self.deposit_nonces
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::new();
+        let mut buffer: Vec<u8> = Vec::with_capacity(INVENTORY_BYTES);
 
         buffer.extend(&self.address.0);
         buffer.extend(&self.eth_balance.0.to_le_bytes::<32>());
         buffer.extend(&self.eth_liabilities.0.to_le_bytes::<32>());
         buffer.extend(&self.usdc_balance.0.to_le_bytes::<32>());
         buffer.extend(&self.usdc_liabilities.0.to_le_bytes::<32>());
-        buffer.extend(self.deposit_nonce.to_le_bytes());
         buffer.extend((self.is_taker as u8).to_le_bytes());
-        buffer.resize(153, 0); // Pad with zeros to reach max possible size Address (20 bytes) + eth_balance (32 bytes) + eth_liabilities (32 bytes) + usdc_balance (32 bytes) + usdc_liabilities (32 bytes) + deposit_nonce (4 bytes) + is_taker (1 byte)
+        for chain_id in SUPPORTED_CHAIN_IDS {
+            buffer.extend(self.deposit_nonce(chain_id).to_le_bytes());
+        }
         buffer
     }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MwError> {
+        if bytes.len() != INVENTORY_BYTES {
+            return Err(MwError::DecryptionError);
+        }
+        let nonce_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let deposit_nonces = SUPPORTED_CHAIN_IDS
+            .iter()
+            .enumerate()
+            .map(|(slot, chain_id)| (*chain_id, nonce_at(149 + 4 * slot)))
+            .filter(|(_, nonce)| *nonce != 0)
+            .collect();
+        Self::from_balances(bytes, deposit_nonces, bytes[148] != 0)
+    }
+    /// Parses an inventory from a version 1 blob, which only had mainnet deposits.
+    pub fn from_legacy_bytes(bytes: &[u8]) -> Result<Self, MwError> {
+        if bytes.len() != LEGACY_INVENTORY_BYTES {
+            return Err(MwError::DecryptionError);
+        }
+        let nonce = u32::from_le_bytes(bytes[148..152].try_into().unwrap());
+        let deposit_nonces = (nonce != 0)
+            .then_some((MAINNET_CHAIN_ID, nonce))
+            .into_iter()
+            .collect();
+        Self::from_balances(bytes, deposit_nonces, bytes[152] != 0)
+    }
+    fn from_balances(
+        bytes: &[u8],
+        deposit_nonces: BTreeMap<u64, u32>,
+        is_taker: bool,
+    ) -> Result<Self, MwError> {
+        let qty_at = |offset: usize| Qty(U256::from_le_slice(&bytes[offset..offset + 32]));
+        Ok(Inventory::new(
+            Address::from_slice(&bytes[0..20]),
+            qty_at(20),
+            qty_at(52),
+            qty_at(84),
+            qty_at(116),
+            deposit_nonces,
+            is_taker,
+        ))
+    }
     pub fn net_eth(&self) -> Qty {
         Qty(self.eth_balance.0 - self.eth_liabilities.0)
     }
@@ -97,7 +171,11 @@ impl Inventory {
             "eth_liabilities": self.eth_liabilities.0.to_string(),
             "usdc_balance": self.usdc_balance.0.to_string(),
             "usdc_liabilities": self.usdc_liabilities.0.to_string(),
-            "deposit_nonce": self.deposit_nonce.to_string(),
+            "deposit_nonces": self
+                .deposit_nonces
+                .iter()
+                .map(|(chain_id, nonce)| (chain_id.to_string(), nonce.to_string()))
+                .collect::<serde_json::Map<String, serde_json::Value>>(),
             "is_taker": self.is_taker.to_string()
         });
         serde_json::to_string(&serializable_inventory).unwrap()
@@ -106,19 +184,44 @@ impl Inventory {
         let value: serde_json::Value = serde_json::from_str(&json).unwrap();
 
         Inventory::new(
-            Address::from_hex(&value["address"].as_str().unwrap().encode_hex()).unwrap(),
+            value["address"]
+                .as_str()
+                .unwrap()
+                .parse::<Address>()
+                .unwrap(),
             Qty(U256::from_str_radix(&value["eth_balance"].as_str().unwrap(), 10).unwrap()),
             Qty(U256::from_str_radix(&value["eth_liabilities"].as_str().unwrap(), 10).unwrap()),
             Qty(U256::from_str_radix(&value["usdc_balance"].as_str().unwrap(), 10).unwrap()),
             Qty(U256::from_str_radix(&value["usdc_liabilities"].as_str().unwrap(), 10).unwrap()),
-            value["deposit_nonce"]
-                .as_str()
-                .unwrap()
-                .parse::<u32>()
-                .unwrap(),
+            Self::deposit_nonces_from_json(&value),
             value["is_taker"].as_str().unwrap().parse::<bool>().unwrap(),
         )
     }
+    // inventories stored before deposits on other chains only have the mainnet "deposit_nonce"
+    fn deposit_nonces_from_json(value: &serde_json::Value) -> BTreeMap<u64, u32> {
+        match value["deposit_nonces"].as_object() {
+            Some(nonces) => nonces
+                .iter()
+                .map(|(chain_id, nonce)| {
+                    (
+                        chain_id.parse::<u64>().unwrap(),
+                        nonce.as_str().unwrap().parse::<u32>().unwrap(),
+                    )
+                })
+                .collect(),
+            None => {
+                let nonce = value["deposit_nonce"]
+                    .as_str()
+                    .unwrap()
+                    .parse::<u32>()
+                    .unwrap();
+                (nonce != 0)
+                    .then_some((MAINNET_CHAIN_ID, nonce))
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
 }
 impl Default for Inventory {
     fn default() -> Self {
@@ -128,73 +231,118 @@ impl Default for Inventory {
             Qty(Uint::ZERO),
             Qty(Uint::ZERO),
             Qty(Uint::ZERO),
-            0,
+            BTreeMap::new(),
             false,
         )
     }
 }
 
+// contract addresses are stored as the bare 0x string, older volumes have it as a JSON string
+fn read_address(path: &std::path::Path) -> Result<Address, Box<dyn std::error::Error>> {
+    Ok(std::fs::read_to_string(path)?
+        .trim()
+        .trim_matches('"')
+        .parse::<Address>()?)
+}
+
+/// Deposits per chain for state from before they were tracked, recovered checkpoints included.
+/// Everything is put on mainnet, where deposits were first taken.
+pub fn mainnet_deposits(inventories: &HashMap<Address, Inventory>) -> BTreeMap<u64, [U256; 2]> {
+    let mut deposits = [U256::ZERO; 2];
+    for inventory in inventories.values() {
+        deposits[0] += inventory.eth_balance.0;
+        deposits[1] += inventory.usdc_balance.0;
+    }
+    BTreeMap::from([(MAINNET_CHAIN_ID, deposits)])
+}
+
+#[derive(Clone)]
 pub struct Warehouse {
     pub inventories: HashMap<Address, Inventory>, // User inventories
     pub oid_qty_by_address: HashMap<Address, HashMap<OrderId, Qty>>, // address, order ids
     pub address_by_oid: HashMap<OrderId, Address>, // order id, address
-    pub deposit_contract: Address,
+    pub deposit_contracts: BTreeMap<u64, Address>, // chain id, DepositRegistry on that chain
+    pub deposit_cursors: BTreeMap<u64, u64>, // chain id, next block the deposit indexer reads there
+    pub chain_deposits: BTreeMap<u64, [U256; 2]>, // chain id, eth and usdc its registry holds
     pub checkpoint_contract: Address,
+    pub resumed_checkpoint_nonce: U256, // set by recovery, checkpoints are never posted below it
     pub rpc_api_key: String,
     pub settlement_orders: Vec<CowSwapOrder>,
     pub signer: PrivateKeySigner,
-    pub encryption_key: Key<Aes256Gcm>,
+    pub leader: PrivateKeySigner, // this container's own key, never stored (see leader.rs)
+    pub encryption_key: Key<Aes256Gcm>, // checkpoint blobs
+    pub legacy_encryption_key: Key<Aes256Gcm>, // checkpoint blobs posted before the key split
+    pub volume_key: Key<Aes256Gcm>, // state written to the data dir
+    pub replication_key: Key<Aes256Gcm>, // the event stream between leader and replicas
+    pub pnl: PnlBook,             // current session pnl
+    pub session_results: HashMap<u64, Vec<SessionResult>>, // session id, ranked results
+    pub public_pnl: HashSet<Address>, // users who opted into the leaderboard
+    pub storage: StorageConfig,   // where store() writes to
+    pub domains: Domains,         // app domains from the config
 }
 
 impl Warehouse {
-    pub fn new(signer: &PrivateKeySigner, encryption_key: &Key<Aes256Gcm>) -> Self {
+    pub fn new(keys: &AppKeys) -> Self {
         Warehouse {
             inventories: HashMap::new(),
             oid_qty_by_address: HashMap::new(),
             address_by_oid: HashMap::new(),
-            deposit_contract: Address::default(),
+            deposit_contracts: BTreeMap::new(),
+            deposit_cursors: BTreeMap::new(),
+            chain_deposits: BTreeMap::new(),
             checkpoint_contract: Address::default(),
+            resumed_checkpoint_nonce: U256::ZERO,
             rpc_api_key: String::new(),
             settlement_orders: Vec::new(),
-            signer: signer.clone(),
-            encryption_key: encryption_key.clone(),
+            signer: keys.signer.clone(),
+            leader: PrivateKeySigner::random(),
+            encryption_key: keys.checkpoint,
+            legacy_encryption_key: keys.legacy_checkpoint,
+            volume_key: keys.volume,
+            replication_key: keys.replication,
+            pnl: PnlBook::new(1),
+            session_results: HashMap::new(),
+            public_pnl: HashSet::new(),
+            storage: StorageConfig::default(),
+            domains: Domains::default(),
         }
     }
-    pub async fn load() -> Self {
-        let shared_secret: String = reqwest::get("http://dstack-guest/key/<tag>")
-            .await
-            .unwrap()
-            .json()
-            .await
-            .unwrap();
-        let signer = PrivateKeySigner::from_slice(shared_secret.as_bytes()).unwrap();
-        // Derive encryption key
-        let hkdf = Hkdf::<Sha256>::new(None, shared_secret.as_bytes());
-        let mut key_bytes = [0u8; 32];
-        hkdf.expand(b"aes-key", &mut key_bytes).unwrap();
-        let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
-        match Self::load_state(&signer, key) {
-            Ok(state) => state,
-            Err(e) => {
-                eprintln!("Failed to load state: {}", e);
-                Self::new(&signer, &key)
-            }
-        }
+    /// Loads state from the configured data dir, a fresh warehouse if the volume is empty. State
+    /// that's there but can't be read, a volume sealed under other keys included, is an error
+    /// rather than silently starting over.
+    pub fn load(config: &Config, keys: &AppKeys) -> Result<Self, Box<dyn std::error::Error>> {
+        let storage = &config.storage;
+        let is_empty = !storage.data_path(SEALED_INVENTORY_STORAGE_FILE).exists()
+            && !storage.data_path(INVENTORY_STORAGE_FILE).exists();
+        let mut warehouse = match is_empty {
+            true => Self::new(keys),
+            false => Self::load_state(storage, keys)
+                .map_err(|e| format!("state in {}: {}", storage.data_dir.display(), e))?,
+        };
+        warehouse.storage = config.storage.clone();
+        warehouse.domains = Domains::new(&config.domains);
+        Ok(warehouse)
     }
 
-    pub fn store(&self) {
-        self.save_state().unwrap();
+    pub fn store(&self) -> Result<(), MwError> {
+        self.save_state()
+            .map_err(|e| MwError::IoError(format!("failed to store the warehouse: {}", e)))
     }
 
     fn load_state(
-        signer: &PrivateKeySigner,
-        encryption_key: &Key<Aes256Gcm>,
+        storage: &StorageConfig,
+        keys: &AppKeys,
     ) -> Result<Warehouse, Box<dyn std::error::Error>> {
-        // Read the file
-        let inventory_file = std::fs::File::open(INVENTORY_STORAGE_PATH)?;
-
-        // Deserialize
-        let inventories: String = serde_json::from_reader(inventory_file)?;
+        // volumes from before the volume key have the inventories in plaintext
+        let inventories: String =
+            match std::fs::read(storage.data_path(SEALED_INVENTORY_STORAGE_FILE)) {
+                Ok(sealed) => String::from_utf8(keys::unseal(
+                    &keys.volume,
+                    SEALED_INVENTORY_STORAGE_FILE,
+                    &sealed,
+                )?)?,
+                Err(_) => std::fs::read_to_string(storage.data_path(INVENTORY_STORAGE_FILE))?,
+            };
         let inventories: HashMap<Address, Inventory> = inventories
             .split('\n')
             .filter(|s| !s.is_empty())
@@ -204,47 +352,145 @@ impl Warehouse {
             })
             .collect();
 
-        let deposit_contract_file = std::fs::File::open(DEPOSIT_CONTRACT_STORAGE_PATH)?;
-        let deposit_contract: String = serde_json::from_reader(deposit_contract_file)?;
-        let deposit_contract: Address = Address::from_hex(&deposit_contract.encode_hex()).unwrap();
-
-        let checkpoint_contract_file = std::fs::File::open(CHECKPOINT_CONTRACT_STORAGE_PATH)?;
-        let checkpoint_contract: String = serde_json::from_reader(checkpoint_contract_file)?;
-        let checkpoint_contract: Address =
-            Address::from_hex(&checkpoint_contract.encode_hex()).unwrap();
-
-        let rpc_api_key_file = std::fs::File::open(RPC_API_KEY_STORAGE_PATH)?;
-        let rpc_api_key: String = serde_json::from_reader(rpc_api_key_file)?;
+        // volumes from before deposits on other chains have a single mainnet registry and cursor
+        let deposit_contracts: BTreeMap<u64, Address> =
+            match std::fs::File::open(storage.data_path(DEPOSIT_CONTRACTS_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => BTreeMap::from([(
+                    MAINNET_CHAIN_ID,
+                    read_address(&storage.data_path(DEPOSIT_CONTRACT_STORAGE_FILE))?,
+                )]),
+            };
+
+        let checkpoint_contract =
+            read_address(&storage.data_path(CHECKPOINT_CONTRACT_STORAGE_FILE))?;
+
+        // the host doesn't have to provide one
+        let rpc_api_key: String =
+            match std::fs::File::open(storage.host_data_path(RPC_API_KEY_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => String::new(),
+            };
+
+        // leaderboard state was added later, so older volumes won't have it
+        let session_results: HashMap<u64, Vec<SessionResult>> =
+            match std::fs::File::open(storage.data_path(SESSION_RESULTS_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => HashMap::new(),
+            };
+        let public_pnl: HashSet<Address> =
+            match std::fs::File::open(storage.data_path(PUBLIC_PNL_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => HashSet::new(),
+            };
+        let deposit_cursors: BTreeMap<u64, u64> =
+            match std::fs::File::open(storage.data_path(DEPOSIT_CURSORS_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => match std::fs::File::open(storage.data_path(DEPOSIT_CURSOR_STORAGE_FILE))
+                {
+                    // null if the indexer hadn't started
+                    Ok(file) => serde_json::from_reader::<_, Option<u64>>(file)?
+                        .map(|cursor| BTreeMap::from([(MAINNET_CHAIN_ID, cursor)]))
+                        .unwrap_or_default(),
+                    Err(_) => BTreeMap::new(),
+                },
+            };
+        // volumes from before deposits were tracked per chain, see mainnet_deposits
+        let chain_deposits: BTreeMap<u64, [U256; 2]> =
+            match std::fs::File::open(storage.data_path(CHAIN_DEPOSITS_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => mainnet_deposits(&inventories),
+            };
+        let resumed_checkpoint_nonce: U256 =
+            match std::fs::File::open(storage.data_path(RESUMED_CHECKPOINT_NONCE_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => U256::ZERO,
+            };
+        // volumes from before queued settlement orders were stored lost them on restart anyway
+        let settlement_orders: Vec<CowSwapOrder> =
+            match std::fs::File::open(storage.data_path(SETTLEMENT_ORDERS_STORAGE_FILE)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(_) => Vec::new(),
+            };
+        // volumes from before the session's pnl was stored only have the finished sessions
+        let pnl: PnlBook = match std::fs::File::open(storage.data_path(SESSION_PNL_STORAGE_FILE)) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(_) => PnlBook::new(session_results.keys().max().map_or(1, |id| id + 1)),
+        };
 
         Ok(Warehouse {
             inventories,
-            deposit_contract,
+            deposit_contracts,
+            deposit_cursors,
+            chain_deposits,
             checkpoint_contract,
+            resumed_checkpoint_nonce,
             rpc_api_key,
             oid_qty_by_address: HashMap::new(),
             address_by_oid: HashMap::new(),
-            settlement_orders: Vec::new(),
-            signer: signer.clone(),
-            encryption_key: encryption_key.clone(),
+            settlement_orders,
+            signer: keys.signer.clone(),
+            leader: PrivateKeySigner::random(),
+            encryption_key: keys.checkpoint,
+            legacy_encryption_key: keys.legacy_checkpoint,
+            volume_key: keys.volume,
+            replication_key: keys.replication,
+            pnl,
+            session_results,
+            public_pnl,
+            storage: storage.clone(),
+            domains: Domains::default(),
         })
     }
 
-    //TODO: I don't think we need to encrypt the state we store in the volume since I think it's stored in the TEE, but should validate
+    // the volume is encrypted by dstack too, sealing the inventories with the volume key keeps them
+    // unreadable to a container of another app that ends up with the same volume
     fn save_state(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut file = std::fs::File::create(INVENTORY_STORAGE_PATH)?;
         let serialized_inventories = self
             .inventories
             .iter()
             .fold(String::new(), |acc, (_, inventory)| {
                 acc + &inventory.to_json() + "\n"
             });
-        file.write_all(serialized_inventories.as_bytes())?;
+        std::fs::write(
+            self.storage.data_path(SEALED_INVENTORY_STORAGE_FILE),
+            keys::seal(
+                &self.volume_key,
+                SEALED_INVENTORY_STORAGE_FILE,
+                serialized_inventories.as_bytes(),
+            )?,
+        )?;
+
+        let file = std::fs::File::create(self.storage.data_path(DEPOSIT_CONTRACTS_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.deposit_contracts)?;
+
+        let mut file =
+            std::fs::File::create(self.storage.data_path(CHECKPOINT_CONTRACT_STORAGE_FILE))?;
+        file.write_all(&self.checkpoint_contract.to_string().as_bytes())?;
 
-        let mut file = std::fs::File::create(DEPOSIT_CONTRACT_STORAGE_PATH)?;
-        file.write_all(&self.deposit_contract.to_string().as_bytes())?;
+        let file = std::fs::File::create(self.storage.data_path(SESSION_RESULTS_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.session_results)?;
 
-        let mut file = std::fs::File::create(CHECKPOINT_CONTRACT_STORAGE_PATH)?;
-        file.write_all(&self.checkpoint_contract.to_string().as_bytes())?;
+        let file = std::fs::File::create(self.storage.data_path(PUBLIC_PNL_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.public_pnl)?;
+
+        let file = std::fs::File::create(self.storage.data_path(SESSION_PNL_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.pnl)?;
+
+        let file = std::fs::File::create(self.storage.data_path(DEPOSIT_CURSORS_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.deposit_cursors)?;
+
+        let file = std::fs::File::create(self.storage.data_path(CHAIN_DEPOSITS_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.chain_deposits)?;
+
+        let file = std::fs::File::create(
+            self.storage
+                .data_path(RESUMED_CHECKPOINT_NONCE_STORAGE_FILE),
+        )?;
+        serde_json::to_writer(file, &self.resumed_checkpoint_nonce)?;
+
+        let file = std::fs::File::create(self.storage.data_path(SETTLEMENT_ORDERS_STORAGE_FILE))?;
+        serde_json::to_writer(file, &self.settlement_orders)?;
 
         Ok(())
     }
@@ -275,10 +521,11 @@ impl Warehouse {
     }
     pub fn fill_bid(&mut self, oid: OrderId, price: Price) -> Result<Qty, MwError> {
         let (qty, inventory) = self.remove_bid(oid, price)?;
+        let volume = qty.0 * price.absolute();
         inventory.eth_balance.add_assign(qty);
-        inventory
-            .usdc_balance
-            .sub_assign(Qty(qty.0 * price.absolute()));
+        inventory.usdc_balance.sub_assign(Qty(volume));
+        let address = inventory.address;
+        self.pnl.record_fill(address, true, qty.0, volume);
         Ok(qty)
     }
     //@Dev: this will not validate that the order is owned by a specific user
@@ -305,10 +552,11 @@ impl Warehouse {
 
     pub fn fill_ask(&mut self, oid: OrderId, price: Price) -> Result<Qty, MwError> {
         let (qty, inventory) = self.remove_ask(oid)?;
+        let volume = qty.0 * price.absolute();
         inventory.eth_balance.sub_assign(qty);
-        inventory
-            .usdc_balance
-            .add_assign(Qty(qty.0 * price.absolute()));
+        inventory.usdc_balance.add_assign(Qty(volume));
+        let address = inventory.address;
+        self.pnl.record_fill(address, false, qty.0, volume);
         Ok(qty)
     }
     //@Dev: this will not validate that the order is owned by a specific user
@@ -331,13 +579,14 @@ impl Warehouse {
     pub fn replace_order(
         &mut self,
         oid: OrderId,
+        old_price: Price,
         new_oid: OrderId,
         new_qty: Qty,
         price: Price,
     ) -> Result<(Qty, &Inventory), MwError> {
-        // Get the address first
-        let (order_qty, address) = if price.is_bid() {
-            let (order_qty, inventory) = self.remove_bid(oid, price)?;
+        // Get the address first, releasing what the old order locked at its own price
+        let (order_qty, address) = if old_price.is_bid() {
+            let (order_qty, inventory) = self.remove_bid(oid, old_price)?;
             (order_qty, inventory.address)
         } else {
             let (order_qty, inventory) = self.remove_ask(oid)?;
@@ -367,6 +616,8 @@ impl Warehouse {
             (order_qty, inventory.address)
         };
         remaining_qty.sub_assign(qty);
+        self.pnl
+            .record_fill(address, price.is_bid(), qty.0, usdc_qty.0);
 
         self.add_order(oid, address, remaining_qty, price);
         Ok(())
@@ -389,7 +640,7 @@ impl Warehouse {
             .as_ref()
             .ok_or(MwError::InvalidBook)?
             .level_pool;
-        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        let timestamp = now_ms();
         Ok(user_orders
             .iter()
             .map(|(oid, qty)| {
@@ -411,6 +662,102 @@ impl Warehouse {
     pub fn clear_settlement_orders(&mut self) {
         self.settlement_orders.clear();
     }
+    /// Drops the oldest `count` settlement orders, the ones a confirmed checkpoint carried.
+    pub fn remove_settlement_orders(&mut self, count: usize) {
+        let count = count.min(self.settlement_orders.len());
+        self.settlement_orders.drain(..count);
+    }
+
+    /// Freezes the current session's pnl and starts tracking the next session.
+    pub fn finalize_session_pnl(&mut self, next_session_id: u64, mark_price: U256) {
+        let results = self.pnl.results(mark_price);
+        self.session_results.insert(self.pnl.session_id, results);
+        self.pnl.roll(next_session_id, mark_price);
+    }
+
+    pub fn set_pnl_public(&mut self, address: Address, is_public: bool) {
+        if is_public {
+            self.public_pnl.insert(address);
+        } else {
+            self.public_pnl.remove(&address);
+        }
+    }
+
+    /// The DepositRegistry on `chain_id`, zero if it hasn't been set.
+    pub fn deposit_contract(&self, chain_id: u64) -> Address {
+        self.deposit_contracts
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Next block the deposit indexer reads on `chain_id`, None until it starts.
+    pub fn deposit_cursor(&self, chain_id: u64) -> Option<u64> {
+        self.deposit_cursors.get(&chain_id).copied()
+    }
+
+    /// Eth and usdc the registry on `chain_id` holds: what was deposited there less what the
+    /// settlements approved there can pull.
+    pub fn chain_deposits(&self, chain_id: u64) -> [U256; 2] {
+        self.chain_deposits
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn credit_chain_deposits(&mut self, chain_id: u64, amounts: [U256; 2]) {
+        let deposits = self.chain_deposits.entry(chain_id).or_default();
+        deposits[0] += amounts[0];
+        deposits[1] += amounts[1];
+    }
+
+    /// Refuses a settlement on `chain_id` that would pull more than that chain's registry holds.
+    pub fn check_chain_deposits(&self, chain_id: u64, amounts: [U256; 2]) -> Result<(), MwError> {
+        let deposits = self.chain_deposits(chain_id);
+        for (token, (held, pulled)) in ["ETH", "USDC"].iter().zip(deposits.iter().zip(amounts)) {
+            if pulled > *held {
+                return Err(MwError::InsufficientChainDeposits {
+                    chain_id,
+                    token: token.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the eth and usdc a settlement costs `user` out of their balances, refusing more than
+    /// they have free. Nothing is taken if either is short.
+    pub fn debit_taker(&mut self, user: Address, amounts: [U256; 2]) -> Result<(), MwError> {
+        let inventory = self.inventories.get(&user).cloned().unwrap_or_default();
+        if inventory.net_eth().0 < amounts[0] {
+            return Err(MwError::InsufficientBalance {
+                token: "ETH".to_string(),
+            });
+        }
+        if inventory.net_usdc().0 < amounts[1] {
+            return Err(MwError::InsufficientBalance {
+                token: "USDC".to_string(),
+            });
+        }
+        if let Some(inventory) = self.inventories.get_mut(&user) {
+            inventory.eth_balance.sub_assign(Qty(amounts[0]));
+            inventory.usdc_balance.sub_assign(Qty(amounts[1]));
+        }
+        Ok(())
+    }
+
+    /// Takes what an approved settlement can pull out of `chain_id`'s deposits.
+    pub fn debit_chain_deposits(
+        &mut self,
+        chain_id: u64,
+        amounts: [U256; 2],
+    ) -> Result<(), MwError> {
+        self.check_chain_deposits(chain_id, amounts)?;
+        let deposits = self.chain_deposits.entry(chain_id).or_default();
+        deposits[0] -= amounts[0];
+        deposits[1] -= amounts[1];
+        Ok(())
+    }
 
     pub fn is_taker(&self, address: Address) -> bool {
         match self.inventories.get(&address) {
@@ -419,21 +766,20 @@ impl Warehouse {
         }
     }
 
-    pub fn get_encrypted_inventory(&self) -> Result<Vec<u8>, MwError> {
-        // Encrypt inventory state
-        let cipher = Aes256Gcm::new(&self.encryption_key);
+    /// Encrypted inventory blob for the checkpoint posted with `checkpoint_nonce`, sorted by
+    /// address. See blob.rs for the format.
+    pub fn get_encrypted_inventory(&self, checkpoint_nonce: U256) -> Result<Vec<u8>, MwError> {
+        let mut inventories: Vec<Inventory> = self.inventories.values().cloned().collect();
+        inventories.sort_by_key(|inventory| inventory.address);
+        blob::encode(&self.encryption_key, checkpoint_nonce, &inventories)
+    }
 
-        Ok(self
-            .inventories
-            .iter()
-            .fold(Vec::new(), |mut encrypted_state, inventory| {
-                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-                let mut buffer: HeaplessVec<u8, 128> =
-                    HeaplessVec::from_slice(&inventory.1.to_bytes()).unwrap();
-                //TODO: this needs error handling that covers the case of the buffer being too big
-                cipher.encrypt_in_place(&nonce, b"", &mut buffer).unwrap();
-                encrypted_state.extend_from_slice(&buffer);
-                encrypted_state
-            }))
+    /// Decrypts a checkpoint blob, falling back to the key from before the HKDF split (see
+    /// keys.rs) for blobs posted by older versions.
+    pub fn decrypt_inventory(&self, encrypted_state: &[u8]) -> Result<InventoryBlob, MwError> {
+        blob::decode(&self.encryption_key, encrypted_state).or_else(|e| match e {
+            MwError::DecryptionError => blob::decode(&self.legacy_encryption_key, encrypted_state),
+            e => Err(e),
+        })
     }
 }
@@ -10,7 +10,10 @@ contract Deploy is Script {
         uint256 deployerPrivateKey = vm.envUint("PRIVATE_KEY");
         vm.startBroadcast(deployerPrivateKey);
 
-        DepositRegistry depositRegistry = new DepositRegistry();
+        DepositRegistry depositRegistry = new DepositRegistry(
+            0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2, // Mainnet WETH address
+            0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48 // Mainnet USDC address
+        );
         Checkpointer checkpointer = new Checkpointer();
 
         vm.stopBroadcast();
@@ -31,7 +31,7 @@ contract TestSetup is Test {
         usdc = MockERC20(0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48);
 
         vm.startPrank(admin);
-        depositRegistry = new DepositRegistry();
+        depositRegistry = new DepositRegistry(address(weth), address(usdc));
         checkpointer = new Checkpointer();
         vm.stopPrank();
 
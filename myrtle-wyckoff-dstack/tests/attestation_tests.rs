@@ -0,0 +1,170 @@
+use alloy::{
+    hex,
+    primitives::{Address, B256},
+    transports::http::reqwest::Url,
+};
+use myrtle_wyckoff_dstack::{
+    attestation::{self, GuestAgent, REPORT_DATA_BYTES},
+    config::Config,
+    errors::MwError,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+const EVENT_LOG: &str = "[{\"imr\":3,\"event\":\"app-id\"}]";
+
+// `json` on top of the deposit chains every config needs
+fn config(json: serde_json::Value) -> Config {
+    let mut full = serde_json::json!({
+        "deposit_chains": [
+            { "chain_id": 1, "rpc_urls": ["https://a.example.com", "https://b.example.com"] },
+        ],
+        "deposit_start_blocks": { "1": 0 },
+    });
+    for (field, value) in json.as_object().unwrap() {
+        full[field] = value.clone();
+    }
+    Config::from_file(serde_json::from_value(full).unwrap()).unwrap()
+}
+
+fn default_config() -> Config {
+    config(serde_json::json!({ "rpc_url": "https://rpc.example.com" }))
+}
+
+// a v4 quote is a 48 byte header and a 584 byte TD report body, report data last in the body
+fn fake_quote(report_data: &[u8]) -> Vec<u8> {
+    let mut quote = vec![0u8; 48 + 520];
+    quote.extend_from_slice(report_data);
+    quote.extend_from_slice(&[7u8; 64]); // signature data
+    quote
+}
+
+// answers a single quote request, over the requested report data unless `report_data` is given
+async fn guest_agent(report_data: Option<[u8; REPORT_DATA_BYTES]>) -> GuestAgent {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let body = loop {
+            let mut buf = [0u8; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((_, body)) = text.split_once("\r\n\r\n") {
+                if let Ok(body) = serde_json::from_str::<serde_json::Value>(body) {
+                    break body;
+                }
+            }
+        };
+        let requested = hex::decode(body["report_data"].as_str().unwrap()).unwrap();
+        let quote = fake_quote(&report_data.map_or(requested, |data| data.to_vec()));
+        let response = serde_json::json!({
+            "quote": hex::encode(quote),
+            "event_log": EVENT_LOG,
+        })
+        .to_string();
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+    });
+    GuestAgent {
+        quote_url: Url::parse(&format!("http://{}/prpc/Tappd.TdxQuote?json", address)).unwrap(),
+    }
+}
+
+#[test]
+fn test_report_data_is_abi_encoded() {
+    let signer = Address::repeat_byte(0xa);
+    let config_hash = B256::repeat_byte(0xb);
+    let report_data = attestation::report_data(signer, config_hash);
+    assert_eq!(&report_data[..12], &[0u8; 12]);
+    assert_eq!(&report_data[12..32], signer.as_slice());
+    assert_eq!(&report_data[32..], config_hash.as_slice());
+    assert_eq!(
+        attestation::quote_report_data(&fake_quote(&report_data)),
+        Some(&report_data[..])
+    );
+    assert_eq!(attestation::quote_report_data(&[0u8; 100]), None);
+}
+
+#[test]
+fn test_config_hash() {
+    let hash = attestation::config_hash(&default_config());
+    assert_eq!(hash, attestation::config_hash(&default_config()));
+    // RPC URLs are left out, they carry api keys
+    assert_eq!(
+        hash,
+        attestation::config_hash(&config(
+            serde_json::json!({ "rpc_url": "https://rpc.example.com/secret-key" })
+        ))
+    );
+    assert!(!attestation::config_json(&default_config())
+        .to_string()
+        .contains("rpc.example.com"));
+    // keys from a dev provider have to show
+    let dev_keys = config(serde_json::json!({
+        "rpc_url": "https://rpc.example.com",
+        "key_provider": "env:MW_DEV_KEY",
+    }));
+    assert_ne!(hash, attestation::config_hash(&dev_keys));
+    let other_domain = config(serde_json::json!({
+        "rpc_url": "https://rpc.example.com",
+        "domains": { "checkpoint_chain_id": 5 },
+    }));
+    assert_ne!(hash, attestation::config_hash(&other_domain));
+}
+
+#[tokio::test]
+async fn test_attest_with_guest_agent() {
+    let signer = Address::repeat_byte(0xa);
+    let config = default_config();
+    let agent = guest_agent(None).await;
+    let attested = attestation::attest(&agent, signer, &config).await.unwrap();
+    assert_eq!(attested.config_hash, attestation::config_hash(&config));
+    assert_eq!(
+        attested.report_data,
+        attestation::report_data(signer, attested.config_hash)
+    );
+    assert_eq!(attested.quote, fake_quote(&attested.report_data));
+    assert_eq!(attested.event_log, EVENT_LOG);
+
+    let json: serde_json::Value = serde_json::from_str(&attested.to_json()).unwrap();
+    assert_eq!(json["signer"], signer.to_string());
+    assert_eq!(json["config"], attestation::config_json(&config));
+    assert_eq!(json["config_hash"], attested.config_hash.to_string());
+    assert_eq!(json["event_log"], EVENT_LOG);
+    assert_eq!(
+        hex::decode(json["quote"].as_str().unwrap()).unwrap(),
+        attested.quote
+    );
+}
+
+#[tokio::test]
+async fn test_refuses_quotes_over_other_data() {
+    let config = default_config();
+    let other = attestation::report_data(Address::repeat_byte(0xe), B256::ZERO);
+    let agent = guest_agent(Some(other)).await;
+    assert!(matches!(
+        attestation::attest(&agent, Address::repeat_byte(0xa), &config).await,
+        Err(MwError::AttestationError(_))
+    ));
+    // nothing listening
+    let agent = GuestAgent {
+        quote_url: Url::parse("http://127.0.0.1:9/prpc/Tappd.TdxQuote?json").unwrap(),
+    };
+    assert!(matches!(
+        attestation::attest(&agent, Address::repeat_byte(0xa), &config).await,
+        Err(MwError::AttestationError(_))
+    ));
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    batch::{
+        self, BatchOperation, BatchResult, OrderBatch, BATCH_CANCEL, BATCH_NEW, BATCH_REPLACE,
+    },
+    errors::MwError,
+    events,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    orderhere::{self, Order},
+    session::{SessionController, SessionSchedule},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{order::OrderId, orderbook_manager::OrderBookManager, quantity::Qty};
+
+const MAKER: u8 = 1;
+const OTHER: u8 = 2;
+
+fn account(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn fund(warehouse: &mut Warehouse, byte: u8, eth: u64, usdc: u64) {
+    warehouse.inventories.insert(
+        account(byte),
+        Inventory::new(
+            account(byte),
+            Qty(U256::from(eth)),
+            Qty(U256::ZERO),
+            Qty(U256::from(usdc)),
+            Qty(U256::ZERO),
+            BTreeMap::new(),
+            false,
+        ),
+    );
+}
+
+fn place(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    byte: u8,
+    price: u64,
+    qty: u64,
+    is_bid: bool,
+) -> u32 {
+    let order = Order {
+        price: U256::from(price),
+        qty: U256::from(qty),
+        is_bid,
+        timestamp: 0,
+    };
+    let (_, _, oid) =
+        orderhere::submit_order(warehouse, orderbook_manager, account(byte), order).unwrap();
+    oid.unwrap().0
+}
+
+fn operation(kind: u8, oid: u32, price: u64, qty: u64, is_bid: bool) -> BatchOperation {
+    BatchOperation {
+        kind,
+        oid,
+        price: U256::from(price),
+        qty: U256::from(qty),
+        is_bid,
+    }
+}
+
+fn order_batch(operations: Vec<BatchOperation>, atomic: bool) -> OrderBatch {
+    OrderBatch {
+        operations,
+        atomic,
+        timestamp: 0,
+    }
+}
+
+// MAKER has 2 eth and 3000 usdc with a 1000 bid for 1 and a 2000 ask for 1 resting,
+// OTHER has a 1900 bid for 1 resting
+fn market() -> (Warehouse, OrderBookManager, u32, u32, u32) {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[4u8; MIN_SECRET_BYTES]).unwrap());
+    let mut orderbook_manager = OrderBookManager::new();
+    fund(&mut warehouse, MAKER, 2, 3000);
+    fund(&mut warehouse, OTHER, 0, 10_000);
+    let bid = place(&mut warehouse, &mut orderbook_manager, MAKER, 1000, 1, true);
+    let ask = place(
+        &mut warehouse,
+        &mut orderbook_manager,
+        MAKER,
+        2000,
+        1,
+        false,
+    );
+    let other = place(&mut warehouse, &mut orderbook_manager, OTHER, 1900, 1, true);
+    (warehouse, orderbook_manager, bid, ask, other)
+}
+
+fn state_hash(warehouse: &Warehouse, orderbook_manager: &OrderBookManager) -> String {
+    let session = SessionController::new(SessionSchedule::default(), 0);
+    events::state_hash(warehouse, orderbook_manager, &session).to_string()
+}
+
+fn liabilities(warehouse: &Warehouse, byte: u8) -> (U256, U256) {
+    let inventory = &warehouse.inventories[&account(byte)];
+    (inventory.eth_liabilities.0, inventory.usdc_liabilities.0)
+}
+
+#[test]
+fn test_atomic_batch_changes_nothing_on_failure() {
+    let (mut warehouse, mut orderbook_manager, bid, _, other) = market();
+    let before = state_hash(&warehouse, &orderbook_manager);
+
+    for (operations, failed_at) in [
+        // someone else's order
+        (
+            vec![
+                operation(BATCH_REPLACE, bid, 1100, 1, true),
+                operation(BATCH_CANCEL, other, 0, 0, false),
+            ],
+            1,
+        ),
+        // the second bid needs the usdc the first one locked
+        (
+            vec![
+                operation(BATCH_NEW, 0, 1000, 2, true),
+                operation(BATCH_NEW, 0, 900, 1, true),
+            ],
+            1,
+        ),
+        // the bid is gone once cancelled
+        (
+            vec![
+                operation(BATCH_CANCEL, bid, 0, 0, false),
+                operation(BATCH_NEW, 0, 2100, 1, false),
+                operation(BATCH_REPLACE, bid, 900, 1, true),
+            ],
+            2,
+        ),
+        (vec![operation(7, 0, 1000, 1, true)], 0),
+    ] {
+        let result = batch::apply_batch(
+            &mut warehouse,
+            &mut orderbook_manager,
+            account(MAKER),
+            &order_batch(operations, true),
+        );
+        assert!(matches!(
+            result,
+            Err(MwError::BatchFailed { index, .. }) if index == failed_at
+        ));
+        assert_eq!(state_hash(&warehouse, &orderbook_manager), before);
+    }
+}
+
+#[test]
+fn test_atomic_batch_can_spend_what_it_releases() {
+    let (mut warehouse, mut orderbook_manager, bid, ask, _) = market();
+    // the cancelled bid frees the usdc for a larger one, the replaced ask keeps its eth locked
+    let results = batch::apply_batch(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(MAKER),
+        &order_batch(
+            vec![
+                operation(BATCH_CANCEL, bid, 0, 0, false),
+                operation(BATCH_NEW, 0, 1500, 2, true),
+                operation(BATCH_REPLACE, ask, 2200, 1, false),
+                operation(BATCH_NEW, 0, 2100, 1, false),
+            ],
+            true,
+        ),
+    )
+    .unwrap();
+    assert_eq!(results.len(), 4);
+    assert!(matches!(results[0], BatchResult::Cancelled { order_id } if order_id == bid));
+    assert!(matches!(
+        results[3],
+        BatchResult::Placed {
+            order_id: Some(_),
+            ..
+        }
+    ));
+    assert_eq!(
+        liabilities(&warehouse, MAKER),
+        (U256::from(2), U256::from(3000))
+    );
+}
+
+#[test]
+fn test_atomic_batch_undoes_fills_on_failure() {
+    let (mut warehouse, mut orderbook_manager, _, ask, _) = market();
+    let before = state_hash(&warehouse, &orderbook_manager);
+    // the bid fills the user's own ask, so there's nothing left to replace and the fill is undone
+    let result = batch::apply_batch(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(MAKER),
+        &order_batch(
+            vec![
+                operation(BATCH_NEW, 0, 2000, 1, true),
+                operation(BATCH_REPLACE, ask, 2100, 1, false),
+            ],
+            true,
+        ),
+    );
+    assert!(matches!(result, Err(MwError::BatchFailed { index: 1, .. })));
+    assert_eq!(state_hash(&warehouse, &orderbook_manager), before);
+}
+
+#[test]
+fn test_batch_reports_each_failure_and_keeps_going() {
+    let (mut warehouse, mut orderbook_manager, bid, ask, other) = market();
+    let results = batch::apply_batch(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(MAKER),
+        &order_batch(
+            vec![
+                // more eth than the user has
+                operation(BATCH_REPLACE, ask, 2000, 5, false),
+                operation(BATCH_CANCEL, other, 0, 0, false),
+                operation(BATCH_REPLACE, bid, 1200, 1, true),
+            ],
+            false,
+        ),
+    )
+    .unwrap();
+    assert!(matches!(
+        &results[0],
+        BatchResult::Failed { error: MwError::InsufficientBalance { token } } if token == "ETH"
+    ));
+    assert!(matches!(
+        &results[1],
+        BatchResult::Failed {
+            error: MwError::UnauthorizedAccess
+        }
+    ));
+    assert!(matches!(results[2], BatchResult::Replaced { order_id, .. } if order_id == bid));
+
+    // the failed replace left the ask resting as it was, in the warehouse and on the book
+    assert_eq!(
+        warehouse.oid_qty_by_address[&account(MAKER)][&OrderId(ask)].0,
+        U256::from(1)
+    );
+    assert!(orderhere::resting_order_price(&orderbook_manager, OrderId(ask)).is_ok());
+    assert_eq!(
+        liabilities(&warehouse, MAKER),
+        (U256::from(1), U256::from(1200))
+    );
+}
+
+#[test]
+fn test_batch_size_limits() {
+    let (mut warehouse, mut orderbook_manager, ..) = market();
+    for size in [0, batch::MAX_BATCH_SIZE + 1] {
+        let operations = vec![operation(BATCH_NEW, 0, 1, 1, true); size];
+        assert!(matches!(
+            batch::apply_batch(
+                &mut warehouse,
+                &mut orderbook_manager,
+                account(MAKER),
+                &order_batch(operations, false),
+            ),
+            Err(MwError::InvalidOrderParams)
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key,
+};
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    blob::{self, BLOB_MAGIC, BLOB_VERSION, HEADER_BYTES, LEGACY_BLOB_VERSION, RECORD_BYTES},
+    chains::{BASE_CHAIN_ID, MAINNET_CHAIN_ID},
+    errors::MwError,
+    warehouse::{Inventory, LEGACY_INVENTORY_BYTES},
+};
+use optimized_lob::quantity::Qty;
+
+fn key() -> Key<Aes256Gcm> {
+    *Key::<Aes256Gcm>::from_slice(&[7u8; 32])
+}
+
+fn inventories() -> Vec<Inventory> {
+    (1..=2)
+        .map(|byte| {
+            let mut inventory = Inventory::default();
+            inventory.address = Address::repeat_byte(byte);
+            inventory.eth_balance = Qty(U256::from(byte) * U256::from(10).pow(U256::from(18)));
+            inventory
+                .deposit_nonces
+                .insert(MAINNET_CHAIN_ID, byte as u32);
+            inventory
+                .deposit_nonces
+                .insert(BASE_CHAIN_ID, 10 + byte as u32);
+            inventory
+        })
+        .collect()
+}
+
+#[test]
+fn test_round_trip() {
+    let encoded = blob::encode(&key(), U256::from(42), &inventories()).unwrap();
+    let decoded = blob::decode(&key(), &encoded).unwrap();
+
+    assert_eq!(decoded.version, BLOB_VERSION);
+    assert_eq!(decoded.checkpoint_nonce, U256::from(42));
+    assert_eq!(decoded.inventories.len(), 2);
+    for (decoded, original) in decoded.inventories.iter().zip(inventories().iter()) {
+        assert_eq!(decoded.to_bytes(), original.to_bytes());
+    }
+}
+
+#[test]
+fn test_empty_round_trip() {
+    let encoded = blob::encode(&key(), U256::ZERO, &[]).unwrap();
+    assert_eq!(encoded.len(), HEADER_BYTES);
+    assert!(blob::decode(&key(), &encoded)
+        .unwrap()
+        .inventories
+        .is_empty());
+}
+
+#[test]
+fn test_nonces_are_fresh() {
+    let first = blob::encode(&key(), U256::ZERO, &inventories()).unwrap();
+    let second = blob::encode(&key(), U256::ZERO, &inventories()).unwrap();
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_header_is_authenticated() {
+    let mut encoded = blob::encode(&key(), U256::from(1), &inventories()).unwrap();
+    // claim the records belong to a different checkpoint
+    encoded[HEADER_BYTES - 5] ^= 1;
+    assert!(matches!(
+        blob::decode(&key(), &encoded),
+        Err(MwError::DecryptionError)
+    ));
+}
+
+#[test]
+fn test_records_cant_be_reordered() {
+    let encoded = blob::encode(&key(), U256::from(1), &inventories()).unwrap();
+    let (header, records) = encoded.split_at(HEADER_BYTES);
+    let (first, second) = records.split_at(RECORD_BYTES);
+    let swapped = [header, second, first].concat();
+    assert!(matches!(
+        blob::decode(&key(), &swapped),
+        Err(MwError::DecryptionError)
+    ));
+}
+
+#[test]
+fn test_malformed_blobs() {
+    let encoded = blob::encode(&key(), U256::from(1), &inventories()).unwrap();
+
+    let mut wrong_version = encoded.clone();
+    wrong_version[4] = BLOB_VERSION + 1;
+    assert!(matches!(
+        blob::decode(&key(), &wrong_version),
+        Err(MwError::InvalidBlob(_))
+    ));
+
+    assert!(matches!(
+        blob::decode(&key(), &encoded[..encoded.len() - 1]),
+        Err(MwError::InvalidBlob(_))
+    ));
+    assert!(matches!(
+        blob::decode(&key(), &[encoded.as_slice(), &[0]].concat()),
+        Err(MwError::InvalidBlob(_))
+    ));
+    assert!(matches!(
+        blob::decode(&key(), b"MWI"),
+        Err(MwError::InvalidBlob(_))
+    ));
+}
+
+#[test]
+fn test_decodes_legacy_blobs() {
+    // a version 1 blob with a single record from before deposits on other chains
+    let mut header = BLOB_MAGIC.to_vec();
+    header.push(LEGACY_BLOB_VERSION);
+    header.extend_from_slice(&U256::from(3).to_be_bytes::<32>());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    let mut record = vec![0u8; LEGACY_INVENTORY_BYTES];
+    record[0..20].copy_from_slice(&Address::repeat_byte(1).0 .0);
+    record[148..152].copy_from_slice(&4u32.to_le_bytes());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let aad = [header.as_slice(), &0u32.to_le_bytes()].concat();
+    let ciphertext = Aes256Gcm::new(&key())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &record,
+                aad: &aad,
+            },
+        )
+        .unwrap();
+    let mut encoded = header;
+    encoded.extend_from_slice(&((nonce.len() + ciphertext.len()) as u32).to_le_bytes());
+    encoded.extend_from_slice(&nonce);
+    encoded.extend_from_slice(&ciphertext);
+
+    let decoded = blob::decode(&key(), &encoded).unwrap();
+    assert_eq!(decoded.version, LEGACY_BLOB_VERSION);
+    assert_eq!(decoded.inventories[0].address, Address::repeat_byte(1));
+    assert_eq!(decoded.inventories[0].deposit_nonce(MAINNET_CHAIN_ID), 4);
+    assert_eq!(decoded.inventories[0].deposit_nonce(BASE_CHAIN_ID), 0);
+}
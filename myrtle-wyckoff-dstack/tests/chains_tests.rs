@@ -0,0 +1,52 @@
+use alloy::primitives::Address;
+use myrtle_wyckoff_dstack::{
+    chains::{self, ChainSpec, BASE, BASE_CHAIN_ID, MAINNET, MAINNET_CHAIN_ID},
+    errors::MwError,
+};
+
+#[test]
+fn test_chain_config_parsing() {
+    let configs = chains::parse_chain_configs(
+        "1=https://eth.example.com|https://eth.example.org, 8453=https://base.example.com",
+    )
+    .unwrap();
+    assert_eq!(configs.len(), 2);
+    assert_eq!(configs[0].spec, MAINNET);
+    assert_eq!(configs[0].rpc_urls.len(), 2);
+    assert_eq!(configs[1].spec, BASE);
+    assert_eq!(configs[1].rpc_urls[0].as_str(), "https://base.example.com/");
+
+    assert!(matches!(
+        chains::parse_chain_configs("10=https://op.example.com"),
+        Err(MwError::UnknownChain { chain_id: 10 })
+    ));
+    for invalid in [
+        "1",
+        "mainnet=https://eth.example.com",
+        "1=not a url",
+        "1=https://eth.example.com|",
+        "1=https://a.example.com,1=https://b.example.com",
+    ] {
+        assert!(matches!(
+            chains::parse_chain_configs(invalid),
+            Err(MwError::InvalidChainConfig(_))
+        ));
+    }
+}
+
+#[test]
+fn test_domains_are_bound_to_the_chain() {
+    let registry = Address::repeat_byte(0xd);
+    let mainnet = ChainSpec::from_chain_id(MAINNET_CHAIN_ID).unwrap();
+    let base = ChainSpec::from_chain_id(BASE_CHAIN_ID).unwrap();
+    // the same registry address on two chains still gets different separators
+    assert_ne!(
+        mainnet.deposit_domain(registry).separator(),
+        base.deposit_domain(registry).separator()
+    );
+    assert_ne!(
+        mainnet.cowswap_domain().separator(),
+        base.cowswap_domain().separator()
+    );
+    assert_ne!(mainnet.usdc, base.usdc);
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use alloy::primitives::{Address, I256, U256};
+use myrtle_wyckoff_dstack::{
+    closer,
+    errors::MwError,
+    events,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    orderhere::{self, Order},
+    session::{SessionController, SessionSchedule},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{orderbook_manager::OrderBookManager, quantity::Qty};
+
+const SHORT: u8 = 1;
+const LONG: u8 = 2;
+
+fn i256(value: i64) -> I256 {
+    I256::try_from(value).unwrap()
+}
+
+fn account(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn fund(warehouse: &mut Warehouse, byte: u8, eth: u64, usdc: u64) {
+    warehouse.inventories.insert(
+        account(byte),
+        Inventory::new(
+            account(byte),
+            Qty(U256::from(eth)),
+            Qty(U256::ZERO),
+            Qty(U256::from(usdc)),
+            Qty(U256::ZERO),
+            BTreeMap::new(),
+            false,
+        ),
+    );
+}
+
+fn place(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    byte: u8,
+    price: u64,
+    qty: u64,
+    is_bid: bool,
+) {
+    let order = Order {
+        price: U256::from(price),
+        qty: U256::from(qty),
+        is_bid,
+        timestamp: 0,
+    };
+    orderhere::submit_order(warehouse, orderbook_manager, account(byte), order).unwrap();
+}
+
+// SHORT sold 2 eth to LONG at 1500 and still has a bid resting, LONG has an ask resting
+fn traded(short_eth: u64, short_usdc: u64) -> (Warehouse, OrderBookManager) {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[3u8; MIN_SECRET_BYTES]).unwrap());
+    let mut orderbook_manager = OrderBookManager::new();
+    fund(&mut warehouse, SHORT, short_eth, short_usdc);
+    fund(&mut warehouse, LONG, 10, 100_000);
+    place(
+        &mut warehouse,
+        &mut orderbook_manager,
+        SHORT,
+        1500,
+        2,
+        false,
+    );
+    place(&mut warehouse, &mut orderbook_manager, LONG, 1500, 2, true);
+    place(&mut warehouse, &mut orderbook_manager, SHORT, 1000, 1, true);
+    place(&mut warehouse, &mut orderbook_manager, LONG, 1900, 1, false);
+    (warehouse, orderbook_manager)
+}
+
+fn position(warehouse: &Warehouse, byte: u8) -> I256 {
+    warehouse
+        .pnl
+        .accounts
+        .get(&account(byte))
+        .map_or(I256::ZERO, |account| account.position)
+}
+
+fn assert_nothing_locked(warehouse: &Warehouse) {
+    assert!(warehouse.address_by_oid.is_empty());
+    for inventory in warehouse.inventories.values() {
+        assert!(inventory.eth_liabilities.0.is_zero());
+        assert!(inventory.usdc_liabilities.0.is_zero());
+    }
+}
+
+#[test]
+fn test_close_offsets_positions_at_mid() {
+    let (mut warehouse, mut orderbook_manager) = traded(10, 100_000);
+    assert_eq!(position(&warehouse, SHORT), i256(-2));
+    assert_eq!(position(&warehouse, LONG), i256(2));
+
+    let report =
+        closer::close_session(&mut warehouse, &mut orderbook_manager, U256::from(1600), 7).unwrap();
+    assert_eq!(report.cancelled_orders.len(), 2);
+    // the long sells first, then the short buys it back from them
+    let offsets: Vec<_> = report
+        .offset_orders
+        .iter()
+        .map(|offset| (offset.address, offset.is_bid, offset.qty_executed))
+        .collect();
+    assert_eq!(
+        offsets,
+        [
+            (account(LONG), false, U256::ZERO),
+            (account(SHORT), true, U256::from(2)),
+        ]
+    );
+    assert!(report.residual_positions.is_empty());
+    assert_eq!(position(&warehouse, SHORT), I256::ZERO);
+    assert_eq!(position(&warehouse, LONG), I256::ZERO);
+    assert_nothing_locked(&warehouse);
+
+    // sold at 1500, bought back at 1600
+    let short = &warehouse.inventories[&account(SHORT)];
+    assert_eq!(short.eth_balance.0, U256::from(10));
+    assert_eq!(short.usdc_balance.0, U256::from(100_000 + 3000 - 3200));
+    let long = &warehouse.inventories[&account(LONG)];
+    assert_eq!(long.eth_balance.0, U256::from(10));
+    assert_eq!(long.usdc_balance.0, U256::from(100_000 - 3000 + 3200));
+}
+
+#[test]
+fn test_close_reports_what_it_could_not_offset() {
+    // the short only has the 3000 usdc it sold for, at 2000 that buys back 1 of its 2 eth
+    let (mut warehouse, mut orderbook_manager) = traded(2, 0);
+    let report =
+        closer::close_session(&mut warehouse, &mut orderbook_manager, U256::from(2000), 7).unwrap();
+    let residuals: Vec<_> = report
+        .residual_positions
+        .iter()
+        .map(|residual| (residual.address, residual.position))
+        .collect();
+    assert_eq!(
+        residuals,
+        [(account(SHORT), i256(-1)), (account(LONG), i256(1)),]
+    );
+    // the unsold half of the long's offset doesn't stay on the book
+    assert_nothing_locked(&warehouse);
+    assert!(report.to_json().contains("\"residual_positions\""));
+}
+
+#[test]
+fn test_close_is_deterministic() {
+    let close = || {
+        let (mut warehouse, mut orderbook_manager) = traded(10, 100_000);
+        let report =
+            closer::close_session(&mut warehouse, &mut orderbook_manager, U256::from(1600), 7)
+                .unwrap();
+        let session = SessionController::new(SessionSchedule::default(), 0);
+        (
+            report.to_json(),
+            events::state_hash(&warehouse, &orderbook_manager, &session),
+        )
+    };
+    assert_eq!(close(), close());
+}
+
+#[test]
+fn test_close_needs_a_mid_price() {
+    let (mut warehouse, mut orderbook_manager) = traded(10, 100_000);
+    assert!(matches!(
+        closer::close_session(&mut warehouse, &mut orderbook_manager, U256::ZERO, 7),
+        Err(MwError::PriceUnavailable { .. })
+    ));
+    // nothing was cancelled
+    assert_eq!(warehouse.address_by_oid.len(), 2);
+}
@@ -0,0 +1,231 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    chains::{BASE_CHAIN_ID, GPV2_SETTLEMENT_ADDRESS, MAINNET, MAINNET_CHAIN_ID},
+    config::{Config, ConfigFile, DEFAULT_DATA_DIR},
+    domains::{DomainConfig, Domains, DEFAULT_CHECKPOINT_CHAIN_ID},
+    errors::MwError,
+    keys::KeyProviderConfig,
+    leader::InstanceRole,
+    sink::SinkConfig,
+};
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn from_json(json: serde_json::Value) -> Result<Config, MwError> {
+    Config::from_file(serde_json::from_value(json).unwrap())
+}
+
+// the least a config needs
+fn minimal() -> serde_json::Value {
+    serde_json::json!({
+        "rpc_url": "https://rpc.example.com",
+        "deposit_chains": [
+            { "chain_id": 1, "rpc_urls": ["https://a.example.com", "https://b.example.com"] },
+        ],
+        "deposit_start_blocks": { "1": 0 },
+    })
+}
+
+fn is_invalid(result: Result<Config, MwError>) -> bool {
+    matches!(result, Err(MwError::InvalidConfig(_)))
+}
+
+#[test]
+fn test_defaults_match_the_old_constants() {
+    let config = from_json(minimal()).unwrap();
+    assert_eq!(config.storage.data_dir, PathBuf::from(DEFAULT_DATA_DIR));
+    assert_eq!(
+        config.storage.data_path("events.jsonl"),
+        PathBuf::from("/mnt/encrypted_data/events.jsonl")
+    );
+    assert_eq!(config.dstack_key_url.host_str(), Some("dstack-guest"));
+    assert_eq!(config.dstack_quote_url.host_str(), Some("dstack-guest"));
+    assert_eq!(config.key_provider, KeyProviderConfig::Dstack);
+    assert_eq!(config.role, InstanceRole::Leader);
+    assert_eq!(config.leader_url, None);
+    assert_eq!(config.sink, SinkConfig::Checkpointer);
+    assert_eq!(config.chains.len(), 1);
+    assert_eq!(config.chains[0].spec, MAINNET);
+    assert_eq!(config.chains[0].rpc_urls.len(), 2);
+
+    let toliman = Domains::new(&config.domains).toliman(Address::repeat_byte(0xc));
+    assert_eq!(toliman.name.as_deref(), Some("MyrtleWyckoff"));
+    assert_eq!(
+        toliman.chain_id,
+        Some(U256::from(DEFAULT_CHECKPOINT_CHAIN_ID))
+    );
+    assert_eq!(Domains::new(&config.domains), Domains::default());
+    assert_eq!(Domains::default().dstack.chain_id, None);
+}
+
+#[test]
+fn test_file_overrides_chains_and_domains() {
+    let weth = Address::repeat_byte(0xa);
+    let checkpointer = Address::repeat_byte(0xc);
+    let config = from_json(serde_json::json!({
+        "rpc_url": "https://rpc.example.com",
+        "data_dir": "/data",
+        "deposit_chains": [
+            {
+                "chain_id": 1,
+                "rpc_urls": ["https://eth.example.com", "https://eth2.example.com"],
+                "weth": weth,
+            },
+            {
+                "chain_id": 8453,
+                "rpc_urls": ["https://base.example.com", "https://base2.example.com"],
+            },
+        ],
+        "domains": { "checkpoint_chain_id": 5 },
+        "deposit_start_blocks": { "1": 10, "8453": 100 },
+        "snapshot_interval_ms": 1000,
+    }))
+    .unwrap();
+    assert_eq!(config.storage.data_dir, PathBuf::from("/data"));
+    assert_eq!(config.chains[0].spec.weth, weth);
+    assert_eq!(config.chains[0].spec.usdc, MAINNET.usdc);
+    assert_eq!(
+        config.chains[1].spec.gpv2_settlement,
+        GPV2_SETTLEMENT_ADDRESS
+    );
+    assert_eq!(config.chain_spec(MAINNET_CHAIN_ID).unwrap().weth, weth);
+    assert_eq!(config.indexer.start_blocks[&MAINNET_CHAIN_ID], 10);
+    assert_eq!(config.indexer.start_blocks[&BASE_CHAIN_ID], 100);
+    assert_eq!(config.scheduler.interval, Duration::from_millis(1000));
+
+    let toliman = Domains::new(&config.domains).toliman(checkpointer);
+    assert_eq!(toliman.chain_id, Some(U256::from(5)));
+    assert_ne!(
+        toliman.separator(),
+        Domains::default().toliman(checkpointer).separator()
+    );
+}
+
+#[test]
+fn test_env_overrides_the_file() {
+    let mut file: ConfigFile = serde_json::from_value(serde_json::json!({
+        "rpc_url": "https://file.example.com",
+        "deposit_chains": [
+            {
+                "chain_id": 1,
+                "rpc_urls": ["https://file.example.com", "https://file2.example.com"],
+            },
+        ],
+        "checkpoint_sink": "checkpointer",
+    }))
+    .unwrap();
+    file.apply_env(env(&[
+        ("RPC_URL", "https://env.example.com"),
+        (
+            "DEPOSIT_CHAINS",
+            "8453=https://base.example.com|https://base2.example.com",
+        ),
+        ("CHECKPOINT_SINK", "file:/tmp/checkpoints"),
+        ("DEPOSIT_START_BLOCK", "8453=7"),
+        ("CHECKPOINT_CHAIN_ID", "10"),
+        ("KEY_PROVIDER", "env:MW_DEV_KEY"),
+        ("INSTANCE_ROLE", "replica"),
+        ("LEADER_URL", "http://leader:8000"),
+        ("OPERATOR_ADDRESS", &Address::repeat_byte(0x0a).to_string()),
+    ]))
+    .unwrap();
+    let config = Config::from_file(file).unwrap();
+    assert_eq!(config.rpc_url.as_str(), "https://env.example.com/");
+    assert_eq!(
+        config.key_provider,
+        KeyProviderConfig::Env("MW_DEV_KEY".to_string())
+    );
+    assert_eq!(config.role, InstanceRole::Replica);
+    assert_eq!(config.leader_url.unwrap().as_str(), "http://leader:8000/");
+    assert_eq!(config.chains.len(), 1);
+    assert_eq!(config.chains[0].spec.chain_id, BASE_CHAIN_ID);
+    assert_eq!(
+        config.sink,
+        SinkConfig::File(PathBuf::from("/tmp/checkpoints"))
+    );
+    assert_eq!(config.indexer.start_blocks[&BASE_CHAIN_ID], 7);
+    assert_eq!(config.domains.checkpoint_chain_id, 10);
+    assert_eq!(config.operator, Some(Address::repeat_byte(0x0a)));
+
+    // a bare start block is mainnet's
+    let mut file = ConfigFile::default();
+    file.apply_env(env(&[
+        ("RPC_URL", "https://env.example.com"),
+        (
+            "DEPOSIT_CHAINS",
+            "1=https://a.example.com|https://b.example.com",
+        ),
+        ("DEPOSIT_START_BLOCK", "42"),
+    ]))
+    .unwrap();
+    let config = Config::from_file(file).unwrap();
+    assert_eq!(config.indexer.start_blocks[&MAINNET_CHAIN_ID], 42);
+
+    assert_eq!(config.operator, None);
+
+    let mut file = ConfigFile::default();
+    assert!(matches!(
+        file.apply_env(env(&[("SNAPSHOT_INTERVAL_MS", "soon")])),
+        Err(MwError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        file.apply_env(env(&[("OPERATOR_ADDRESS", "someone")])),
+        Err(MwError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_rejects_invalid_configs() {
+    assert!(is_invalid(from_json(serde_json::json!({}))));
+    assert!(is_invalid(from_json(
+        serde_json::json!({ "rpc_url": "not a url" })
+    )));
+    for invalid in [
+        serde_json::json!({ "deposit_chains": [{ "chain_id": 10, "rpc_urls": ["https://op.example.com"] }] }),
+        serde_json::json!({ "deposit_chains": [{ "chain_id": 1, "rpc_urls": [] }] }),
+        // a single endpoint would be trusted with every deposit
+        serde_json::json!({ "deposit_chains": [
+            { "chain_id": 1, "rpc_urls": ["https://a.example.com"] },
+        ] }),
+        serde_json::json!({ "deposit_chains": [] }),
+        serde_json::json!({ "deposit_chains": [
+            { "chain_id": 1, "rpc_urls": ["https://a.example.com", "https://b.example.com"] },
+            { "chain_id": 1, "rpc_urls": ["https://c.example.com", "https://d.example.com"] },
+        ] }),
+        serde_json::json!({ "data_dir": "relative/dir" }),
+        serde_json::json!({ "dstack_key_url": "nope" }),
+        serde_json::json!({ "dstack_quote_url": "nope" }),
+        serde_json::json!({ "checkpoint_sink": "s3" }),
+        // calldata can only be posted to a chain there's an rpc for
+        serde_json::json!({ "checkpoint_sink": "calldata:8453:0x0101010101010101010101010101010101010101" }),
+        serde_json::json!({ "key_provider": "raw:deadbeef" }),
+        serde_json::json!({ "role": "follower" }),
+        serde_json::json!({ "leader_url": "http://leader:8000" }),
+        serde_json::json!({ "role": "replica", "leader_url": "nope" }),
+        serde_json::json!({ "snapshot_interval_ms": 0 }),
+        serde_json::json!({ "deposit_start_blocks": { "8453": 1 } }),
+        // mainnet has no start block
+        serde_json::json!({ "deposit_start_blocks": {} }),
+        serde_json::json!({ "domains": { "name": "" } }),
+        serde_json::json!({ "domains": { "checkpoint_chain_id": 0 } }),
+    ] {
+        let mut json = minimal();
+        for (field, value) in invalid.as_object().unwrap() {
+            json[field] = value.clone();
+        }
+        assert!(is_invalid(from_json(json)), "{}", invalid);
+    }
+    // typos don't silently fall back to defaults
+    assert!(
+        serde_json::from_value::<ConfigFile>(serde_json::json!({ "data_dri": "/data" })).is_err()
+    );
+    assert!(serde_json::from_value::<DomainConfig>(serde_json::json!({ "nmae": "x" })).is_err());
+}
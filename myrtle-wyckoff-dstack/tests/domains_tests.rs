@@ -0,0 +1,144 @@
+use alloy::{
+    primitives::{keccak256, Address, B256, U256},
+    sol_types::{SolStruct, SolValue},
+};
+use myrtle_wyckoff_dstack::{
+    artifacts::{ICheckpointer, IDepositRegistry},
+    chains::MAINNET,
+    domains::{self, DomainConfig, Domains},
+    errors::MwError,
+};
+
+// what Checkpointer.sol and DepositRegistry.sol are given by set_domain_separator
+fn solidity_separator(name: &str, version: &str, chain_id: u64, contract: Address) -> B256 {
+    keccak256(
+        (
+            keccak256(
+                "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)",
+            ),
+            keccak256(name),
+            keccak256(version),
+            U256::from(chain_id),
+            contract,
+        )
+            .abi_encode(),
+    )
+}
+
+#[test]
+fn test_domains_are_bound_to_the_contracts() {
+    let domains = Domains::default();
+    let checkpointer = Address::repeat_byte(0xc);
+    assert_eq!(
+        domains.toliman(checkpointer).separator(),
+        solidity_separator("MyrtleWyckoff", "1", 33626250, checkpointer)
+    );
+    assert_ne!(
+        domains.toliman(checkpointer).separator(),
+        domains.toliman(Address::repeat_byte(0xd)).separator()
+    );
+
+    let registry = Address::repeat_byte(0xe);
+    assert_eq!(
+        MAINNET.deposit_domain(registry).separator(),
+        solidity_separator("MyrtleWyckoff", "1", 1, registry)
+    );
+
+    let renamed = Domains::new(&DomainConfig {
+        name: "Renamed".to_string(),
+        ..DomainConfig::default()
+    });
+    assert_ne!(
+        renamed.toliman(checkpointer).separator(),
+        domains.toliman(checkpointer).separator()
+    );
+    assert_ne!(renamed.dstack.separator(), domains.dstack.separator());
+}
+
+#[test]
+fn test_check_separator() {
+    let checkpointer = Address::repeat_byte(0xc);
+    let domain = Domains::default().toliman(checkpointer);
+    // not set on chain yet
+    assert!(!domains::check_separator(checkpointer, &domain, B256::ZERO).unwrap());
+    assert!(domains::check_separator(checkpointer, &domain, domain.separator()).unwrap());
+    // set for another contract, or by a deployment using another name or chain
+    let other = Domains::default()
+        .toliman(Address::repeat_byte(0xd))
+        .separator();
+    assert!(matches!(
+        domains::check_separator(checkpointer, &domain, other),
+        Err(MwError::DomainSeparatorMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_domain_json() {
+    let checkpointer = Address::repeat_byte(0xc);
+    let domain = Domains::default().toliman(checkpointer);
+    let json = domains::domain_json(&domain);
+    assert_eq!(json["name"], "MyrtleWyckoff");
+    assert_eq!(json["chain_id"], "33626250");
+    assert_eq!(json["verifying_contract"], checkpointer.to_string());
+    assert_eq!(json["separator"], domain.separator().to_string());
+    // user requests aren't bound to a chain
+    assert!(domains::domain_json(&Domains::default().dstack)["chain_id"].is_null());
+}
+
+#[test]
+fn test_checkpoints_hash_like_the_checkpointer() {
+    let checkpoint = ICheckpointer::Checkpoint {
+        nonce: U256::from(3),
+        inventory_root: B256::repeat_byte(7),
+        inventory_state: vec![1, 0, 255],
+        settlement_orders: vec!["order1".to_string(), "order2".to_string()],
+    };
+    // Checkpointer.hash_checkpoint
+    let state: Vec<u8> = checkpoint
+        .inventory_state
+        .iter()
+        .flat_map(|byte| U256::from(*byte).to_be_bytes::<32>())
+        .collect();
+    let orders: Vec<u8> = checkpoint
+        .settlement_orders
+        .iter()
+        .flat_map(|order| keccak256(order).0)
+        .collect();
+    let hash = keccak256(
+        (
+            keccak256(
+                "Checkpoint(uint256 nonce,bytes32 inventory_root,uint8[] inventory_state,string[] settlement_orders)",
+            ),
+            checkpoint.nonce,
+            checkpoint.inventory_root,
+            keccak256(state),
+            keccak256(orders),
+        )
+            .abi_encode(),
+    );
+    assert_eq!(checkpoint.eip712_hash_struct(), hash);
+}
+
+#[test]
+fn test_settlement_orders_hash_like_the_registry() {
+    let order = IDepositRegistry::Order {
+        ethAmount: U256::from(10).pow(U256::from(18)),
+        usdcAmount: U256::from(1_000_000_000),
+        isBid: true,
+        nonce: U256::from(4),
+    };
+    // DepositRegistry.hash_order
+    let hash = keccak256(
+        (
+            keccak256("Order(uint256 ethAmount,uint256 usdcAmount,bool isBid,uint256 nonce)"),
+            order.ethAmount,
+            order.usdcAmount,
+            order.isBid,
+            order.nonce,
+        )
+            .abi_encode(),
+    );
+    assert_eq!(order.eip712_hash_struct(), hash);
+    // what the registry hashed before, without the type, isn't what the app signs
+    assert_ne!(hash, keccak256(order.abi_encode()));
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use alloy::{
+    primitives::{Address, B256},
+    signers::Signature,
+    transports::TransportErrorKind,
+};
+use myrtle_wyckoff_dstack::errors::{self, MwError};
+use rocket::http::Status;
+
+fn json(error: &MwError) -> serde_json::Value {
+    serde_json::from_str(&error.to_json()).unwrap()
+}
+
+#[test]
+fn test_error_body() {
+    let error = MwError::OrderNotFound { order_id: 7 };
+    let body = json(&error);
+    assert_eq!(body["code"], "order_not_found");
+    assert_eq!(body["message"], "Order 7 not found");
+    assert_eq!(body["details"]["order_id"], "7");
+    assert_eq!(error.status_code(), Status::NotFound);
+
+    let body = json(&MwError::GulpError("nonce moved".to_string()));
+    assert_eq!(body["code"], "gulp_error");
+    assert_eq!(body["details"]["reason"], "nonce moved");
+    assert_eq!(
+        json(&MwError::InvalidSignature)["details"],
+        serde_json::json!({})
+    );
+}
+
+#[test]
+fn test_codes_are_unique() {
+    let errors = [
+        MwError::InvalidSignature,
+        MwError::UnauthorizedAccess,
+        MwError::SigningError,
+        MwError::TransactionError,
+        MwError::RpcError(String::new()),
+        MwError::InvalidParameter {
+            field: String::new(),
+            reason: String::new(),
+        },
+        MwError::IoError(String::new()),
+        MwError::SerializationError(String::new()),
+    ];
+    let codes: HashSet<_> = errors.iter().map(MwError::code).collect();
+    assert_eq!(codes.len(), errors.len());
+}
+
+#[test]
+fn test_status_codes() {
+    // the caller's fault
+    assert_eq!(
+        MwError::InvalidSignature.status_code(),
+        Status::Unauthorized
+    );
+    assert_eq!(MwError::NotTaker.status_code(), Status::Forbidden);
+    assert_eq!(
+        MwError::InvalidOrderParams.status_code(),
+        Status::BadRequest
+    );
+    assert_eq!(
+        MwError::GulpError(String::new()).status_code(),
+        Status::Conflict
+    );
+    // the app's or the chain's
+    assert_eq!(
+        MwError::SigningError.status_code(),
+        Status::InternalServerError
+    );
+    assert_eq!(
+        MwError::SnapshotError(String::new()).status_code(),
+        Status::InternalServerError
+    );
+    assert_eq!(MwError::TransactionError.status_code(), Status::BadGateway);
+    assert_eq!(
+        MwError::RpcError(String::new()).status_code(),
+        Status::BadGateway
+    );
+}
+
+#[test]
+fn test_conversions() {
+    let error: MwError = std::io::Error::new(std::io::ErrorKind::NotFound, "gone").into();
+    assert!(matches!(&error, MwError::IoError(message) if message == "gone"));
+    assert_eq!(error.status_code(), Status::InternalServerError);
+
+    let error: MwError = serde_json::from_str::<u64>("nope").unwrap_err().into();
+    assert_eq!(error.code(), "serialization_error");
+
+    let error: MwError = TransportErrorKind::custom_str("connection refused").into();
+    assert_eq!(error.code(), "rpc_error");
+}
+
+#[test]
+fn test_parse_param() {
+    let address = Address::repeat_byte(1);
+    assert_eq!(
+        errors::parse_param::<Address>("checkpointer_address", &address.to_string()).unwrap(),
+        address
+    );
+    assert_eq!(errors::parse_param::<u32>("order_id", "42").unwrap(), 42);
+
+    // malformed input is a 400 naming the parameter, never a panic
+    for result in [
+        errors::parse_param::<u32>("order_id", "-1").map(|_| ()),
+        errors::parse_param::<Signature>("signature", "0x1234").map(|_| ()),
+        errors::parse_param::<B256>("state_hash", "zz").map(|_| ()),
+        errors::parse_param::<Address>("asset", "0xnotanasset").map(|_| ()),
+    ] {
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), "invalid_parameter");
+        assert_eq!(error.status_code(), Status::BadRequest);
+    }
+    let error = errors::parse_param::<u32>("order_id", "x").unwrap_err();
+    assert_eq!(json(&error)["details"]["field"], "order_id");
+    assert!(error.to_string().starts_with("Invalid order_id: "));
+}
+
+#[test]
+fn test_parse_user() {
+    let user = Address::repeat_byte(0xa);
+    assert_eq!(errors::parse_user(&user.to_string()).unwrap(), user);
+    assert_eq!(
+        errors::parse_user("0x0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a").unwrap(),
+        user
+    );
+    // raw public keys, truncated addresses and non hex aren't users
+    for user in [
+        "",
+        "short",
+        &"a".repeat(64),
+        "0x0a0a",
+        &format!("0x{}", "g".repeat(40)),
+    ] {
+        assert!(matches!(
+            errors::parse_user(user),
+            Err(MwError::InvalidParameter { field, .. }) if field == "user"
+        ));
+    }
+}
+
+#[test]
+fn test_status_json() {
+    let body: serde_json::Value =
+        serde_json::from_str(&errors::status_json(Status::NotFound)).unwrap();
+    assert_eq!(body["code"], "not_found");
+    assert_eq!(body["message"], "Not Found");
+    let body: serde_json::Value =
+        serde_json::from_str(&errors::status_json(Status::UnprocessableEntity)).unwrap();
+    assert_eq!(body["code"], "unprocessable_entity");
+}
@@ -0,0 +1,460 @@
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    chains::{BASE_CHAIN_ID, MAINNET_CHAIN_ID},
+    events::{self, Event, EventRecord},
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    orderhere::{CancelAll, Order},
+    session::{SessionController, SessionSchedule},
+    warehouse::Warehouse,
+};
+use optimized_lob::orderbook_manager::OrderBookManager;
+
+struct State {
+    warehouse: Warehouse,
+    orderbook_manager: OrderBookManager,
+    session: SessionController,
+}
+
+fn fresh_state() -> State {
+    State {
+        warehouse: Warehouse::new(&AppKeys::derive(&[0u8; MIN_SECRET_BYTES]).unwrap()),
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    }
+}
+
+// folds events the way the sequencer does, recording each outcome and state hash
+fn record(state: &mut State, events: Vec<Event>) -> Vec<EventRecord> {
+    let mut records = Vec::new();
+    extend(state, &mut records, events);
+    records
+}
+
+fn extend(state: &mut State, records: &mut Vec<EventRecord>, events: Vec<Event>) {
+    for event in events {
+        let seq = records.len() as u64 + 1;
+        let result = events::apply(
+            &mut state.warehouse,
+            &mut state.orderbook_manager,
+            &mut state.session,
+            seq - 1,
+            &event,
+        );
+        records.push(EventRecord {
+            seq,
+            timestamp: seq - 1,
+            event,
+            error: result.err().map(|error| error.to_string()),
+            state_hash: events::state_hash(
+                &state.warehouse,
+                &state.orderbook_manager,
+                &state.session,
+            ),
+            hash_version: events::STATE_HASH_VERSION,
+        });
+    }
+}
+
+// the seq replay stopped at if it diverged
+fn replay(records: &[EventRecord]) -> Result<State, u64> {
+    let mut replayed = fresh_state();
+    events::replay(
+        &mut replayed.warehouse,
+        &mut replayed.orderbook_manager,
+        &mut replayed.session,
+        records,
+    )?;
+    Ok(replayed)
+}
+
+fn order(price: u64, qty: u64, is_bid: bool) -> Order {
+    Order {
+        price: U256::from(price),
+        qty: U256::from(qty),
+        is_bid,
+        timestamp: 0,
+    }
+}
+
+// a user's resting orders by oid
+fn resting_orders(state: &State, user: Address) -> Vec<u32> {
+    let mut oids: Vec<u32> = state
+        .warehouse
+        .oid_qty_by_address
+        .get(&user)
+        .map(|orders| orders.keys().map(|oid| oid.0).collect())
+        .unwrap_or_default();
+    oids.sort();
+    oids
+}
+
+#[test]
+fn test_genesis_releases_what_resting_orders_held() {
+    let mut live = fresh_state();
+    trading_records(&mut live);
+    let maker = Address::repeat_byte(1);
+    assert!(!live.warehouse.inventories[&maker]
+        .eth_liabilities
+        .0
+        .is_zero());
+
+    // the book starts empty, so nothing stays locked behind orders that are gone
+    let mut restarted = fresh_state();
+    record(
+        &mut restarted,
+        vec![Event::genesis(&live.warehouse, &live.session)],
+    );
+    let inventory = &restarted.warehouse.inventories[&maker];
+    assert!(inventory.eth_liabilities.0.is_zero());
+    assert!(inventory.usdc_liabilities.0.is_zero());
+    assert_eq!(
+        inventory.eth_balance,
+        live.warehouse.inventories[&maker].eth_balance
+    );
+    assert!(resting_orders(&restarted, maker).is_empty());
+}
+
+// a maker resting two asks, a taker partially filling one, then a replace, cancels (one of
+// them someone else's, so rejected) and a cancel-all
+fn trading_records(state: &mut State) -> Vec<EventRecord> {
+    let maker = Address::repeat_byte(1);
+    let taker = Address::repeat_byte(2);
+    let deposit = |user| Event::Deposit {
+        user,
+        deposit_nonce: 0,
+        deposits: [U256::from(10), U256::from(100_000)],
+    };
+    let genesis = Event::genesis(&state.warehouse, &state.session);
+    let mut records = record(
+        state,
+        vec![
+            genesis,
+            deposit(maker),
+            deposit(taker),
+            Event::NewOrder {
+                user: maker,
+                order: order(1500, 2, false),
+            },
+            Event::NewOrder {
+                user: maker,
+                order: order(1600, 1, false),
+            },
+            Event::NewOrder {
+                user: taker,
+                order: order(1500, 1, true),
+            },
+        ],
+    );
+    let asks = resting_orders(state, maker);
+    assert_eq!(asks.len(), 2);
+    extend(
+        state,
+        &mut records,
+        vec![Event::ReplaceOrder {
+            user: maker,
+            order: order(1550, 1, false),
+            oid: asks[1],
+        }],
+    );
+    let asks = resting_orders(state, maker);
+    extend(
+        state,
+        &mut records,
+        vec![
+            Event::CancelOrder {
+                user: taker,
+                oid: asks[1],
+            },
+            Event::CancelOrder {
+                user: maker,
+                oid: asks[0],
+            },
+            Event::NewOrder {
+                user: taker,
+                order: order(1400, 1, true),
+            },
+            Event::CancelAll {
+                user: taker,
+                cancel: CancelAll {
+                    scope_book: false,
+                    book_id: 0,
+                    side: 0,
+                    timestamp: 0,
+                },
+            },
+        ],
+    );
+    records
+}
+
+fn session_events() -> Vec<Event> {
+    let user = Address::repeat_byte(1);
+    let state = fresh_state();
+    vec![
+        Event::genesis(&state.warehouse, &state.session),
+        Event::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            deposit_contract: Address::repeat_byte(0xd),
+            checkpoint_contract: Address::repeat_byte(0xc),
+        },
+        Event::Deposit {
+            user,
+            deposit_nonce: 0,
+            deposits: [U256::from(5), U256::from(1000)],
+        },
+        // stale nonce, rejected
+        Event::Deposit {
+            user,
+            deposit_nonce: 0,
+            deposits: [U256::from(5), U256::from(1000)],
+        },
+        Event::SetPnlVisibility {
+            user,
+            is_public: true,
+        },
+    ]
+}
+
+#[test]
+fn test_replay_matches_recorded_state() {
+    let mut live = fresh_state();
+    let records = record(&mut live, session_events());
+    assert!(records[2].error.is_none());
+    assert!(records[3].error.is_some());
+
+    let mut replayed = fresh_state();
+    let state_hash = events::replay(
+        &mut replayed.warehouse,
+        &mut replayed.orderbook_manager,
+        &mut replayed.session,
+        &records,
+    )
+    .unwrap();
+    assert_eq!(state_hash, records.last().unwrap().state_hash);
+    assert_eq!(
+        replayed.warehouse.inventories[&Address::repeat_byte(1)]
+            .usdc_balance
+            .0,
+        U256::from(1000)
+    );
+}
+
+#[test]
+fn test_replay_detects_tampering() {
+    let mut live = fresh_state();
+    let mut records = record(&mut live, session_events());
+    records[2].event = Event::Deposit {
+        user: Address::repeat_byte(1),
+        deposit_nonce: 0,
+        deposits: [U256::from(5), U256::from(1_000_000)],
+    };
+
+    let mut replayed = fresh_state();
+    let result = events::replay(
+        &mut replayed.warehouse,
+        &mut replayed.orderbook_manager,
+        &mut replayed.session,
+        &records,
+    );
+    assert_eq!(result, Err(3));
+}
+
+#[test]
+fn test_records_round_trip() {
+    let mut live = fresh_state();
+    let records = record(&mut live, session_events());
+    let json = serde_json::to_string(&records).unwrap();
+    let decoded: Vec<EventRecord> = serde_json::from_str(&json).unwrap();
+
+    let mut replayed = fresh_state();
+    assert!(events::replay(
+        &mut replayed.warehouse,
+        &mut replayed.orderbook_manager,
+        &mut replayed.session,
+        &decoded,
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_events_from_before_multiple_chains_are_mainnet() {
+    let registry = Address::repeat_byte(0xd);
+    let genesis: Event = serde_json::from_value(serde_json::json!({
+        "type": "genesis",
+        "inventories": [],
+        "deposit_contract": registry,
+        "deposit_cursor": 42,
+        "checkpoint_contract": Address::repeat_byte(0xc),
+        "session_id": 1,
+        "pnl_session_id": 1,
+    }))
+    .unwrap();
+    let set_contracts: Event = serde_json::from_value(serde_json::json!({
+        "type": "set_contract_addresses",
+        "deposit_contract": registry,
+        "checkpoint_contract": Address::repeat_byte(0xc),
+    }))
+    .unwrap();
+    assert!(matches!(
+        set_contracts,
+        Event::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            ..
+        }
+    ));
+
+    let mut state = fresh_state();
+    record(&mut state, vec![genesis]);
+    assert_eq!(state.warehouse.deposit_contract(MAINNET_CHAIN_ID), registry);
+    assert_eq!(state.warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(42));
+    assert!(state.warehouse.deposit_contract(BASE_CHAIN_ID).is_zero());
+}
+
+#[test]
+fn test_registries_are_set_per_chain() {
+    let mut state = fresh_state();
+    let set_registry = |chain_id, byte| Event::SetContractAddresses {
+        chain_id,
+        deposit_contract: Address::repeat_byte(byte),
+        checkpoint_contract: Address::repeat_byte(0xc),
+    };
+    record(
+        &mut state,
+        vec![
+            set_registry(MAINNET_CHAIN_ID, 0xd),
+            Event::DepositsIndexed {
+                chain_id: MAINNET_CHAIN_ID,
+                from_block: 10,
+                to_block: 19,
+                deposits: vec![],
+            },
+            set_registry(BASE_CHAIN_ID, 0xb),
+        ],
+    );
+    let hash_before =
+        events::state_hash(&state.warehouse, &state.orderbook_manager, &state.session);
+    assert_eq!(
+        state.warehouse.deposit_contract(MAINNET_CHAIN_ID),
+        Address::repeat_byte(0xd)
+    );
+    assert_eq!(
+        state.warehouse.deposit_contract(BASE_CHAIN_ID),
+        Address::repeat_byte(0xb)
+    );
+    // setting base's registry leaves mainnet's cursor alone
+    assert_eq!(state.warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(20));
+
+    // a new registry on mainnet restarts only mainnet's cursor
+    record(&mut state, vec![set_registry(MAINNET_CHAIN_ID, 0xe)]);
+    assert_eq!(state.warehouse.deposit_cursor(MAINNET_CHAIN_ID), None);
+    assert_ne!(
+        events::state_hash(&state.warehouse, &state.orderbook_manager, &state.session),
+        hash_before
+    );
+}
+
+#[test]
+fn test_replay_of_trading_matches_recorded_state() {
+    let mut live = fresh_state();
+    let records = trading_records(&mut live);
+    let errors: Vec<bool> = records
+        .iter()
+        .map(|record| record.error.is_some())
+        .collect();
+    assert_eq!(
+        errors,
+        [false, false, false, false, false, false, false, true, false, false, false]
+    );
+    // only the replaced ask is left
+    let maker = Address::repeat_byte(1);
+    assert_eq!(resting_orders(&live, maker).len(), 1);
+    assert!(resting_orders(&live, Address::repeat_byte(2)).is_empty());
+
+    let replayed = replay(&records).unwrap_or_else(|seq| panic!("diverged at {}", seq));
+    assert_eq!(
+        events::state_hash(
+            &replayed.warehouse,
+            &replayed.orderbook_manager,
+            &replayed.session
+        ),
+        records.last().unwrap().state_hash
+    );
+    assert_eq!(
+        resting_orders(&replayed, maker),
+        resting_orders(&live, maker)
+    );
+    assert_eq!(
+        replayed.warehouse.inventories[&maker].to_json(),
+        live.warehouse.inventories[&maker].to_json()
+    );
+
+    // the same trading on a fresh state records the same hashes
+    let mut again = fresh_state();
+    let rerecorded = trading_records(&mut again);
+    let hashes = |records: &[EventRecord]| -> Vec<_> {
+        records.iter().map(|record| record.state_hash).collect()
+    };
+    assert_eq!(hashes(&rerecorded), hashes(&records));
+}
+
+#[test]
+fn test_replay_detects_tampered_orders() {
+    let mut live = fresh_state();
+    let records = trading_records(&mut live);
+
+    // a bigger fill
+    let mut tampered = records.clone();
+    tampered[5].event = Event::NewOrder {
+        user: Address::repeat_byte(2),
+        order: order(1500, 2, true),
+    };
+    assert_eq!(replay(&tampered).err(), Some(6));
+
+    // a replace to another price
+    let mut tampered = records.clone();
+    let Event::ReplaceOrder { user, oid, .. } = records[6].event.clone() else {
+        panic!("expected the replace")
+    };
+    tampered[6].event = Event::ReplaceOrder {
+        user,
+        order: order(1700, 1, false),
+        oid,
+    };
+    assert_eq!(replay(&tampered).err(), Some(7));
+
+    // dropping a cancel
+    let mut tampered = records.clone();
+    tampered[8].event = Event::CancelOrder {
+        user: Address::repeat_byte(1),
+        oid: u32::MAX,
+    };
+    assert_eq!(replay(&tampered).err(), Some(9));
+}
+
+#[test]
+fn test_genesis_carries_the_session() {
+    let mut live = fresh_state();
+    trading_records(&mut live);
+    live.warehouse.finalize_session_pnl(2, U256::from(1500));
+    live.session.finalize(90 * 60 * 1000).unwrap();
+    live.warehouse.pnl.record_fill(
+        Address::repeat_byte(1),
+        true,
+        U256::from(1),
+        U256::from(1500),
+    );
+    let genesis = Event::genesis(&live.warehouse, &live.session);
+    let genesis: Event = serde_json::from_str(&serde_json::to_string(&genesis).unwrap()).unwrap();
+
+    // a restart a while later, the session carries on
+    let mut restarted = fresh_state();
+    record(&mut restarted, vec![genesis]);
+    assert_eq!(restarted.session.state(), live.session.state());
+    assert_eq!(restarted.session.session_id, 2);
+    assert_eq!(restarted.warehouse.pnl.session_id, 2);
+    assert!(!live.warehouse.pnl.accounts.is_empty());
+    assert_eq!(
+        restarted.warehouse.pnl.accounts,
+        live.warehouse.pnl.accounts
+    );
+}
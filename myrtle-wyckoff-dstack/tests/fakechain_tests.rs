@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use alloy::primitives::{Address, B256, U256};
+use myrtle_wyckoff_dstack::{
+    artifacts::IDepositRegistry,
+    chains::{Chain, MAINNET},
+    domains::{self, Domains},
+    errors::MwError,
+    fakechain::{fake_block_hash, FakeChain},
+    gulper::{self, IndexedDeposit},
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    leader, recovery,
+    rpc::ChainClient,
+    settler,
+    sink::{CheckpointSink, CheckpointerSink, TxPolicy},
+    snapshotter::{self, CheckpointDraft},
+    verifier,
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::quantity::Qty;
+
+fn registry() -> Address {
+    Address::repeat_byte(0xd)
+}
+
+fn checkpointer() -> Address {
+    Address::repeat_byte(0xc)
+}
+
+fn amounts(eth: u64, usdc: u64) -> [U256; 2] {
+    [U256::from(eth), U256::from(usdc)]
+}
+
+fn chain(clients: Vec<Arc<FakeChain>>) -> Chain<FakeChain> {
+    Chain::new(MAINNET, clients)
+}
+
+// two users depositing over blocks 10 and 20, head at 30
+fn deposits() -> Arc<FakeChain> {
+    let fake = FakeChain::new();
+    fake.set_head(10);
+    fake.deposit(registry(), Address::repeat_byte(1), amounts(1, 100));
+    fake.deposit(registry(), Address::repeat_byte(2), amounts(0, 50));
+    fake.set_head(20);
+    fake.deposit(registry(), Address::repeat_byte(1), amounts(2, 0));
+    fake.set_head(30);
+    Arc::new(fake)
+}
+
+fn warehouse(keys: &AppKeys) -> Warehouse {
+    let mut warehouse = Warehouse::new(keys);
+    warehouse.checkpoint_contract = checkpointer();
+    warehouse
+}
+
+#[tokio::test]
+async fn test_deposits_and_logs() {
+    let fake = deposits();
+    let user = Address::repeat_byte(1);
+    let logged = fake.deposit_logs(registry(), 0, 15).await.unwrap();
+    assert_eq!(logged.len(), 2);
+    assert_eq!(
+        fake.deposit_logs(registry(), 11, 30).await.unwrap().len(),
+        1
+    );
+    // deposits are read as of a block
+    assert_eq!(
+        gulper::fetch_user_deposits(fake.as_ref(), registry(), user, 0, 15)
+            .await
+            .unwrap(),
+        vec![IndexedDeposit {
+            user,
+            index: 0,
+            amounts: amounts(1, 100),
+        }]
+    );
+    let later = gulper::fetch_user_deposits(fake.as_ref(), registry(), user, 1, 30)
+        .await
+        .unwrap();
+    assert_eq!(later.len(), 1);
+    assert_eq!(later[0].index, 1);
+}
+
+#[tokio::test]
+async fn test_fake_proofs_verify() {
+    let fake = deposits();
+    let chain = chain(vec![fake.clone()]);
+    let logged = fake.deposit_logs(registry(), 0, 30).await.unwrap();
+    verifier::verify_deposits(&chain, registry(), 30, &logged)
+        .await
+        .unwrap();
+    let block = verifier::trusted_block(&chain, 30).await.unwrap();
+    assert_eq!(block.hash, fake_block_hash(30));
+
+    // the second deposit didn't exist yet at block 15
+    assert!(matches!(
+        verifier::verify_deposits(&chain, registry(), 15, &logged).await,
+        Err(MwError::DepositVerificationError(_))
+    ));
+    let mut inflated = logged.clone();
+    inflated[0].amounts = amounts(1000, 100);
+    assert!(matches!(
+        verifier::verify_deposits(&chain, registry(), 30, &inflated).await,
+        Err(MwError::DepositVerificationError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_endpoints_have_to_agree() {
+    let honest = deposits();
+    let logged = honest.deposit_logs(registry(), 0, 30).await.unwrap();
+    // an endpoint serving a registry with an extra deposit reports another state root
+    let lying = deposits();
+    lying.deposit(registry(), Address::repeat_byte(9), amounts(1000, 0));
+    let down = deposits();
+    down.set_failing(true);
+
+    let chain_with_liar = chain(vec![honest.clone(), lying.clone(), down]);
+    assert_eq!(chain_with_liar.quorum, 2);
+    assert!(matches!(
+        verifier::verify_deposits(&chain_with_liar, registry(), 30, &logged).await,
+        Err(MwError::DepositVerificationError(_))
+    ));
+    // outvoted
+    let outvoted = chain(vec![honest.clone(), lying, honest]);
+    verifier::verify_deposits(&outvoted, registry(), 30, &logged)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_checkpoints_post_and_recover() {
+    let keys = AppKeys::derive(&[5u8; MIN_SECRET_BYTES]).unwrap();
+    let mut posted = warehouse(&keys);
+    let user = Address::repeat_byte(1);
+    posted.inventories.insert(
+        user,
+        Inventory {
+            address: user,
+            eth_balance: Qty(U256::from(3)),
+            ..Inventory::default()
+        },
+    );
+    let fake = Arc::new(FakeChain::new());
+    fake.set_domain_separator(
+        checkpointer(),
+        posted.domains.toliman(checkpointer()).separator(),
+    );
+    let sink = CheckpointerSink {
+        client: fake.clone(),
+        policy: TxPolicy::default(),
+    };
+    // the Checkpointer needs a leader to take checkpoints
+    let unclaimed = snapshotter::sign(CheckpointDraft::from_warehouse(&posted), U256::ZERO)
+        .await
+        .unwrap();
+    assert!(matches!(
+        sink.publish(&unclaimed).await,
+        Err(MwError::SnapshotError(_))
+    ));
+    leader::claim(
+        fake.as_ref(),
+        checkpointer(),
+        posted.signer.address(),
+        posted.leader.address(),
+        &TxPolicy::default(),
+    )
+    .await
+    .unwrap();
+    for expected_nonce in 0..2u64 {
+        let nonce = sink.next_nonce(checkpointer()).await.unwrap();
+        assert_eq!(nonce, U256::from(expected_nonce));
+        let signed = snapshotter::sign(CheckpointDraft::from_warehouse(&posted), nonce)
+            .await
+            .unwrap();
+        assert!(sink.publish(&signed).await.unwrap().is_some());
+    }
+    assert_eq!(fake.sent().len(), 4); // the revert, the claim and two checkpoints
+
+    // the Checkpointer only takes its next nonce
+    let stale = snapshotter::sign(CheckpointDraft::from_warehouse(&posted), U256::ZERO)
+        .await
+        .unwrap();
+    assert!(matches!(
+        sink.publish(&stale).await,
+        Err(MwError::SnapshotError(_))
+    ));
+
+    let mut recovered = warehouse(&keys);
+    let next_nonce = recovery::recover(&mut recovered, fake.as_ref(), checkpointer())
+        .await
+        .unwrap();
+    assert_eq!(next_nonce, U256::from(2));
+    assert_eq!(recovered.inventories[&user].eth_balance.0, U256::from(3));
+
+    // nothing to recover from
+    let mut empty = warehouse(&keys);
+    assert!(
+        recovery::recover(&mut empty, fake.as_ref(), Address::repeat_byte(0xe))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_domain_separators_and_settlement_nonce() {
+    let fake = deposits();
+    let domains = Domains::default();
+    assert!(
+        !domains::verify_checkpointer(fake.as_ref(), &domains, checkpointer())
+            .await
+            .unwrap()
+    );
+    fake.set_domain_separator(checkpointer(), domains.toliman(checkpointer()).separator());
+    assert!(
+        domains::verify_checkpointer(fake.as_ref(), &domains, checkpointer())
+            .await
+            .unwrap()
+    );
+    fake.set_domain_separator(registry(), B256::repeat_byte(1));
+    let chain = chain(vec![fake.clone()]);
+    assert!(matches!(
+        domains::verify_deposit_registry(&chain, registry()).await,
+        Err(MwError::DomainSeparatorMismatch { .. })
+    ));
+
+    let order = |nonce: u64| IDepositRegistry::Order {
+        ethAmount: U256::from(1),
+        usdcAmount: U256::ZERO,
+        isBid: false,
+        nonce: U256::from(nonce),
+    };
+    fake.set_settlement_nonce(registry(), U256::from(3));
+    settler::check_settlement_nonce(&chain, registry(), &order(3))
+        .await
+        .unwrap();
+    assert!(matches!(
+        settler::check_settlement_nonce(&chain, registry(), &order(2)).await,
+        Err(MwError::InvalidOrderParams)
+    ));
+
+    fake.set_failing(true);
+    assert!(matches!(
+        settler::check_settlement_nonce(&chain, registry(), &order(3)).await,
+        Err(MwError::RpcError(_))
+    ));
+}
@@ -0,0 +1,260 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    chains::{Chain, Chains, BASE_CHAIN_ID, MAINNET, MAINNET_CHAIN_ID},
+    config::StorageConfig,
+    errors::MwError,
+    fakechain::FakeChain,
+    gulper::{self, DepositIndexer, IndexedDeposit, IndexerConfig},
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    sequencer,
+    session::{SessionController, SessionPhase, SessionSchedule, SessionState},
+    warehouse::Warehouse,
+};
+use optimized_lob::orderbook_manager::OrderBookManager;
+
+fn warehouse() -> Warehouse {
+    Warehouse::new(&AppKeys::derive(&[0u8; MIN_SECRET_BYTES]).unwrap())
+}
+
+fn deposit(user: u8, index: u32, eth: u64, usdc: u64) -> IndexedDeposit {
+    IndexedDeposit {
+        user: Address::repeat_byte(user),
+        index,
+        amounts: [U256::from(eth), U256::from(usdc)],
+    }
+}
+
+fn balances(warehouse: &Warehouse, user: u8) -> (U256, U256, u32) {
+    let inventory = &warehouse.inventories[&Address::repeat_byte(user)];
+    (
+        inventory.eth_balance.0,
+        inventory.usdc_balance.0,
+        inventory.deposit_nonce(MAINNET_CHAIN_ID),
+    )
+}
+
+#[test]
+fn test_indexes_every_depositor() {
+    let mut warehouse = warehouse();
+    let deposits = vec![
+        deposit(1, 0, 1, 100),
+        deposit(2, 0, 0, 50),
+        deposit(1, 1, 2, 0),
+    ];
+    let credited =
+        gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 10, 19, &deposits).unwrap();
+    assert_eq!(credited, 3);
+    assert_eq!(balances(&warehouse, 1), (U256::from(3), U256::from(100), 2));
+    assert_eq!(balances(&warehouse, 2), (U256::ZERO, U256::from(50), 1));
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(20));
+}
+
+#[test]
+fn test_reindexing_is_a_no_op() {
+    let mut warehouse = warehouse();
+    let deposits = vec![deposit(1, 0, 1, 100)];
+    gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 10, 19, &deposits).unwrap();
+    // same deposit seen again in the next range, e.g. after a restart
+    let credited =
+        gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 20, 29, &deposits).unwrap();
+    assert_eq!(credited, 0);
+    assert_eq!(balances(&warehouse, 1), (U256::from(1), U256::from(100), 1));
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(30));
+}
+
+#[test]
+fn test_gap_credits_nothing() {
+    let mut warehouse = warehouse();
+    let deposits = vec![deposit(2, 0, 0, 50), deposit(1, 1, 2, 0)];
+    let result = gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 10, 19, &deposits);
+    assert!(matches!(result, Err(MwError::GulpError(_))));
+    assert!(warehouse.inventories.is_empty());
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), None);
+}
+
+#[test]
+fn test_rejects_stale_range() {
+    let mut warehouse = warehouse();
+    gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 10, 19, &[]).unwrap();
+    let result = gulper::index_deposits(
+        &mut warehouse,
+        MAINNET_CHAIN_ID,
+        10,
+        19,
+        &[deposit(1, 0, 1, 0)],
+    );
+    assert!(matches!(result, Err(MwError::GulpError(_))));
+    assert!(warehouse.inventories.is_empty());
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(20));
+}
+
+#[test]
+fn test_start_block_is_required() {
+    let mut config = IndexerConfig::default();
+    assert!(matches!(
+        config.start_block(MAINNET_CHAIN_ID),
+        Err(MwError::GulpError(_))
+    ));
+    // genesis is a start block like any other, not a missing one
+    config.start_blocks.insert(MAINNET_CHAIN_ID, 0);
+    assert_eq!(config.start_block(MAINNET_CHAIN_ID).unwrap(), 0);
+
+    let mut warehouse = warehouse();
+    gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 0, 9, &[]).unwrap();
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(10));
+    let result = gulper::index_deposits(&mut warehouse, MAINNET_CHAIN_ID, 0, 9, &[]);
+    assert!(matches!(result, Err(MwError::GulpError(_))));
+}
+
+#[test]
+fn test_multiple_new_deposits_credit_exact_range() {
+    let mut warehouse = warehouse();
+    gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &[deposit(1, 0, 1, 10)])
+        .unwrap();
+    // three deposits since the last gulp, each credited once and the nonce lands past the last
+    let deposits = vec![
+        deposit(1, 1, 2, 20),
+        deposit(1, 2, 3, 30),
+        deposit(1, 3, 4, 40),
+    ];
+    assert_eq!(
+        gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &deposits).unwrap(),
+        3
+    );
+    assert_eq!(
+        balances(&warehouse, 1),
+        (U256::from(10), U256::from(100), 4)
+    );
+
+    // the next gulp overlaps the last one, only the new deposit counts
+    let deposits = vec![deposit(1, 3, 4, 40), deposit(1, 4, 5, 50)];
+    assert_eq!(
+        gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &deposits).unwrap(),
+        1
+    );
+    assert_eq!(
+        balances(&warehouse, 1),
+        (U256::from(15), U256::from(150), 5)
+    );
+}
+
+#[test]
+fn test_zero_new_deposits_is_a_no_op() {
+    let mut warehouse = warehouse();
+    assert_eq!(
+        gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &[]).unwrap(),
+        0
+    );
+    assert!(warehouse.inventories.is_empty());
+
+    gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &[deposit(1, 0, 1, 10)])
+        .unwrap();
+    assert_eq!(
+        gulper::credit_indexed_deposits(&mut warehouse, MAINNET_CHAIN_ID, &[]).unwrap(),
+        0
+    );
+    assert_eq!(balances(&warehouse, 1), (U256::from(1), U256::from(10), 1));
+}
+
+#[test]
+fn test_chains_have_separate_nonces_and_cursors() {
+    let mut warehouse = warehouse();
+    gulper::index_deposits(
+        &mut warehouse,
+        MAINNET_CHAIN_ID,
+        10,
+        19,
+        &[deposit(1, 0, 1, 10)],
+    )
+    .unwrap();
+    // the first deposit on base is index 0 in base's registry, even though mainnet is past it
+    gulper::index_deposits(
+        &mut warehouse,
+        BASE_CHAIN_ID,
+        500,
+        599,
+        &[deposit(1, 0, 2, 20), deposit(1, 1, 3, 30)],
+    )
+    .unwrap();
+    let inventory = &warehouse.inventories[&Address::repeat_byte(1)];
+    assert_eq!(inventory.deposit_nonce(MAINNET_CHAIN_ID), 1);
+    assert_eq!(inventory.deposit_nonce(BASE_CHAIN_ID), 2);
+    // balances aren't split by chain
+    assert_eq!(inventory.eth_balance.0, U256::from(6));
+    assert_eq!(inventory.usdc_balance.0, U256::from(60));
+    assert_eq!(warehouse.deposit_cursor(MAINNET_CHAIN_ID), Some(20));
+    assert_eq!(warehouse.deposit_cursor(BASE_CHAIN_ID), Some(600));
+    // but each registry only holds what was deposited into it
+    assert_eq!(
+        warehouse.chain_deposits(MAINNET_CHAIN_ID),
+        [U256::from(1), U256::from(10)]
+    );
+    assert_eq!(
+        warehouse.chain_deposits(BASE_CHAIN_ID),
+        [U256::from(5), U256::from(50)]
+    );
+
+    // mainnet's deposit 0 was credited, base's deposit 0 being seen again is skipped
+    assert_eq!(
+        gulper::credit_indexed_deposits(&mut warehouse, BASE_CHAIN_ID, &[deposit(1, 0, 2, 20)])
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_polls_outside_the_deposit_window_are_skipped() {
+    let registry = Address::repeat_byte(0xd);
+    let directory = std::env::temp_dir().join(format!("mw-gulper-window-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    let mut warehouse = warehouse();
+    warehouse.storage = StorageConfig {
+        data_dir: directory.clone(),
+        host_data_dir: directory.clone(),
+    };
+    warehouse
+        .deposit_contracts
+        .insert(MAINNET_CHAIN_ID, registry);
+    let trading = SessionState {
+        phase: SessionPhase::Trading,
+        phase_started_at: chrono::Utc::now().timestamp_millis() as u64,
+    };
+    let sequencer = sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::resume(SessionSchedule::default(), 1, trading),
+    });
+    let fake = Arc::new(FakeChain::new());
+    fake.set_head(10);
+    fake.deposit(
+        registry,
+        Address::repeat_byte(1),
+        [U256::from(1), U256::from(100)],
+    );
+    fake.set_head(30);
+    let chains = Chains::new(vec![Chain::new(MAINNET, vec![fake])]);
+    let config = IndexerConfig {
+        confirmations: 0,
+        start_blocks: BTreeMap::from([(MAINNET_CHAIN_ID, 0)]),
+        ..IndexerConfig::default()
+    };
+
+    let indexer = DepositIndexer::new();
+    assert_eq!(
+        indexer
+            .run_once(&sequencer, &chains, &config)
+            .await
+            .unwrap(),
+        0
+    );
+    let status = indexer.status();
+    assert_eq!(status.last_error, None);
+    assert!(status.last_indexed_blocks.is_empty());
+    // the range is still there for when deposits open
+    assert_eq!(sequencer.snapshot().deposit_cursor(MAINNET_CHAIN_ID), None);
+    let _ = std::fs::remove_dir_all(&directory);
+}
@@ -3,6 +3,8 @@ use alloy::{
     signers::{LocalWallet, Signature, Signer},
 };
 use myrtle_wyckoff_dstack::{
+    chains::MAINNET_CHAIN_ID,
+    config::{ChainFileConfig, Config, ConfigFile},
     jtrain::Jtrain,
     orderhere::{self, Order},
     settler::create_settlement_order,
@@ -75,7 +77,24 @@ impl TestContext {
                     modify_order
                 ],
             )
-            .manage(Jtrain::new("http://localhost:8545".parse().unwrap()).await);
+            .manage(
+                Jtrain::new(
+                    &Config::from_file(ConfigFile {
+                        rpc_url: Some("http://localhost:8545".to_string()),
+                        deposit_chains: vec![ChainFileConfig {
+                            chain_id: MAINNET_CHAIN_ID,
+                            rpc_urls: vec!["http://localhost:8545".to_string(); 2],
+                            weth: None,
+                            usdc: None,
+                            gpv2_settlement: None,
+                        }],
+                        deposit_start_blocks: [(MAINNET_CHAIN_ID, 0)].into(),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .await,
+            );
 
         Self {
             rocket_client: Client::tracked(rocket).expect("Failed to create rocket client"),
@@ -0,0 +1,166 @@
+use std::{path::PathBuf, str::FromStr};
+
+use alloy::transports::http::reqwest::Url;
+use myrtle_wyckoff_dstack::{
+    errors::MwError,
+    keys::{
+        self, AppKeys, DevKeyProvider, DstackKeyProvider, KeyProviderConfig, TestKeyProvider,
+        MIN_SECRET_BYTES,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+const SECRET_HEX: &str = "0x0101010101010101010101010101010101010101010101010101010101010101";
+
+fn is_key_error<T>(result: Result<T, MwError>) -> bool {
+    matches!(result, Err(MwError::KeyError(_)))
+}
+
+// answers a single request with `body` as JSON, like the guest agent's /key/<tag>
+async fn guest_agent(body: &'static str) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    });
+    Url::parse(&format!("http://{}/key/test", address)).unwrap()
+}
+
+#[test]
+fn test_parse_secret() {
+    assert_eq!(keys::parse_secret(SECRET_HEX).unwrap(), vec![1u8; 32]);
+    assert_eq!(
+        keys::parse_secret(&format!(" {}\n", &SECRET_HEX[2..])).unwrap(),
+        vec![1u8; 32]
+    );
+    // the raw string used to be fed to the signer as is
+    assert!(is_key_error(keys::parse_secret("not hex at all")));
+    assert!(is_key_error(keys::parse_secret("0x0101")));
+}
+
+#[test]
+fn test_derived_keys_are_separate_and_deterministic() {
+    let keys = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    let again = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    assert_eq!(keys.signer.address(), again.signer.address());
+    assert_eq!(keys.checkpoint, again.checkpoint);
+    assert_eq!(keys.volume, again.volume);
+    assert_ne!(keys.checkpoint, keys.volume);
+    assert_ne!(keys.replication, keys.checkpoint);
+    assert_ne!(keys.replication, keys.volume);
+    assert_ne!(
+        keys.signer.to_bytes().as_slice(),
+        keys.checkpoint.as_slice()
+    );
+    // the secret itself isn't the signing key
+    assert_ne!(
+        keys.signer.to_bytes().as_slice(),
+        &[1u8; MIN_SECRET_BYTES][..]
+    );
+
+    // the checkpoint key from before the split is still there to read old blobs
+    assert_eq!(keys.legacy_checkpoint, again.legacy_checkpoint);
+    assert_ne!(keys.legacy_checkpoint, keys.checkpoint);
+
+    let other = AppKeys::derive(&[2u8; MIN_SECRET_BYTES]).unwrap();
+    assert_ne!(keys.signer.address(), other.signer.address());
+    assert_ne!(keys.checkpoint, other.checkpoint);
+    assert_ne!(keys.legacy_checkpoint, other.legacy_checkpoint);
+    assert!(is_key_error(AppKeys::derive(&[1u8; 16])));
+}
+
+#[tokio::test]
+async fn test_providers() {
+    let keys = keys::load_keys(&TestKeyProvider::new(1)).await.unwrap();
+    let expected = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    assert_eq!(keys.signer.address(), expected.signer.address());
+
+    let path = std::env::temp_dir().join(format!("mw-dev-key-{}", std::process::id()));
+    std::fs::write(&path, format!("{}\n", SECRET_HEX)).unwrap();
+    let from_file = keys::load_keys(&DevKeyProvider::File(path.clone()))
+        .await
+        .unwrap();
+    assert_eq!(from_file.signer.address(), expected.signer.address());
+    std::fs::remove_file(&path).unwrap();
+    assert!(is_key_error(
+        keys::load_keys(&DevKeyProvider::File(path)).await
+    ));
+
+    std::env::set_var("MW_TEST_DEV_KEY", SECRET_HEX);
+    let from_env = keys::load_keys(&DevKeyProvider::Env("MW_TEST_DEV_KEY".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(from_env.signer.address(), expected.signer.address());
+    assert!(is_key_error(
+        keys::load_keys(&DevKeyProvider::Env("MW_TEST_UNSET_KEY".to_string())).await
+    ));
+}
+
+#[tokio::test]
+async fn test_dstack_guest_agent() {
+    let key_url =
+        guest_agent("\"0x0101010101010101010101010101010101010101010101010101010101010101\"").await;
+    let keys = keys::load_keys(&DstackKeyProvider { key_url })
+        .await
+        .unwrap();
+    let expected = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    assert_eq!(keys.signer.address(), expected.signer.address());
+
+    let key_url = guest_agent("\"short\"").await;
+    assert!(is_key_error(
+        keys::load_keys(&DstackKeyProvider { key_url }).await
+    ));
+    // nothing listening
+    let key_url = Url::parse("http://127.0.0.1:9/key/test").unwrap();
+    assert!(is_key_error(
+        keys::load_keys(&DstackKeyProvider { key_url }).await
+    ));
+}
+
+#[test]
+fn test_key_provider_config_parsing() {
+    assert_eq!(
+        KeyProviderConfig::from_str("dstack").unwrap(),
+        KeyProviderConfig::Dstack
+    );
+    assert_eq!(
+        KeyProviderConfig::from_str("file:/run/dev.key").unwrap(),
+        KeyProviderConfig::File(PathBuf::from("/run/dev.key"))
+    );
+    assert_eq!(
+        KeyProviderConfig::from_str("env:MW_DEV_KEY").unwrap(),
+        KeyProviderConfig::Env("MW_DEV_KEY".to_string())
+    );
+    for invalid in ["", "file:", "env:", "vault:secret", "test"] {
+        assert!(
+            is_key_error(KeyProviderConfig::from_str(invalid)),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_seal_round_trip() {
+    let keys = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    let sealed = keys::seal(&keys.volume, "inventories.sealed", b"balances").unwrap();
+    assert_eq!(
+        keys::unseal(&keys.volume, "inventories.sealed", &sealed).unwrap(),
+        b"balances"
+    );
+    // another file, another key, or a truncated file
+    assert!(keys::unseal(&keys.volume, "other.sealed", &sealed).is_err());
+    assert!(keys::unseal(&keys.checkpoint, "inventories.sealed", &sealed).is_err());
+    assert!(keys::unseal(&keys.volume, "inventories.sealed", &sealed[..8]).is_err());
+}
@@ -0,0 +1,327 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    chains::{Chain, Chains, MAINNET, MAINNET_CHAIN_ID},
+    config::StorageConfig,
+    errors::MwError,
+    fakechain::FakeChain,
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    leader::{self, Campaign, InstanceRole, Leadership, LEADER_LEASE_SECS},
+    rpc::ChainClient,
+    sequencer::{self, Command, SequencerHandle},
+    session::{SessionController, SessionSchedule},
+    sink::{CheckpointSink, CheckpointerSink, TxPolicy},
+    snapshotter::{self, CheckpointDraft},
+    warehouse::Warehouse,
+};
+use optimized_lob::orderbook_manager::OrderBookManager;
+
+fn registry() -> Address {
+    Address::repeat_byte(0xd)
+}
+
+fn checkpointer() -> Address {
+    Address::repeat_byte(0xc)
+}
+
+fn warehouse(keys: &AppKeys) -> Warehouse {
+    let mut warehouse = Warehouse::new(keys);
+    warehouse.checkpoint_contract = checkpointer();
+    warehouse
+}
+
+// a leader with the registry and the Checkpointer set
+async fn spawn_leader(name: &str) -> (SequencerHandle, PathBuf) {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[9u8; MIN_SECRET_BYTES]).unwrap());
+    let data_dir = std::env::temp_dir().join(format!("mw-leader-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    warehouse.storage = StorageConfig {
+        data_dir: data_dir.clone(),
+        host_data_dir: data_dir.clone(),
+    };
+    let sequencer = sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    });
+    sequencer
+        .submit(Command::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            deposit_contract: registry(),
+            checkpoint_contract: checkpointer(),
+        })
+        .await
+        .unwrap();
+    (sequencer, data_dir)
+}
+
+#[test]
+fn test_roles() {
+    assert_eq!(
+        InstanceRole::from_str("leader").unwrap(),
+        InstanceRole::Leader
+    );
+    assert_eq!(
+        InstanceRole::from_str("replica").unwrap(),
+        InstanceRole::Replica
+    );
+    assert!(InstanceRole::from_str("follower").is_err());
+    assert_eq!(InstanceRole::Replica.to_string(), "replica");
+
+    let address = Address::repeat_byte(1);
+    let leader = Leadership {
+        role: InstanceRole::Leader,
+        address,
+    };
+    leader.require_leader("take_snapshot").unwrap();
+    let replica = Leadership {
+        role: InstanceRole::Replica,
+        address,
+    };
+    assert!(!replica.is_leader());
+    assert!(matches!(
+        replica.require_leader("take_snapshot"),
+        Err(MwError::NotLeader { action }) if action == "take_snapshot"
+    ));
+    let json: serde_json::Value = serde_json::from_str(&replica.to_json()).unwrap();
+    assert_eq!(json["address"], address.to_string());
+    assert_eq!(json["role"], "replica");
+}
+
+#[test]
+fn test_leader_keys_are_per_container() {
+    // same shared secret, so the same signer, but each container has its own leader key
+    let keys = AppKeys::derive(&[6u8; MIN_SECRET_BYTES]).unwrap();
+    let first = Warehouse::new(&keys);
+    let second = Warehouse::new(&keys);
+    assert_eq!(first.signer.address(), second.signer.address());
+    assert_ne!(first.leader.address(), second.leader.address());
+    assert_ne!(first.leader.address(), first.signer.address());
+}
+
+#[tokio::test]
+async fn test_claim() {
+    let fake = FakeChain::new();
+    let admin = Address::repeat_byte(0xa);
+    let leader_address = Address::repeat_byte(1);
+    let policy = TxPolicy::default();
+    assert!(
+        leader::claim(&fake, checkpointer(), admin, leader_address, &policy)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(fake.leader(checkpointer()).await.unwrap(), leader_address);
+    // already elected, or nothing to claim
+    assert!(
+        leader::claim(&fake, checkpointer(), admin, leader_address, &policy)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        leader::claim(&fake, Address::ZERO, admin, leader_address, &policy)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(fake.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn test_claim_contracts() {
+    let keys = AppKeys::derive(&[7u8; MIN_SECRET_BYTES]).unwrap();
+    let mut warehouse = Warehouse::new(&keys);
+    let data_dir = std::env::temp_dir().join(format!("mw-leader-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    warehouse.storage = StorageConfig {
+        data_dir: data_dir.clone(),
+        host_data_dir: data_dir.clone(),
+    };
+    let leader_address = warehouse.leader.address();
+    let sequencer = sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    });
+    sequencer
+        .submit(Command::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            deposit_contract: registry(),
+            checkpoint_contract: checkpointer(),
+        })
+        .await
+        .unwrap();
+
+    let checkpoint_chain = Arc::new(FakeChain::new());
+    let deposit_chain = Arc::new(FakeChain::new());
+    let chains = Chains::new(vec![Chain::new(MAINNET, vec![deposit_chain.clone()])]);
+    let snapshot = sequencer.snapshot();
+    assert_eq!(snapshot.leader_address, leader_address);
+    leader::claim_contracts(
+        &snapshot,
+        &chains,
+        checkpoint_chain.as_ref(),
+        &TxPolicy::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        checkpoint_chain.leader(checkpointer()).await.unwrap(),
+        leader_address
+    );
+    assert_eq!(
+        deposit_chain.leader(registry()).await.unwrap(),
+        leader_address
+    );
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_replaced_leader_cannot_checkpoint() {
+    let keys = AppKeys::derive(&[8u8; MIN_SECRET_BYTES]).unwrap();
+    let old_leader = warehouse(&keys);
+    let new_leader = warehouse(&keys);
+    let fake = Arc::new(FakeChain::new());
+    fake.set_domain_separator(
+        checkpointer(),
+        old_leader.domains.toliman(checkpointer()).separator(),
+    );
+    fake.set_leader(checkpointer(), old_leader.leader.address());
+    let sink = CheckpointerSink {
+        client: fake.clone(),
+        policy: TxPolicy::default(),
+    };
+    let sign = |warehouse: &Warehouse, nonce: u64| {
+        snapshotter::sign(
+            CheckpointDraft::from_warehouse(warehouse),
+            U256::from(nonce),
+        )
+    };
+    sink.publish(&sign(&old_leader, 0).await.unwrap())
+        .await
+        .unwrap();
+
+    // the new container has the shared secret too, but not the elected key
+    assert!(matches!(
+        sink.publish(&sign(&new_leader, 1).await.unwrap()).await,
+        Err(MwError::SnapshotError(_))
+    ));
+    // nor can it be elected while the old leader's lease runs
+    let claim = || {
+        leader::claim(
+            fake.as_ref(),
+            checkpointer(),
+            new_leader.signer.address(),
+            new_leader.leader.address(),
+            &TxPolicy::default(),
+        )
+    };
+    fake.set_time(LEADER_LEASE_SECS);
+    assert!(matches!(claim().await, Err(MwError::SnapshotError(_))));
+    fake.set_time(LEADER_LEASE_SECS + 1);
+    claim().await.unwrap();
+    assert!(matches!(
+        sink.publish(&sign(&old_leader, 1).await.unwrap()).await,
+        Err(MwError::SnapshotError(_))
+    ));
+    sink.publish(&sign(&new_leader, 1).await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        sink.next_nonce(checkpointer()).await.unwrap(),
+        U256::from(2)
+    );
+}
+
+#[tokio::test]
+async fn test_campaign_waits_out_the_old_leaders_lease() {
+    let (sequencer, data_dir) = spawn_leader("campaign").await;
+    let fake = Arc::new(FakeChain::new());
+    let chains = Chains::new(vec![Chain::new(MAINNET, vec![fake.clone()])]);
+    let policy = TxPolicy::default();
+    // the key the container had before it restarted
+    let old_key = Address::repeat_byte(0xe);
+    fake.set_leader(checkpointer(), old_key);
+    fake.set_leader(registry(), old_key);
+
+    let mut campaign = Campaign::new(InstanceRole::Leader);
+    assert_eq!(
+        campaign
+            .run_once(&sequencer, &chains, fake.as_ref(), &policy)
+            .await
+            .unwrap(),
+        InstanceRole::Replica
+    );
+    assert!(campaign.is_candidate());
+    // the registry is left alone until the Checkpointer is won
+    assert_eq!(fake.leader(registry()).await.unwrap(), old_key);
+    assert!(matches!(
+        sequencer
+            .submit(Command::ClearSettlementOrders { count: 0 })
+            .await,
+        Err(MwError::NotLeader { .. })
+    ));
+
+    fake.set_time(LEADER_LEASE_SECS + 1);
+    assert_eq!(
+        campaign
+            .run_once(&sequencer, &chains, fake.as_ref(), &policy)
+            .await
+            .unwrap(),
+        InstanceRole::Leader
+    );
+    let leader_address = sequencer.snapshot().leader_address;
+    assert_eq!(fake.leader(checkpointer()).await.unwrap(), leader_address);
+    assert_eq!(fake.leader(registry()).await.unwrap(), leader_address);
+    // holding the lease, it stays the leader
+    campaign
+        .run_once(&sequencer, &chains, fake.as_ref(), &policy)
+        .await
+        .unwrap();
+    assert!(!campaign.is_candidate());
+    sequencer
+        .submit(Command::ClearSettlementOrders { count: 0 })
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_replaced_leader_steps_down_for_good() {
+    let (sequencer, data_dir) = spawn_leader("replaced").await;
+    let fake = Arc::new(FakeChain::new());
+    let chains = Chains::new(vec![Chain::new(MAINNET, vec![fake.clone()])]);
+    let policy = TxPolicy::default();
+    let mut campaign = Campaign::new(InstanceRole::Leader);
+    assert_eq!(
+        campaign
+            .run_once(&sequencer, &chains, fake.as_ref(), &policy)
+            .await
+            .unwrap(),
+        InstanceRole::Leader
+    );
+
+    // its lease ran out and another container was elected, which sequenced without it
+    fake.set_leader(checkpointer(), Address::repeat_byte(0xe));
+    for now in [0, 10 * LEADER_LEASE_SECS] {
+        fake.set_time(now);
+        assert_eq!(
+            campaign
+                .run_once(&sequencer, &chains, fake.as_ref(), &policy)
+                .await
+                .unwrap(),
+            InstanceRole::Replica
+        );
+        assert!(!campaign.is_candidate());
+    }
+    assert_eq!(
+        fake.leader(checkpointer()).await.unwrap(),
+        Address::repeat_byte(0xe)
+    );
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{keccak256, Address, U256};
+use myrtle_wyckoff_dstack::{
+    merkle::{self, InventoryCommitment, MerkleTree},
+    warehouse::Inventory,
+};
+use optimized_lob::quantity::Qty;
+
+fn inventory(byte: u8, usdc_balance: u64) -> Inventory {
+    let mut inventory = Inventory::default();
+    inventory.address = Address::repeat_byte(byte);
+    inventory.usdc_balance = Qty(U256::from(usdc_balance));
+    inventory
+}
+
+#[test]
+fn test_every_leaf_proves_against_root() {
+    for leaf_count in 1..=9u8 {
+        let leaves: Vec<_> = (0..leaf_count).map(|i| keccak256([i])).collect();
+        let tree = MerkleTree::from_leaves(leaves.clone());
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert!(merkle::verify(tree.root(), *leaf, &proof));
+        }
+        assert!(tree.proof(leaves.len()).is_none());
+    }
+}
+
+#[test]
+fn test_empty_tree_root_is_zero() {
+    let commitment = InventoryCommitment::new(&HashMap::new());
+    assert!(commitment.root().is_zero());
+    assert!(commitment.proof_json(Address::repeat_byte(1)).is_none());
+}
+
+#[test]
+fn test_inventory_commitment() {
+    let inventories: HashMap<Address, Inventory> =
+        [inventory(3, 30), inventory(1, 10), inventory(2, 20)]
+            .into_iter()
+            .map(|inventory| (inventory.address, inventory))
+            .collect();
+    let commitment = InventoryCommitment::new(&inventories);
+
+    // the root doesn't depend on map ordering
+    let reordered: HashMap<Address, Inventory> = inventories.clone().into_iter().rev().collect();
+    assert_eq!(
+        commitment.root(),
+        InventoryCommitment::new(&reordered).root()
+    );
+
+    let proof: serde_json::Value =
+        serde_json::from_str(&commitment.proof_json(Address::repeat_byte(2)).unwrap()).unwrap();
+    assert_eq!(proof["index"], "1");
+    let nodes: Vec<_> = proof["proof"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node.as_str().unwrap().parse().unwrap())
+        .collect();
+    let leaf = merkle::inventory_leaf(&inventories[&Address::repeat_byte(2)]);
+    assert!(merkle::verify(commitment.root(), leaf, &nodes));
+
+    // a changed balance doesn't verify
+    let forged = merkle::inventory_leaf(&inventory(2, 2000));
+    assert!(!merkle::verify(commitment.root(), forged, &nodes));
+}
+
+#[test]
+fn test_commitment_bytes_rebuild_the_tree() {
+    let inventories: HashMap<Address, Inventory> = [inventory(1, 10), inventory(2, 20)]
+        .into_iter()
+        .map(|inventory| (inventory.address, inventory))
+        .collect();
+    let mut commitment = InventoryCommitment::new(&inventories);
+    commitment.checkpoint_nonce = U256::from(7);
+    let rebuilt = InventoryCommitment::from_bytes(&commitment.to_bytes()).unwrap();
+    assert_eq!(rebuilt.checkpoint_nonce, U256::from(7));
+    assert_eq!(rebuilt.root(), commitment.root());
+    assert_eq!(
+        rebuilt.proof_json(Address::repeat_byte(2)),
+        commitment.proof_json(Address::repeat_byte(2))
+    );
+
+    let bytes = commitment.to_bytes();
+    assert!(InventoryCommitment::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
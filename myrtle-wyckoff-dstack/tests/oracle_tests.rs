@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    errors::MwError,
+    oracle::{FixedPriceSource, ListedAsset, PriceOracle, PriceOrigin, PriceSource},
+};
+
+fn weth() -> Address {
+    ListedAsset::weth().token
+}
+
+fn fixed_oracle(prices: HashMap<Address, U256>) -> PriceOracle<FixedPriceSource> {
+    PriceOracle::new(
+        FixedPriceSource::new(prices),
+        vec![ListedAsset::weth()],
+        5_000,
+        60_000,
+    )
+}
+
+// a quote source that can be taken down between requests
+struct FlakySource {
+    price: Arc<RwLock<Option<U256>>>,
+}
+impl PriceSource for FlakySource {
+    async fn fetch_price(&self, asset: Address) -> Result<U256, MwError> {
+        self.price
+            .read()
+            .unwrap()
+            .ok_or(MwError::QuoteError(format!("no quote for {}", asset)))
+    }
+}
+
+#[tokio::test]
+async fn test_fixed_price() {
+    // 1500.5 USDC, the fraction isn't truncated away
+    let oracle = fixed_oracle(HashMap::from([(weth(), U256::from(1_500_500_000u64))]));
+
+    let mark_price = oracle.get_price_at(weth(), None, 1_000).await.unwrap();
+    assert_eq!(mark_price.price, U256::from(1_500_500_000u64));
+    assert_eq!(mark_price.origin, PriceOrigin::Quoter);
+    assert_eq!(mark_price.book_price(), U256::from(1501));
+}
+
+#[tokio::test]
+async fn test_unlisted_asset() {
+    let oracle = fixed_oracle(HashMap::new());
+
+    let result = oracle.get_price_at(Address::ZERO, None, 1_000).await;
+    assert!(matches!(result, Err(MwError::UnlistedAsset { .. })));
+}
+
+#[tokio::test]
+async fn test_falls_back_to_book_mid() {
+    let oracle = fixed_oracle(HashMap::new());
+
+    let mark_price = oracle
+        .get_price_at(weth(), Some(U256::from(1450)), 1_000)
+        .await
+        .unwrap();
+    assert_eq!(mark_price.price, U256::from(1_450_000_000u64));
+    assert_eq!(mark_price.origin, PriceOrigin::BookMid);
+    assert_eq!(mark_price.book_price(), U256::from(1450));
+
+    let result = oracle.get_price_at(weth(), None, 1_000).await;
+    assert!(matches!(result, Err(MwError::PriceUnavailable { .. })));
+}
+
+#[tokio::test]
+async fn test_falls_back_to_stale_quote() {
+    let quote = Arc::new(RwLock::new(Some(U256::from(1_500_250_000u64))));
+    let oracle = PriceOracle::new(
+        FlakySource {
+            price: quote.clone(),
+        },
+        vec![ListedAsset::weth()],
+        5_000,
+        60_000,
+    );
+    oracle.get_price_at(weth(), None, 1_000).await.unwrap();
+    *quote.write().unwrap() = None;
+
+    // past max_age the quoter is asked again, and the cached quote stands in while it's down
+    let mark_price = oracle.get_price_at(weth(), None, 10_000).await.unwrap();
+    assert_eq!(mark_price.price, U256::from(1_500_250_000u64));
+    assert_eq!(mark_price.origin, PriceOrigin::StaleQuote);
+    assert_eq!(mark_price.timestamp, 1_000);
+
+    // a book mid is preferred over a stale quote
+    let mark_price = oracle
+        .get_price_at(weth(), Some(U256::from(1450)), 10_000)
+        .await
+        .unwrap();
+    assert_eq!(mark_price.origin, PriceOrigin::BookMid);
+
+    // up to max_staleness
+    let mark_price = oracle.get_price_at(weth(), None, 61_000).await.unwrap();
+    assert_eq!(mark_price.origin, PriceOrigin::StaleQuote);
+    let result = oracle.get_price_at(weth(), None, 61_001).await;
+    assert!(matches!(result, Err(MwError::PriceUnavailable { .. })));
+}
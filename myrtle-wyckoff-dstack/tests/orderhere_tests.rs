@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    errors::MwError,
+    events,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    orderhere::{self, CancelAll, Order},
+    session::{SessionController, SessionSchedule},
+    structs::{self, MAX_CLOCK_SKEW_MS, MAX_REQUEST_AGE_MS},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{
+    order::OrderId, orderbook_manager::OrderBookManager, price::Price, quantity::Qty,
+};
+
+const MAKER: u8 = 1;
+const OTHER: u8 = 2;
+
+fn account(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn fund(warehouse: &mut Warehouse, byte: u8) {
+    warehouse.inventories.insert(
+        account(byte),
+        Inventory::new(
+            account(byte),
+            Qty(U256::from(10)),
+            Qty(U256::ZERO),
+            Qty(U256::from(100_000)),
+            Qty(U256::ZERO),
+            BTreeMap::new(),
+            false,
+        ),
+    );
+}
+
+fn place(
+    warehouse: &mut Warehouse,
+    orderbook_manager: &mut OrderBookManager,
+    byte: u8,
+    price: u64,
+    is_bid: bool,
+) -> u32 {
+    let order = Order {
+        price: U256::from(price),
+        qty: U256::from(1),
+        is_bid,
+        timestamp: 0,
+    };
+    let (_, _, oid) =
+        orderhere::submit_order(warehouse, orderbook_manager, account(byte), order).unwrap();
+    oid.unwrap().0
+}
+
+// MAKER has bids at 1000 and 1100 and an ask at 2000, OTHER has a bid at 1200
+fn market() -> (Warehouse, OrderBookManager, [u32; 3]) {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[5u8; MIN_SECRET_BYTES]).unwrap());
+    let mut orderbook_manager = OrderBookManager::new();
+    fund(&mut warehouse, MAKER);
+    fund(&mut warehouse, OTHER);
+    let oids = [
+        place(&mut warehouse, &mut orderbook_manager, MAKER, 1000, true),
+        place(&mut warehouse, &mut orderbook_manager, MAKER, 1100, true),
+        place(&mut warehouse, &mut orderbook_manager, MAKER, 2000, false),
+    ];
+    place(&mut warehouse, &mut orderbook_manager, OTHER, 1200, true);
+    (warehouse, orderbook_manager, oids)
+}
+
+fn cancel_all(side: u8) -> CancelAll {
+    CancelAll {
+        scope_book: false,
+        book_id: 0,
+        side,
+        timestamp: 0,
+    }
+}
+
+fn state_hash(warehouse: &Warehouse, orderbook_manager: &OrderBookManager) -> String {
+    let session = SessionController::new(SessionSchedule::default(), 0);
+    events::state_hash(warehouse, orderbook_manager, &session).to_string()
+}
+
+#[test]
+fn test_cancel_all_by_side() {
+    let (mut warehouse, mut orderbook_manager, [low_bid, high_bid, ask]) = market();
+    let cancelled = orderhere::submit_cancel_all(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(MAKER),
+        &cancel_all(1),
+    )
+    .unwrap();
+    assert_eq!(cancelled, [low_bid, high_bid]);
+    let inventory = &warehouse.inventories[&account(MAKER)];
+    assert!(inventory.usdc_liabilities.0.is_zero());
+    assert_eq!(inventory.eth_liabilities.0, U256::from(1));
+
+    let cancelled = orderhere::submit_cancel_all(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(MAKER),
+        &cancel_all(0),
+    )
+    .unwrap();
+    assert_eq!(cancelled, [ask]);
+    // OTHER's bid is still there
+    assert_eq!(warehouse.address_by_oid.len(), 1);
+}
+
+#[test]
+fn test_cancel_all_cancels_all_or_nothing() {
+    let (mut warehouse, mut orderbook_manager, _) = market();
+    // an order the warehouse holds but the book lost, after all of MAKER's others
+    warehouse.add_order(
+        OrderId(u32::MAX),
+        account(MAKER),
+        Qty(U256::from(1)),
+        Price::from_u256(U256::from(900), true),
+    );
+    let before = state_hash(&warehouse, &orderbook_manager);
+    assert!(matches!(
+        orderhere::submit_cancel_all(
+            &mut warehouse,
+            &mut orderbook_manager,
+            account(MAKER),
+            &cancel_all(0),
+        ),
+        Err(MwError::OrderNotFound { order_id: u32::MAX })
+    ));
+    assert_eq!(state_hash(&warehouse, &orderbook_manager), before);
+    assert_eq!(warehouse.oid_qty_by_address[&account(MAKER)].len(), 4);
+}
+
+#[test]
+fn test_cancel_all_rejects_unknown_sides() {
+    let (mut warehouse, mut orderbook_manager, _) = market();
+    assert!(matches!(
+        orderhere::submit_cancel_all(
+            &mut warehouse,
+            &mut orderbook_manager,
+            account(MAKER),
+            &cancel_all(3),
+        ),
+        Err(MwError::InvalidOrderParams)
+    ));
+    assert!(orderhere::submit_cancel_all(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(3),
+        &cancel_all(0),
+    )
+    .unwrap()
+    .is_empty());
+}
+
+#[test]
+fn test_timestamps_are_bounded_both_ways() {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    let order = |timestamp| Order {
+        price: U256::from(1500),
+        qty: U256::from(1),
+        is_bid: true,
+        timestamp,
+    };
+    assert!(order(now).validate_timestamp().is_ok());
+    assert!(order(now + MAX_CLOCK_SKEW_MS / 2)
+        .validate_timestamp()
+        .is_ok());
+    for timestamp in [
+        now - MAX_REQUEST_AGE_MS - 1000,
+        // signed for later, it would be replayable once it's current
+        now + MAX_CLOCK_SKEW_MS + 1000,
+        u64::MAX,
+    ] {
+        assert!(matches!(
+            order(timestamp).validate_timestamp(),
+            Err(MwError::InvalidTimestamp)
+        ));
+        assert!(matches!(
+            structs::validate_timestamp(timestamp),
+            Err(MwError::InvalidTimestamp)
+        ));
+    }
+}
+
+#[test]
+fn test_submit_order_returns_qty_then_volume() {
+    const TAKER: u8 = 3;
+    let (mut warehouse, mut orderbook_manager, _) = market();
+    fund(&mut warehouse, TAKER);
+    // crosses OTHER's bid at 1200 and MAKER's at 1100
+    let order = Order {
+        price: U256::from(1100),
+        qty: U256::from(2),
+        is_bid: false,
+        timestamp: 0,
+    };
+    let (qty_executed, volume_executed, oid) = orderhere::submit_order(
+        &mut warehouse,
+        &mut orderbook_manager,
+        account(TAKER),
+        order,
+    )
+    .unwrap();
+    assert_eq!(qty_executed.0, U256::from(2));
+    assert_eq!(volume_executed.0, U256::from(1200 + 1100));
+    // filled in full, nothing left to rest
+    assert!(oid.is_none());
+}
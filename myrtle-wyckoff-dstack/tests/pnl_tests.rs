@@ -0,0 +1,49 @@
+use alloy::primitives::{Address, I256, U256};
+use myrtle_wyckoff_dstack::pnl::{AccountPnl, PnlBook};
+
+fn i256(value: i64) -> I256 {
+    I256::try_from(value).unwrap()
+}
+
+#[test]
+fn test_realized_and_unrealized_pnl() {
+    let mut account = AccountPnl::default();
+    // buy 10 @ 100, buy 10 @ 120, sell 10 @ 130
+    account.record_fill(true, U256::from(10), U256::from(1000));
+    account.record_fill(true, U256::from(10), U256::from(1200));
+    account.record_fill(false, U256::from(10), U256::from(1300));
+
+    assert_eq!(account.position, i256(10));
+    assert_eq!(account.realized, i256(200));
+    assert_eq!(account.unrealized(U256::from(100)), i256(-100));
+}
+
+#[test]
+fn test_position_flip() {
+    let mut account = AccountPnl::default();
+    // buy 5 @ 100 then sell 8 @ 110, leaving a 3 short opened at 110
+    account.record_fill(true, U256::from(5), U256::from(500));
+    account.record_fill(false, U256::from(8), U256::from(880));
+
+    assert_eq!(account.position, i256(-3));
+    assert_eq!(account.realized, i256(50));
+    assert_eq!(account.unrealized(U256::from(100)), i256(30));
+}
+
+#[test]
+fn test_results_ranked_and_rolled() {
+    let winner = Address::repeat_byte(1);
+    let loser = Address::repeat_byte(2);
+    let mut book = PnlBook::new(1);
+    book.record_fill(loser, true, U256::from(1), U256::from(150));
+    book.record_fill(winner, false, U256::from(1), U256::from(150));
+
+    let results = book.results(U256::from(100));
+    assert_eq!(results[0].address, winner);
+    assert_eq!(results[0].total, i256(50));
+    assert_eq!(results[1].total, i256(-50));
+
+    book.roll(2, U256::from(100));
+    let results = book.results(U256::from(100));
+    assert!(results.iter().all(|result| result.total.is_zero()));
+}
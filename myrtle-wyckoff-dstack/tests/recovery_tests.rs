@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    config::StorageConfig,
+    fakechain::FakeChain,
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    leader, recovery,
+    scheduler::SnapshotTracker,
+    sequencer,
+    session::{SessionController, SessionSchedule},
+    sink::{CheckpointSink, CheckpointerSink, FileSink, TxPolicy},
+    snapshotter::{self, CheckpointDraft},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{orderbook_manager::OrderBookManager, quantity::Qty};
+
+fn checkpointer() -> Address {
+    Address::repeat_byte(0xc)
+}
+
+fn keys() -> AppKeys {
+    AppKeys::derive(&[8u8; MIN_SECRET_BYTES]).unwrap()
+}
+
+// a Checkpointer holding two checkpoints of a user with an ask and a bid resting
+async fn posted(user: Address) -> Arc<FakeChain> {
+    let mut warehouse = Warehouse::new(&keys());
+    warehouse.checkpoint_contract = checkpointer();
+    warehouse.inventories.insert(
+        user,
+        Inventory {
+            address: user,
+            eth_balance: Qty(U256::from(3)),
+            eth_liabilities: Qty(U256::from(1)),
+            usdc_balance: Qty(U256::from(5000)),
+            usdc_liabilities: Qty(U256::from(2000)),
+            ..Inventory::default()
+        },
+    );
+    let fake = Arc::new(FakeChain::new());
+    fake.set_domain_separator(
+        checkpointer(),
+        warehouse.domains.toliman(checkpointer()).separator(),
+    );
+    leader::claim(
+        fake.as_ref(),
+        checkpointer(),
+        warehouse.signer.address(),
+        warehouse.leader.address(),
+        &TxPolicy::default(),
+    )
+    .await
+    .unwrap();
+    let sink = CheckpointerSink {
+        client: fake.clone(),
+        policy: TxPolicy::default(),
+    };
+    for _ in 0..2 {
+        let nonce = sink.next_nonce(checkpointer()).await.unwrap();
+        let signed = snapshotter::sign(CheckpointDraft::from_warehouse(&warehouse), nonce)
+            .await
+            .unwrap();
+        sink.publish(&signed).await.unwrap();
+    }
+    fake
+}
+
+#[tokio::test]
+async fn test_recovery_releases_what_resting_orders_locked() {
+    let user = Address::repeat_byte(1);
+    let fake = posted(user).await;
+
+    let mut recovered = Warehouse::new(&keys());
+    let next_nonce = recovery::recover(&mut recovered, fake.as_ref(), checkpointer())
+        .await
+        .unwrap();
+    assert_eq!(next_nonce, U256::from(2));
+    assert_eq!(recovered.resumed_checkpoint_nonce, U256::from(2));
+    // the book starts empty, so nothing is locked and everything can be traded or withdrawn
+    let inventory = &recovered.inventories[&user];
+    assert_eq!(inventory.eth_balance.0, U256::from(3));
+    assert_eq!(inventory.usdc_balance.0, U256::from(5000));
+    assert!(inventory.eth_liabilities.0.is_zero());
+    assert!(inventory.usdc_liabilities.0.is_zero());
+    assert_eq!(inventory.net_eth().0, U256::from(3));
+}
+
+#[tokio::test]
+async fn test_checkpoints_resume_from_the_recovered_nonce() {
+    let user = Address::repeat_byte(1);
+    let fake = posted(user).await;
+    let directory = std::env::temp_dir().join(format!("mw-recovery-resume-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let mut warehouse = Warehouse::new(&keys());
+    warehouse.storage = StorageConfig {
+        data_dir: directory.clone(),
+        host_data_dir: directory.clone(),
+    };
+    let mut jtrain = Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    };
+    jtrain.recover(fake.as_ref(), checkpointer()).await.unwrap();
+    let sequencer = sequencer::spawn_genesis(jtrain);
+
+    // a sink with no checkpoints of its own carries on after the recovered ones
+    let sink = FileSink {
+        directory: directory.join("checkpoints"),
+    };
+    assert_eq!(sink.next_nonce(checkpointer()).await.unwrap(), U256::ZERO);
+    let tracker = SnapshotTracker::new();
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    assert!(sink.path(U256::from(2)).exists());
+    assert!(!sink.path(U256::ZERO).exists());
+    assert_eq!(tracker.status().last_checkpoint_nonce, Some(U256::from(2)));
+    assert_eq!(
+        sink.next_nonce(checkpointer()).await.unwrap(),
+        U256::from(3)
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
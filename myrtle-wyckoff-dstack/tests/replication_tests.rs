@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+
+use alloy::primitives::{Address, B256};
+use myrtle_wyckoff_dstack::{
+    chains::MAINNET_CHAIN_ID,
+    config::StorageConfig,
+    errors::MwError,
+    events::{self, EVENT_LOG_STORAGE_FILE},
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    leader::InstanceRole,
+    replication::{self, EventStreamDecoder, RecordFeed, REQUEST_TOKEN_TTL_MS},
+    sequencer::{self, Command, SequencerHandle},
+    session::{SessionController, SessionSchedule},
+    warehouse::Warehouse,
+};
+use optimized_lob::orderbook_manager::OrderBookManager;
+
+fn keys() -> AppKeys {
+    AppKeys::derive(&[9u8; MIN_SECRET_BYTES]).unwrap()
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("mw-replication-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn jtrain(data_dir: &Path) -> Jtrain {
+    let mut warehouse = Warehouse::new(&keys());
+    warehouse.storage = StorageConfig {
+        data_dir: data_dir.to_path_buf(),
+        host_data_dir: data_dir.to_path_buf(),
+    };
+    Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    }
+}
+
+fn set_checkpointer(byte: u8) -> Command {
+    Command::SetContractAddresses {
+        chain_id: MAINNET_CHAIN_ID,
+        deposit_contract: Address::repeat_byte(0xd),
+        checkpoint_contract: Address::repeat_byte(byte),
+    }
+}
+
+// applies frames off the feed until the replica is at the leader's seq
+async fn catch_up(feed: &mut RecordFeed, leader: &SequencerHandle, replica: &SequencerHandle) {
+    while replica.snapshot().seq < leader.snapshot().seq {
+        let frame = feed.next().await.unwrap();
+        replication::apply_frame(replica, &keys().replication, &frame)
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_request_tokens() {
+    let key = keys().replication;
+    let now = 1_000_000;
+    let token = replication::request_token(&key, 7, now).unwrap();
+    replication::check_request_token(&key, &token, 7, now + 1_000).unwrap();
+
+    let unauthorized =
+        |result: Result<(), MwError>| matches!(result, Err(MwError::UnauthorizedAccess));
+    // for another position in the log, stale, or from another app
+    assert!(unauthorized(replication::check_request_token(
+        &key, &token, 8, now
+    )));
+    assert!(unauthorized(replication::check_request_token(
+        &key,
+        &token,
+        7,
+        now + REQUEST_TOKEN_TTL_MS + 1
+    )));
+    let other = AppKeys::derive(&[10u8; MIN_SECRET_BYTES]).unwrap();
+    assert!(unauthorized(replication::check_request_token(
+        &other.replication,
+        &token,
+        7,
+        now
+    )));
+    assert!(unauthorized(replication::check_request_token(
+        &key, "not hex", 7, now
+    )));
+}
+
+#[test]
+fn test_records_are_sealed() {
+    let key = keys().replication;
+    let record = events::EventRecord {
+        seq: 3,
+        timestamp: 42,
+        event: events::Event::ClearSettlementOrders { count: 1 },
+        error: None,
+        state_hash: B256::repeat_byte(1),
+        hash_version: events::STATE_HASH_VERSION,
+    };
+    let frame = replication::seal_record(&key, &record).unwrap();
+    let opened = replication::open_record(&key, &frame).unwrap();
+    assert_eq!(opened.seq, 3);
+    assert_eq!(opened.state_hash, record.state_hash);
+
+    // only containers of this app can read or forge the stream
+    let other = AppKeys::derive(&[10u8; MIN_SECRET_BYTES]).unwrap();
+    assert!(replication::open_record(&other.replication, &frame).is_err());
+    let mut tampered = frame.clone();
+    tampered.replace_range(30..32, if &frame[30..32] == "00" { "01" } else { "00" });
+    assert!(replication::open_record(&key, &tampered).is_err());
+}
+
+#[test]
+fn test_event_stream_decoder() {
+    let mut decoder = EventStreamDecoder::default();
+    assert!(decoder.push(b":\n\ndata: ab").is_empty());
+    assert_eq!(decoder.push(b"cd\n\ndata:ef\r\n\r\n"), vec!["abcd", "ef"]);
+    assert_eq!(
+        decoder.push(b"event: x\ndata: 1\ndata: 2\n\n"),
+        vec!["1\n2"]
+    );
+}
+
+#[tokio::test]
+async fn test_replica_follows_the_leader() {
+    let leader_dir = data_dir("leader");
+    let replica_dir = data_dir("replica");
+    let leader = sequencer::spawn(jtrain(&leader_dir));
+    leader.submit(set_checkpointer(0xc)).await.unwrap();
+    let replica = sequencer::spawn_replica(jtrain(&replica_dir));
+    assert_eq!(replica.snapshot().role, InstanceRole::Replica);
+    assert_eq!(replica.snapshot().seq, 0);
+
+    // the backlog from the log, then live records
+    let mut feed = RecordFeed::open(
+        &leader,
+        leader_dir.join(EVENT_LOG_STORAGE_FILE),
+        keys().replication,
+        0,
+    )
+    .unwrap();
+    catch_up(&mut feed, &leader, &replica).await;
+    leader.submit(set_checkpointer(0xe)).await.unwrap();
+    catch_up(&mut feed, &leader, &replica).await;
+    assert_eq!(replica.snapshot().seq, leader.snapshot().seq);
+    assert_eq!(replica.snapshot().state_hash, leader.snapshot().state_hash);
+    assert_eq!(
+        replica.snapshot().checkpoint_contract,
+        Address::repeat_byte(0xe)
+    );
+
+    // only the leader sequences
+    assert!(matches!(
+        replica.submit(set_checkpointer(0xf)).await,
+        Err(MwError::NotLeader { .. })
+    ));
+    let records = events::read_records(leader_dir.join(EVENT_LOG_STORAGE_FILE)).unwrap();
+    let genesis = records[0].clone();
+    assert!(matches!(
+        leader.submit(Command::Replicate { record: genesis }).await,
+        Err(MwError::ReplicationError(_))
+    ));
+    // nothing the replica has can be ahead of the leader
+    assert!(RecordFeed::open(
+        &leader,
+        leader_dir.join(EVENT_LOG_STORAGE_FILE),
+        keys().replication,
+        leader.snapshot().seq + 1,
+    )
+    .is_err());
+
+    // a restarted replica rebuilds from its copy of the log and carries on from there
+    let restarted = sequencer::spawn_replica(jtrain(&replica_dir));
+    assert_eq!(restarted.snapshot().seq, leader.snapshot().seq);
+    assert_eq!(
+        restarted.snapshot().state_hash,
+        leader.snapshot().state_hash
+    );
+
+    std::fs::remove_dir_all(&leader_dir).unwrap();
+    std::fs::remove_dir_all(&replica_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_promotion_checks_the_state_hash() {
+    let leader_dir = data_dir("promote-leader");
+    let replica_dir = data_dir("promote-replica");
+    let leader = sequencer::spawn(jtrain(&leader_dir));
+    leader.submit(set_checkpointer(0xc)).await.unwrap();
+    let replica = sequencer::spawn_replica(jtrain(&replica_dir));
+    let mut feed = RecordFeed::open(
+        &leader,
+        leader_dir.join(EVENT_LOG_STORAGE_FILE),
+        keys().replication,
+        0,
+    )
+    .unwrap();
+    catch_up(&mut feed, &leader, &replica).await;
+
+    // the leader took one more command the replica hasn't seen
+    leader.submit(set_checkpointer(0xe)).await.unwrap();
+    let expected = Some((leader.snapshot().seq, leader.snapshot().state_hash));
+    assert!(matches!(
+        replica.submit(Command::Promote { expected }).await,
+        Err(MwError::ReplicationError(_))
+    ));
+    assert_eq!(replica.snapshot().role, InstanceRole::Replica);
+
+    catch_up(&mut feed, &leader, &replica).await;
+    let promoted = replica.submit(Command::Promote { expected }).await.unwrap();
+    assert_eq!(promoted.seq, leader.snapshot().seq);
+    assert_eq!(replica.snapshot().role, InstanceRole::Leader);
+
+    // sequencing carries on from the old leader's seq
+    let applied = replica.submit(set_checkpointer(0xf)).await.unwrap();
+    assert_eq!(applied.seq, leader.snapshot().seq + 1);
+    assert!(matches!(
+        replica.submit(Command::Promote { expected: None }).await,
+        Err(MwError::ReplicationError(_))
+    ));
+
+    std::fs::remove_dir_all(&leader_dir).unwrap();
+    std::fs::remove_dir_all(&replica_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_diverged_replica_stops() {
+    let leader_dir = data_dir("diverge-leader");
+    let replica_dir = data_dir("diverge-replica");
+    let leader = sequencer::spawn(jtrain(&leader_dir));
+    leader.submit(set_checkpointer(0xc)).await.unwrap();
+    let records = events::read_records(leader_dir.join(EVENT_LOG_STORAGE_FILE)).unwrap();
+    let replica = sequencer::spawn_replica(jtrain(&replica_dir));
+
+    // out of order records are refused without touching the replica
+    assert!(matches!(
+        replica
+            .submit(Command::Replicate {
+                record: records[1].clone(),
+            })
+            .await,
+        Err(MwError::ReplicationError(_))
+    ));
+    replica
+        .submit(Command::Replicate {
+            record: records[0].clone(),
+        })
+        .await
+        .unwrap();
+
+    let mut forged = records[1].clone();
+    forged.state_hash = B256::repeat_byte(1);
+    assert!(matches!(
+        replica.submit(Command::Replicate { record: forged }).await,
+        Err(MwError::ReplicationError(_))
+    ));
+    // once diverged, even the right record and promotion are refused
+    assert!(matches!(
+        replica
+            .submit(Command::Replicate {
+                record: records[1].clone(),
+            })
+            .await,
+        Err(MwError::ReplicationError(_))
+    ));
+    assert!(matches!(
+        replica.submit(Command::Promote { expected: None }).await,
+        Err(MwError::ReplicationError(_))
+    ));
+    assert_eq!(replica.snapshot().seq, 1);
+
+    std::fs::remove_dir_all(&leader_dir).unwrap();
+    std::fs::remove_dir_all(&replica_dir).unwrap();
+}
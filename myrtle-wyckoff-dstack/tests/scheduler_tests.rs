@@ -0,0 +1,346 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use alloy::{
+    primitives::{Address, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolStruct,
+};
+use myrtle_wyckoff_dstack::{
+    artifacts::IDepositRegistry,
+    chains::{MAINNET, MAINNET_CHAIN_ID},
+    config::{Config, StorageConfig},
+    errors::MwError,
+    events::EVENT_LOG_STORAGE_FILE,
+    fakechain::FakeChain,
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    scheduler::{SchedulerConfig, SnapshotStatus, SnapshotTracker},
+    sequencer::{self, Command, SequencerHandle},
+    session::{SessionController, SessionPhase, SessionSchedule, SessionState},
+    sink::{CheckpointerSink, FileSink, GasSettings, TxPolicy},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{orderbook_manager::OrderBookManager, quantity::Qty};
+
+fn registry() -> Address {
+    Address::repeat_byte(0xd)
+}
+
+fn checkpointer() -> Address {
+    Address::repeat_byte(0xc)
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("mw-scheduler-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// a leader in the finalize phase, where `taker` can settle against the mainnet registry
+fn spawn(data_dir: &Path, taker: Address) -> SequencerHandle {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[2u8; MIN_SECRET_BYTES]).unwrap());
+    warehouse.storage = StorageConfig {
+        data_dir: data_dir.to_path_buf(),
+        host_data_dir: data_dir.to_path_buf(),
+    };
+    warehouse.checkpoint_contract = checkpointer();
+    warehouse
+        .deposit_contracts
+        .insert(MAINNET_CHAIN_ID, registry());
+    warehouse
+        .chain_deposits
+        .insert(MAINNET_CHAIN_ID, [U256::from(10), U256::from(10_000)]);
+    warehouse.inventories.insert(
+        taker,
+        Inventory::new(
+            taker,
+            Qty(U256::from(10)),
+            Qty(U256::ZERO),
+            Qty(U256::from(10_000)),
+            Qty(U256::ZERO),
+            BTreeMap::new(),
+            true,
+        ),
+    );
+    let finalizing = SessionState {
+        phase: SessionPhase::Finalize,
+        phase_started_at: chrono::Utc::now().timestamp_millis() as u64,
+    };
+    sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::resume(SessionSchedule::default(), 1, finalizing),
+    })
+}
+
+fn settle(taker: &PrivateKeySigner, nonce: u64) -> Command {
+    let order = IDepositRegistry::Order {
+        ethAmount: U256::from(1),
+        usdcAmount: U256::from(2000),
+        isBid: true,
+        nonce: U256::from(nonce),
+    };
+    let signature = taker
+        .sign_hash_sync(&order.eip712_signing_hash(&MAINNET.deposit_domain(registry())))
+        .unwrap();
+    Command::NewSettlementOrder {
+        chain: MAINNET,
+        user: taker.address(),
+        order,
+        signature,
+    }
+}
+
+#[test]
+fn test_backoff_doubles_up_to_max() {
+    let config = SchedulerConfig {
+        interval: Duration::from_secs(5),
+        max_backoff: Duration::from_secs(60),
+    };
+    assert_eq!(config.backoff(0), Duration::from_secs(5));
+    assert_eq!(config.backoff(1), Duration::from_secs(10));
+    assert_eq!(config.backoff(3), Duration::from_secs(40));
+    assert_eq!(config.backoff(4), Duration::from_secs(60));
+    assert_eq!(config.backoff(u32::MAX), Duration::from_secs(60));
+}
+
+#[test]
+fn test_gas_bump_keeps_nonce() {
+    let gas = GasSettings {
+        tx_nonce: 7,
+        gas_price: 1_000,
+    };
+    let bumped = gas.bumped(15);
+    assert_eq!(bumped.tx_nonce, 7);
+    assert!(bumped.gas_price >= 1_150);
+    // a zero gas price still goes up
+    assert!(
+        GasSettings {
+            tx_nonce: 0,
+            gas_price: 0
+        }
+        .bumped(15)
+        .gas_price
+            > 0
+    );
+}
+
+#[test]
+fn test_fresh_tracker_status() {
+    let tracker = SnapshotTracker::new();
+    assert!(tracker.latest_commitment().is_none());
+    let status: serde_json::Value = serde_json::from_str(&tracker.status().to_json()).unwrap();
+    assert!(status["last_success_at"].is_null());
+    assert!(status["last_error"].is_null());
+    assert_eq!(status["consecutive_failures"], "0");
+}
+
+#[test]
+fn test_status_json() {
+    let status = SnapshotStatus {
+        last_success_at: Some(1_000),
+        last_checkpoint_nonce: Some(U256::from(3)),
+        last_posted_seq: Some(42),
+        last_error: Some("Snapshot error: timed out".to_string()),
+        last_error_at: Some(2_000),
+        consecutive_failures: 1,
+        ..SnapshotStatus::default()
+    };
+    let json: serde_json::Value = serde_json::from_str(&status.to_json()).unwrap();
+    assert_eq!(json["last_checkpoint_nonce"], "3");
+    assert_eq!(json["last_posted_seq"], "42");
+    assert_eq!(json["last_error"], "Snapshot error: timed out");
+    assert!(json["last_tx_hash"].is_null());
+}
+
+#[tokio::test]
+async fn test_run_once_skips_unchanged_state() {
+    let directory = data_dir("unchanged");
+    let taker = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, taker.address());
+    let sink = FileSink {
+        directory: directory.join("checkpoints"),
+    };
+    let tracker = SnapshotTracker::new();
+
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    assert!(sink.path(U256::ZERO).exists());
+    assert_eq!(
+        tracker.status().last_posted_seq,
+        Some(sequencer.snapshot().seq)
+    );
+    assert!(tracker.latest_commitment().is_some());
+    // nothing sequenced since
+    assert!(!tracker.run_once(&sequencer, &sink).await.unwrap());
+    assert!(!sink.path(U256::from(1)).exists());
+
+    sequencer
+        .submit(Command::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            deposit_contract: registry(),
+            checkpoint_contract: checkpointer(),
+        })
+        .await
+        .unwrap();
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    assert!(sink.path(U256::from(1)).exists());
+    assert_eq!(tracker.status().last_checkpoint_nonce, Some(U256::from(1)));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_commitment_survives_a_restart() {
+    let directory = data_dir("restart");
+    let taker = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, taker.address());
+    let sink = FileSink {
+        directory: directory.join("checkpoints"),
+    };
+    let storage = StorageConfig {
+        data_dir: directory.clone(),
+        host_data_dir: directory.clone(),
+    };
+    let volume_key = AppKeys::derive(&[2u8; MIN_SECRET_BYTES]).unwrap().volume;
+    let tracker = SnapshotTracker::load(&storage, volume_key);
+    assert!(tracker.latest_commitment().is_none());
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    let posted = tracker.latest_commitment().unwrap();
+
+    let restarted = SnapshotTracker::load(&storage, volume_key).latest_commitment();
+    let restarted = restarted.expect("the commitment should be stored");
+    assert_eq!(restarted.root(), posted.root());
+    assert_eq!(restarted.checkpoint_nonce, posted.checkpoint_nonce);
+    assert!(restarted.proof_json(taker.address()).is_some());
+
+    // another volume key can't read it, proofs wait for the next checkpoint instead
+    let other_key = AppKeys::derive(&[3u8; MIN_SECRET_BYTES]).unwrap().volume;
+    assert!(SnapshotTracker::load(&storage, other_key)
+        .latest_commitment()
+        .is_none());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_run_once_clears_settlement_orders_once_posted() {
+    let directory = data_dir("clears");
+    let taker = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, taker.address());
+    sequencer.submit(settle(&taker, 0)).await.unwrap();
+    assert_eq!(sequencer.snapshot().settlement_orders.len(), 1);
+    // the taker pays the bid's usdc as soon as it's queued
+    assert_eq!(
+        sequencer.snapshot().inventories[&taker.address()].usdc_balance,
+        Qty(U256::from(8000))
+    );
+    let sink = FileSink {
+        directory: directory.join("checkpoints"),
+    };
+    let tracker = SnapshotTracker::new();
+
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    let file = std::fs::File::open(sink.path(U256::ZERO)).unwrap();
+    let posted: serde_json::Value = serde_json::from_reader(file).unwrap();
+    assert_eq!(posted["settlement_orders"].as_array().unwrap().len(), 1);
+    let snapshot = sequencer.snapshot();
+    assert!(snapshot.settlement_orders.is_empty());
+    // clearing them is part of what was posted, so it doesn't count as a change
+    assert_eq!(tracker.status().last_posted_seq, Some(snapshot.seq));
+    assert!(!tracker.run_once(&sequencer, &sink).await.unwrap());
+
+    // one that arrives after the checkpoint waits for the next one
+    sequencer.submit(settle(&taker, 1)).await.unwrap();
+    assert_eq!(sequencer.snapshot().settlement_orders.len(), 1);
+    assert!(tracker.run_once(&sequencer, &sink).await.unwrap());
+    assert!(sequencer.snapshot().settlement_orders.is_empty());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_run_once_keeps_settlement_orders_when_posting_fails() {
+    let directory = data_dir("fails");
+    let taker = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, taker.address());
+    sequencer.submit(settle(&taker, 0)).await.unwrap();
+    let fake = Arc::new(FakeChain::new());
+    let sink = CheckpointerSink {
+        client: fake.clone(),
+        policy: TxPolicy::default(),
+    };
+    let tracker = SnapshotTracker::new();
+
+    // no leader elected on the Checkpointer, so the checkpoint reverts
+    assert!(matches!(
+        tracker.run_once(&sequencer, &sink).await,
+        Err(MwError::SnapshotError(_))
+    ));
+    fake.set_failing(true);
+    assert!(tracker.run_once(&sequencer, &sink).await.is_err());
+    let status = tracker.status();
+    assert_eq!(status.consecutive_failures, 2);
+    assert!(status.last_error.is_some());
+    assert!(status.last_posted_seq.is_none());
+    assert!(tracker.latest_commitment().is_none());
+    assert_eq!(sequencer.snapshot().settlement_orders.len(), 1);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_settlement_orders_survive_a_restart_before_posting() {
+    let directory = data_dir("queued");
+    let taker = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, taker.address());
+    sequencer.submit(settle(&taker, 0)).await.unwrap();
+    // stored with the warehouse as well as logged
+    sequencer.submit(Command::PrepareCheckpoint).await.unwrap();
+    drop(sequencer);
+
+    // the log is replayed on restart
+    let sequencer = spawn(&directory, taker.address());
+    assert_eq!(sequencer.snapshot().settlement_orders.len(), 1);
+    drop(sequencer);
+
+    // without the log, the volume's state starts a new one and Genesis carries them
+    std::fs::remove_file(directory.join(EVENT_LOG_STORAGE_FILE)).unwrap();
+    let config = Config::from_file(
+        serde_json::from_value(serde_json::json!({
+            "rpc_url": "https://rpc.example.com",
+            "data_dir": directory,
+            "host_data_dir": directory,
+            "deposit_chains": [
+                { "chain_id": 1, "rpc_urls": ["https://a.example.com", "https://b.example.com"] },
+            ],
+            "deposit_start_blocks": { "1": 0 },
+        }))
+        .unwrap(),
+    )
+    .unwrap();
+    let keys = AppKeys::derive(&[2u8; MIN_SECRET_BYTES]).unwrap();
+    let warehouse = Warehouse::load(&config, &keys).unwrap();
+    assert_eq!(warehouse.settlement_orders.len(), 1);
+    let sequencer = sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session: SessionController::new(SessionSchedule::default(), 0),
+    });
+    assert_eq!(sequencer.snapshot().settlement_orders.len(), 1);
+
+    let sink = FileSink {
+        directory: directory.join("checkpoints"),
+    };
+    assert!(SnapshotTracker::new()
+        .run_once(&sequencer, &sink)
+        .await
+        .unwrap());
+    let file = std::fs::File::open(sink.path(U256::ZERO)).unwrap();
+    let posted: serde_json::Value = serde_json::from_reader(file).unwrap();
+    assert_eq!(posted["settlement_orders"].as_array().unwrap().len(), 1);
+    assert!(sequencer.snapshot().settlement_orders.is_empty());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
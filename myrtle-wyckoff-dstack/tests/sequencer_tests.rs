@@ -0,0 +1,254 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use alloy::{
+    primitives::{Address, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolStruct,
+};
+use myrtle_wyckoff_dstack::{
+    chains::MAINNET_CHAIN_ID,
+    config::StorageConfig,
+    errors::MwError,
+    events::Event,
+    jtrain::Jtrain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    orderhere::{CancelAll, Order},
+    sequencer::{self, Command, SequencerHandle, SnapshotChanges, StateSnapshot},
+    session::{SessionController, SessionSchedule},
+    warehouse::{Inventory, Warehouse},
+};
+use optimized_lob::{orderbook_manager::OrderBookManager, quantity::Qty};
+
+fn data_dir(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("mw-sequencer-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+// a session that's been trading for a minute, with `user` holding 1 eth and 1000 usdc
+fn spawn(data_dir: &Path, user: Address) -> SequencerHandle {
+    let mut warehouse = Warehouse::new(&AppKeys::derive(&[6u8; MIN_SECRET_BYTES]).unwrap());
+    warehouse.storage = StorageConfig {
+        data_dir: data_dir.to_path_buf(),
+        host_data_dir: data_dir.to_path_buf(),
+    };
+    warehouse.inventories.insert(
+        user,
+        Inventory::new(
+            user,
+            Qty(U256::from(1)),
+            Qty(U256::ZERO),
+            Qty(U256::from(1000)),
+            Qty(U256::ZERO),
+            BTreeMap::new(),
+            false,
+        ),
+    );
+    let schedule = SessionSchedule::default();
+    let session = SessionController::new(schedule, now() - schedule.pre_session_ms - 60_000);
+    sequencer::spawn(Jtrain {
+        warehouse,
+        orderbook_manager: OrderBookManager::new(),
+        session,
+    })
+}
+
+fn new_order(signer: &PrivateKeySigner, price: u64, qty: u64) -> Command {
+    let order = Order {
+        price: U256::from(price),
+        qty: U256::from(qty),
+        is_bid: true,
+        timestamp: now(),
+    };
+    let keys = AppKeys::derive(&[6u8; MIN_SECRET_BYTES]).unwrap();
+    let domain = Warehouse::new(&keys).domains.dstack;
+    let signature = signer
+        .sign_hash_sync(&order.eip712_signing_hash(&domain))
+        .unwrap();
+    Command::NewOrder {
+        user: signer.address(),
+        order,
+        signature,
+    }
+}
+
+fn shared(before: &StateSnapshot, after: &StateSnapshot) -> SnapshotChanges {
+    SnapshotChanges {
+        inventories: !Arc::ptr_eq(&before.inventories, &after.inventories),
+        orders: !Arc::ptr_eq(&before.orders, &after.orders),
+        settlement_orders: !Arc::ptr_eq(&before.settlement_orders, &after.settlement_orders),
+        pnl: !Arc::ptr_eq(&before.pnl, &after.pnl),
+        session_results: !Arc::ptr_eq(&before.session_results, &after.session_results),
+        public_pnl: !Arc::ptr_eq(&before.public_pnl, &after.public_pnl),
+    }
+}
+
+#[tokio::test]
+async fn test_snapshots_only_copy_what_changed() {
+    let directory = data_dir("shared");
+    let user = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, user.address());
+    let genesis = sequencer.snapshot();
+    assert_eq!(genesis.seq, 1);
+
+    sequencer
+        .submit(Command::SetContractAddresses {
+            chain_id: MAINNET_CHAIN_ID,
+            deposit_contract: Address::repeat_byte(0xd),
+            checkpoint_contract: Address::repeat_byte(0xc),
+        })
+        .await
+        .unwrap();
+    let addresses_set = sequencer.snapshot();
+    assert_eq!(addresses_set.seq, 2);
+    assert_eq!(addresses_set.checkpoint_contract, Address::repeat_byte(0xc));
+    assert_eq!(
+        addresses_set.deposit_contract(MAINNET_CHAIN_ID),
+        Address::repeat_byte(0xd)
+    );
+    assert_eq!(shared(&genesis, &addresses_set), SnapshotChanges::default());
+
+    let applied = sequencer.submit(new_order(&user, 500, 2)).await.unwrap();
+    assert_eq!(applied.seq, 3);
+    let placed = sequencer.snapshot();
+    assert_eq!(
+        shared(&addresses_set, &placed),
+        SnapshotChanges {
+            inventories: true,
+            orders: true,
+            pnl: true,
+            ..SnapshotChanges::default()
+        }
+    );
+    assert_eq!(placed.orders[&user.address()].len(), 1);
+    assert_eq!(
+        placed.inventories[&user.address()].usdc_liabilities.0,
+        U256::from(1000)
+    );
+    // readers holding the old snapshot still see the state it was taken at
+    assert!(addresses_set.orders.is_empty());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_rejected_events_arent_logged() {
+    let directory = data_dir("rejected");
+    let user = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, user.address());
+    let before = sequencer.snapshot();
+
+    // more usdc than the user has
+    assert!(matches!(
+        sequencer.submit(new_order(&user, 600, 2)).await,
+        Err(MwError::InsufficientBalance { .. })
+    ));
+    // nothing changed, so there's nothing to replay
+    let after = sequencer.snapshot();
+    assert_eq!(after.seq, before.seq);
+    assert_eq!(after.state_hash, before.state_hash);
+    assert_eq!(
+        after.inventories[&user.address()].to_bytes(),
+        before.inventories[&user.address()].to_bytes()
+    );
+
+    // an order signed by someone else is refused before it's sequenced
+    let Command::NewOrder {
+        order, signature, ..
+    } = new_order(&PrivateKeySigner::random(), 500, 1)
+    else {
+        unreachable!()
+    };
+    assert!(matches!(
+        sequencer
+            .submit(Command::NewOrder {
+                user: user.address(),
+                order,
+                signature,
+            })
+            .await,
+        Err(MwError::InvalidSignature)
+    ));
+    assert_eq!(sequencer.snapshot().seq, after.seq);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_restart_keeps_resting_orders() {
+    let directory = data_dir("restart");
+    let user = PrivateKeySigner::random();
+    let sequencer = spawn(&directory, user.address());
+    sequencer.submit(new_order(&user, 500, 2)).await.unwrap();
+    let before = sequencer.snapshot();
+    drop(sequencer);
+
+    // the log is replayed, the order still rests and still holds its usdc
+    let sequencer = spawn(&directory, user.address());
+    let after = sequencer.snapshot();
+    assert_eq!(after.seq, before.seq);
+    assert_eq!(after.state_hash, before.state_hash);
+    assert_eq!(after.orders[&user.address()].len(), 1);
+    assert_eq!(
+        after.inventories[&user.address()].usdc_liabilities,
+        Qty(U256::from(1000))
+    );
+
+    // cancelling it frees the whole balance again
+    let cancel = CancelAll {
+        scope_book: false,
+        book_id: 0,
+        side: 0,
+        timestamp: now(),
+    };
+    let keys = AppKeys::derive(&[6u8; MIN_SECRET_BYTES]).unwrap();
+    let domain = Warehouse::new(&keys).domains.dstack;
+    let signature = user
+        .sign_hash_sync(&cancel.eip712_signing_hash(&domain))
+        .unwrap();
+    sequencer
+        .submit(Command::CancelAll {
+            user: user.address(),
+            cancel,
+            signature,
+        })
+        .await
+        .unwrap();
+    sequencer.submit(new_order(&user, 500, 2)).await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_snapshot_changes_of_events() {
+    assert_eq!(
+        SnapshotChanges::of(&Event::ClearSettlementOrders { count: 1 }),
+        SnapshotChanges {
+            settlement_orders: true,
+            ..SnapshotChanges::default()
+        }
+    );
+    assert_eq!(
+        SnapshotChanges::of(&Event::SetPnlVisibility {
+            user: Address::ZERO,
+            is_public: true,
+        }),
+        SnapshotChanges {
+            public_pnl: true,
+            ..SnapshotChanges::default()
+        }
+    );
+    assert!(
+        SnapshotChanges::of(&Event::CloseSession {
+            mark_price: U256::from(1),
+        })
+        .orders
+    );
+}
@@ -0,0 +1,131 @@
+use alloy::{
+    primitives::Address,
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolStruct,
+};
+use myrtle_wyckoff_dstack::{
+    config::StorageConfig,
+    domains::Domains,
+    errors::MwError,
+    session::{
+        self, SessionAction, SessionController, SessionPhase, SessionSchedule, SessionState,
+    },
+    structs::UserRequest,
+};
+
+const MINUTE: u64 = 60 * 1000;
+
+#[test]
+fn test_session_schedule() {
+    let session = SessionController::new(SessionSchedule::default(), 0);
+
+    assert_eq!(session.phase_at(0).0, SessionPhase::PreSession);
+    assert_eq!(session.next_transition_at(0), Some(30 * MINUTE));
+    assert_eq!(session.phase_at(30 * MINUTE).0, SessionPhase::Trading);
+    assert_eq!(session.phase_at(80 * MINUTE).0, SessionPhase::Closing);
+    assert_eq!(session.phase_at(90 * MINUTE).0, SessionPhase::Finalize);
+    // finalize waits on the settler
+    assert_eq!(session.phase_at(500 * MINUTE).0, SessionPhase::Finalize);
+    assert_eq!(session.next_transition_at(500 * MINUTE), None);
+}
+
+#[test]
+fn test_route_restrictions() {
+    let session = SessionController::new(SessionSchedule::default(), 0);
+
+    assert!(session.ensure_allowed(SessionAction::Deposit, 0).is_ok());
+    assert!(matches!(
+        session.ensure_allowed(SessionAction::PlaceOrder, 0),
+        Err(MwError::ActionNotAllowed { .. })
+    ));
+    assert!(session
+        .ensure_allowed(SessionAction::PlaceOrder, 31 * MINUTE)
+        .is_ok());
+    assert!(session
+        .ensure_allowed(SessionAction::Withdraw, 31 * MINUTE)
+        .is_err());
+    assert!(session
+        .ensure_allowed(SessionAction::CancelOrder, 85 * MINUTE)
+        .is_ok());
+    assert!(session
+        .ensure_allowed(SessionAction::PlaceOrder, 85 * MINUTE)
+        .is_err());
+}
+
+#[test]
+fn test_finalize_starts_next_session() {
+    let mut session = SessionController::new(SessionSchedule::default(), 0);
+
+    assert!(session.finalize(31 * MINUTE).is_err());
+    assert_eq!(session.finalize(95 * MINUTE).unwrap(), 2);
+    assert_eq!(session.phase_at(95 * MINUTE).0, SessionPhase::PreSession);
+    assert_eq!(session.next_transition_at(95 * MINUTE), Some(125 * MINUTE));
+}
+
+#[test]
+fn test_session_resumes_from_stored_state() {
+    let mut session = SessionController::new(SessionSchedule::default(), 0);
+    session.finalize(90 * MINUTE).unwrap();
+    let state = session.state();
+    assert_eq!(
+        state,
+        SessionState {
+            phase: SessionPhase::PreSession,
+            phase_started_at: 90 * MINUTE,
+        }
+    );
+
+    let data_dir = std::env::temp_dir().join(format!("mw-session-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    let storage = StorageConfig {
+        data_dir: data_dir.clone(),
+        host_data_dir: data_dir.clone(),
+    };
+    assert_eq!(SessionState::load(&storage).unwrap(), None);
+    state.store(&storage).unwrap();
+    let stored = SessionState::load(&storage).unwrap().unwrap();
+    std::fs::remove_dir_all(&data_dir).unwrap();
+
+    // a restart during trading picks the session back up where the schedule has it
+    let resumed = SessionController::resume(SessionSchedule::default(), 2, stored);
+    assert_eq!(resumed.session_id, 2);
+    assert_eq!(
+        resumed.phase_at(130 * MINUTE),
+        (SessionPhase::Trading, 120 * MINUTE)
+    );
+}
+
+#[test]
+fn test_only_the_operator_can_finalize() {
+    // the operator's key lives outside the enclave
+    let operator = PrivateKeySigner::random();
+    let request = UserRequest {
+        user: operator.address(),
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        request_type: "finalize-session".to_string(),
+    };
+    let domain = Domains::default().dstack;
+    let signature = operator
+        .sign_hash_sync(&request.eip712_signing_hash(&domain))
+        .unwrap();
+    request
+        .validate_signature(&domain, signature, request.user)
+        .unwrap();
+    session::authorize_operator(Some(operator.address()), request.user).unwrap();
+
+    for (configured, signer) in [
+        (
+            Some(operator.address()),
+            PrivateKeySigner::random().address(),
+        ),
+        (Some(Address::ZERO), Address::ZERO),
+        // no operator configured
+        (None, operator.address()),
+    ] {
+        assert!(matches!(
+            session::authorize_operator(configured, signer),
+            Err(MwError::UnauthorizedAccess)
+        ));
+    }
+}
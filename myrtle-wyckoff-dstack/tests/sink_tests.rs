@@ -0,0 +1,145 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    blob,
+    chains::{Chain, Chains, BASE, BASE_CHAIN_ID},
+    config::StorageConfig,
+    errors::MwError,
+    fakechain::FakeChain,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    sink::{CheckpointSink, FileSink, Sink, SinkConfig, TxPolicy},
+    snapshotter::{self, CheckpointDraft},
+    warehouse::Warehouse,
+};
+
+fn temp_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("mw-sink-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    directory
+}
+
+#[test]
+fn test_sink_config_parsing() {
+    assert_eq!(
+        SinkConfig::from_str("checkpointer").unwrap(),
+        SinkConfig::Checkpointer
+    );
+    assert_eq!(
+        SinkConfig::from_str("calldata:0x0101010101010101010101010101010101010101").unwrap(),
+        SinkConfig::Calldata {
+            chain_id: None,
+            to: Address::repeat_byte(1)
+        }
+    );
+    assert_eq!(
+        SinkConfig::from_str("calldata:8453:0x0101010101010101010101010101010101010101").unwrap(),
+        SinkConfig::Calldata {
+            chain_id: Some(BASE_CHAIN_ID),
+            to: Address::repeat_byte(1)
+        }
+    );
+    assert_eq!(
+        SinkConfig::from_str("file:/tmp/checkpoints").unwrap(),
+        SinkConfig::File(PathBuf::from("/tmp/checkpoints"))
+    );
+    for invalid in [
+        "",
+        "suave",
+        "calldata:nope",
+        "calldata:base:0x0101010101010101010101010101010101010101",
+        "blob:0x0101010101010101010101010101010101010101",
+        "file:",
+    ] {
+        assert!(matches!(
+            SinkConfig::from_str(invalid),
+            Err(MwError::InvalidSinkConfig(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_file_sink_round_trip() {
+    let warehouse = Warehouse::new(&AppKeys::derive(&[3u8; MIN_SECRET_BYTES]).unwrap());
+    let sink = FileSink {
+        directory: temp_directory("round-trip"),
+    };
+    assert!(sink.is_ready(Address::ZERO));
+
+    for expected_nonce in 0..2u64 {
+        let nonce = sink.next_nonce(Address::ZERO).await.unwrap();
+        assert_eq!(nonce, U256::from(expected_nonce));
+        let signed = snapshotter::sign(CheckpointDraft::from_warehouse(&warehouse), nonce)
+            .await
+            .unwrap();
+        assert_eq!(sink.publish(&signed).await.unwrap(), None);
+
+        let file = std::fs::File::open(sink.path(nonce)).unwrap();
+        let posted: serde_json::Value = serde_json::from_reader(file).unwrap();
+        assert_eq!(posted["nonce"], nonce.to_string());
+        assert_ne!(posted["leader_signature"], posted["signature"]);
+        let inventory_state =
+            alloy::hex::decode(posted["inventory_state"].as_str().unwrap()).unwrap();
+        let decoded = blob::decode(&warehouse.encryption_key, &inventory_state).unwrap();
+        assert_eq!(decoded.checkpoint_nonce, nonce);
+    }
+    assert_eq!(sink.next_nonce(Address::ZERO).await.unwrap(), U256::from(2));
+    std::fs::remove_dir_all(&sink.directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_calldata_sink_never_reuses_a_nonce() {
+    let warehouse = Warehouse::new(&AppKeys::derive(&[3u8; MIN_SECRET_BYTES]).unwrap());
+    let data_dir = temp_directory("calldata");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    let storage = StorageConfig {
+        data_dir: data_dir.clone(),
+        host_data_dir: data_dir.clone(),
+    };
+    let suave = Arc::new(FakeChain::new());
+    let base = Arc::new(FakeChain::new());
+    let chains = Chains::new(vec![Chain::new(BASE, vec![base.clone()])]);
+    let config = SinkConfig::Calldata {
+        chain_id: Some(BASE_CHAIN_ID),
+        to: Address::repeat_byte(1),
+    };
+    let sink = Sink::new(
+        config,
+        suave.clone(),
+        &chains,
+        TxPolicy::default(),
+        &storage,
+    )
+    .unwrap();
+    let sign = |nonce: U256| snapshotter::sign(CheckpointDraft::from_warehouse(&warehouse), nonce);
+
+    let nonce = sink.next_nonce(Address::ZERO).await.unwrap();
+    assert_eq!(nonce, U256::ZERO);
+    assert!(sink
+        .publish(&sign(nonce).await.unwrap())
+        .await
+        .unwrap()
+        .is_some());
+    // posted on base, not suave
+    assert_eq!(base.sent().len(), 1);
+    assert!(suave.sent().is_empty());
+
+    // a post that fails gives up its nonce rather than signing it again
+    base.set_failing(true);
+    let nonce = sink.next_nonce(Address::ZERO).await.unwrap();
+    assert_eq!(nonce, U256::from(1));
+    assert!(sink.publish(&sign(nonce).await.unwrap()).await.is_err());
+    base.set_failing(false);
+    assert_eq!(sink.next_nonce(Address::ZERO).await.unwrap(), U256::from(2));
+
+    // a chain that isn't configured has no client to post through
+    let unknown = SinkConfig::Calldata {
+        chain_id: Some(10),
+        to: Address::repeat_byte(1),
+    };
+    assert!(matches!(
+        Sink::new(unknown, suave, &chains, TxPolicy::default(), &storage),
+        Err(MwError::UnknownChain { chain_id: 10 })
+    ));
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
@@ -0,0 +1,241 @@
+use alloy::{
+    primitives::{keccak256, Address, B256, U256},
+    rpc::types::EIP1186AccountProofResponse,
+    sol_types::SolValue,
+};
+use alloy_rlp::RlpEncodable;
+use alloy_trie::{EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use myrtle_wyckoff_dstack::{
+    errors::MwError,
+    fakechain::prove,
+    gulper::IndexedDeposit,
+    verifier::{self, TrustedBlock, DEPOSIT_REGISTRY_SLOT},
+};
+
+#[derive(RlpEncodable)]
+struct TrieAccount {
+    nonce: u64,
+    balance: U256,
+    storage_root: B256,
+    code_hash: B256,
+}
+
+fn registry() -> Address {
+    Address::repeat_byte(0xd)
+}
+
+fn deposit(user: u8, index: u32, eth: u64, usdc: u64) -> IndexedDeposit {
+    IndexedDeposit {
+        user: Address::repeat_byte(user),
+        index,
+        amounts: [U256::from(eth), U256::from(usdc)],
+    }
+}
+
+// a registry holding `registered` as each user's deposits, proven at a block with the returned
+// state root for the slots `deposits` need
+fn registry_proof(
+    registered: &[IndexedDeposit],
+    deposits: &[IndexedDeposit],
+) -> (TrustedBlock, EIP1186AccountProofResponse) {
+    let mut slots: Vec<(B256, U256)> = Vec::new();
+    for deposit in registered {
+        let count = verifier::deposit_count_slot(deposit.user);
+        match slots.iter_mut().find(|(key, _)| *key == count) {
+            Some((_, value)) => *value = (*value).max(U256::from(deposit.index + 1)),
+            None => slots.push((count, U256::from(deposit.index + 1))),
+        }
+        let [eth_slot, usdc_slot] = verifier::deposit_amount_slots(deposit.user, deposit.index);
+        slots.push((eth_slot, deposit.amounts[0]));
+        slots.push((usdc_slot, deposit.amounts[1]));
+    }
+    let keys = verifier::storage_keys(deposits);
+    let (storage_root, storage_proofs) = prove(
+        slots
+            .iter()
+            .filter(|(_, value)| !value.is_zero())
+            .map(|(key, value)| (*key, alloy_rlp::encode(value)))
+            .collect(),
+        &keys,
+    );
+    let account = TrieAccount {
+        nonce: 1,
+        balance: U256::ZERO,
+        storage_root,
+        code_hash: KECCAK_EMPTY,
+    };
+    let other = TrieAccount {
+        nonce: 7,
+        balance: U256::from(1),
+        storage_root: EMPTY_ROOT_HASH,
+        code_hash: KECCAK_EMPTY,
+    };
+    let (state_root, account_proofs) = prove(
+        vec![
+            (registry(), alloy_rlp::encode(&account)),
+            (Address::repeat_byte(0xe), alloy_rlp::encode(&other)),
+        ],
+        &[registry()],
+    );
+    let value = |key: &B256| {
+        slots
+            .iter()
+            .find(|(slot, _)| slot == key)
+            .map_or(U256::ZERO, |(_, value)| *value)
+    };
+    let proof = serde_json::from_value(serde_json::json!({
+        "address": registry(),
+        "balance": "0x0",
+        "codeHash": KECCAK_EMPTY,
+        "nonce": "0x1",
+        "storageHash": storage_root,
+        "accountProof": account_proofs[0],
+        "storageProof": keys
+            .iter()
+            .zip(storage_proofs)
+            .map(|(key, proof)| serde_json::json!({
+                "key": key,
+                "value": value(key),
+                "proof": proof,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+    .unwrap();
+    let block = TrustedBlock {
+        number: 100,
+        hash: B256::repeat_byte(0xb),
+        state_root,
+    };
+    (block, proof)
+}
+
+fn verify(
+    block: &TrustedBlock,
+    proof: &EIP1186AccountProofResponse,
+    deposits: &[IndexedDeposit],
+) -> Result<(), MwError> {
+    let proven = verifier::verify_proof_response(block, registry(), proof)?;
+    verifier::check_deposits(&proven, deposits)
+}
+
+#[test]
+fn test_slots_match_solidity_layout() {
+    let user = Address::repeat_byte(1);
+    // keccak256(abi.encode(user, 2)) for the array length, the array data starts at its hash
+    let count_slot = keccak256((user, U256::from(DEPOSIT_REGISTRY_SLOT)).abi_encode());
+    assert_eq!(verifier::deposit_count_slot(user), count_slot);
+    let data = U256::from_be_bytes(keccak256(count_slot).0);
+    assert_eq!(
+        verifier::deposit_amount_slots(user, 3),
+        [
+            B256::from(data + U256::from(6)),
+            B256::from(data + U256::from(7))
+        ]
+    );
+    // the count slot is shared by every deposit of a user
+    assert_eq!(
+        verifier::storage_keys(&[deposit(1, 0, 1, 1), deposit(1, 1, 2, 2)]).len(),
+        5
+    );
+}
+
+#[test]
+fn test_block_needs_a_quorum() {
+    let a = (B256::repeat_byte(1), B256::repeat_byte(2));
+    let b = (B256::repeat_byte(3), B256::repeat_byte(4));
+    let block = verifier::agree_on_block(100, &[Some(a), Some(b), Some(a)], 2).unwrap();
+    assert_eq!(block.hash, a.0);
+    assert_eq!(block.state_root, a.1);
+    // endpoints that didn't answer don't count towards the quorum
+    assert!(matches!(
+        verifier::agree_on_block(100, &[Some(a), None, Some(b)], 2),
+        Err(MwError::DepositVerificationError(_))
+    ));
+    assert!(matches!(
+        verifier::agree_on_block(100, &[None, None, None], 2),
+        Err(MwError::DepositVerificationError(_))
+    ));
+}
+
+#[test]
+fn test_verifies_registered_deposits() {
+    let registered = vec![
+        deposit(1, 0, 1, 100),
+        deposit(1, 1, 2, 0),
+        deposit(2, 0, 0, 50),
+    ];
+    let (block, proof) = registry_proof(&registered, &registered);
+    verify(&block, &proof, &registered).unwrap();
+    // a subset is fine too
+    let (block, proof) = registry_proof(&registered, &registered[1..2]);
+    verify(&block, &proof, &registered[1..2]).unwrap();
+}
+
+#[test]
+fn test_rejects_deposits_not_in_the_registry() {
+    let registered = vec![deposit(1, 0, 1, 100)];
+
+    // amounts the RPC made up
+    let inflated = vec![deposit(1, 0, 1000, 100)];
+    let (block, proof) = registry_proof(&registered, &inflated);
+    assert!(matches!(
+        verify(&block, &proof, &inflated),
+        Err(MwError::DepositVerificationError(_))
+    ));
+
+    // a deposit past the user's count
+    let missing = vec![deposit(1, 1, 0, 0)];
+    let (block, proof) = registry_proof(&registered, &missing);
+    assert!(matches!(
+        verify(&block, &proof, &missing),
+        Err(MwError::DepositVerificationError(_))
+    ));
+
+    // a user who never deposited
+    let stranger = vec![deposit(9, 0, 0, 0)];
+    let (block, proof) = registry_proof(&registered, &stranger);
+    assert!(matches!(
+        verify(&block, &proof, &stranger),
+        Err(MwError::DepositVerificationError(_))
+    ));
+}
+
+#[test]
+fn test_rejects_tampered_proofs() {
+    let registered = vec![deposit(1, 0, 1, 100)];
+    let (block, proof) = registry_proof(&registered, &registered);
+
+    // state root the endpoints didn't agree on
+    let other_block = TrustedBlock {
+        state_root: B256::repeat_byte(0xf),
+        ..block.clone()
+    };
+    assert!(matches!(
+        verify(&other_block, &proof, &registered),
+        Err(MwError::DepositVerificationError(_))
+    ));
+
+    // a slot value changed to match a made up deposit
+    let mut tampered = proof.clone();
+    tampered.storage_proof[1].value = U256::from(1000);
+    assert!(matches!(
+        verify(&block, &tampered, &[deposit(1, 0, 1000, 100)]),
+        Err(MwError::DepositVerificationError(_))
+    ));
+
+    // storage root swapped for another account's
+    let mut tampered = proof.clone();
+    tampered.storage_hash = EMPTY_ROOT_HASH;
+    assert!(matches!(
+        verify(&block, &tampered, &registered),
+        Err(MwError::DepositVerificationError(_))
+    ));
+
+    // proof for another contract
+    let mut tampered = proof;
+    tampered.address = Address::repeat_byte(0xe);
+    assert!(matches!(
+        verify(&block, &tampered, &registered),
+        Err(MwError::DepositVerificationError(_))
+    ));
+}
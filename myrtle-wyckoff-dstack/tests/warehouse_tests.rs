@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use alloy::primitives::{Address, U256};
+use myrtle_wyckoff_dstack::{
+    blob::{self, HEADER_BYTES, RECORD_BYTES},
+    chains::{BASE_CHAIN_ID, MAINNET_CHAIN_ID},
+    config::Config,
+    errors::MwError,
+    keys::{AppKeys, MIN_SECRET_BYTES},
+    warehouse::{self, Inventory, Warehouse, LEGACY_INVENTORY_BYTES},
+};
+use optimized_lob::quantity::Qty;
+
+fn warehouse(key: u8) -> Warehouse {
+    Warehouse::new(&AppKeys::derive(&[key; MIN_SECRET_BYTES]).unwrap())
+}
+
+fn inventory(byte: u8) -> Inventory {
+    Inventory::new(
+        Address::repeat_byte(byte),
+        Qty(U256::from(1) << 200),
+        Qty(U256::from(2)),
+        Qty(U256::from(3_000_000)),
+        Qty(U256::from(4)),
+        BTreeMap::from([(MAINNET_CHAIN_ID, 7), (BASE_CHAIN_ID, byte as u32)]),
+        byte % 2 == 0,
+    )
+}
+
+#[test]
+fn test_inventory_bytes_round_trip() {
+    let original = inventory(2);
+    let decoded = Inventory::from_bytes(&original.to_bytes()).unwrap();
+    assert_eq!(decoded.to_bytes(), original.to_bytes());
+    assert!(Inventory::from_bytes(&original.to_bytes()[1..]).is_err());
+    assert_eq!(decoded.deposit_nonce(MAINNET_CHAIN_ID), 7);
+    assert_eq!(decoded.deposit_nonce(BASE_CHAIN_ID), 2);
+}
+
+#[test]
+fn test_legacy_inventory_is_mainnet() {
+    // 4 quantities, then the mainnet deposit nonce, then is_taker
+    let mut bytes = vec![0u8; LEGACY_INVENTORY_BYTES];
+    bytes[0..20].copy_from_slice(&Address::repeat_byte(5).0 .0);
+    bytes[148..152].copy_from_slice(&3u32.to_le_bytes());
+    bytes[152] = 1;
+    let inventory = Inventory::from_legacy_bytes(&bytes).unwrap();
+    assert_eq!(inventory.address, Address::repeat_byte(5));
+    assert_eq!(inventory.deposit_nonce(MAINNET_CHAIN_ID), 3);
+    assert_eq!(inventory.deposit_nonce(BASE_CHAIN_ID), 0);
+    assert!(inventory.is_taker);
+    assert!(Inventory::from_bytes(&bytes).is_err());
+
+    let json = serde_json::json!({
+        "address": Address::repeat_byte(5).to_string(),
+        "eth_balance": "1",
+        "eth_liabilities": "0",
+        "usdc_balance": "2",
+        "usdc_liabilities": "0",
+        "deposit_nonce": "3",
+        "is_taker": "true",
+    });
+    let inventory = Inventory::from_json(json.to_string());
+    assert_eq!(inventory.deposit_nonce(MAINNET_CHAIN_ID), 3);
+    assert_eq!(
+        Inventory::from_json(inventory.to_json()).to_bytes(),
+        inventory.to_bytes()
+    );
+}
+
+#[test]
+fn test_encrypted_inventory_round_trip() {
+    let mut warehouse = warehouse(1);
+    for byte in 1..=3 {
+        warehouse
+            .inventories
+            .insert(Address::repeat_byte(byte), inventory(byte));
+    }
+    let encrypted = warehouse.get_encrypted_inventory(U256::from(9)).unwrap();
+    assert_eq!(encrypted.len(), HEADER_BYTES + 3 * RECORD_BYTES);
+
+    let decrypted = warehouse.decrypt_inventory(&encrypted).unwrap();
+    assert_eq!(decrypted.checkpoint_nonce, U256::from(9));
+    // sorted by address
+    let addresses: Vec<Address> = decrypted
+        .inventories
+        .iter()
+        .map(|inventory| inventory.address)
+        .collect();
+    assert_eq!(
+        addresses,
+        vec![
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3)
+        ]
+    );
+    for inventory in decrypted.inventories.iter() {
+        assert_eq!(
+            inventory.to_bytes(),
+            warehouse.inventories[&inventory.address].to_bytes()
+        );
+    }
+
+    // a container with a different key can't read it
+    assert!(self::warehouse(2).decrypt_inventory(&encrypted).is_err());
+}
+
+#[test]
+fn test_decrypts_blobs_from_before_the_key_split() {
+    let keys = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+    let encrypted = blob::encode(&keys.legacy_checkpoint, U256::from(4), &[inventory(1)]).unwrap();
+    let decrypted = warehouse(1).decrypt_inventory(&encrypted).unwrap();
+    assert_eq!(decrypted.checkpoint_nonce, U256::from(4));
+    assert_eq!(decrypted.inventories[0].to_bytes(), inventory(1).to_bytes());
+    assert!(warehouse(2).decrypt_inventory(&encrypted).is_err());
+}
+
+#[test]
+fn test_load_refuses_state_it_cannot_read() {
+    let data_dir = std::env::temp_dir().join(format!("mw-warehouse-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    let config = Config::from_file(
+        serde_json::from_value(serde_json::json!({
+            "rpc_url": "https://rpc.example.com",
+            "data_dir": data_dir,
+            "host_data_dir": data_dir,
+            "deposit_chains": [
+                { "chain_id": 1, "rpc_urls": ["https://a.example.com", "https://b.example.com"] },
+            ],
+            "deposit_start_blocks": { "1": 0 },
+        }))
+        .unwrap(),
+    )
+    .unwrap();
+    let keys = AppKeys::derive(&[1u8; MIN_SECRET_BYTES]).unwrap();
+
+    // an empty volume starts fresh
+    let mut warehouse = Warehouse::load(&config, &keys).unwrap();
+    assert!(warehouse.inventories.is_empty());
+    warehouse
+        .inventories
+        .insert(Address::repeat_byte(1), inventory(1));
+    warehouse.checkpoint_contract = Address::repeat_byte(0xc);
+    warehouse.credit_chain_deposits(BASE_CHAIN_ID, [U256::from(5), U256::from(50)]);
+    // a session in progress, with a finished one before it
+    warehouse.finalize_session_pnl(2, U256::from(1000));
+    warehouse.pnl.record_fill(
+        Address::repeat_byte(1),
+        true,
+        U256::from(2),
+        U256::from(3000),
+    );
+    warehouse.store().unwrap();
+
+    let loaded = Warehouse::load(&config, &keys).unwrap();
+    assert_eq!(
+        loaded.inventories[&Address::repeat_byte(1)].to_bytes(),
+        inventory(1).to_bytes()
+    );
+    assert_eq!(loaded.checkpoint_contract, Address::repeat_byte(0xc));
+    assert_eq!(loaded.chain_deposits, warehouse.chain_deposits);
+    assert_eq!(loaded.pnl.session_id, 2);
+    assert_eq!(
+        loaded.pnl.accounts[&Address::repeat_byte(1)],
+        warehouse.pnl.accounts[&Address::repeat_byte(1)]
+    );
+    assert!(loaded.session_results.contains_key(&1));
+
+    // sealed under other keys
+    let other_keys = AppKeys::derive(&[2u8; MIN_SECRET_BYTES]).unwrap();
+    assert!(Warehouse::load(&config, &other_keys).is_err());
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[test]
+fn test_settlements_only_pull_what_a_chain_holds() {
+    let mut warehouse = warehouse(1);
+    warehouse.credit_chain_deposits(MAINNET_CHAIN_ID, [U256::from(1), U256::from(5000)]);
+    warehouse.credit_chain_deposits(BASE_CHAIN_ID, [U256::from(5), U256::ZERO]);
+
+    // the 6 eth deposited in total can't all come out of mainnet
+    assert!(matches!(
+        warehouse.debit_chain_deposits(MAINNET_CHAIN_ID, [U256::from(2), U256::ZERO]),
+        Err(MwError::InsufficientChainDeposits { chain_id: MAINNET_CHAIN_ID, token })
+            if token == "ETH"
+    ));
+    assert!(matches!(
+        warehouse.check_chain_deposits(BASE_CHAIN_ID, [U256::ZERO, U256::from(1)]),
+        Err(MwError::InsufficientChainDeposits { chain_id: BASE_CHAIN_ID, token })
+            if token == "USDC"
+    ));
+    assert_eq!(
+        warehouse.chain_deposits(MAINNET_CHAIN_ID),
+        [U256::from(1), U256::from(5000)]
+    );
+
+    warehouse
+        .debit_chain_deposits(BASE_CHAIN_ID, [U256::from(2), U256::ZERO])
+        .unwrap();
+    assert_eq!(
+        warehouse.chain_deposits(BASE_CHAIN_ID),
+        [U256::from(3), U256::ZERO]
+    );
+}
+
+#[test]
+fn test_settlements_debit_the_taker() {
+    let mut warehouse = warehouse(1);
+    let taker = Address::repeat_byte(1);
+    warehouse.inventories.insert(taker, inventory(1));
+
+    // 3_000_000 usdc with 4 locked behind orders
+    assert!(matches!(
+        warehouse.debit_taker(taker, [U256::ZERO, U256::from(2_999_997)]),
+        Err(MwError::InsufficientBalance { token }) if token == "USDC"
+    ));
+    assert!(matches!(
+        warehouse.debit_taker(Address::repeat_byte(2), [U256::from(1), U256::ZERO]),
+        Err(MwError::InsufficientBalance { token }) if token == "ETH"
+    ));
+    assert_eq!(
+        warehouse.inventories[&taker].to_bytes(),
+        inventory(1).to_bytes()
+    );
+
+    warehouse
+        .debit_taker(taker, [U256::from(1), U256::from(2_999_996)])
+        .unwrap();
+    let inventory = &warehouse.inventories[&taker];
+    assert_eq!(
+        inventory.eth_balance,
+        Qty((U256::from(1) << 200) - U256::from(1))
+    );
+    assert_eq!(inventory.usdc_balance, Qty(U256::from(4)));
+    assert_eq!(inventory.net_usdc(), Qty(U256::ZERO));
+}
+
+#[test]
+fn test_untracked_deposits_are_on_mainnet() {
+    let mut warehouse = warehouse(1);
+    warehouse
+        .inventories
+        .insert(Address::repeat_byte(1), inventory(1));
+    warehouse
+        .inventories
+        .insert(Address::repeat_byte(2), inventory(2));
+    let deposits = warehouse::mainnet_deposits(&warehouse.inventories);
+    assert_eq!(
+        deposits,
+        BTreeMap::from([(
+            MAINNET_CHAIN_ID,
+            [U256::from(1) << 201, U256::from(6_000_000)]
+        )])
+    );
+}
@@ -14,6 +14,7 @@ interface IDepositRegistry {
     function USDC() external view returns (address);
     function WETH() external view returns (address);
     function admin() external view returns (address);
+    function leader() external view returns (address);
     function deposit(
         address user,
         uint256 ethAmount,
@@ -34,8 +35,10 @@ interface IDepositRegistry {
     ) external view returns (bytes4);
     function pull_settlement_funds(
         Order calldata order,
-        bytes calldata signature
+        bytes calldata signature,
+        bytes calldata leaderSignature
     ) external;
     function set_admin(address newAdmin) external;
+    function set_leader(address newLeader) external;
     function settlement_nonce() external view returns (uint256);
 }
@@ -10,9 +10,19 @@ contract DepositRegistryScript is Script {
     function setUp() public {}
 
     function run() public {
+        // defaults to the mainnet tokens, set WETH and USDC to deploy on another chain
+        // e.g. Base: WETH=0x4200000000000000000000000000000000000006 USDC=0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913
+        address weth = vm.envOr(
+            "WETH",
+            address(0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2)
+        );
+        address usdc = vm.envOr(
+            "USDC",
+            address(0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48)
+        );
         vm.startBroadcast();
 
-        depositRegistry = new DepositRegistry();
+        depositRegistry = new DepositRegistry(weth, usdc);
 
         vm.stopBroadcast();
     }
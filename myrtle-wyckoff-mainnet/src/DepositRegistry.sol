@@ -8,15 +8,15 @@ import {SignatureCheckerLib} from "../lib/solady/src/utils/SignatureCheckerLib.s
 contract DepositRegistry {
     address public admin; // Should be set to dstack container shared secret address
     uint256 public settlement_nonce;
+    // the dstack app proves deposits against this mapping's storage, keep it in slot 2
     mapping(address => uint256[2][]) public deposit_registry; // [eth_amount, usdc_amount]
-    ERC20 internal constant WETH =
-        ERC20(0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2); // Mainnet WETH address
+    // token addresses differ per chain, so they're set on deployment
+    ERC20 internal immutable WETH;
     address internal constant HookTrampoline =
-        0x01DcB88678aedD0C4cC9552B20F4718550250574; // Mainnet HookTrampoline address
-    ERC20 internal constant USDC =
-        ERC20(0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48); // Mainnet USDC address
+        0x01DcB88678aedD0C4cC9552B20F4718550250574; // HookTrampoline address, same on mainnet and Base
+    ERC20 internal immutable USDC;
     address internal constant GPv2Settlement =
-        0x9008D19f58AAbD9eD0D60971565AA8510560ab41; // Mainnet GPv2Settlement address
+        0x9008D19f58AAbD9eD0D60971565AA8510560ab41; // GPv2Settlement address, same on mainnet and Base
     /// @dev keccak256(
     ///     abi.encode(
     ///     keccak256(
@@ -29,8 +29,32 @@ contract DepositRegistry {
     ///     )
     /// )
     bytes32 internal domainSeparator;
-    constructor() {
+    // Per-container key of the dstack instance currently allowed to approve settlements, set by
+    // the admin. Every container shares the admin key, only the elected one holds this one
+    address public leader;
+    // The leader before the latest election. Approvals it signed still verify for LEADER_HANDOVER
+    // after it's replaced, so settlements it approved before a failover can still be pulled
+    address public previous_leader;
+    uint256 public leader_set_at;
+    uint256 public constant LEADER_HANDOVER = 1 hours;
+
+    /// @notice Emitted whenever the admin sets the leader, even if it didn't change
+    /// @param leader The key approvals have to be signed by from now on
+    event LeaderSet(address leader);
+
+    /// @notice Emitted for every deposit, the dstack app indexes these to credit depositors
+    /// @param index Position of the deposit in deposit_registry[user]
+    event Deposit(
+        address indexed user,
+        uint256 indexed index,
+        uint256 eth_amount,
+        uint256 usdc_amount
+    );
+
+    constructor(address weth, address usdc) {
         admin = msg.sender;
+        WETH = ERC20(weth);
+        USDC = ERC20(usdc);
     }
 
     function set_admin(address new_admin) external {
@@ -38,6 +62,16 @@ contract DepositRegistry {
         admin = new_admin;
     }
 
+    function set_leader(address new_leader) external {
+        require(msg.sender == admin, "Only the admin can set the leader");
+        if (new_leader != leader) {
+            previous_leader = leader;
+            leader_set_at = block.timestamp;
+        }
+        leader = new_leader;
+        emit LeaderSet(new_leader);
+    }
+
     function set_domain_separator(bytes32 domain_separator) external {
         if (msg.sender != admin) {
             revert("Only the admin can set the domain separator");
@@ -45,9 +79,32 @@ contract DepositRegistry {
         if (domainSeparator != 0) {
             revert("Domain separator already set");
         }
+        // the registry is deployed on more than one chain, an approval signed for another
+        // chain's registry must not verify here
+        require(
+            domain_separator ==
+                keccak256(
+                    abi.encode(
+                        keccak256(
+                            "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
+                        ),
+                        keccak256(bytes("MyrtleWyckoff")),
+                        keccak256(bytes("1")),
+                        block.chainid,
+                        address(this)
+                    )
+                ),
+            "Domain separator is for another chain or contract"
+        );
         domainSeparator = domain_separator;
     }
 
+    /// @notice The separator settlement approvals are verified against, the dstack app checks it
+    /// matches the domain it signs with on startup
+    function domain_separator() external view returns (bytes32) {
+        return domainSeparator;
+    }
+
     function deposit(
         address user,
         uint256 eth_amount,
@@ -61,6 +118,12 @@ contract DepositRegistry {
             USDC.transferFrom(user, address(this), usdc_amount);
         }
         deposit_registry[user].push([eth_amount, usdc_amount]);
+        emit Deposit(
+            user,
+            deposit_registry[user].length - 1,
+            eth_amount,
+            usdc_amount
+        );
     }
     // nonce is the index of the next time the user will deposit
     function get_deposits(
@@ -68,7 +131,8 @@ contract DepositRegistry {
         address user
     ) external view returns (uint256[2][] memory) {
         uint256[2][] memory deposits = deposit_registry[user];
-        require(_nonce < deposits.length, "Nonce is out of bounds");
+        // nonce == length means there's nothing new, which returns an empty array
+        require(_nonce <= deposits.length, "Nonce is out of bounds");
         uint256[2][] memory amounts = new uint256[2][](
             deposits.length - _nonce
         );
@@ -90,11 +154,32 @@ contract DepositRegistry {
         bool isBid;
         uint256 nonce;
     }
+    bytes32 internal constant ORDER_TYPEHASH =
+        keccak256(
+            "Order(uint256 ethAmount,uint256 usdcAmount,bool isBid,uint256 nonce)"
+        );
+
+    // EIP-712 hashStruct of a settlement order, what the dstack app signs
+    function hash_order(
+        Order calldata _order
+    ) internal pure returns (bytes32) {
+        return
+            keccak256(
+                abi.encode(
+                    ORDER_TYPEHASH,
+                    _order.ethAmount,
+                    _order.usdcAmount,
+                    _order.isBid,
+                    _order.nonce
+                )
+            );
+    }
 
-    // Approves a pull of funds for a settlement order
+    // Approves a pull of funds for a settlement order, signed by both the admin and the leader
     function pull_settlement_funds(
         Order calldata settlement_order,
-        bytes calldata signature
+        bytes calldata signature,
+        bytes calldata leader_signature
     ) external {
         // Only the HookTrampoline contract can pull funds
         require(
@@ -103,21 +188,32 @@ contract DepositRegistry {
         );
         // Nonce must match the current nonce
         require(settlement_order.nonce == settlement_nonce, "Nonce mismatch");
+        require(leader != address(0), "No leader set");
+        bytes32 message = EfficientHashLib.hash(
+            abi.encodePacked(
+                "\x19\x01",
+                domainSeparator,
+                hash_order(settlement_order)
+            )
+        );
         // Signature must be from the admin (dstack app shared secret)
         require(
             SignatureCheckerLib.isValidSignatureNowCalldata(
                 admin,
-                EfficientHashLib.hash(
-                    abi.encodePacked(
-                        "\x19\x01",
-                        domainSeparator,
-                        EfficientHashLib.hash(abi.encode(settlement_order))
-                    )
-                ),
+                message,
                 signature
             ),
             "Invalid signature"
         );
+        // and from the leader, so a container that isn't the leader can't approve on its own
+        require(
+            SignatureCheckerLib.isValidSignatureNowCalldata(
+                leader,
+                message,
+                leader_signature
+            ) || signed_by_previous_leader(message, leader_signature),
+            "Invalid leader signature"
+        );
 
         // Increment nonce for replay protection
         settlement_nonce++;
@@ -129,6 +225,21 @@ contract DepositRegistry {
         }
     }
 
+    // Whether the leader replaced within the last LEADER_HANDOVER signed `message`
+    function signed_by_previous_leader(
+        bytes32 message,
+        bytes calldata leader_signature
+    ) internal view returns (bool) {
+        return
+            previous_leader != address(0) &&
+            block.timestamp <= leader_set_at + LEADER_HANDOVER &&
+            SignatureCheckerLib.isValidSignatureNowCalldata(
+                previous_leader,
+                message,
+                leader_signature
+            );
+    }
+
     // EIP-1271 signature validation
     function isValidSignature(
         bytes32 _hash,
@@ -10,6 +10,8 @@ contract DepositRegistryTest is Test {
     DepositRegistry public depositRegistry;
     address public admin;
     uint256 adminKey;
+    address public leader;
+    uint256 leaderKey;
     address public user;
     bytes32 domain_hash;
     ERC20 public constant WETH =
@@ -21,8 +23,16 @@ contract DepositRegistryTest is Test {
     address internal constant GPv2Settlement =
         0x9008D19f58AAbD9eD0D60971565AA8510560ab41;
 
+    event Deposit(
+        address indexed user,
+        uint256 indexed index,
+        uint256 eth_amount,
+        uint256 usdc_amount
+    );
+
     function setUp() public {
         (admin, adminKey) = makeAddrAndKey("admin");
+        (leader, leaderKey) = makeAddrAndKey("leader");
         user = makeAddr("user");
 
         // Deploy mock tokens at the expected addresses
@@ -36,7 +46,7 @@ contract DepositRegistryTest is Test {
         usdc.initialize("USD Coin", "USDC", 6);
 
         vm.startPrank(admin);
-        depositRegistry = new DepositRegistry();
+        depositRegistry = new DepositRegistry(address(WETH), address(USDC));
 
         domain_hash = keccak256(
             abi.encode(
@@ -50,6 +60,7 @@ contract DepositRegistryTest is Test {
             )
         );
         depositRegistry.set_domain_separator(domain_hash);
+        depositRegistry.set_leader(leader);
         vm.stopPrank();
 
         // Deal tokens to user
@@ -65,6 +76,42 @@ contract DepositRegistryTest is Test {
     function test_InitialState() public view {
         assertEq(depositRegistry.admin(), admin);
         assertEq(depositRegistry.settlement_nonce(), 0);
+        assertEq(depositRegistry.leader(), leader);
+    }
+
+    function test_SetLeader_OnlyAdmin() public {
+        vm.prank(leader);
+        vm.expectRevert("Only the admin can set the leader");
+        depositRegistry.set_leader(user);
+    }
+
+    // EIP-712 hashStruct, spelled out the way alloy's eip712_hash_struct builds it in the app
+    function hashOrder(
+        DepositRegistry.Order memory order
+    ) internal pure returns (bytes32) {
+        return
+            keccak256(
+                abi.encode(
+                    keccak256(
+                        "Order(uint256 ethAmount,uint256 usdcAmount,bool isBid,uint256 nonce)"
+                    ),
+                    order.ethAmount,
+                    order.usdcAmount,
+                    order.isBid,
+                    order.nonce
+                )
+            );
+    }
+
+    function signOrder(
+        uint256 key,
+        DepositRegistry.Order memory order
+    ) internal view returns (bytes memory) {
+        bytes32 message = keccak256(
+            abi.encodePacked("\x19\x01", domain_hash, hashOrder(order))
+        );
+        (uint8 v, bytes32 r, bytes32 s) = vm.sign(key, message);
+        return abi.encodePacked(r, s, v);
     }
 
     function test_SetAdmin() public {
@@ -81,6 +128,33 @@ contract DepositRegistryTest is Test {
         depositRegistry.set_admin(newAdmin);
     }
 
+    function test_SetDomainSeparator_OtherChain() public {
+        vm.startPrank(admin);
+        DepositRegistry registry = new DepositRegistry(
+            address(WETH),
+            address(USDC)
+        );
+        bytes32 base_domain_hash = keccak256(
+            abi.encode(
+                keccak256(
+                    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
+                ),
+                keccak256(bytes("MyrtleWyckoff")),
+                keccak256(bytes("1")),
+                block.chainid + 1,
+                address(registry)
+            )
+        );
+        vm.expectRevert("Domain separator is for another chain or contract");
+        registry.set_domain_separator(base_domain_hash);
+        // nor can it reuse the separator of another registry on this chain
+        vm.expectRevert("Domain separator is for another chain or contract");
+        registry.set_domain_separator(domain_hash);
+        vm.stopPrank();
+        assertEq(registry.domain_separator(), bytes32(0));
+        assertEq(depositRegistry.domain_separator(), domain_hash);
+    }
+
     function test_Deposit() public {
         uint256 ethAmount = 1 ether;
         uint256 usdcAmount = 1000 * 1e6;
@@ -93,6 +167,16 @@ contract DepositRegistryTest is Test {
         assertEq(deposits[0][1], usdcAmount);
     }
 
+    function test_Deposit_EmitsIndexedEvent() public {
+        vm.startPrank(user);
+        depositRegistry.deposit(user, 1 ether, 1000 * 1e6);
+
+        vm.expectEmit(true, true, false, true, address(depositRegistry));
+        emit Deposit(user, 1, 2 ether, 0);
+        depositRegistry.deposit(user, 2 ether, 0);
+        vm.stopPrank();
+    }
+
     function test_Deposit_OnlyUser() public {
         vm.prank(admin);
         vm.expectRevert("Only the user can deposit");
@@ -118,6 +202,27 @@ contract DepositRegistryTest is Test {
         assertEq(deposits[2][1], 3000 * 1e6);
     }
 
+    function test_GetDeposits_FromLaterNonce() public {
+        vm.startPrank(user);
+        depositRegistry.deposit(user, 1 ether, 1000 * 1e6);
+        depositRegistry.deposit(user, 2 ether, 2000 * 1e6);
+        depositRegistry.deposit(user, 3 ether, 3000 * 1e6);
+        vm.stopPrank();
+
+        uint256[2][] memory deposits = depositRegistry.get_deposits(1, user);
+        assertEq(deposits.length, 2);
+        assertEq(deposits[0][0], 2 ether);
+        assertEq(deposits[1][0], 3 ether);
+    }
+
+    function test_GetDeposits_NoNewDeposits() public {
+        assertEq(depositRegistry.get_deposits(0, user).length, 0);
+
+        vm.prank(user);
+        depositRegistry.deposit(user, 1 ether, 1000 * 1e6);
+        assertEq(depositRegistry.get_deposits(1, user).length, 0);
+    }
+
     function test_GetDeposits_OutOfBounds() public {
         vm.expectRevert("Nonce is out of bounds");
         depositRegistry.get_deposits(1, user);
@@ -132,17 +237,13 @@ contract DepositRegistryTest is Test {
             nonce: 0
         });
 
-        // Create signature
-        bytes32 encodedOrder = keccak256(abi.encode(order));
-        bytes32 message = keccak256(
-            abi.encodePacked("\x19\x01", domain_hash, encodedOrder)
-        );
-        (uint8 v, bytes32 r, bytes32 s) = vm.sign(adminKey, message);
-        bytes memory signature = abi.encodePacked(r, s, v);
-
         // Call pull_settlement_funds as HookTrampoline
         vm.prank(HookTrampoline);
-        depositRegistry.pull_settlement_funds(order, signature);
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(leaderKey, order)
+        );
 
         // Verify state changes
         assertEq(depositRegistry.settlement_nonce(), 1);
@@ -153,6 +254,43 @@ contract DepositRegistryTest is Test {
         assertEq(USDC.allowance(address(depositRegistry), GPv2Settlement), 0);
     }
 
+    function test_OrderHashIsEip712() public {
+        DepositRegistry.Order memory order = DepositRegistry.Order({
+            ethAmount: 1 ether,
+            usdcAmount: 1000 * 1e6,
+            isBid: false,
+            nonce: 0
+        });
+
+        // what the registry hashed before, the plain abi encoding, doesn't verify
+        bytes32 message = keccak256(
+            abi.encodePacked(
+                "\x19\x01",
+                domain_hash,
+                keccak256(abi.encode(order))
+            )
+        );
+        (uint8 v, bytes32 r, bytes32 s) = vm.sign(adminKey, message);
+        vm.startPrank(HookTrampoline);
+        vm.expectRevert("Invalid signature");
+        depositRegistry.pull_settlement_funds(
+            order,
+            abi.encodePacked(r, s, v),
+            signOrder(leaderKey, order)
+        );
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(leaderKey, order)
+        );
+        vm.stopPrank();
+        assertEq(depositRegistry.settlement_nonce(), 1);
+        assertEq(
+            USDC.allowance(address(depositRegistry), GPv2Settlement),
+            1000 * 1e6
+        );
+    }
+
     function test_PullSettlementFunds_OnlyHookTrampoline() public {
         DepositRegistry.Order memory order = DepositRegistry.Order({
             ethAmount: 1 ether,
@@ -163,6 +301,72 @@ contract DepositRegistryTest is Test {
 
         vm.prank(user);
         vm.expectRevert("Only the HookTrampoline contract can pull funds");
-        depositRegistry.pull_settlement_funds(order, new bytes(0));
+        depositRegistry.pull_settlement_funds(order, new bytes(0), new bytes(0));
+    }
+
+    function test_PullSettlementFunds_NeedsLeaderSignature() public {
+        DepositRegistry.Order memory order = DepositRegistry.Order({
+            ethAmount: 1 ether,
+            usdcAmount: 1000 * 1e6,
+            isBid: true,
+            nonce: 0
+        });
+        (, uint256 otherKey) = makeAddrAndKey("other");
+
+        vm.startPrank(HookTrampoline);
+        vm.expectRevert("Invalid leader signature");
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(otherKey, order)
+        );
+        vm.expectRevert("Invalid signature");
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(leaderKey, order),
+            signOrder(leaderKey, order)
+        );
+        vm.stopPrank();
+        assertEq(depositRegistry.settlement_nonce(), 0);
+    }
+
+    function test_PullSettlementFunds_AfterHandover() public {
+        (address newLeader, uint256 newLeaderKey) = makeAddrAndKey("newLeader");
+        vm.prank(admin);
+        depositRegistry.set_leader(newLeader);
+        assertEq(depositRegistry.previous_leader(), leader);
+
+        // approved by the old leader before the failover, still pulled after it
+        DepositRegistry.Order memory order = DepositRegistry.Order({
+            ethAmount: 1 ether,
+            usdcAmount: 1000 * 1e6,
+            isBid: true,
+            nonce: 0
+        });
+        vm.prank(HookTrampoline);
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(leaderKey, order)
+        );
+        assertEq(depositRegistry.settlement_nonce(), 1);
+
+        // but not once the handover is over
+        order.nonce = 1;
+        vm.warp(block.timestamp + depositRegistry.LEADER_HANDOVER() + 1);
+        vm.startPrank(HookTrampoline);
+        vm.expectRevert("Invalid leader signature");
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(leaderKey, order)
+        );
+        depositRegistry.pull_settlement_funds(
+            order,
+            signOrder(adminKey, order),
+            signOrder(newLeaderKey, order)
+        );
+        vm.stopPrank();
+        assertEq(depositRegistry.settlement_nonce(), 2);
     }
 }
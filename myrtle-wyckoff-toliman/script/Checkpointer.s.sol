@@ -18,9 +18,9 @@ contract CheckpointerScript is Script {
                 keccak256(
                     "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
                 ),
+                keccak256(bytes("MyrtleWyckoff")),
                 keccak256(bytes("1")),
-                keccak256(bytes("1")),
-                1,
+                block.chainid,
                 address(checkpointer)
             )
         );
@@ -2,6 +2,7 @@
 pragma solidity ^0.8.13;
 import {EfficientHashLib} from "../lib/solady/src/utils/EfficientHashLib.sol";
 import {SignatureCheckerLib} from "../lib/solady/src/utils/SignatureCheckerLib.sol";
+import {MerkleProofLib} from "../lib/solady/src/utils/MerkleProofLib.sol";
 
 contract Checkpointer {
     address public admin; // Should be set to dstack app shared secret address
@@ -19,10 +20,23 @@ contract Checkpointer {
     ///     )
     /// )
     bytes32 internal domainSeparator;
+    // Per-container key of the dstack instance currently allowed to checkpoint, set by the admin.
+    // Every container shares the admin key, only the elected one holds this one
+    address public leader;
+    event LeaderSet(address leader);
+    // The leader holds the Checkpointer for LEADER_LEASE after it's elected or last checkpointed,
+    // another key can only be elected once that runs out. Every container holds the admin key, so
+    // without it any of them could take over from a leader that's still running
+    uint256 public constant LEADER_LEASE = 10 minutes;
+    uint256 public leader_renewed_at;
 
-    // Vec of AES encoded inventories structured as (user: Address, eth_balance: i128, usdc_balance: i128, deposit nonce: u32, is_taker: u8)
+    // Versioned blob of AES-GCM encrypted inventories, see myrtle-wyckoff-dstack/src/blob.rs for the format
     // In prod this should store multiple checkpoints and overwrite oldest with newest
     uint8[] public inventory_checkpoint;
+    // Merkle root over keccak256 of each canonical inventory encoding, sorted by address, pairs hashed sorted
+    bytes32 public inventory_root;
+    // Settlement orders from the latest checkpoint, kept so the app can be recovered from chain state
+    string[] internal settlement_orders;
 
     constructor() {
         admin = msg.sender;
@@ -34,50 +48,154 @@ contract Checkpointer {
         if (domainSeparator != 0) {
             revert("Domain separator already set");
         }
+        // a checkpoint signed for another chain's or contract's Checkpointer must not verify here
+        require(
+            domain_separator ==
+                keccak256(
+                    abi.encode(
+                        keccak256(
+                            "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
+                        ),
+                        keccak256(bytes("MyrtleWyckoff")),
+                        keccak256(bytes("1")),
+                        block.chainid,
+                        address(this)
+                    )
+                ),
+            "Domain separator is for another chain or contract"
+        );
         domainSeparator = domain_separator;
     }
 
+    /// @notice The separator checkpoints are verified against, the dstack app checks it matches
+    /// the domain it signs with on startup
+    function domain_separator() external view returns (bytes32) {
+        return domainSeparator;
+    }
+
     function set_admin(address new_admin) external {
         require(msg.sender == admin, "Only the admin can set a new admin");
         admin = new_admin;
     }
 
+    function set_leader(address new_leader) external {
+        require(msg.sender == admin, "Only the admin can set the leader");
+        require(
+            leader == address(0) ||
+                new_leader == leader ||
+                block.timestamp > leader_renewed_at + LEADER_LEASE,
+            "The leader's lease hasn't run out"
+        );
+        leader = new_leader;
+        leader_renewed_at = block.timestamp;
+        emit LeaderSet(new_leader);
+    }
+
     struct Checkpoint {
         uint256 nonce;
+        bytes32 inventory_root;
         uint8[] inventory_state;
         string[] settlement_orders;
     }
+    bytes32 internal constant CHECKPOINT_TYPEHASH =
+        keccak256(
+            "Checkpoint(uint256 nonce,bytes32 inventory_root,uint8[] inventory_state,string[] settlement_orders)"
+        );
 
-    // Register new blob containing encrypted inventory state
+    // EIP-712 hashStruct of a checkpoint, what the dstack app signs. Arrays are hashed over their
+    // encoded elements, each padded to 32 bytes, strings over their keccak256
+    function hash_checkpoint(
+        Checkpoint calldata _checkpoint
+    ) internal pure returns (bytes32) {
+        bytes32[] memory order_hashes = new bytes32[](
+            _checkpoint.settlement_orders.length
+        );
+        for (uint256 i = 0; i < _checkpoint.settlement_orders.length; i++) {
+            order_hashes[i] = keccak256(
+                bytes(_checkpoint.settlement_orders[i])
+            );
+        }
+        return
+            keccak256(
+                abi.encode(
+                    CHECKPOINT_TYPEHASH,
+                    _checkpoint.nonce,
+                    _checkpoint.inventory_root,
+                    keccak256(abi.encodePacked(_checkpoint.inventory_state)),
+                    keccak256(abi.encodePacked(order_hashes))
+                )
+            );
+    }
+
+    // Register new blob containing encrypted inventory state, signed by both the admin and the leader,
+    // which renews the leader's lease
     function checkpoint(
         bytes calldata signature,
+        bytes calldata leader_signature,
         Checkpoint calldata _checkpoint
     ) external {
         require(
             _checkpoint.nonce == inventory_checkpoint_nonce,
             "Nonce mismatch"
         );
+        require(leader != address(0), "No leader set");
 
+        bytes32 message = EfficientHashLib.hash(
+            abi.encodePacked(
+                "\x19\x01",
+                domainSeparator,
+                hash_checkpoint(_checkpoint)
+            )
+        );
         require(
-            SignatureCheckerLib.isValidSignatureNow(
+            SignatureCheckerLib.isValidSignatureNowCalldata(
                 admin,
-                EfficientHashLib.hash(
-                    abi.encodePacked(
-                        "\x19\x01",
-                        domainSeparator,
-                        EfficientHashLib.hash(abi.encode(_checkpoint))
-                    )
-                ),
+                message,
                 signature
             ),
             "Invalid signature"
         );
+        require(
+            SignatureCheckerLib.isValidSignatureNowCalldata(
+                leader,
+                message,
+                leader_signature
+            ),
+            "Invalid leader signature"
+        );
         inventory_checkpoint_nonce++;
+        leader_renewed_at = block.timestamp;
         inventory_checkpoint = _checkpoint.inventory_state;
+        inventory_root = _checkpoint.inventory_root;
+        delete settlement_orders;
+        for (uint256 i = 0; i < _checkpoint.settlement_orders.length; i++) {
+            settlement_orders.push(_checkpoint.settlement_orders[i]);
+        }
 
         emit SettlementOrders(_checkpoint.settlement_orders);
     }
 
+    function get_inventory_checkpoint() external view returns (uint8[] memory) {
+        return inventory_checkpoint;
+    }
+
+    function get_settlement_orders() external view returns (string[] memory) {
+        return settlement_orders;
+    }
+
+    // Check a user's inventory encoding against the latest checkpoint
+    function verify_inventory(
+        bytes32[] calldata proof,
+        bytes calldata inventory
+    ) external view returns (bool) {
+        return
+            MerkleProofLib.verifyCalldata(
+                proof,
+                inventory_root,
+                keccak256(inventory)
+            );
+    }
+
     function validateSignature(
         bytes memory signature,
         bytes32 messageHash,
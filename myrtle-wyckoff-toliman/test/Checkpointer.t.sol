@@ -8,11 +8,14 @@ contract CheckpointerTest is Test {
     Checkpointer public checkpointer;
     address admin;
     uint256 adminKey;
+    address leader;
+    uint256 leaderKey;
     bytes32 domain_hash;
 
     function setUp() public {
         // Create a deterministic admin address for testing
         (admin, adminKey) = makeAddrAndKey("admin");
+        (leader, leaderKey) = makeAddrAndKey("leader");
         vm.startBroadcast(adminKey);
         checkpointer = new Checkpointer();
         // Set domain hash
@@ -23,17 +26,212 @@ contract CheckpointerTest is Test {
                 ),
                 keccak256(bytes("MyrtleWyckoff")),
                 keccak256(bytes("1")),
-                33626250,
+                block.chainid,
                 address(checkpointer)
             )
         );
         checkpointer.set_domain_separator(domain_hash);
+        checkpointer.set_leader(leader);
         vm.stopBroadcast();
     }
 
+    // EIP-712 hashStruct, spelled out the way alloy's eip712_hash_struct builds it in the app
+    function hashCheckpoint(
+        Checkpointer.Checkpoint memory checkpoint
+    ) internal pure returns (bytes32) {
+        bytes memory state = new bytes(0);
+        for (uint256 i = 0; i < checkpoint.inventory_state.length; i++) {
+            state = bytes.concat(
+                state,
+                abi.encode(checkpoint.inventory_state[i])
+            );
+        }
+        bytes memory orders = new bytes(0);
+        for (uint256 i = 0; i < checkpoint.settlement_orders.length; i++) {
+            orders = bytes.concat(
+                orders,
+                keccak256(bytes(checkpoint.settlement_orders[i]))
+            );
+        }
+        return
+            keccak256(
+                abi.encode(
+                    keccak256(
+                        "Checkpoint(uint256 nonce,bytes32 inventory_root,uint8[] inventory_state,string[] settlement_orders)"
+                    ),
+                    checkpoint.nonce,
+                    checkpoint.inventory_root,
+                    keccak256(state),
+                    keccak256(orders)
+                )
+            );
+    }
+
+    function sign(
+        uint256 key,
+        Checkpointer.Checkpoint memory checkpoint
+    ) internal view returns (bytes memory) {
+        bytes32 message = keccak256(
+            abi.encodePacked("\x19\x01", domain_hash, hashCheckpoint(checkpoint))
+        );
+        (uint8 v, bytes32 r, bytes32 s) = vm.sign(key, message);
+        return abi.encodePacked(r, s, v);
+    }
+
     function test_InitialState() public view {
         assertEq(checkpointer.admin(), admin);
         assertEq(checkpointer.inventory_checkpoint_nonce(), 0);
+        assertEq(checkpointer.domain_separator(), domain_hash);
+        assertEq(checkpointer.leader(), leader);
+    }
+
+    function test_SetLeader() public {
+        address newLeader = makeAddr("newLeader");
+
+        // only the admin elects leaders, a leader can't hand over on its own
+        vm.prank(leader);
+        vm.expectRevert("Only the admin can set the leader");
+        checkpointer.set_leader(newLeader);
+
+        // not while the elected leader's lease runs
+        vm.prank(admin);
+        vm.expectRevert("The leader's lease hasn't run out");
+        checkpointer.set_leader(newLeader);
+
+        vm.warp(block.timestamp + checkpointer.LEADER_LEASE() + 1);
+        vm.prank(admin);
+        checkpointer.set_leader(newLeader);
+        assertEq(checkpointer.leader(), newLeader);
+        assertEq(checkpointer.leader_renewed_at(), block.timestamp);
+    }
+
+    function test_CheckpointsRenewTheLease() public {
+        Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
+            nonce: 0,
+            inventory_root: keccak256("root"),
+            inventory_state: new uint8[](0),
+            settlement_orders: new string[](0)
+        });
+        uint256 lease = checkpointer.LEADER_LEASE();
+        vm.warp(block.timestamp + lease);
+        checkpointer.checkpoint(
+            sign(adminKey, checkpoint),
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
+        assertEq(checkpointer.leader_renewed_at(), block.timestamp);
+
+        // a leader that keeps checkpointing keeps the Checkpointer
+        vm.warp(block.timestamp + lease);
+        vm.prank(admin);
+        vm.expectRevert("The leader's lease hasn't run out");
+        checkpointer.set_leader(makeAddr("newLeader"));
+        // re-electing the leader itself is always fine
+        vm.prank(admin);
+        checkpointer.set_leader(leader);
+
+        vm.warp(block.timestamp + lease + 1);
+        vm.prank(admin);
+        checkpointer.set_leader(makeAddr("newLeader"));
+        assertEq(checkpointer.leader(), makeAddr("newLeader"));
+    }
+
+    function test_CheckpointNeedsLeaderSignature() public {
+        Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
+            nonce: 0,
+            inventory_root: keccak256("root"),
+            inventory_state: new uint8[](0),
+            settlement_orders: new string[](0)
+        });
+        bytes memory signature = sign(adminKey, checkpoint);
+
+        // another container with the shared secret but not the leader key
+        (, uint256 otherKey) = makeAddrAndKey("other");
+        vm.expectRevert("Invalid leader signature");
+        checkpointer.checkpoint(
+            signature,
+            sign(otherKey, checkpoint),
+            checkpoint
+        );
+        // the leader alone isn't enough either
+        vm.expectRevert("Invalid signature");
+        checkpointer.checkpoint(
+            sign(leaderKey, checkpoint),
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
+
+        // a replaced leader's checkpoints stop verifying
+        vm.warp(block.timestamp + checkpointer.LEADER_LEASE() + 1);
+        vm.prank(admin);
+        checkpointer.set_leader(makeAddr("newLeader"));
+        vm.expectRevert("Invalid leader signature");
+        checkpointer.checkpoint(
+            signature,
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
+    }
+
+    function test_SetDomainSeparator_OtherChain() public {
+        vm.startPrank(admin);
+        Checkpointer other = new Checkpointer();
+        bytes32 other_chain_hash = keccak256(
+            abi.encode(
+                keccak256(
+                    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
+                ),
+                keccak256(bytes("MyrtleWyckoff")),
+                keccak256(bytes("1")),
+                block.chainid + 1,
+                address(other)
+            )
+        );
+        vm.expectRevert("Domain separator is for another chain or contract");
+        other.set_domain_separator(other_chain_hash);
+        // nor can it reuse the separator of another Checkpointer on this chain
+        vm.expectRevert("Domain separator is for another chain or contract");
+        other.set_domain_separator(domain_hash);
+        vm.stopPrank();
+        assertEq(other.domain_separator(), bytes32(0));
+    }
+
+    function test_CheckpointHashIsEip712() public {
+        string[] memory settlementOrders = new string[](2);
+        settlementOrders[0] = "order1";
+        settlementOrders[1] = "order2";
+        uint8[] memory inventoryState = new uint8[](3);
+        inventoryState[0] = 1;
+        inventoryState[2] = 255;
+        Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
+            nonce: 0,
+            inventory_root: keccak256("root"),
+            inventory_state: inventoryState,
+            settlement_orders: settlementOrders
+        });
+        checkpointer.checkpoint(
+            sign(adminKey, checkpoint),
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
+        assertEq(checkpointer.inventory_checkpoint_nonce(), 1);
+
+        // what the contract hashed before, the plain abi encoding, doesn't verify
+        checkpoint.nonce = 1;
+        bytes32 message = keccak256(
+            abi.encodePacked(
+                "\x19\x01",
+                domain_hash,
+                keccak256(abi.encode(checkpoint))
+            )
+        );
+        (uint8 v, bytes32 r, bytes32 s) = vm.sign(adminKey, message);
+        vm.expectRevert("Invalid signature");
+        checkpointer.checkpoint(
+            abi.encodePacked(r, s, v),
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
     }
 
     function test_SetAdmin() public {
@@ -60,28 +258,28 @@ contract CheckpointerTest is Test {
 
         Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
             nonce: 0,
+            inventory_root: keccak256("root1"),
             inventory_state: inventoryState,
             settlement_orders: settlementOrders
         });
 
-        // Create signature
-        bytes32 encodedCheckpoint = keccak256(abi.encode(checkpoint));
-        bytes32 message = keccak256(
-            abi.encodePacked("\x19\x01", domain_hash, encodedCheckpoint)
-        );
-        (uint8 v, bytes32 r, bytes32 s) = vm.sign(adminKey, message);
-        bytes memory signature = abi.encodePacked(r, s, v);
+        bytes memory signature = sign(adminKey, checkpoint);
 
         // Test checkpoint submission
         vm.expectEmit(true, true, true, true);
         emit Checkpointer.SettlementOrders(settlementOrders);
 
-        checkpointer.checkpoint(signature, checkpoint);
+        checkpointer.checkpoint(
+            signature,
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
 
         // Verify state changes
         assertEq(checkpointer.inventory_checkpoint_nonce(), 1);
         assertEq(checkpointer.inventory_checkpoint(0), 1);
         assertEq(checkpointer.inventory_checkpoint(1), 2);
+        assertEq(checkpointer.inventory_root(), keccak256("root1"));
 
         uint8[] memory inventoryState_2 = new uint8[](2);
         inventoryState_2[0] = 3;
@@ -92,28 +290,36 @@ contract CheckpointerTest is Test {
 
         Checkpointer.Checkpoint memory checkpoint_2 = Checkpointer.Checkpoint({
             nonce: 1,
+            inventory_root: keccak256("root2"),
             inventory_state: inventoryState_2,
             settlement_orders: settlementOrders_2
         });
 
-        // Create signature
-        bytes32 encodedCheckpoint_2 = keccak256(abi.encode(checkpoint_2));
-        bytes32 message_2 = keccak256(
-            abi.encodePacked("\x19\x01", domain_hash, encodedCheckpoint_2)
-        );
-        (uint8 v_2, bytes32 r_2, bytes32 s_2) = vm.sign(adminKey, message_2);
-        bytes memory signature_2 = abi.encodePacked(r_2, s_2, v_2);
+        bytes memory signature_2 = sign(adminKey, checkpoint_2);
 
         // Test checkpoint submission
         vm.expectEmit(true, true, true, true);
         emit Checkpointer.SettlementOrders(settlementOrders_2);
 
-        checkpointer.checkpoint(signature_2, checkpoint_2);
+        checkpointer.checkpoint(
+            signature_2,
+            sign(leaderKey, checkpoint_2),
+            checkpoint_2
+        );
 
         // Verify state changes
         assertEq(checkpointer.inventory_checkpoint_nonce(), 2);
         assertEq(checkpointer.inventory_checkpoint(0), 3);
         assertEq(checkpointer.inventory_checkpoint(1), 4);
+        assertEq(checkpointer.inventory_root(), keccak256("root2"));
+
+        // latest checkpoint can be read back in full for recovery
+        uint8[] memory storedState = checkpointer.get_inventory_checkpoint();
+        assertEq(storedState.length, 2);
+        assertEq(storedState[0], 3);
+        string[] memory storedOrders = checkpointer.get_settlement_orders();
+        assertEq(storedOrders.length, 1);
+        assertEq(storedOrders[0], "order2");
     }
 
     function testFail_CheckpointInvalidNonce() public {
@@ -122,11 +328,41 @@ contract CheckpointerTest is Test {
 
         Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
             nonce: 1, // Invalid nonce
+            inventory_root: bytes32(0),
             inventory_state: inventoryState,
             settlement_orders: settlementOrders
         });
 
         bytes memory signature = new bytes(65);
-        checkpointer.checkpoint(signature, checkpoint);
+        checkpointer.checkpoint(signature, signature, checkpoint);
+    }
+
+    function test_VerifyInventory() public {
+        bytes memory inventoryA = abi.encodePacked(makeAddr("a"), uint256(1));
+        bytes memory inventoryB = abi.encodePacked(makeAddr("b"), uint256(2));
+        bytes32 leafA = keccak256(inventoryA);
+        bytes32 leafB = keccak256(inventoryB);
+        bytes32 root = leafA < leafB
+            ? keccak256(abi.encodePacked(leafA, leafB))
+            : keccak256(abi.encodePacked(leafB, leafA));
+
+        Checkpointer.Checkpoint memory checkpoint = Checkpointer.Checkpoint({
+            nonce: 0,
+            inventory_root: root,
+            inventory_state: new uint8[](0),
+            settlement_orders: new string[](0)
+        });
+        checkpointer.checkpoint(
+            sign(adminKey, checkpoint),
+            sign(leaderKey, checkpoint),
+            checkpoint
+        );
+
+        bytes32[] memory proof = new bytes32[](1);
+        proof[0] = leafB;
+        assertTrue(checkpointer.verify_inventory(proof, inventoryA));
+        proof[0] = leafA;
+        assertTrue(checkpointer.verify_inventory(proof, inventoryB));
+        assertFalse(checkpointer.verify_inventory(proof, inventoryA));
     }
 }